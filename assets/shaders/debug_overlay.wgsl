@@ -0,0 +1,96 @@
+// Debug overlay for tuning `cell_size`, drawn over the particles. Each layer is a separate
+// fragment entrypoint, so the CPU just draws whichever layers are switched on, see
+// `WrachDebugOverlay`.
+
+#import types::WorldSettings;
+
+@group(0) @binding(0) var<uniform> settings: WorldSettings;
+@group(0) @binding(2) var<storage, read> indices: array<u32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // Position in the simulation, in the same units as the particle positions
+    @location(0) world: vec2<f32>,
+}
+
+// A single triangle that covers the whole view.
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // The same mapping from simulation to view as `draw.wgsl`.
+    out.world = settings.view_anchor + uv * settings.view_dimensions;
+    return out;
+}
+
+// Boundaries between the spatial bin cells.
+@fragment
+fn grid(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Derivatives have to be taken before any discards.
+    let cell_position = (in.world - settings.view_anchor) / settings.cell_size;
+    let distance_to_edge = abs(fract(cell_position - 0.5) - 0.5) / fwidth(cell_position);
+
+    if !is_in_grid(in.world) || min(distance_to_edge.x, distance_to_edge.y) > 1.0 {
+        discard;
+    }
+    return vec4<f32>(0.5, 0.5, 0.5, 0.5);
+}
+
+// A heat map of how many particles are in each cell, from blue for a single particle to red for a
+// full cell.
+@fragment
+fn occupancy(in: VertexOutput) -> @location(0) vec4<f32> {
+    if !is_in_grid(in.world) {
+        discard;
+    }
+
+    let count = particles_in_cell(in.world);
+    if count == 0u {
+        discard;
+    }
+    let heat = clamp(f32(count) / f32(#{MAX_PARTICLES_IN_CELL}), 0.0, 1.0);
+    return vec4<f32>(heat, 0.0, 1.0 - heat, 0.4);
+}
+
+// Cells with more particles than get the full physics.
+@fragment
+fn overflow(in: VertexOutput) -> @location(0) vec4<f32> {
+    if !is_in_grid(in.world) || particles_in_cell(in.world) <= #{MAX_PARTICLES_IN_CELL} {
+        discard;
+    }
+    return vec4<f32>(1.0, 1.0, 0.0, 0.6);
+}
+
+// An outline of the viewport. Nothing outside of it is simulated, the physics ends the lifetime of
+// any particle that leaves it.
+@fragment
+fn viewport(in: VertexOutput) -> @location(0) vec4<f32> {
+    let bottom_left = settings.view_anchor;
+    let top_right = settings.view_anchor + settings.view_dimensions;
+    let distance_to_edge = min(in.world - bottom_left, top_right - in.world) / fwidth(in.world);
+
+    if min(distance_to_edge.x, distance_to_edge.y) > 1.0 {
+        discard;
+    }
+    return vec4<f32>(0.0, 1.0, 0.0, 1.0);
+}
+
+// The coordinates of the cell that a position is in. The grid starts at the view's anchor, the same
+// as the physics' `cell_index()`.
+fn grid_coord(world: vec2<f32>) -> vec2<i32> {
+    return vec2<i32>(floor((world - settings.view_anchor) / settings.cell_size));
+}
+
+// Whether a position is in one of the cells being simulated.
+fn is_in_grid(world: vec2<f32>) -> bool {
+    let coord = grid_coord(world);
+    return all(coord >= vec2<i32>(0)) && all(coord < vec2<i32>(settings.grid_dimensions));
+}
+
+// The number of particles in the cell that a position is in. The position must be in the grid.
+fn particles_in_cell(world: vec2<f32>) -> u32 {
+    let coord = vec2<u32>(grid_coord(world));
+    let cell = coord.y * settings.grid_dimensions.x + coord.x;
+    return indices[cell + 1u] - indices[cell];
+}
@@ -1,9 +1,18 @@
-// Just draws particles as simple pixels
+// Just draws particles as simple pixels, coloured by how far through their lifetime they are
 
-#import types::WorldSettings;
+#import types::{WorldSettings, PackedParticle};
+
+// See `WrachAgeingCurve`
+struct AgeingCurve {
+    young: vec4<f32>,
+    old: vec4<f32>,
+    exponent: f32,
+}
 
 @group(0) @binding(0) var<uniform> settings: WorldSettings;
-@group(0) @binding(1) var<storage, read_write> positions: array<vec2<f32>>;
+@group(0) @binding(1) var<storage, read> particles: array<PackedParticle>;
+@group(0) @binding(2) var<storage, read> indices: array<u32>;
+@group(1) @binding(0) var<uniform> ageing_curve: AgeingCurve;
 
 struct VertexInput {
     @builtin(vertex_index) index: u32,
@@ -19,21 +28,41 @@ struct VertexOutput {
 fn vertex(input: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     var local_position: vec2<f32>;
-    var pixel_size = 0.4;
+    // A little smaller than the particle, so that touching particles can still be told apart
+    var pixel_size = settings.particle_radius * 0.8;
     // The GPU view target is in the range: `[-1.0, -1.0, 0.0, 0.0]`. So here we scale the viewport
     // coordinates to that.
     var factor: vec2<f32> = 1.0 / (settings.view_dimensions / 2.0);
 
+    // The CPU only knows an upper bound for the number of particles, so the spare instances are
+    // moved outside of the view. The guard item at the end of the grid has the exact number.
+    let total_cells = settings.grid_dimensions.x * settings.grid_dimensions.y;
+    if input.instance >= indices[total_cells] {
+        out.position = vec4<f32>(2.0, 2.0, 0.0, 1.0);
+        return out;
+    }
+
     let index = square_indices[input.index];
     local_position = square_vertices[index] * factor * pixel_size;
-    let particle_position = (positions[input.instance] * factor) - 1.0;
+    let particle = particles[input.instance];
+    let relative_to_view = particle.position - settings.view_anchor;
+    let particle_position = (relative_to_view * factor) - 1.0;
     let view_position = vec4<f32>(particle_position + local_position, 0.0, 1.0);
 
     out.position = view_position;
-    out.color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    out.color = age_color(particle.age);
     return out;
 }
 
+// Fade from young to old over the particle's lifetime. A lifetime of 0 means forever.
+fn age_color(age: vec2<u32>) -> vec4<f32> {
+    if age.y == 0u {
+        return ageing_curve.young;
+    }
+    let progress = clamp(f32(age.x) / f32(age.y), 0.0, 1.0);
+    return mix(ageing_curve.young, ageing_curve.old, pow(progress, ageing_curve.exponent));
+}
+
 @fragment
 fn fragment(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
     return color;
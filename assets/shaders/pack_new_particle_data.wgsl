@@ -1,45 +1,87 @@
-#import types::WorldSettings;
+#import types::{WorldSettings, PackedParticle};
 
 @group(0) @binding(0) var<uniform> settings: WorldSettings;
-@group(0) @binding(1) var<storage, read> positions_out: array<vec2<f32>>;
-@group(0) @binding(2) var<storage, read> velocities_out: array<vec2<f32>>;
-@group(0) @binding(3) var<storage, read_write> indices: array<atomic<u32>>;
-@group(0) @binding(4) var<storage, read_write> positions_in: array<vec2<f32>>;
-@group(0) @binding(5) var<storage, read_write> velocities_in: array<vec2<f32>>;
-
-@compute @workgroup_size(1024)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    if global_id.x >= settings.particles_in_frame_count {
+@group(0) @binding(1) var<storage, read> particles_out: array<PackedParticle>;
+@group(0) @binding(2) var<storage, read> particles_new: array<PackedParticle>;
+@group(0) @binding(3) var<storage, read> indices: array<u32>;
+@group(0) @binding(4) var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(0) @binding(5) var<storage, read_write> particles_in: array<PackedParticle>;
+
+// One entrypoint for each of the supported workgroup sizes, see `WORKGROUP_SIZES` in the shared
+// crate. The CPU picks which one to use.
+@compute @workgroup_size(32)
+fn main_32(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    pack_particle(global_id.x);
+}
+
+@compute @workgroup_size(64)
+fn main_64(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    pack_particle(global_id.x);
+}
+
+@compute @workgroup_size(128)
+fn main_128(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    pack_particle(global_id.x);
+}
+
+@compute @workgroup_size(256)
+fn main_256(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    pack_particle(global_id.x);
+}
+
+fn pack_particle(particle_index: u32) {
+    // The CPU only knows an upper bound for the number of particles that the physics integrated,
+    // because the GPU culls some by itself. The physics expires the particles between the ones it
+    // integrated and the upper bound, so they're left out below.
+    let integrated_count = settings.particles_in_frame_count;
+    if particle_index >= integrated_count + settings.new_particles_count {
+        return;
+    }
+
+    // Newly added particles get packed alongside the existing ones, so they just appear in the
+    // next frame as if they'd always been there.
+    var particle: PackedParticle;
+    if particle_index < integrated_count {
+        particle = particles_out[particle_index];
+    } else {
+        particle = particles_new[particle_index - integrated_count];
+    }
+
+    // Particles at the end of their lifetime, including those that drained into a sink, weren't
+    // counted by the physics, so they just aren't part of the next frame. A lifetime of 0 means
+    // forever.
+    if particle.age.y != 0u && particle.age.x >= particle.age.y {
         return;
     }
 
-    let particle_index = global_id.x;
+    // Neither were new particles outside the view, because it moved after they were staged. They
+    // don't have a cell in the grid.
+    let top_right = settings.view_anchor + settings.view_dimensions;
+    if any(particle.position < settings.view_anchor) || any(particle.position > top_right) {
+        return;
+    }
 
     // TODO: may need an offset in the future if we decide not to use 0,0 as the origin
-    let position_relative_to_viewport_x = positions_out[particle_index].x - settings.view_anchor.x;
-    let position_relative_to_viewport_y = positions_out[particle_index].y - settings.view_anchor.y;
+    let position_relative_to_viewport_x = particle.position.x - settings.view_anchor.x;
+    let position_relative_to_viewport_y = particle.position.y - settings.view_anchor.y;
 
     let cell_x = u32(
         floor(
-            position_relative_to_viewport_x / f32(settings.cell_size)
+            position_relative_to_viewport_x / settings.cell_size
         )
     );
     let cell_y = u32(
         floor(
-            position_relative_to_viewport_y / f32(settings.cell_size)
+            position_relative_to_viewport_y / settings.cell_size
         )
     );
 
-    // NB:
-    //   We add one to the cell index because our current implementation of prefix sums shifts all
-    //   its items one to the right.
-    let prefix_hack = 1u;
-    let cell_index = ((cell_y * settings.grid_dimensions.x) + cell_x) + prefix_hack;
+    let cell_index = (cell_y * settings.grid_dimensions.x) + cell_x;
 
-    let count = atomicSub(&indices[cell_index], 1u);
-    let destination_index = count - 1;
+    // The prefix sum leaves each cell pointing to the start of its particles, and counting the
+    // cell's particles back down gives each one its own place after that.
+    let offset = atomicSub(&cell_counts[cell_index], 1u) - 1u;
+    let destination_index = indices[cell_index] + offset;
 
-    // TODO: probably best to put positions_out[index] in a variable to prevent double reads.
-    positions_in[destination_index] = positions_out[particle_index];
-    velocities_in[destination_index] = velocities_out[particle_index];
+    particles_in[destination_index] = particle;
 }
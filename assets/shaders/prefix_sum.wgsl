@@ -1,123 +1,204 @@
-// Inspired by https://github.com/kishimisu/WebGPU-Radix-Sort/blob/4c7d5cb8cf6bc584b18ee3ee38422b74e52fb6b3/src/shaders/prefix_sum.js
+// A single-pass prefix sum using "decoupled look-back".
+//
+// See: Merrill & Garland, "Single-pass Parallel Prefix Scan with Decoupled Look-back", 2016.
+//
+// The items are split into partitions, one per workgroup. Each workgroup scans its own partition
+// and then looks back through the descriptors of the preceding partitions to find the total of
+// all the items before it. It doesn't need to wait for every preceding partition to finish,
+// because as soon as it finds a partition that has published its inclusive prefix it can stop
+// looking. So there's no limit on the number of items, and no separate pass for adding block sums.
+//
+// WebGPU doesn't guarantee that one workgroup ever makes progress whilst another is waiting for it,
+// so a workgroup only waits so long for a preceding partition to publish its sum. After that it
+// sums the partition's items itself. See: Smith, Levien & Owens, "Decoupled Fallback: A Portable
+// Single-Pass GPU Scan".
+//
+// The scan is exclusive, ie every item becomes the sum of all the items before it. So for particle
+// counts per cell, every cell ends up pointing to the start of its particles, and the guard item
+// at the end holds the total.
+//
+// The counts are read from a separate buffer, that the integration shader atomically adds to. They
+// aren't changed here, because a workgroup might need to sum another's items. The packing shader
+// counts them back down as it places each cell's particles.
 
 #import types::WorldSettings;
 
 @group(0) @binding(0) var<uniform> settings: WorldSettings;
-@group(0) @binding(1) var<storage, read_write> items: array<u32>;
-@group(0) @binding(2) var<storage, read_write> blockSums: array<u32>;
-
-const WORKGROUP_SIZE_X: u32 = 32;
-const WORKGROUP_SIZE_Y: u32 = 32;
-const THREADS_PER_WORKGROUP: u32 = WORKGROUP_SIZE_X * WORKGROUP_SIZE_Y;
-const ITEMS_PER_WORKGROUP: u32 = THREADS_PER_WORKGROUP * 2;
-
-const WORKGROUP_MEMORY_SIZE: u32 = ITEMS_PER_WORKGROUP * 2;
-var<workgroup> temp: array<u32, WORKGROUP_MEMORY_SIZE>;
-
-@compute @workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, 1)
-fn reduce_downsweep(
-    @builtin(workgroup_id) w_id: vec3<u32>,
-    @builtin(num_workgroups) w_dim: vec3<u32>,
-    @builtin(local_invocation_index) TID: u32, // Local thread ID
+@group(0) @binding(1) var<storage, read> counts: array<u32>;
+@group(0) @binding(2) var<storage, read_write> items: array<u32>;
+// Layout: [next partition ID, finished partitions count, partition descriptors...]
+@group(0) @binding(3) var<storage, read_write> scan_state: array<atomic<u32>>;
+
+const THREADS_PER_WORKGROUP: u32 = 256;
+const ITEMS_PER_THREAD: u32 = 8;
+const ITEMS_PER_PARTITION: u32 = THREADS_PER_WORKGROUP * ITEMS_PER_THREAD;
+
+const NEXT_PARTITION_ID: u32 = 0;
+const FINISHED_PARTITIONS: u32 = 1;
+const DESCRIPTORS_START: u32 = 2;
+
+// A partition descriptor packs a flag into the top 2 bits and a sum into the rest, so that both can
+// be read and written with a single atomic.
+const FLAG_NOT_READY: u32 = 0u;
+const FLAG_AGGREGATE: u32 = 1u;
+const FLAG_INCLUSIVE: u32 = 2u;
+const FLAG_SHIFT: u32 = 30u;
+const VALUE_MASK: u32 = (1u << FLAG_SHIFT) - 1u;
+
+// How many times to check on a preceding partition before summing its items instead.
+const MAX_SPINS: u32 = 64u;
+
+var<workgroup> thread_sums: array<u32, THREADS_PER_WORKGROUP>;
+var<workgroup> partition_id: u32;
+var<workgroup> partition_prefix: u32;
+var<workgroup> is_last_to_finish: bool;
+
+@compute @workgroup_size(THREADS_PER_WORKGROUP, 1, 1)
+fn main(
+    @builtin(local_invocation_index) thread_id: u32,
+    @builtin(num_workgroups) workgroups: vec3<u32>,
 ) {
-    let TOTAL_ITEMS = (settings.grid_dimensions.x * settings.grid_dimensions.y) + 2;
-    let WORKGROUP_ID = w_id.x + w_id.y * w_dim.x;
-    let WID = WORKGROUP_ID * THREADS_PER_WORKGROUP;
-    let GID = WID + TID; // Global thread ID
-
-    let ELM_TID = TID * 2; // Element pair local ID
-    let ELM_GID = (GID * 2); // Element pair global ID
-
-    // Load input to shared memory
-    if ELM_GID >= TOTAL_ITEMS {
-        temp[ELM_TID] = 0u;
-    } else {
-        temp[ELM_TID] = items[ELM_GID];
+    // An extra "guard item" at the end holds the total.
+    let total_items = (settings.grid_dimensions.x * settings.grid_dimensions.y) + 1;
+    let total_partitions = workgroups.x;
+
+    // Partitions are handed out in the order that workgroups start, rather than by workgroup ID.
+    // This guarantees that the partitions a workgroup waits on have all already started, so
+    // looking back can't deadlock.
+    if thread_id == 0 {
+        partition_id = atomicAdd(&scan_state[NEXT_PARTITION_ID], 1u);
     }
-    if ELM_GID + 1 >= TOTAL_ITEMS {
-        temp[ELM_TID + 1] = 0u;
-    } else {
-        temp[ELM_TID + 1] = items[ELM_GID + 1];
+    let partition_index = workgroupUniformLoad(&partition_id);
+
+    // Each thread serially scans a handful of consecutive items.
+    let first_item = partition_index * ITEMS_PER_PARTITION + thread_id * ITEMS_PER_THREAD;
+    var local: array<u32, ITEMS_PER_THREAD>;
+    var running_total = 0u;
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        let index = first_item + i;
+        local[i] = running_total;
+        if index < total_items {
+            running_total += counts[index];
+        }
     }
+    thread_sums[thread_id] = running_total;
 
-    var offset: u32 = 1;
-
-    // Up-sweep (reduce) phase
-    for (var d: u32 = ITEMS_PER_WORKGROUP >> 1; d > 0; d = d >> 1) {
+    // Then the threads' totals are scanned across the workgroup.
+    for (var offset = 1u; offset < THREADS_PER_WORKGROUP; offset = offset * 2) {
         workgroupBarrier();
-
-        if TID < d {
-            var ai: u32 = offset * (ELM_TID + 1) - 1;
-            var bi: u32 = offset * (ELM_TID + 2) - 1;
-            temp[bi] += temp[ai];
+        var sum = thread_sums[thread_id];
+        if thread_id >= offset {
+            sum += thread_sums[thread_id - offset];
         }
+        workgroupBarrier();
+        thread_sums[thread_id] = sum;
+    }
+    workgroupBarrier();
 
-        offset = offset * 2;
+    if thread_id == 0 {
+        partition_prefix = look_back(
+            partition_index,
+            thread_sums[THREADS_PER_WORKGROUP - 1],
+            total_items
+        );
     }
+    let prefix = workgroupUniformLoad(&partition_prefix);
 
-    // Save workgroup sum and clear last element
-    if TID == 0 {
-        let last_offset = ITEMS_PER_WORKGROUP - 1;
+    var thread_prefix = prefix;
+    if thread_id > 0 {
+        thread_prefix += thread_sums[thread_id - 1];
+    }
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        let index = first_item + i;
+        if index < total_items {
+            items[index] = thread_prefix + local[i];
+        }
+    }
 
-        blockSums[WORKGROUP_ID] = temp[last_offset];
-        temp[last_offset] = 0u;
+    if thread_id == 0 {
+        let finished = atomicAdd(&scan_state[FINISHED_PARTITIONS], 1u) + 1u;
+        is_last_to_finish = finished == total_partitions;
     }
+    if workgroupUniformLoad(&is_last_to_finish) {
+        reset_for_next_frame(thread_id, total_partitions);
+    }
+}
 
-    // Down-sweep phase
-    for (var d: u32 = 1; d < ITEMS_PER_WORKGROUP; d = d * 2) {
-        offset = offset >> 1;
-        workgroupBarrier();
+// Publish this partition's sum and find the sum of all the items in the preceding partitions.
+fn look_back(partition_index: u32, aggregate: u32, total_items: u32) -> u32 {
+    let descriptor = DESCRIPTORS_START + partition_index;
 
-        if TID < d {
-            var ai: u32 = offset * (ELM_TID + 1) - 1;
-            var bi: u32 = offset * (ELM_TID + 2) - 1;
+    if partition_index == 0 {
+        atomicStore(&scan_state[descriptor], pack_descriptor(FLAG_INCLUSIVE, aggregate));
+        return 0u;
+    }
 
-            let t: u32 = temp[ai];
-            temp[ai] = temp[bi];
-            temp[bi] += t;
+    // Let later partitions make progress whilst we're still looking back ourselves.
+    atomicStore(&scan_state[descriptor], pack_descriptor(FLAG_AGGREGATE, aggregate));
+
+    var prefix = 0u;
+    var previous = descriptor - 1u;
+    var spins = 0u;
+    loop {
+        let previous_descriptor = atomicLoad(&scan_state[previous]);
+        let flag = previous_descriptor >> FLAG_SHIFT;
+        if flag == FLAG_NOT_READY {
+            // Spin until the previous partition has at least published its own sum, or give up
+            // waiting and work it out here.
+            spins++;
+            if spins < MAX_SPINS {
+                continue;
+            }
+            let previous_aggregate = sum_partition(previous - DESCRIPTORS_START, total_items);
+            // It's the same sum that the partition will publish itself, so it doesn't matter if
+            // it gets there first.
+            _ = atomicCompareExchangeWeak(
+                &scan_state[previous],
+                FLAG_NOT_READY,
+                pack_descriptor(FLAG_AGGREGATE, previous_aggregate)
+            );
+            prefix += previous_aggregate;
+        } else {
+            prefix += previous_descriptor & VALUE_MASK;
+            if flag == FLAG_INCLUSIVE {
+                break;
+            }
+        }
+        // The first partition has nothing before it.
+        if previous == DESCRIPTORS_START {
+            break;
         }
+        previous -= 1u;
+        spins = 0u;
     }
-    workgroupBarrier();
 
-    // @tombh: ...
-    let final_index = ELM_GID;
-
-    // Copy result from shared memory to global memory
-    if final_index >= TOTAL_ITEMS {
-        return;
-    }
-    items[final_index] = temp[ELM_TID];
+    atomicStore(&scan_state[descriptor], pack_descriptor(FLAG_INCLUSIVE, prefix + aggregate));
+    return prefix;
+}
 
-    if final_index + 1 >= TOTAL_ITEMS {
-        return;
+// The sum of all of a partition's items, for when its own workgroup is taking too long to publish it.
+fn sum_partition(partition_index: u32, total_items: u32) -> u32 {
+    let first_item = partition_index * ITEMS_PER_PARTITION;
+    let end = min(first_item + ITEMS_PER_PARTITION, total_items);
+    var sum = 0u;
+    for (var index = first_item; index < end; index++) {
+        sum += counts[index];
     }
-    items[final_index + 1] = temp[ELM_TID + 1];
+    return sum;
 }
 
-@compute @workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, 1)
-fn add_block_sums(
-    @builtin(workgroup_id) w_id: vec3<u32>,
-    @builtin(num_workgroups) w_dim: vec3<u32>,
-    @builtin(local_invocation_index) TID: u32, // Local thread ID
-) {
-    let TOTAL_ITEMS = (settings.grid_dimensions.x * settings.grid_dimensions.y) + 2;
-    let WORKGROUP_ID = w_id.x + w_id.y * w_dim.x;
-    let WID = WORKGROUP_ID * THREADS_PER_WORKGROUP;
-    let GID = WID + TID; // Global thread ID
-
-    let ELM_ID = GID * 2;
-
-    // @tombh: ...
-    // let final_index = ELM_ID - 1;
+fn pack_descriptor(flag: u32, value: u32) -> u32 {
+    return (flag << FLAG_SHIFT) | (value & VALUE_MASK);
+}
 
-    if ELM_ID >= TOTAL_ITEMS {
-        return;
+// Only the last workgroup to finish runs this, by which point no other workgroup will read the
+// scan state again in this frame.
+fn reset_for_next_frame(thread_id: u32, total_partitions: u32) {
+    for (var index = thread_id; index < total_partitions; index += THREADS_PER_WORKGROUP) {
+        atomicStore(&scan_state[DESCRIPTORS_START + index], FLAG_NOT_READY);
     }
-    let blockSum = blockSums[WORKGROUP_ID];
-    items[ELM_ID] += blockSum;
-
-    if ELM_ID + 1 >= TOTAL_ITEMS {
-        return;
+    if thread_id == 0 {
+        atomicStore(&scan_state[NEXT_PARTITION_ID], 0u);
+        atomicStore(&scan_state[FINISHED_PARTITIONS], 0u);
     }
-    items[ELM_ID + 1] += blockSum;
 }
@@ -0,0 +1,98 @@
+#import types::{WorldSettings, PackedParticle};
+
+// Only used when `WrachConfig::deterministic` is on. Packing places particles into their cells
+// with atomics, so the order of particles within a cell depends on how the GPU happened to
+// schedule its threads. Sorting each cell's particles by their own data gives the same order every
+// time, and therefore the same physics every time.
+
+@group(0) @binding(0) var<uniform> settings: WorldSettings;
+@group(0) @binding(1) var<storage, read> indices: array<u32>;
+@group(0) @binding(2) var<storage, read_write> particles_in: array<PackedParticle>;
+
+// One entrypoint for each of the supported workgroup sizes, see `WORKGROUP_SIZES` in the shared
+// crate. The CPU picks which one to use.
+@compute @workgroup_size(32)
+fn main_32(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    sort_cell(global_id.x);
+}
+
+@compute @workgroup_size(64)
+fn main_64(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    sort_cell(global_id.x);
+}
+
+@compute @workgroup_size(128)
+fn main_128(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    sort_cell(global_id.x);
+}
+
+@compute @workgroup_size(256)
+fn main_256(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    sort_cell(global_id.x);
+}
+
+// Insertion sort, cells only ever have a handful of particles.
+fn sort_cell(cell: u32) {
+    if cell >= settings.grid_dimensions.x * settings.grid_dimensions.y {
+        return;
+    }
+
+    let start = indices[cell];
+    let end = indices[cell + 1u];
+    for (var i = start + 1u; i < end; i++) {
+        let particle = particles_in[i];
+        var j = i;
+        loop {
+            if j <= start || !is_before(particle, particles_in[j - 1u]) {
+                break;
+            }
+            particles_in[j] = particles_in[j - 1u];
+            j--;
+        }
+        particles_in[j] = particle;
+    }
+}
+
+// Whether one particle comes before another, comparing position, then velocity, then age, then
+// temperature, then material.
+fn is_before(particle: PackedParticle, other: PackedParticle) -> bool {
+    let key = vec4<u32>(
+        ordered_bits(particle.position.x),
+        ordered_bits(particle.position.y),
+        ordered_bits(particle.velocity.x),
+        ordered_bits(particle.velocity.y)
+    );
+    let other_key = vec4<u32>(
+        ordered_bits(other.position.x),
+        ordered_bits(other.position.y),
+        ordered_bits(other.velocity.x),
+        ordered_bits(other.velocity.y)
+    );
+    for (var i = 0u; i < 4u; i++) {
+        if key[i] != other_key[i] {
+            return key[i] < other_key[i];
+        }
+    }
+    for (var i = 0u; i < 2u; i++) {
+        if particle.age[i] != other.age[i] {
+            return particle.age[i] < other.age[i];
+        }
+    }
+    let temperature_key = ordered_bits(particle.temperature);
+    let other_temperature_key = ordered_bits(other.temperature);
+    if temperature_key != other_temperature_key {
+        return temperature_key < other_temperature_key;
+    }
+    return particle.material < other.material;
+}
+
+// The bits of a float rearranged so that comparing them as integers gives the same order as Rust's
+// `f32::total_cmp()`. So `-0.0` and `0.0` aren't treated as equal, which would leave their order
+// up to chance.
+fn ordered_bits(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+    if (bits & 0x80000000u) != 0u {
+        return ~bits;
+    }
+    return bits | 0x80000000u;
+}
@@ -7,9 +7,52 @@ struct WorldSettings {
     view_anchor: vec2<f32>,
     /// The dimensions of the spatial bin grid, the unit is a cell
     grid_dimensions: vec2<u32>,
-    /// The size of a spatial bin cell
-    cell_size: u32,
-    /// Total number of particles simulated in this frame. This will normally be much smaller than
-    /// the total number of particles that we have a record of.
+    /// The size of a spatial bin cell, in the same units as the positions of particles. That's
+    /// `WrachConfig::cell_size` particles across.
+    cell_size: f32,
+    /// Total number of particles simulated in this frame, or more if the GPU has culled some that
+    /// the CPU hasn't seen yet, see `Culling`. This will normally be much smaller than the total
+    /// number of particles that we have a record of.
     particles_in_frame_count: u32,
+    /// Number of freshly added particles waiting in `Buffers::PARTICLES_NEW` to be merged into
+    /// the frame's particle data by the GPU.
+    new_particles_count: u32,
+    /// The temperature that particles cool, or warm, towards when nothing else is heating them
+    ambient_temperature: f32,
+    /// The fraction of the difference in temperature that flows between two neighbouring particles
+    /// every frame, from 0 to 1
+    thermal_conductivity: f32,
+    /// The fraction of the difference from `ambient_temperature` that particles lose every frame,
+    /// from 0 to 1
+    cooling_rate: f32,
+    /// The number of heat sources in the heat sources buffer
+    heat_sources_count: u32,
+    /// The number of reactions in the reactions buffer
+    reactions_count: u32,
+    /// The number of sinks in the sinks buffer
+    sinks_count: u32,
+    /// A different number every frame, so that reactions which only happen with some probability
+    /// don't keep happening to the same particles
+    random_seed: u32,
+    /// How much faster particles fall every frame
+    gravity: f32,
+    /// Half the distance that particles are kept apart from each other
+    particle_radius: f32,
+    /// The fastest that a particle can move in a single frame
+    max_speed: f32,
+}
+
+/// Everything about a single particle, see `PackedParticle` in the shared crate
+struct PackedParticle {
+    /// Where the particle is
+    position: vec2<f32>,
+    /// How far the particle moves every frame
+    velocity: vec2<f32>,
+    /// The number of frames the particle has been simulated for, and how many it lives for. A
+    /// lifetime of 0 means that it lives forever.
+    age: vec2<u32>,
+    /// The particle's temperature
+    temperature: f32,
+    /// The material that the particle is made of
+    material: u32,
 }
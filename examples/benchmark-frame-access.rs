@@ -0,0 +1,78 @@
+//! Compare copying every particle out of the simulation with borrowing them through
+//! `WrachAPI::frame()`, and with turning readback off altogether.
+//!
+//! `cargo run --release --example benchmark-frame-access`
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use wrach_api::{Vec2, WrachAPI};
+use wrach_bevy::{Particle, ReadbackBuffers, WrachConfig};
+
+extern crate bevy;
+extern crate wrach_api;
+
+const NUMBER_OF_PARTICLES: u32 = 1_000_000;
+const WARMUP_TICKS: u32 = 10;
+const MEASURED_TICKS: u32 = 100;
+
+fn main() {
+    let copied = measure(ReadbackBuffers::ALL, |wrach| {
+        // This is what `WrachAPI` used to do every tick.
+        let frame = wrach.frame();
+        let positions: Vec<(f32, f32)> = frame.positions.iter().map(|p| (p.x, p.y)).collect();
+        let velocities: Vec<(f32, f32)> = frame.velocities.iter().map(|v| (v.x, v.y)).collect();
+        positions.len() + velocities.len()
+    });
+
+    let borrowed = measure(ReadbackBuffers::ALL, |wrach| {
+        let frame = wrach.frame();
+        frame.positions.len() + frame.velocities.len()
+    });
+
+    let no_readback = measure(ReadbackBuffers::NONE, |wrach| wrach.frame().positions.len());
+
+    println!("{NUMBER_OF_PARTICLES} particles, average time per tick:");
+    println!("  Copied:      {copied:?}");
+    println!("  Borrowed:    {borrowed:?}");
+    println!("  No readback: {no_readback:?}");
+}
+
+fn measure(readback: ReadbackBuffers, mut access: impl FnMut(&WrachAPI) -> usize) -> Duration {
+    let config = WrachConfig::builder()
+        .dimensions(2000, 2000)
+        .readback(readback)
+        .build()
+        .expect("Benchmark config should be valid");
+    let mut wrach = WrachAPI::new(config);
+
+    let mut rng = rand::thread_rng();
+    let mut particles: Vec<Particle> = Vec::new();
+    for _ in 0..NUMBER_OF_PARTICLES {
+        particles.push(Particle {
+            position: Vec2::new(
+                rng.gen_range(0.0..config.dimensions.0.into()),
+                rng.gen_range(0.0..config.dimensions.1.into()),
+            ),
+            velocity: Vec2::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5)),
+        });
+    }
+    wrach.add_particles(particles);
+
+    for _ in 0..WARMUP_TICKS {
+        wrach.tick();
+    }
+
+    let mut total = 0;
+    let start = Instant::now();
+    for _ in 0..MEASURED_TICKS {
+        wrach.tick();
+        total += access(&wrach);
+    }
+    let elapsed = start.elapsed();
+
+    // Make sure the work done accessing the data can't be optimised away.
+    assert!(total > 0 || readback.is_none());
+
+    elapsed / MEASURED_TICKS
+}
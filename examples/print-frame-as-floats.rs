@@ -22,6 +22,7 @@ fn main() {
         wrach.tick();
     }
 
-    println!("Positions: {:?}", wrach.positions);
-    println!("Velocities: {:?}", wrach.velocities);
+    let frame = wrach.frame();
+    println!("Positions: {:?}", frame.positions);
+    println!("Velocities: {:?}", frame.velocities);
 }
@@ -8,7 +8,10 @@ use bevy::prelude::*;
 use bevy::window::WindowResolution;
 
 use rand::Rng;
-use wrach_bevy::{DrawPlugin, Particle, WrachPlugin, WrachState};
+use wrach_bevy::{
+    DrawPlugin, InteractionTool, Particle, WrachDebugOverlay, WrachDiagnosticsPlugin,
+    WrachInteraction, WrachInteractionPlugin, WrachPlugin, WrachState,
+};
 
 const NUMBER_OF_PARTICLES: u32 = 125_000;
 const SCALE: f32 = 4.0;
@@ -32,7 +35,9 @@ fn main() {
             LogDiagnosticsPlugin::default(),
             FrameTimeDiagnosticsPlugin,
             wrach,
+            WrachDiagnosticsPlugin::default(),
             DrawPlugin::default(),
+            WrachInteractionPlugin::default(),
         ))
         .add_systems(Startup, startup)
         .add_systems(PreUpdate, keyboard_events)
@@ -60,6 +65,8 @@ fn random_float(magnitude: f32) -> f32 {
 fn keyboard_events(
     mut keyboard_input_events: EventReader<KeyboardInput>,
     mut app_exit_events: ResMut<Events<bevy::app::AppExit>>,
+    mut debug_overlay: ResMut<WrachDebugOverlay>,
+    mut interaction: ResMut<WrachInteraction>,
 ) {
     for event in keyboard_input_events.read() {
         if event.state == ButtonState::Released {
@@ -75,11 +82,16 @@ fn keyboard_events(
             _ => {}
         }
 
-        #[allow(clippy::single_match)]
         match &event.key_code {
             KeyCode::KeyQ => {
                 app_exit_events.send(AppExit::Success);
             }
+            KeyCode::KeyD => debug_overlay.toggle(),
+            KeyCode::Digit1 => interaction.tool = InteractionTool::Spawn,
+            KeyCode::Digit2 => interaction.tool = InteractionTool::Erase,
+            KeyCode::Digit3 => interaction.tool = InteractionTool::Push,
+            KeyCode::Digit4 => interaction.tool = InteractionTool::Pull,
+            KeyCode::Digit5 => interaction.tool = InteractionTool::Drag,
             _ => {}
         }
     }
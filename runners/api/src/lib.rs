@@ -4,43 +4,216 @@
 // https://rust-lang.github.io/rust-clippy/master/index.html#/pub_use
 #![expect(clippy::pub_use, reason = "I think it's the only way to re-export?")]
 
+extern crate alloc;
+
+use alloc::sync::Arc;
+use std::path::Path;
+
 use bevy::prelude::PluginGroup as _;
-use bevy::{app::App, winit::WinitPlugin, DefaultPlugins};
+use bevy::render::{
+    renderer::{initialize_renderer, RenderInstance, WgpuWrapper},
+    settings::{RenderCreation, WgpuSettings},
+    RenderPlugin,
+};
+use bevy::{
+    app::{App, PluginsState},
+    ecs::world::Mut,
+    tasks::block_on,
+    winit::WinitPlugin,
+    DefaultPlugins,
+};
 use wrach_bevy::{WrachPlugin, WrachState};
 
+pub use bevy::math::UVec2;
 pub use bevy::math::Vec2;
+pub use wrach_bevy::Emitter;
+pub use wrach_bevy::EmitterId;
+pub use wrach_bevy::HeatSource;
+pub use wrach_bevy::HeatSourceId;
+pub use wrach_bevy::Material;
+pub use wrach_bevy::MaterialProperties;
+pub use wrach_bevy::Materials;
 pub use wrach_bevy::Particle;
+pub use wrach_bevy::ParticleEdit;
+pub use wrach_bevy::ParticleProperties;
+pub use wrach_bevy::Reaction;
+pub use wrach_bevy::ReadbackBuffers;
+pub use wrach_bevy::Recording;
+pub use wrach_bevy::RecordingError;
+pub use wrach_bevy::Sink;
+pub use wrach_bevy::SinkId;
+pub use wrach_bevy::ThermalConfig;
+pub use wrach_bevy::WorkgroupSize;
 pub use wrach_bevy::WrachConfig;
+pub use wrach_bevy::WrachConfigBuilder;
+pub use wrach_bevy::WrachConfigError;
+pub use wrach_bevy::WrachError;
+pub use wrach_bevy::WrachStats;
 
 /// Main struct for Wrach physics simulations
 #[non_exhaustive]
 pub struct WrachAPI {
     /// An instance of a Bevy app, already setup for Wrach
     pub app: App,
+}
+
+/// A view onto the simulation's data from the most recent frame. Nothing is copied, all the slices
+/// borrow directly from the simulation's state.
+///
+/// Particles are packed by spatial bin cell, see `indices`. The slices are the size of the GPU
+/// buffers, so there may be unused space at the end.
+#[non_exhaustive]
+pub struct Frame<'frame> {
     /// All the positions of the particles
-    pub positions: Vec<(f32, f32)>,
+    pub positions: &'frame [Vec2],
     /// All the velocities of the particles
-    pub velocities: Vec<(f32, f32)>,
+    pub velocities: &'frame [Vec2],
+    /// For every spatial bin cell, the index of its first particle. The extra item at the end is
+    /// the total number of particles.
+    pub indices: &'frame [u32],
+    /// The age and lifetime of every particle, in frames. A lifetime of 0 means forever.
+    pub ages: &'frame [UVec2],
+    /// The temperature of every particle, see `ThermalConfig`
+    pub temperatures: &'frame [f32],
+    /// The material of every particle, see `Materials`
+    pub materials: &'frame [u32],
 }
 
 impl WrachAPI {
+    /// The number of ticks in a row that a replay waits for the GPU to run a frame before giving
+    /// up. The first few ticks never run a frame, whilst the pipelines are being compiled.
+    const MAX_REPLAY_TICKS_WITHOUT_A_FRAME: u32 = 1000;
+
     /// Instantiate
+    ///
+    /// # Panics
+    /// If the config is invalid, see `try_new()` to handle that as an error.
     #[must_use]
     #[inline]
+    #[expect(
+        clippy::expect_used,
+        reason = "`try_new()` is there for anyone who wants to handle the error"
+    )]
     pub fn new(config: WrachConfig) -> Self {
-        let mut wrach = Self {
-            app: App::new(),
-            positions: Vec::new(),
-            velocities: Vec::new(),
+        Self::try_new(config).expect("Invalid Wrach config")
+    }
+
+    /// Instantiate, checking the config first.
+    ///
+    /// # Errors
+    /// If the config is invalid, see `WrachConfig::validate()`.
+    #[inline]
+    pub fn try_new(config: WrachConfig) -> Result<Self, WrachConfigError> {
+        config.validate()?;
+        Ok(Self::new_with_renderer(config, RenderPlugin::default()))
+    }
+
+    /// Instantiate on a software GPU adapter that runs on the CPU, like Mesa's llvmpipe or
+    /// Windows' WARP. For machines without a GPU, but it's a lot slower.
+    ///
+    /// # Panics
+    /// If there's no software adapter available, or the config is invalid.
+    #[must_use]
+    #[inline]
+    #[expect(
+        clippy::expect_used,
+        reason = "`try_new_on_cpu()` is there for anyone who wants to handle the error"
+    )]
+    pub fn new_on_cpu(config: WrachConfig) -> Self {
+        Self::try_new_on_cpu(config).expect("Invalid Wrach config")
+    }
+
+    /// Instantiate on a software GPU adapter, checking the config first. See `new_on_cpu()`.
+    ///
+    /// # Errors
+    /// If the config is invalid, see `WrachConfig::validate()`.
+    ///
+    /// # Panics
+    /// If there's no software adapter available.
+    #[inline]
+    pub fn try_new_on_cpu(config: WrachConfig) -> Result<Self, WrachConfigError> {
+        config.validate()?;
+        let settings = WgpuSettings::default();
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: settings.backends.unwrap_or(wgpu::Backends::all()),
+            flags: settings.instance_flags,
+            dx12_shader_compiler: settings.dx12_shader_compiler.clone(),
+            gles_minor_version: settings.gles3_minor_version,
+        });
+        let adapter_options = wgpu::RequestAdapterOptions {
+            force_fallback_adapter: true,
+            ..Default::default()
+        };
+        let (device, queue, adapter_info, adapter) =
+            block_on(initialize_renderer(&instance, &settings, &adapter_options));
+
+        let renderer = RenderPlugin {
+            render_creation: RenderCreation::manual(
+                device,
+                queue,
+                adapter_info,
+                adapter,
+                RenderInstance(Arc::new(WgpuWrapper::new(instance))),
+            ),
+            ..Default::default()
         };
+        Ok(Self::new_with_renderer(config, renderer))
+    }
+
+    /// Instantiate with a specific setup for Bevy's renderer.
+    fn new_with_renderer(config: WrachConfig, renderer: RenderPlugin) -> Self {
+        let mut wrach = Self::new_pending_with_renderer(config, renderer);
+        wrach.finish_setup();
+        wrach
+    }
+
+    /// Instantiate without waiting for the GPU. On the web the renderer can only be setup
+    /// asynchronously, so poll `is_ready()` from the browser's event loop and then call
+    /// `finish_setup()` before ticking. On native this is ready immediately.
+    ///
+    /// # Errors
+    /// If the config is invalid, see `WrachConfig::validate()`.
+    #[inline]
+    pub fn new_pending(config: WrachConfig) -> Result<Self, WrachConfigError> {
+        config.validate()?;
+        Ok(Self::new_pending_with_renderer(
+            config,
+            RenderPlugin::default(),
+        ))
+    }
+
+    /// Whether the GPU has been setup, see `new_pending()`.
+    #[must_use]
+    #[inline]
+    pub fn is_ready(&mut self) -> bool {
+        self.app.plugins_state() != PluginsState::Adding
+    }
+
+    /// Finish setting up the simulation once the GPU is ready, see `new_pending()`.
+    #[inline]
+    pub fn finish_setup(&mut self) {
+        if self.app.plugins_state() == PluginsState::Cleaned {
+            return;
+        }
+        self.app.finish();
+        self.app.cleanup();
+    }
+
+    /// Instantiate with a specific setup for Bevy's renderer, without waiting for the renderer to
+    /// be ready.
+    fn new_pending_with_renderer(config: WrachConfig, renderer: RenderPlugin) -> Self {
+        let mut wrach = Self { app: App::new() };
 
         let plugin = WrachPlugin::new(config);
         wrach
             .app
-            .add_plugins(DefaultPlugins.build().disable::<WinitPlugin>())
+            .add_plugins(
+                DefaultPlugins
+                    .build()
+                    .disable::<WinitPlugin>()
+                    .set(renderer),
+            )
             .add_plugins(plugin);
-        wrach.app.finish();
-        wrach.app.cleanup();
         wrach
     }
 
@@ -48,29 +221,31 @@ impl WrachAPI {
     #[inline]
     pub fn tick(&mut self) {
         self.app.update();
-        self.read_data();
     }
 
-    /// Get data from the simulation
-    // TODO: Check performance of this. Are we using the data directly? There's no copying going
-    // on?
+    /// Get the data from the most recent frame read back from the GPU, see
+    /// `WrachConfig::readback`. Buffers that aren't read back are always empty.
     #[inline]
-    pub fn read_data(&mut self) {
-        let state = self.app.world().resource::<WrachState>();
-
-        self.positions = state
-            .packed_data
-            .positions
-            .iter()
-            .map(|particle| (particle.x, particle.y))
-            .collect();
+    #[must_use]
+    pub fn frame(&self) -> Frame<'_> {
+        let data = &self.get_simulation_state().packed_data;
+        Frame {
+            positions: &data.positions,
+            velocities: &data.velocities,
+            indices: &data.indices,
+            ages: &data.ages,
+            temperatures: &data.temperatures,
+            materials: &data.materials,
+        }
+    }
 
-        self.velocities = state
-            .packed_data
-            .velocities
-            .iter()
-            .map(|particle| (particle.x, particle.y))
-            .collect();
+    /// Move the view onto the simulation, `anchor` is its new bottom-left corner. Only the
+    /// particles in the view are simulated, the rest wait in the particle store until the view
+    /// moves over them again.
+    #[inline]
+    pub fn set_viewport(&mut self, anchor: Vec2) {
+        let mut state = self.app.world_mut().resource_mut::<WrachState>();
+        state.set_viewport_anchor(anchor);
     }
 
     /// Add particles to the simulation
@@ -80,16 +255,158 @@ impl WrachAPI {
         state.add_particles(particles);
     }
 
+    /// Add particles that all share the same properties, like a lifetime
+    #[inline]
+    pub fn add_particles_with(&mut self, particles: Vec<Particle>, properties: ParticleProperties) {
+        self.get_simulation_state_mut()
+            .add_particles_with(particles, properties);
+    }
+
+    /// Start adding particles every frame, see `Emitter`
+    #[inline]
+    pub fn add_emitter(&mut self, emitter: Emitter) -> EmitterId {
+        self.get_simulation_state_mut().add_emitter(emitter)
+    }
+
+    /// Stop an emitter, returns it if it was still running
+    #[inline]
+    pub fn remove_emitter(&mut self, id: EmitterId) -> Option<Emitter> {
+        self.get_simulation_state_mut().remove_emitter(id)
+    }
+
+    /// Start removing all the particles that enter a circle, see `Sink`
+    ///
+    /// # Errors
+    /// If there are already too many sinks.
+    #[inline]
+    pub fn add_sink(&mut self, sink: Sink) -> Result<SinkId, WrachError> {
+        self.get_simulation_state_mut().add_sink(sink)
+    }
+
+    /// Stop a sink, returns it if it existed
+    #[inline]
+    pub fn remove_sink(&mut self, id: SinkId) -> Option<Sink> {
+        self.get_simulation_state_mut().remove_sink(id)
+    }
+
+    /// Start heating, or cooling, the particles in a circle every frame, see `HeatSource`
+    ///
+    /// # Errors
+    /// If there are already too many heat sources.
+    #[inline]
+    pub fn add_heat_source(&mut self, source: HeatSource) -> Result<HeatSourceId, WrachError> {
+        self.get_simulation_state_mut().add_heat_source(source)
+    }
+
+    /// Stop a heat source, returns it if it existed
+    #[inline]
+    pub fn remove_heat_source(&mut self, id: HeatSourceId) -> Option<HeatSource> {
+        self.get_simulation_state_mut().remove_heat_source(id)
+    }
+
+    /// Replace the materials of the simulation and the reactions between them, see `Materials`
+    ///
+    /// # Errors
+    /// If the materials are invalid.
+    #[inline]
+    pub fn set_materials(&mut self, materials: Materials) -> Result<(), WrachError> {
+        self.get_simulation_state_mut().set_materials(materials)
+    }
+
+    /// Change the particles that are already being simulated, eg: remove them or push them around.
+    /// It's applied on the next tick. Needs all the buffers to be read back with no latency, see
+    /// `WrachConfig::readback`.
+    #[inline]
+    pub fn edit_particles(&mut self, edit: ParticleEdit) {
+        self.get_simulation_state_mut().edit_particles(edit);
+    }
+
+    /// Statistics about the simulation, like the number of particles and their kinetic energy.
+    /// Mostly from the most recent frame read back from the GPU, see `WrachStats`.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> WrachStats {
+        self.get_simulation_state().stats()
+    }
+
+    /// Start recording everything that goes into the simulation, for replaying with `replay()`.
+    /// For a faithful replay, start before adding any particles.
+    #[inline]
+    pub fn start_recording(&mut self) {
+        self.get_simulation_state_mut().start_recording();
+    }
+
+    /// Stop recording, see `start_recording()`. Save the recording with `Recording::save()`.
+    #[inline]
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.get_simulation_state_mut().stop_recording()
+    }
+
+    /// Reproduce a session saved with `Recording::save()`, without a window. The simulation is
+    /// returned at the frame that the recording stopped on.
+    ///
+    /// # Errors
+    /// If the recording can't be loaded, or it can't be replayed.
+    #[inline]
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, RecordingError> {
+        Self::replay_recording(Recording::load(path)?)
+    }
+
+    /// Reproduce a recorded session, see `replay()`.
+    ///
+    /// # Errors
+    /// If an input can't be replayed, or the GPU stops running frames.
+    #[inline]
+    pub fn replay_recording(recording: Recording) -> Result<Self, RecordingError> {
+        let mut wrach = Self::try_new(recording.config)
+            .map_err(|error| RecordingError::Config(error.into()))?;
+        let frames = recording.last_frame.saturating_sub(recording.first_frame);
+        let mut events = recording.events.into_iter().peekable();
+        let mut ticks_without_a_frame = 0_u32;
+
+        loop {
+            let frame = wrach.get_simulation_state().gpu_frame;
+            while let Some(event) =
+                events.next_if(|event| event.frame.saturating_sub(recording.first_frame) <= frame)
+            {
+                wrach
+                    .get_simulation_state_mut()
+                    .replay_input(event.input)
+                    .map_err(RecordingError::Replay)?;
+            }
+            if frame >= frames {
+                return Ok(wrach);
+            }
+
+            wrach.tick();
+            if wrach.get_simulation_state().gpu_frame == frame {
+                ticks_without_a_frame = ticks_without_a_frame.saturating_add(1);
+                if ticks_without_a_frame > Self::MAX_REPLAY_TICKS_WITHOUT_A_FRAME {
+                    return Err(RecordingError::Stalled { frame });
+                }
+            } else {
+                ticks_without_a_frame = 0;
+            }
+        }
+    }
+
     /// Return the internal Bevy state for the simulation.
     #[inline]
     pub fn get_simulation_state(&self) -> &WrachState {
         self.app.world().resource::<WrachState>()
     }
+
+    /// Return the internal Bevy state for the simulation, for changing it.
+    #[inline]
+    pub fn get_simulation_state_mut(&mut self) -> Mut<'_, WrachState> {
+        self.app.world_mut().resource_mut::<WrachState>()
+    }
 }
 
 #[expect(
     clippy::indexing_slicing,
     clippy::default_numeric_fallback,
+    clippy::unwrap_used,
     reason = "Tests aren't so strict"
 )]
 #[cfg(test)]
@@ -100,11 +417,13 @@ mod test {
 
     #[test]
     fn test_api_returns_data() {
-        let mut wrach = WrachAPI::new(WrachConfig {
-            dimensions: (10, 10),
-            cell_size: 3,
-            ..Default::default()
-        });
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
 
         let mut particles: Vec<Particle> = Vec::new();
         for _ in 0..3 {
@@ -119,9 +438,219 @@ mod test {
             wrach.tick();
         }
 
-        assert_eq!(wrach.positions.len(), 164);
-        assert_ne!(wrach.positions[0], (0.0, 0.0));
-        assert_eq!(wrach.velocities.len(), 164);
-        assert_ne!(wrach.velocities[0], (0.0, 0.0));
+        let frame = wrach.frame();
+        assert_eq!(frame.positions.len(), 144);
+        assert_ne!(frame.positions[0], Vec2::new(0.0, 0.0));
+        assert_eq!(frame.velocities.len(), 144);
+        assert_ne!(frame.velocities[0], Vec2::new(0.0, 0.0));
+        assert_eq!(frame.indices.last(), Some(&3));
+    }
+
+    #[test]
+    fn frame_borrows_directly_from_the_simulation_state() {
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(0.5, 0.5),
+        }]);
+        wrach.tick();
+        wrach.tick();
+
+        let frame = wrach.frame();
+        let state = wrach.get_simulation_state();
+        assert_eq!(
+            frame.positions.as_ptr(),
+            state.packed_data.positions.as_ptr()
+        );
+    }
+
+    #[test]
+    fn can_run_on_the_cpu() {
+        let mut wrach = WrachAPI::new_on_cpu(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(0.5, 0.5),
+        }]);
+        for _ in 0..3 {
+            wrach.tick();
+        }
+
+        assert_eq!(wrach.frame().indices.last(), Some(&1));
+    }
+
+    #[test]
+    fn readback_can_be_turned_off() {
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .readback(ReadbackBuffers::NONE)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(0.5, 0.5),
+        }]);
+        for _ in 0..3 {
+            wrach.tick();
+        }
+
+        assert!(wrach.frame().positions.is_empty());
+    }
+
+    #[test]
+    fn invalid_config_is_an_error_not_a_panic() {
+        let mut config = WrachConfig::default();
+        config.cell_size = 0;
+        assert_eq!(
+            WrachAPI::try_new(config).err(),
+            Some(WrachConfigError::ZeroCellSize)
+        );
+    }
+
+    #[test]
+    fn stats_count_the_particles() {
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles(vec![
+            Particle {
+                position: Vec2::new(5.0, 5.0),
+                velocity: Vec2::new(0.0, 0.0),
+            },
+            Particle {
+                position: Vec2::new(1.0, 1.0),
+                velocity: Vec2::new(0.0, 0.0),
+            },
+        ]);
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        let stats = wrach.stats();
+        assert_eq!(stats.particles_in_frame, 2);
+        assert_eq!(stats.active_cells, 2);
+        assert_eq!(stats.max_particles_per_cell, 1);
+    }
+
+    #[test]
+    fn particles_expire_at_the_end_of_their_lifetime() {
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles_with(
+            vec![Particle {
+                position: Vec2::new(5.0, 5.0),
+                velocity: Vec2::new(0.0, 0.0),
+            }],
+            ParticleProperties::default().lifetime(3),
+        );
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(1.0, 1.0),
+            velocity: Vec2::new(0.0, 0.0),
+        }]);
+        for _ in 0..10 {
+            wrach.tick();
+        }
+
+        let frame = wrach.frame();
+        assert_eq!(frame.indices.last(), Some(&1));
+        assert_eq!(frame.ages[0], UVec2::ZERO);
+        assert_eq!(wrach.stats().particles_in_frame, 1);
+    }
+
+    #[test]
+    fn erasing_particles() {
+        let mut wrach = WrachAPI::new(
+            WrachConfig::builder()
+                .dimensions(10, 10)
+                .cell_size(3)
+                .build()
+                .unwrap(),
+        );
+        wrach.add_particles(vec![
+            Particle {
+                position: Vec2::new(8.0, 8.0),
+                velocity: Vec2::new(0.0, 0.0),
+            },
+            Particle {
+                position: Vec2::new(1.0, 1.0),
+                velocity: Vec2::new(0.0, 0.0),
+            },
+        ]);
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        wrach.edit_particles(ParticleEdit::Remove {
+            center: Vec2::new(1.0, 1.0),
+            radius: 2.0,
+        });
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        assert_eq!(wrach.stats().particles_in_frame, 1);
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        let config = WrachConfig::builder()
+            .dimensions(10, 10)
+            .cell_size(3)
+            .deterministic(true)
+            .build()
+            .unwrap();
+        let mut wrach = WrachAPI::new(config);
+        wrach.start_recording();
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(0.5, 0.5),
+        }]);
+        for _ in 0..3 {
+            wrach.tick();
+        }
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(2.0, 8.0),
+            velocity: Vec2::new(-0.5, 0.0),
+        }]);
+        for _ in 0..3 {
+            wrach.tick();
+        }
+        let recording = wrach.stop_recording().unwrap();
+
+        let path = std::env::temp_dir().join("wrach-replays-a-recorded-session.wrachrec");
+        recording.save(&path).unwrap();
+        let replayed = WrachAPI::replay(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let original_state = wrach.get_simulation_state();
+        let replayed_state = replayed.get_simulation_state();
+        assert_eq!(replayed_state.gpu_frame, original_state.gpu_frame);
+        assert_eq!(
+            replayed_state.packed_data.checksum(),
+            original_state.packed_data.checksum()
+        );
     }
 }
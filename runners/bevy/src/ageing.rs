@@ -0,0 +1,159 @@
+//! Particle lifetimes. Every particle has an age and a lifetime, `UVec2(age, lifetime)`, where a
+//! lifetime of 0 means that it lives forever. The physics ages particles by a frame every time it
+//! integrates them, and the packing pass leaves out the particles that have reached the end of
+//! their lifetime.
+//!
+//! The CPU needs to know how many particles are in each frame without reading anything back from
+//! the GPU. Particles only ever age by exactly one frame per frame, so the frame that each particle
+//! expires in is already known when it's merged into the simulation. Particles whose lifetimes the
+//! GPU ends early, like those in a sink, are counted separately, see `Culling`.
+
+use alloc::collections::BTreeMap;
+
+use bevy::math::UVec2;
+
+/// When the simulated particles expire, so that `ShaderWorldSettings::particles_in_frame_count`
+/// keeps up with the GPU.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Expiries {
+    /// How many particles leave the simulation, keyed by the value of `WrachState::gpu_frame` from
+    /// which they're no longer counted
+    due: BTreeMap<u64, u32>,
+    /// The lifetimes of the batch of new particles currently being merged into the simulation
+    merging: Vec<u32>,
+}
+
+impl Expiries {
+    /// The number of simulated particles that will expire at some point.
+    #[inline]
+    #[must_use]
+    pub fn mortal_count(&self) -> u32 {
+        self.due
+            .values()
+            .fold(0, |total, count| total.saturating_add(*count))
+    }
+
+    /// Whether a particle's age has reached its lifetime, the same as the physics'
+    /// `Particle::is_expired()`.
+    #[inline]
+    #[must_use]
+    pub const fn is_expired(age: UVec2) -> bool {
+        age.y != 0 && age.x >= age.y
+    }
+
+    /// Remember the lifetimes of a batch of new particles that's about to be merged.
+    pub(crate) fn stage_batch(&mut self, lifetimes: Vec<u32>) {
+        self.merging = lifetimes;
+    }
+
+    /// The staged batch has been merged into the simulation by the frame that's just run. New
+    /// particles aren't integrated in the frame that they're merged, so they're a frame younger than
+    /// everything else.
+    pub(crate) fn merge_batch(&mut self, gpu_frame: u64) {
+        for lifetime in core::mem::take(&mut self.merging) {
+            if lifetime != 0 {
+                self.add(gpu_frame.saturating_add(lifetime.into()));
+            }
+        }
+    }
+
+    /// Count and forget all the particles that have left the simulation by `gpu_frame`.
+    pub(crate) fn take_expired(&mut self, gpu_frame: u64) -> u32 {
+        let remaining = self.due.split_off(&gpu_frame.saturating_add(1));
+        let expired = core::mem::replace(&mut self.due, remaining);
+        expired
+            .values()
+            .fold(0, |total, count| total.saturating_add(*count))
+    }
+
+    /// Work everything out again from the ages of particles that are about to be simulated in
+    /// `gpu_frame`, eg: after the CPU has replaced the GPU's data.
+    pub(crate) fn reset(&mut self, gpu_frame: u64, ages: &[UVec2]) {
+        self.due.clear();
+        for age in ages {
+            if age.y != 0 {
+                let remaining = age.y.saturating_sub(age.x);
+                self.add(gpu_frame.saturating_add(remaining.into()));
+            }
+        }
+    }
+
+    /// Count a particle that leaves the simulation from `gpu_frame`.
+    fn add(&mut self, gpu_frame: u64) {
+        let count = self.due.entry(gpu_frame).or_default();
+        *count = count.saturating_add(1);
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::UVec2;
+
+    use super::Expiries;
+    use crate::{
+        tests::utils::{particle, simulation},
+        ParticleProperties,
+    };
+
+    #[test]
+    fn merged_particles_expire_after_their_lifetime() {
+        let mut expiries = Expiries::default();
+        expiries.stage_batch(vec![2, 0, 3]);
+        expiries.merge_batch(1);
+        assert_eq!(expiries.mortal_count(), 2);
+
+        assert_eq!(expiries.take_expired(2), 0);
+        assert_eq!(expiries.take_expired(3), 1);
+        assert_eq!(
+            expiries.take_expired(3),
+            0,
+            "Expiries are only counted once"
+        );
+        assert_eq!(expiries.take_expired(10), 1);
+        assert_eq!(expiries.mortal_count(), 0);
+    }
+
+    #[test]
+    fn resetting_from_ages() {
+        let mut expiries = Expiries::default();
+        expiries.reset(5, &[UVec2::new(1, 3), UVec2::ZERO, UVec2::new(0, 1)]);
+        assert_eq!(expiries.take_expired(6), 1);
+        assert_eq!(expiries.take_expired(7), 1);
+    }
+
+    #[test]
+    fn particles_disappear_at_the_end_of_their_lifetime() {
+        let mut simulation = simulation();
+        simulation.state.add_particles_with(
+            vec![particle(5.0, 15.0), particle(10.0, 15.0)],
+            ParticleProperties::default().lifetime(3),
+        );
+        simulation.add_particles(vec![particle(20.0, 15.0)]);
+
+        let mut counts = Vec::new();
+        for _ in 0..6 {
+            simulation.tick().unwrap();
+            counts.push(
+                simulation
+                    .state
+                    .packed_data
+                    .indices
+                    .last()
+                    .copied()
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(counts, vec![3, 3, 3, 1, 1, 1]);
+        assert_eq!(simulation.state.packed_data.ages, vec![UVec2::ZERO]);
+        assert_eq!(
+            simulation.state.shader_settings.particles_in_frame_count, 1,
+            "The CPU's count should keep up without reading anything back"
+        );
+    }
+}
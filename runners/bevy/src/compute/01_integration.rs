@@ -3,49 +3,55 @@
 use bevy::reflect::TypePath;
 use bevy_easy_compute::prelude::{AppComputeWorkerBuilder, ComputeShader, ShaderRef};
 
-use super::{buffers::Buffers, PhysicsComputeWorker};
+use super::{buffers::Buffers, workgroups::entry_point, PhysicsComputeWorker};
 
 impl PhysicsComputeWorker {
     /// Shader for integrating the physics results onto the movement of particles
     pub fn integration(
         mut builder: AppComputeWorkerBuilder<Self>,
         total_cells: u32,
+        workgroup_size: u32,
     ) -> AppComputeWorkerBuilder<Self> {
-        builder.add_pass::<IntegrationShader>(
-            IntegrationShader::workgroups(total_cells),
+        add_sized_pass!(
+            builder,
+            IntegrationShader,
+            workgroup_size,
+            total_cells,
             &[
                 Buffers::WORLD_SETTINGS_UNIFORM,
                 Buffers::INDICES_MAIN,
-                Buffers::POSITIONS_IN,
-                Buffers::POSITIONS_OUT,
-                Buffers::VELOCITIES_IN,
-                Buffers::VELOCITIES_OUT,
-            ],
+                Buffers::PARTICLES_IN,
+                Buffers::PARTICLES_OUT,
+                Buffers::CELL_COUNTS,
+                Buffers::PARTICLES_NEW,
+                Buffers::HEAT_SOURCES,
+                Buffers::REACTIONS,
+                Buffers::MATERIAL_PROPERTIES,
+                Buffers::SINKS_UNIFORM,
+            ]
         );
         builder
     }
 }
 
-/// The shader for the first pass
+/// The shader for the first pass. There's an invocation for every cell. As well as doing the
+/// physics, it counts how many particles end up in each cell, including newly added particles.
 #[derive(TypePath)]
-struct IntegrationShader;
+struct IntegrationShader<const WORKGROUP_SIZE: u32>;
 
-impl IntegrationShader {
-    // TODO: Explain and explore workgroup sizes
-    /// Calculate workgroup sizes
+impl<const WORKGROUP_SIZE: u32> IntegrationShader<WORKGROUP_SIZE> {
+    /// Calculate workgroups
     const fn workgroups(total_cells: u32) -> [u32; 3] {
-        let partition = 32;
-        let main_workgroup_size = u32::div_ceil(total_cells, partition);
-        [main_workgroup_size, 1, 1]
+        [total_cells.div_ceil(WORKGROUP_SIZE), 1, 1]
     }
 }
 
-impl ComputeShader for IntegrationShader {
+impl<const WORKGROUP_SIZE: u32> ComputeShader for IntegrationShader<WORKGROUP_SIZE> {
     fn shader() -> ShaderRef {
         "embedded://wrach_bevy/plugin/../../../../assets/shaders/wrach_physics_shaders.spv".into()
     }
 
     fn entry_point<'shader>() -> &'shader str {
-        "main"
+        entry_point(WORKGROUP_SIZE)
     }
 }
@@ -1,9 +1,10 @@
 //! The Prefix Sum algorithm computes a running sum of elements in an array.
-//! It works by iterating through the array, maintaining a cumulative sum.
+//! It works by iterating through the array, maintaining a cumulative sum. Ours is exclusive, each
+//! item is the sum of the elements before it, and the guard item at the end is the total.
 //!
 //! For example:
-//! Input:  [0, 1, 0, 3, 2]
-//! Output: [0, 1, 1, 4, 6]
+//! Input:  [0, 1, 0, 3, 2, 0]
+//! Output: [0, 0, 1, 1, 4, 6]
 //!
 //! In particle simulations it's used for being able to efficiently lookup nearby particles through
 //! spatial bins, or cells, as we call them here. A cell contains exponentially fewer particles and
@@ -16,78 +17,65 @@
 //! efficiently such that all particle data for a cell is stored together. This also contributes
 //! towards reducing bank conflicts when looking up the particle data.
 
-use bevy::reflect::TypePath;
-use bevy_easy_compute::prelude::{AppComputeWorkerBuilder, ComputeShader, ShaderRef};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::CommandEncoderDescriptor,
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use bevy_easy_compute::prelude::{
+    AppComputeWorker, AppComputeWorkerBuilder, ComputeShader, ShaderRef,
+};
 
 use super::{buffers::Buffers, PhysicsComputeWorker};
 
 impl PhysicsComputeWorker {
-    /// Our current prefix sum implementation only has 2 passes, we can easily add more passes, but I
-    /// don't think we'll ever need that many cells (ie prefix sum items). Besides, hopefully we'll
-    /// move to a "sub group"-based algorithm soon which is both faster and doesn't require multiple
-    /// passes. I suspect that will force a dependency on Vulkan though.
-    pub const MAX_CELLS_FOR_PREFIX_SUM_PIPELINE: u32 = 4_194_304;
-
     /// This is calculated by:
-    ///   `local.workgroup_size.x * local.workgroup_size.y * 2`
+    ///   `THREADS_PER_WORKGROUP * ITEMS_PER_THREAD`
     /// See prefix sum shader for hardcoded workgroup sizes.
     pub const PREFIX_SUM_ITEMS_PER_WORKGROUP: u32 = 2048;
 
+    /// The workgroups of the prefix sum share their progress through descriptors that pack a
+    /// 2-bit flag alongside a sum. So no sum, and therefore no particle count, can be bigger than
+    /// this.
+    pub const PREFIX_SUM_MAX_TOTAL: u32 = 1 << 30;
+
     /// An extra "guard item" at then end, so we can store the final cell's particle count.
     /// So if the final cell has no particles, instead of: [0, 3, 3, 4], we do: [0, 3, 3, 4, 4].
     /// And if the final cell has 1 particle, instead of: [0, 3, 3, 4], we do: [0, 3, 3, 4, 5].
-    pub const PREFIX_SUM_GUARD_ITEM: usize = 1;
+    pub const PREFIX_SUM_GUARD_ITEM: u32 = 1;
 
-    // TODO: Move to a shared CPU/GPU crate.
-    /// Our current GPU implementation of prefix sum, offsets all items one to the right. Hopefully
-    /// we'll move to a subgroup-based version soon that doesn't have this issue.
-    pub const PREFIX_SUM_OFFSET_HACK: usize = 1;
+    /// The number of items in the prefix sum's scratch buffer: 2 counters followed by a
+    /// descriptor for each partition of the items.
+    pub const fn prefix_sum_state_size(total_cells: u32) -> u32 {
+        PrefixSumShader::workgroups(total_cells)[0].saturating_add(2)
+    }
 
     /// Count the number of particles per cell
     pub fn prefix_sum(
         mut builder: AppComputeWorkerBuilder<Self>,
         total_cells: u32,
     ) -> AppComputeWorkerBuilder<Self> {
-        builder.add_pass::<PrefixSumShaderDownSweep>(
-            PrefixSumShaderDownSweep::workgroups(total_cells),
+        builder.add_pass::<PrefixSumShader>(
+            PrefixSumShader::workgroups(total_cells),
             &[
                 Buffers::WORLD_SETTINGS_UNIFORM,
+                Buffers::CELL_COUNTS,
                 Buffers::INDICES_MAIN,
-                Buffers::INDICES_BLOCK_SUMS,
+                Buffers::PREFIX_SUM_STATE,
             ],
         );
 
-        let remaining = total_cells.div_ceil(Self::PREFIX_SUM_ITEMS_PER_WORKGROUP);
-        if remaining > 1 {
-            builder
-                .add_pass::<PrefixSumShaderDownSweep>(
-                    PrefixSumShaderDownSweep::workgroups(remaining),
-                    &[
-                        Buffers::WORLD_SETTINGS_UNIFORM,
-                        // Note how, this time, the block sums are in place of the indices.
-                        Buffers::INDICES_BLOCK_SUMS,
-                        Buffers::INDICES_BLOCK_SUMS,
-                    ],
-                )
-                .add_pass::<PrefixSumShaderBoxSums>(
-                    PrefixSumShaderBoxSums::workgroups(total_cells),
-                    &[
-                        Buffers::WORLD_SETTINGS_UNIFORM,
-                        Buffers::INDICES_MAIN,
-                        Buffers::INDICES_BLOCK_SUMS,
-                    ],
-                );
-        };
-
         builder
     }
 }
 
-/// First stage of a 2-stage prefix sum algorithm.
+/// A single-pass prefix sum that supports any number of items. See the shader for details.
 #[derive(TypePath)]
-struct PrefixSumShaderDownSweep;
+struct PrefixSumShader;
 
-impl PrefixSumShaderDownSweep {
+impl PrefixSumShader {
     /// Calculate workgroups
     const fn workgroups(total_cells: u32) -> [u32; 3] {
         let main_workgroup_size = u32::div_ceil(
@@ -98,43 +86,44 @@ impl PrefixSumShaderDownSweep {
     }
 }
 
-impl ComputeShader for PrefixSumShaderDownSweep {
+impl ComputeShader for PrefixSumShader {
     fn shader() -> ShaderRef {
         "embedded://wrach_bevy/plugin/../../../../assets/shaders/prefix_sum.wgsl".into()
     }
 
     fn entry_point<'shader>() -> &'shader str {
-        "reduce_downsweep"
-    }
-}
-
-/// Second stage of a 2-stage prefix sum algorithm.
-#[derive(TypePath)]
-struct PrefixSumShaderBoxSums;
-
-impl PrefixSumShaderBoxSums {
-    /// Calculate workgroups
-    const fn workgroups(total_cells: u32) -> [u32; 3] {
-        let main_workgroup_size = u32::div_ceil(
-            total_cells,
-            PhysicsComputeWorker::PREFIX_SUM_ITEMS_PER_WORKGROUP,
-        );
-        [main_workgroup_size, 1, 1]
+        "main"
     }
 }
 
-impl ComputeShader for PrefixSumShaderBoxSums {
-    fn shader() -> ShaderRef {
-        "embedded://wrach_bevy/plugin/../../../../assets/shaders/prefix_sum.wgsl".into()
-    }
-
-    fn entry_point<'shader>() -> &'shader str {
-        "add_block_sums"
-    }
+/// Zero the cell counts after the compute worker's submission, ready for the physics to count
+/// into them again in the next frame. The prefix sum can't do it itself as it reads them, because
+/// its workgroups sometimes read each other's counts. The packing pass counts them back down as it
+/// places the particles, but clearing them here means nothing left over from a frame that was cut
+/// short, eg: by the worker being rebuilt, can throw off the next one.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "We have no choice because of the magic Bevy systems function signature"
+)]
+pub fn clear_cell_counts(
+    compute_worker: Res<AppComputeWorker<PhysicsComputeWorker>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(cell_counts) = compute_worker.get_buffer(Buffers::CELL_COUNTS) else {
+        return;
+    };
+
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("wrach_clear_cell_counts"),
+    });
+    encoder.clear_buffer(cell_counts, 0, None);
+    render_queue.submit([encoder.finish()]);
 }
 
 #[expect(
     clippy::default_numeric_fallback,
+    clippy::unwrap_used,
     reason = "Tests don't need to be so strict"
 )]
 #[cfg(test)]
@@ -142,6 +131,7 @@ mod test {
     use bevy::math::Vec2;
     use bevy::math::Vec4;
 
+    use crate::compute::PhysicsComputeWorker;
     use crate::particle_store::ParticleStore;
     use crate::tests::utils::WrachTestAPI;
     use crate::Particle;
@@ -159,6 +149,7 @@ mod test {
         });
         let mut store = ParticleStore::new(
             cell_size,
+            1.0,
             Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
         );
 
@@ -191,12 +182,9 @@ mod test {
         }
 
         let gpu_packed_data = &wrach.get_simulation_state().packed_data;
-        let cpu_packed_data = store.create_packed_data();
+        let cpu_packed_data = store.create_packed_data().unwrap();
 
-        assert_eq!(
-            cpu_packed_data.indices,
-            vec![0, 0, 2, 2, 2, 2, 3, 3, 3, 3, 4]
-        );
+        assert_eq!(cpu_packed_data.indices, vec![0, 2, 2, 2, 2, 3, 3, 3, 3, 4]);
 
         //assert_eq!(gpu_packed_data.positions, cpu_packed_data.positions);
 
@@ -204,6 +192,10 @@ mod test {
             gpu_packed_data.indices, cpu_packed_data.indices,
             "GPU packed indices do not match CPU packed indices"
         );
+
+        // No shifting, the first cell's particles start at the very beginning.
+        assert_eq!(gpu_packed_data.indices.first(), Some(&0));
+        assert_eq!(gpu_packed_data.indices.last(), Some(&4));
     }
 
     #[test]
@@ -218,6 +210,7 @@ mod test {
         });
         let mut store = ParticleStore::new(
             cell_size,
+            1.0,
             Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
         );
 
@@ -250,12 +243,67 @@ mod test {
         }
 
         let gpu_packed_data = &wrach.get_simulation_state().packed_data;
-        let cpu_packed_data = store.create_packed_data();
+        let cpu_packed_data = store.create_packed_data().unwrap();
 
         assert_eq!(gpu_packed_data.indices.len(), cpu_packed_data.indices.len());
         assert_eq!(
             gpu_packed_data.indices, cpu_packed_data.indices,
             "GPU packed indices do not match CPU packed indices"
         );
+        assert_eq!(gpu_packed_data.indices.last(), Some(&4));
+    }
+
+    #[test]
+    fn prefix_sum_for_arrays_spanning_many_workgroups() {
+        let dimensions = (600, 600);
+        let cell_size = 3;
+
+        let mut wrach = WrachTestAPI::new(WrachConfig {
+            dimensions,
+            cell_size,
+            ..Default::default()
+        });
+        let mut store = ParticleStore::new(
+            cell_size,
+            1.0,
+            Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
+        );
+
+        // Spread particles over the whole viewport so that every partition of the prefix sum
+        // has to carry totals over from the ones before it.
+        let mut particles = Vec::new();
+        for y in 0_u8..60 {
+            for x in 0_u8..60 {
+                particles.push(Particle {
+                    position: Vec2::new(
+                        f32::from(x).mul_add(10.0, 0.5),
+                        f32::from(y).mul_add(10.0, 0.5),
+                    ),
+                    velocity: Vec2::new(0.0, 0.0),
+                });
+            }
+        }
+
+        wrach.add_particles(particles.clone());
+        for particle in particles {
+            store.add_particle(particle);
+        }
+
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        let gpu_packed_data = &wrach.get_simulation_state().packed_data;
+        let cpu_packed_data = store.create_packed_data().unwrap();
+
+        assert!(
+            u32::try_from(cpu_packed_data.indices.len()).unwrap()
+                > PhysicsComputeWorker::PREFIX_SUM_ITEMS_PER_WORKGROUP * 10
+        );
+        assert_eq!(
+            gpu_packed_data.indices, cpu_packed_data.indices,
+            "GPU packed indices do not match CPU packed indices"
+        );
+        assert_eq!(gpu_packed_data.indices.last(), Some(&3600));
     }
 }
@@ -1,27 +1,31 @@
 //! With the generated prefix sum of the particles cell locations, pack the new particle data into
-//! the "*_IN" buffers ready for the next frame of the simulation.
+//! `Buffers::PARTICLES_IN` ready for the next frame of the simulation.
 
 use bevy::reflect::TypePath;
 use bevy_easy_compute::prelude::{AppComputeWorkerBuilder, ComputeShader, ShaderRef};
 
-use super::{buffers::Buffers, PhysicsComputeWorker};
+use super::{buffers::Buffers, workgroups::entry_point, PhysicsComputeWorker};
 
 impl PhysicsComputeWorker {
     /// Count the number of particles per cell
     pub fn particle_data(
         mut builder: AppComputeWorkerBuilder<Self>,
         total_particles: u32,
+        workgroup_size: u32,
     ) -> AppComputeWorkerBuilder<Self> {
-        builder.add_pass::<PackNewParticleDataShader>(
-            PackNewParticleDataShader::workgroups(total_particles),
+        add_sized_pass!(
+            builder,
+            PackNewParticleDataShader,
+            workgroup_size,
+            total_particles,
             &[
                 Buffers::WORLD_SETTINGS_UNIFORM,
-                Buffers::POSITIONS_OUT,
-                Buffers::VELOCITIES_OUT,
+                Buffers::PARTICLES_OUT,
+                Buffers::PARTICLES_NEW,
                 Buffers::INDICES_MAIN,
-                Buffers::POSITIONS_IN,
-                Buffers::VELOCITIES_IN,
-            ],
+                Buffers::CELL_COUNTS,
+                Buffers::PARTICLES_IN,
+            ]
         );
         builder
     }
@@ -29,26 +33,22 @@ impl PhysicsComputeWorker {
 
 /// Efficiently pack particles ready for the next frame
 #[derive(TypePath)]
-struct PackNewParticleDataShader;
+struct PackNewParticleDataShader<const WORKGROUP_SIZE: u32>;
 
-impl PackNewParticleDataShader {
+impl<const WORKGROUP_SIZE: u32> PackNewParticleDataShader<WORKGROUP_SIZE> {
     /// Calculate workgroups
     const fn workgroups(total_particles: u32) -> [u32; 3] {
-        [
-            total_particles.div_ceil(PhysicsComputeWorker::PARTICLE_WORKGROUP_LOCAL_SIZE),
-            1,
-            1,
-        ]
+        [total_particles.div_ceil(WORKGROUP_SIZE), 1, 1]
     }
 }
 
-impl ComputeShader for PackNewParticleDataShader {
+impl<const WORKGROUP_SIZE: u32> ComputeShader for PackNewParticleDataShader<WORKGROUP_SIZE> {
     fn shader() -> ShaderRef {
         "embedded://wrach_bevy/plugin/../../../../assets/shaders/pack_new_particle_data.wgsl".into()
     }
 
     fn entry_point<'shader>() -> &'shader str {
-        "main"
+        entry_point(WORKGROUP_SIZE)
     }
 }
 
@@ -81,6 +81,7 @@ mod test {
         });
         let mut store = ParticleStore::new(
             cell_size,
+            1.0,
             Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
         );
 
@@ -113,7 +114,7 @@ mod test {
         }
 
         let gpu_packed_data = &wrach.get_simulation_state().packed_data;
-        let cpu_packed_data = store.create_packed_data();
+        let cpu_packed_data = store.create_packed_data().unwrap();
 
         assert_eq!(
             cpu_packed_data.positions,
@@ -138,4 +139,49 @@ mod test {
             vec![Vec2::new(5.0, 5.0), Vec2::new(10.0, 10.0)]
         );
     }
+
+    #[test]
+    fn packed_data_for_particles_added_in_later_frames() {
+        let dimensions = (10, 10);
+        let cell_size = 3;
+
+        let mut wrach = WrachTestAPI::new(WrachConfig {
+            dimensions,
+            cell_size,
+            ..Default::default()
+        });
+        let mut store = ParticleStore::new(
+            cell_size,
+            1.0,
+            Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
+        );
+
+        let first = Particle {
+            position: Vec2::new(0.5, 0.5),
+            velocity: Vec2::new(0.0, 0.0),
+        };
+        let second = Particle {
+            position: Vec2::new(8.5, 8.5),
+            velocity: Vec2::new(0.0, 0.0),
+        };
+
+        wrach.add_particles(vec![first]);
+        store.add_particle(first);
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        wrach.add_particles(vec![second]);
+        store.add_particle(second);
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        let state = wrach.get_simulation_state();
+        let cpu_packed_data = store.create_packed_data().unwrap();
+
+        assert_eq!(state.shader_settings.particles_in_frame_count, 2);
+        assert_eq!(state.packed_data.indices, cpu_packed_data.indices);
+        assert_eq!(state.packed_data.positions[0..2], cpu_packed_data.positions);
+    }
 }
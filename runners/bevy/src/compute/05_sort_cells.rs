@@ -0,0 +1,94 @@
+//! For deterministic simulations, sort the particles within each cell once they've been packed.
+//! See `WrachConfig::deterministic`.
+
+use bevy::reflect::TypePath;
+use bevy_easy_compute::prelude::{AppComputeWorkerBuilder, ComputeShader, ShaderRef};
+
+use super::{buffers::Buffers, workgroups::entry_point, PhysicsComputeWorker};
+
+impl PhysicsComputeWorker {
+    /// Put the particles of every cell into the same order, whatever order they were packed in
+    pub fn sort_cells(
+        mut builder: AppComputeWorkerBuilder<Self>,
+        total_cells: u32,
+        workgroup_size: u32,
+    ) -> AppComputeWorkerBuilder<Self> {
+        add_sized_pass!(
+            builder,
+            SortCellsShader,
+            workgroup_size,
+            total_cells,
+            &[
+                Buffers::WORLD_SETTINGS_UNIFORM,
+                Buffers::INDICES_MAIN,
+                Buffers::PARTICLES_IN,
+            ]
+        );
+        builder
+    }
+}
+
+/// Sort the particles within cells
+#[derive(TypePath)]
+struct SortCellsShader<const WORKGROUP_SIZE: u32>;
+
+impl<const WORKGROUP_SIZE: u32> SortCellsShader<WORKGROUP_SIZE> {
+    /// Calculate workgroups
+    const fn workgroups(total_cells: u32) -> [u32; 3] {
+        [total_cells.div_ceil(WORKGROUP_SIZE), 1, 1]
+    }
+}
+
+impl<const WORKGROUP_SIZE: u32> ComputeShader for SortCellsShader<WORKGROUP_SIZE> {
+    fn shader() -> ShaderRef {
+        "embedded://wrach_bevy/plugin/../../../../assets/shaders/sort_cells.wgsl".into()
+    }
+
+    fn entry_point<'shader>() -> &'shader str {
+        entry_point(WORKGROUP_SIZE)
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::indexing_slicing,
+    reason = "Test's don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use crate::tests::utils::WrachTestAPI;
+    use crate::Particle;
+    use crate::WrachConfig;
+
+    #[test]
+    fn particles_in_a_cell_are_sorted() {
+        let mut wrach = WrachTestAPI::new(WrachConfig {
+            dimensions: (10, 10),
+            cell_size: 3,
+            deterministic: true,
+            ..Default::default()
+        });
+
+        wrach.add_particles(vec![
+            Particle {
+                position: Vec2::new(2.5, 2.5),
+                velocity: Vec2::ZERO,
+            },
+            Particle {
+                position: Vec2::new(0.1, 0.1),
+                velocity: Vec2::ZERO,
+            },
+        ]);
+        for _ in 0..4 {
+            wrach.tick();
+        }
+
+        let positions = &wrach.get_simulation_state().packed_data.positions;
+        assert_eq!(
+            positions[0..2],
+            vec![Vec2::new(0.1, 0.1), Vec2::new(2.5, 2.5)]
+        );
+    }
+}
@@ -8,14 +8,22 @@ impl Buffers {
     pub const WORLD_SETTINGS_UNIFORM: &'static str = "world_config";
     /// Efficient packing of particle indices and spatial bin cell counts
     pub const INDICES_MAIN: &'static str = "indices_main";
-    /// A scratch buffer for prefix sum calculations
-    pub const INDICES_BLOCK_SUMS: &'static str = "indices_block_sums";
-    /// Pixel positions buffer ID for reading
-    pub const POSITIONS_IN: &'static str = "positions_in";
-    /// Pixel positions buffer ID for writing
-    pub const POSITIONS_OUT: &'static str = "positions_out";
-    /// Pixel velocities buffer ID for reading
-    pub const VELOCITIES_IN: &'static str = "velocities_in";
-    /// Pixel velocities buffer ID for writing
-    pub const VELOCITIES_OUT: &'static str = "velocities_out";
+    /// Particles per spatial bin cell, counted during integration and consumed by the prefix sum
+    pub const CELL_COUNTS: &'static str = "cell_counts";
+    /// A scratch buffer for the prefix sum to share progress between its workgroups
+    pub const PREFIX_SUM_STATE: &'static str = "prefix_sum_state";
+    /// Particles buffer ID for reading, see `ShaderPackedParticle`
+    pub const PARTICLES_IN: &'static str = "particles_in";
+    /// Particles buffer ID for writing
+    pub const PARTICLES_OUT: &'static str = "particles_out";
+    /// Newly added particles waiting to be merged into the simulation
+    pub const PARTICLES_NEW: &'static str = "particles_new";
+    /// Heat sources from the CPU, see `WrachState::add_heat_source()`
+    pub const HEAT_SOURCES: &'static str = "heat_sources";
+    /// Sinks from the CPU, see `WrachState::add_sink()`
+    pub const SINKS_UNIFORM: &'static str = "sinks";
+    /// The reactions table from the CPU, see `WrachState::set_materials()`
+    pub const REACTIONS: &'static str = "reactions";
+    /// The properties of every material from the CPU, see `WrachState::set_materials()`
+    pub const MATERIAL_PROPERTIES: &'static str = "material_properties";
 }
@@ -1,92 +1,213 @@
 //! The code that manages the GPU compute workers
 
-use bevy::{prelude::*, render::render_resource::BufferUsages};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{BufferUsages, CommandEncoderDescriptor},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
 use bevy_easy_compute::prelude::*;
 
-use crate::{compute::buffers::Buffers, config_shader::ShaderWorldSettings, WrachState};
+use crate::{
+    compute::{buffers::Buffers, workgroups},
+    config_shader::{ShaderMaterialProperties, ShaderPackedParticle, ShaderReaction},
+    emitters::sinks_buffer,
+    error::{report_error_in_world, WrachError},
+    plugin::bind_groups::get_buffers_for_renderer,
+    WorkgroupSize, WrachConfigError, WrachState,
+};
 
 /// The main GPU compute pipeline for physics simulations
 #[derive(Resource)]
 pub struct PhysicsComputeWorker;
 
 impl PhysicsComputeWorker {
-    /// The size of the local shader workgroups for particle-related shaders. This is basically the
-    /// number of threads that a single workgroup invocation will run. Apparently Nvidia has 32-width
-    /// workgroups and AMD has 64, so I think a multiple of 64 is best to get full occupancy?
-    pub const PARTICLE_WORKGROUP_LOCAL_SIZE: u32 = 64;
+    /// The maximum number of newly added particles that can be merged into the simulation in a
+    /// single frame. Any more than this just wait their turn for the next frame. It's kept small so
+    /// that adding a few particles every frame only costs a small upload.
+    pub const MAX_NEW_PARTICLES_PER_FRAME: u32 = 16_384;
+
+    /// Buffers whose contents need to survive a rebuild of the worker. The particles out buffer is
+    /// completely overwritten every frame and the prefix sum state is back to zero at the end of
+    /// every frame, so they don't need copying. The world settings and the sinks are uploaded fresh
+    /// by the new worker.
+    const PERSISTENT_BUFFERS: [&'static str; 6] = [
+        Buffers::INDICES_MAIN,
+        Buffers::PARTICLES_IN,
+        Buffers::PARTICLES_NEW,
+        Buffers::HEAT_SOURCES,
+        Buffers::REACTIONS,
+        Buffers::MATERIAL_PROPERTIES,
+    ];
+
+    /// Replace the worker with a freshly built one, for when buffer sizes or workgroup sizes have
+    /// changed. `bevy_easy_compute` fixes both when a worker is built,
+    /// [see](https://github.com/AnthonyTornetta/bevy_easy_compute/issues/14). The contents of the
+    /// old buffers are copied across on the GPU, so the simulation carries on from exactly where it
+    /// was.
+    pub fn rebuild(world: &mut World) -> Result<(), WrachError> {
+        let Some(old_worker) = world.remove_resource::<AppComputeWorker<Self>>() else {
+            return Ok(());
+        };
+
+        let new_worker = Self::build(world);
+        Self::copy_persistent_buffers(world, &old_worker, &new_worker);
+        world.insert_resource(new_worker);
+
+        // The renderer reads directly from the compute buffers, so it needs to know about the new
+        // ones.
+        get_buffers_for_renderer(world)
+    }
+
+    /// Copy the contents of all the buffers that need to survive from one worker to the other. Any
+    /// uploads already queued for the old worker are applied before the copy, because wgpu always
+    /// flushes pending writes at the beginning of a submission.
+    fn copy_persistent_buffers(
+        world: &World,
+        old_worker: &AppComputeWorker<Self>,
+        new_worker: &AppComputeWorker<Self>,
+    ) {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+        let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("wrach_rebuild_worker"),
+        });
+
+        for name in Self::PERSISTENT_BUFFERS {
+            let (Some(source), Some(destination)) =
+                (old_worker.get_buffer(name), new_worker.get_buffer(name))
+            else {
+                warn!("Couldn't find `{name}` buffer to copy while rebuilding worker");
+                continue;
+            };
+
+            let size = source.size().min(destination.size());
+            encoder.copy_buffer_to_buffer(source, 0, destination, 0, size);
+        }
+
+        render_queue.submit([encoder.finish()]);
+    }
 }
 
 impl ComputeWorker for PhysicsComputeWorker {
+    /// `bevy_easy_compute` doesn't let building fail, so anything that goes wrong is reported and
+    /// the worker is built with the closest thing that works.
     #[expect(
         clippy::expect_used,
-        reason = "`expect`s until there's a way to use `?` in systems"
+        reason = "`u32`s always fit into `usize` on the platforms that we support"
     )]
     fn build(world: &mut World) -> AppComputeWorker<Self> {
+        let mut errors = Vec::new();
         let mut state = world.resource_mut::<WrachState>();
 
-        let (cells, _grid) = state.particle_store.spatial_bin.get_active_cells();
-        #[expect(
-            clippy::arithmetic_side_effects,
-            reason = "Overflow and division by zero are unlikely"
-        )]
-        let total_cells_usize =
-            cells.len() + Self::PREFIX_SUM_GUARD_ITEM + Self::PREFIX_SUM_OFFSET_HACK;
-        let total_cells: u32 = total_cells_usize
+        // Buffers are never built smaller than what's currently needed, but they may be bigger if
+        // they've been grown.
+        let total_cells = state.cells_capacity.max(state.required_cells_capacity());
+        let total_cells_usize: usize = total_cells
             .try_into()
             // Wow, imagine if we're simulating that many cells!
-            .expect("Couldn't convert total cells count into u32");
-
-        assert!(
-            total_cells < Self::MAX_CELLS_FOR_PREFIX_SUM_PIPELINE,
-            "More cells than our current 2-pass prefix sum pipeline can handle"
-        );
+            .expect("Couldn't convert total cells count into usize");
 
         debug!("Total spatial bins cells: {:?}", total_cells);
 
-        let max_particles = state.particle_store.max_particles_per_frame();
+        let max_particles_per_frame = state
+            .particle_store
+            .max_particles_per_frame()
+            .unwrap_or_else(|error| {
+                errors.push(error);
+                0
+            });
+        let mut max_particles = state.particles_capacity.max(max_particles_per_frame);
+        if max_particles >= Self::PREFIX_SUM_MAX_TOTAL {
+            errors.push(WrachError::PrefixSumLimit {
+                particles: max_particles,
+                max: Self::PREFIX_SUM_MAX_TOTAL,
+            });
+            max_particles = Self::PREFIX_SUM_MAX_TOTAL.saturating_sub(1);
+        }
         let max_particles_usize: usize = max_particles
             .try_into()
             .expect("Couldn't convert `max_particles` to `Vec` capacity");
 
         let indices = vec![0_u32; total_cells_usize];
+        let prefix_sum_state_size: usize = Self::prefix_sum_state_size(total_cells)
+            .try_into()
+            .expect("Couldn't convert prefix sum state size to usize");
+        let prefix_sum_state = vec![0_u32; prefix_sum_state_size];
 
-        let positions = vec![Vec2::default(); max_particles_usize];
-        let velocities = vec![Vec2::default(); max_particles_usize];
-
-        let shader_settings = ShaderWorldSettings {
-            view_dimensions: Vec2::new(
-                state.config.dimensions.0.into(),
-                state.config.dimensions.1.into(),
-            ),
-            view_anchor: Vec2::new(0.0, 0.0),
-            grid_dimensions: state.particle_store.spatial_bin.grid_dimensions,
-            cell_size: state.config.cell_size.into(),
-            particles_in_frame_count: 0,
-        };
+        let particles = vec![ShaderPackedParticle::default(); max_particles_usize];
+
+        let new_particles_capacity = Self::MAX_NEW_PARTICLES_PER_FRAME.min(max_particles);
+        let new_particles_capacity_usize: usize = new_particles_capacity
+            .try_into()
+            .expect("Couldn't convert new particles capacity to `Vec` capacity");
+        let new_particles = vec![ShaderPackedParticle::default(); new_particles_capacity_usize];
+        let heat_sources_usize: usize = wrach_cpu_gpu_shared::MAX_HEAT_SOURCES
+            .try_into()
+            .expect("Couldn't convert heat sources capacity to `Vec` capacity");
+        let heat_sources = vec![Vec4::default(); heat_sources_usize];
+        let reactions_usize: usize = wrach_cpu_gpu_shared::MAX_REACTIONS
+            .try_into()
+            .expect("Couldn't convert reactions capacity to `Vec` capacity");
+        let reactions = vec![ShaderReaction::default(); reactions_usize];
+        let materials_usize: usize = wrach_cpu_gpu_shared::MAX_MATERIALS
+            .try_into()
+            .expect("Couldn't convert materials capacity to `Vec` capacity");
+        let material_properties = vec![ShaderMaterialProperties::default(); materials_usize];
+        let sinks = sinks_buffer(&state.emitters.sinks_to_gpu());
+
+        let shader_settings = state.current_shader_settings();
         state.shader_settings = shader_settings;
+        state.particles_capacity = max_particles;
+        state.new_particles_capacity = new_particles_capacity;
+        state.cells_capacity = total_cells;
 
         info!("{:?}", shader_settings);
 
+        if !workgroups::is_supported(state.workgroup_size) {
+            errors.push(WrachConfigError::UnsupportedWorkgroupSize(state.workgroup_size).into());
+            state.workgroup_size = WorkgroupSize::DEFAULT_THREADS;
+        }
+        let workgroup_size = state.workgroup_size;
+        let is_deterministic = state.config.deterministic;
+
+        for error in errors {
+            report_error_in_world(world, error);
+        }
+
+        // Buffers whose contents survive a frame need to be copyable, so they can be carried over
+        // to a new worker when growing.
+        let copyable = BufferUsages::COPY_SRC | BufferUsages::COPY_DST;
+
         let mut builder = AppComputeWorkerBuilder::new(world);
         builder
             .set_extra_buffer_usages(Some(BufferUsages::VERTEX))
             .add_uniform(Buffers::WORLD_SETTINGS_UNIFORM, &shader_settings)
+            // GPU-only, but cleared by the CPU every frame, see `clear_cell_counts()`
+            .set_extra_buffer_usages(Some(BufferUsages::COPY_DST))
+            .add_storage(Buffers::CELL_COUNTS, &indices)
             .set_extra_buffer_usages(None)
-            // GPU-only
-            .add_storage(Buffers::INDICES_BLOCK_SUMS, &indices)
-            .add_storage(Buffers::POSITIONS_OUT, &positions)
-            .add_storage(Buffers::VELOCITIES_OUT, &velocities)
-            // Readable from the CPU
-            .add_staging(Buffers::INDICES_MAIN, &indices)
-            .set_extra_buffer_usages(Some(BufferUsages::VERTEX))
-            .add_staging(Buffers::POSITIONS_IN, &positions)
-            .set_extra_buffer_usages(None)
-            .add_staging(Buffers::VELOCITIES_IN, &velocities);
-
-        builder = Self::integration(builder, total_cells);
-        builder = Self::particles_per_cell_count(builder, max_particles);
+            .add_uniform(Buffers::SINKS_UNIFORM, &sinks)
+            .add_storage(Buffers::PREFIX_SUM_STATE, &prefix_sum_state)
+            .add_storage(Buffers::PARTICLES_OUT, &particles)
+            .set_extra_buffer_usages(Some(copyable))
+            .add_storage(Buffers::PARTICLES_NEW, &new_particles)
+            .add_storage(Buffers::HEAT_SOURCES, &heat_sources)
+            .add_storage(Buffers::REACTIONS, &reactions)
+            .add_storage(Buffers::MATERIAL_PROPERTIES, &material_properties)
+            // Readable from the CPU, see `readback.rs`
+            .add_storage(Buffers::INDICES_MAIN, &indices)
+            .set_extra_buffer_usages(Some(BufferUsages::VERTEX | copyable))
+            .add_storage(Buffers::PARTICLES_IN, &particles)
+            .set_extra_buffer_usages(None);
+
+        builder = Self::integration(builder, total_cells, workgroup_size);
         builder = Self::prefix_sum(builder, total_cells);
-        builder = Self::particle_data(builder, max_particles);
+        builder = Self::particle_data(builder, max_particles, workgroup_size);
+        if is_deterministic {
+            builder = Self::sort_cells(builder, total_cells, workgroup_size);
+        }
 
         builder.build()
     }
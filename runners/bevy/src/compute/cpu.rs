@@ -0,0 +1,518 @@
+//! Run the whole simulation pipeline on the CPU, for when there's no GPU with compute shaders, like
+//! in browsers without WebGPU. It's the same physics code that gets compiled to SPIR-V, just run one
+//! cell at a time. The prefix sum and packing are ports of their WGSL shaders.
+
+use bevy::math::{UVec2, Vec2, Vec4};
+use wrach_cpu_gpu_shared::PackedParticle;
+
+use crate::{
+    ageing::Expiries,
+    compute::PhysicsComputeWorker,
+    config_shader::ShaderPackedParticle,
+    emitters::sinks_buffer,
+    state::{GPUUpload, Particle},
+    Materials, WrachConfig, WrachConfigError, WrachError, WrachState,
+};
+
+/// A simulation that doesn't need a GPU, or even Bevy's `App`. It keeps the same `WrachState` as
+/// the GPU simulation, so its results are read in exactly the same way, from `state.packed_data`.
+#[non_exhaustive]
+#[expect(
+    clippy::partial_pub_fields,
+    reason = "The state is for end users, the buffers are only for the pipeline itself"
+)]
+pub struct CpuSimulation {
+    /// All the simulation state, the same as what the Bevy plugin keeps as a resource
+    pub state: WrachState,
+    /// Particles written by the physics, the equivalent of `Buffers::PARTICLES_OUT`
+    particles_out: Vec<PackedParticle>,
+    /// Particles being merged into the simulation this frame, the equivalent of
+    /// `Buffers::PARTICLES_NEW`
+    particles_new: Vec<PackedParticle>,
+    /// The heat sources, `(x, y, radius, power)`
+    heat_sources: Vec<Vec4>,
+    /// The sinks, `(x, y, radius, 0)`, the equivalent of `Buffers::SINKS_UNIFORM`
+    sinks: Vec<Vec4>,
+    /// The reactions table
+    reactions: Vec<wrach_cpu_gpu_shared::Reaction>,
+    /// The properties of every material, the equivalent of `Buffers::MATERIAL_PROPERTIES`
+    material_properties: Vec<wrach_cpu_gpu_shared::MaterialProperties>,
+    /// Particles per spatial bin cell, counted by the physics for the prefix sum
+    cell_counts: Vec<u32>,
+}
+
+impl CpuSimulation {
+    /// Instantiate
+    ///
+    /// # Errors
+    /// If the config is invalid, see `WrachConfig::validate()`.
+    #[inline]
+    pub fn new(config: WrachConfig) -> Result<Self, WrachConfigError> {
+        config.validate()?;
+        let mut state = WrachState::new(config);
+        state.shader_settings = state.current_shader_settings();
+        // Memory on the CPU grows as needed, so these are only limited by the same limits as the
+        // GPU pipeline.
+        state.particles_capacity = PhysicsComputeWorker::PREFIX_SUM_MAX_TOTAL.saturating_sub(1);
+        state.new_particles_capacity = PhysicsComputeWorker::MAX_NEW_PARTICLES_PER_FRAME;
+        state.cells_capacity = state.required_cells_capacity();
+
+        Ok(Self {
+            state,
+            particles_out: Vec::new(),
+            particles_new: Vec::new(),
+            heat_sources: Vec::new(),
+            sinks: Vec::new(),
+            reactions: Vec::new(),
+            material_properties: Materials::default()
+                .properties_to_gpu()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            cell_counts: Vec::new(),
+        })
+    }
+
+    /// Add particles to the simulation
+    #[inline]
+    pub fn add_particles(&mut self, particles: Vec<Particle>) {
+        self.state.add_particles(particles);
+    }
+
+    /// Move the view onto the simulation
+    #[inline]
+    pub fn set_viewport_anchor(&mut self, anchor: Vec2) {
+        self.state.set_viewport_anchor(anchor);
+        self.state.cells_capacity = self.state.required_cells_capacity();
+    }
+
+    /// Simulate a single frame. The results are always immediately available in
+    /// `state.packed_data`, there's no readback latency on the CPU.
+    ///
+    /// # Errors
+    /// If there are too many new particles to count.
+    #[inline]
+    pub fn tick(&mut self) -> Result<(), WrachError> {
+        self.state.run_emitters();
+        // There's no way for a CPU frame to not run, so the previous batch of new particles is
+        // always part of the simulation by now.
+        self.state.stage_new_particles(true)?;
+        self.state.apply_particle_edits(true)?;
+        self.apply_uploads();
+
+        let settings: wrach_cpu_gpu_shared::WorldSettings = self.state.shader_settings.into();
+        let particles_count = self.particles_count();
+        let cells_count = self.cells_count();
+        self.state.packed_data.indices.resize(cells_count, 0);
+        let data = &self.state.packed_data;
+        let mut particles_in = interleave(
+            &data.positions,
+            &data.velocities,
+            &data.ages,
+            &data.temperatures,
+            &data.materials,
+        );
+        particles_in.resize(particles_count, PackedParticle::default());
+        // The physics expires whatever's between the particles that it integrates and the upper
+        // bound in the settings, see `expire_leftover_particles()`.
+        let integrated_count = usize::try_from(settings.particles_in_frame_count)
+            .unwrap_or(usize::MAX)
+            .max(particles_count);
+        self.particles_out
+            .resize(integrated_count, PackedParticle::default());
+        self.cell_counts.resize(cells_count, 0);
+
+        wrach_physics_shaders::physics_on_cpu(
+            &settings,
+            &self.state.packed_data.indices,
+            &particles_in,
+            &mut self.particles_out,
+            &mut self.cell_counts,
+            &self.particles_new,
+            &self.heat_sources,
+            &self.reactions,
+            &self.material_properties,
+            &sinks_buffer(&self.sinks),
+        );
+
+        self.prefix_sum();
+        self.pack(&settings);
+
+        self.state.gpu_frame = self.state.gpu_frame.saturating_add(1);
+        self.state.packed_data_frame = self.state.gpu_frame;
+        let total = self.state.packed_data.indices.last().copied().unwrap_or(0);
+        self.state.culling.observe(self.state.gpu_frame, total);
+        Ok(())
+    }
+
+    /// Do what the GPU would do with the queued uploads. Packed data isn't uploaded here, it's only
+    /// ever used to seed the simulation and so is copied straight into place.
+    fn apply_uploads(&mut self) {
+        for upload in core::mem::take(&mut self.state.gpu_uploads) {
+            match upload {
+                GPUUpload::PackedData(data) => self.state.packed_data = data,
+                GPUUpload::NewParticles(data) => {
+                    self.particles_new = interleave(
+                        &data.positions,
+                        &data.velocities,
+                        &data.ages,
+                        &data.temperatures,
+                        &data.materials,
+                    );
+                }
+                GPUUpload::Settings(settings) => self.state.shader_settings = settings,
+                GPUUpload::HeatSources(sources) => self.heat_sources = sources,
+                GPUUpload::Sinks(sinks) => self.sinks = sinks,
+                GPUUpload::Reactions(reactions) => {
+                    self.reactions = reactions.into_iter().map(Into::into).collect();
+                }
+                GPUUpload::MaterialProperties(properties) => {
+                    self.material_properties = properties.into_iter().map(Into::into).collect();
+                }
+            }
+        }
+    }
+
+    /// The number of particles that take part in the current frame, not including new ones. It's
+    /// however many the last frame packed, because the settings only have an upper bound, see
+    /// `Culling`.
+    const fn particles_count(&self) -> usize {
+        self.state.packed_data.positions.len()
+    }
+
+    /// The number of items in the indices, the spatial bin cells and the guard item.
+    #[expect(
+        clippy::expect_used,
+        reason = "`u32` always fits into `usize` on the platforms that we support"
+    )]
+    fn cells_count(&self) -> usize {
+        self.state
+            .required_cells_capacity()
+            .try_into()
+            .expect("Couldn't convert cell count to usize")
+    }
+
+    /// An exclusive prefix sum of the cell counts into the indices, leaving the total in the guard
+    /// item. See `prefix_sum.wgsl`.
+    fn prefix_sum(&mut self) {
+        let mut total = 0_u32;
+        for (index, count) in self
+            .state
+            .packed_data
+            .indices
+            .iter_mut()
+            .zip(&self.cell_counts)
+        {
+            *index = total;
+            total = total.saturating_add(*count);
+        }
+    }
+
+    /// Move the integrated and the new particles into their cells, leaving out the particles that
+    /// have expired or are outside the view. See `pack_new_particle_data.wgsl`.
+    fn pack(&mut self, settings: &wrach_cpu_gpu_shared::WorldSettings) {
+        let new_particles_count: usize = self
+            .state
+            .shader_settings
+            .new_particles_count
+            .try_into()
+            .unwrap_or(usize::MAX);
+        let integrated = self
+            .particles_out
+            .iter()
+            .filter(|particle| !Expiries::is_expired(particle.age));
+        let particles = integrated
+            .chain(self.particles_new.iter().take(new_particles_count))
+            .filter(|particle| wrach_physics_shaders::is_in_view(particle.position, settings));
+
+        let total = self.state.packed_data.indices.last().copied().unwrap_or(0);
+        let total_usize: usize = total.try_into().unwrap_or(usize::MAX);
+        let mut packed = vec![PackedParticle::default(); total_usize];
+
+        for particle in particles {
+            let cell = wrach_physics_shaders::cell_index(particle.position, settings);
+            let (Some(start), Some(count)) = (
+                self.state.packed_data.indices.get(cell),
+                self.cell_counts.get_mut(cell),
+            ) else {
+                continue;
+            };
+            // The prefix sum leaves each cell pointing to the start of its particles, and counting
+            // the cell's particles back down gives each one its own place after that.
+            *count = count.saturating_sub(1);
+            let destination: usize = start
+                .saturating_add(*count)
+                .try_into()
+                .unwrap_or(usize::MAX);
+            if let Some(particle_in) = packed.get_mut(destination) {
+                *particle_in = *particle;
+            }
+        }
+        // See `clear_cell_counts()`
+        self.cell_counts.fill(0);
+
+        if self.state.config.deterministic {
+            sort_cells(&self.state.packed_data.indices, &mut packed);
+        }
+
+        let data = &mut self.state.packed_data;
+        data.positions = packed.iter().map(|particle| particle.position).collect();
+        data.velocities = packed.iter().map(|particle| particle.velocity).collect();
+        data.ages = packed.iter().map(|particle| particle.age).collect();
+        data.temperatures = packed.iter().map(|particle| particle.temperature).collect();
+        data.materials = packed.iter().map(|particle| particle.material).collect();
+    }
+}
+
+/// Interleave separate lists of particle data, the way that the GPU keeps it.
+fn interleave(
+    positions: &[Vec2],
+    velocities: &[Vec2],
+    ages: &[UVec2],
+    temperatures: &[f32],
+    materials: &[u32],
+) -> Vec<PackedParticle> {
+    ShaderPackedParticle::interleave(positions, velocities, ages, temperatures, materials)
+        .into_iter()
+        .map(Into::into)
+        .collect()
+}
+
+/// Sort the particles within each cell, so that they're in the same order as on the GPU. See
+/// `sort_cells.wgsl`.
+fn sort_cells(indices: &[u32], particles: &mut [PackedParticle]) {
+    for bounds in indices.windows(2) {
+        let (Some(start_index), Some(end_index)) = (bounds.first(), bounds.get(1)) else {
+            continue;
+        };
+        let start: usize = (*start_index).try_into().unwrap_or(usize::MAX);
+        let end: usize = (*end_index).try_into().unwrap_or(usize::MAX);
+        let Some(cell) = particles.get_mut(start..end) else {
+            continue;
+        };
+
+        cell.sort_by(|particle, other| {
+            particle
+                .position
+                .x
+                .total_cmp(&other.position.x)
+                .then(particle.position.y.total_cmp(&other.position.y))
+                .then(particle.velocity.x.total_cmp(&other.velocity.x))
+                .then(particle.velocity.y.total_cmp(&other.velocity.y))
+                .then(particle.age.x.cmp(&other.age.x))
+                .then(particle.age.y.cmp(&other.age.y))
+                .then(particle.temperature.total_cmp(&other.temperature))
+                .then(particle.material.cmp(&other.material))
+        });
+    }
+}
+
+#[cfg(test)]
+#[expect(
+    clippy::indexing_slicing,
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests aren't so strict"
+)]
+mod test {
+    use bevy::math::Vec4;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use super::*;
+    use crate::{particle_store::ParticleStore, tests::utils::simulation_with};
+
+    /// Run a deterministic simulation of randomly placed particles and hash the result.
+    fn checksum_of_seeded_run(seed: u64) -> u64 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut simulation = simulation_with(WrachConfig {
+            deterministic: true,
+            ..Default::default()
+        });
+
+        let particles = core::iter::repeat_with(|| Particle {
+            position: Vec2::new(rng.gen_range(0.0..30.0), rng.gen_range(0.0..30.0)),
+            velocity: Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)),
+        })
+        .take(200)
+        .collect();
+        simulation.add_particles(particles);
+        for _ in 0..20 {
+            simulation.tick().unwrap();
+        }
+
+        simulation.state.packed_data.checksum()
+    }
+
+    #[test]
+    fn packs_particles_like_the_cpu_store() {
+        let dimensions = (10, 10);
+        let cell_size = 3;
+        let mut simulation = CpuSimulation::new(WrachConfig {
+            dimensions,
+            cell_size,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut store = ParticleStore::new(
+            cell_size,
+            1.0,
+            Vec4::new(0.0, 0.0, dimensions.0.into(), dimensions.1.into()),
+        );
+
+        let particles = vec![
+            Particle {
+                position: Vec2::new(8.5, 8.5),
+                velocity: Vec2::ZERO,
+            },
+            Particle {
+                position: Vec2::new(0.5, 0.5),
+                velocity: Vec2::ZERO,
+            },
+            Particle {
+                position: Vec2::new(5.0, 5.0),
+                velocity: Vec2::ZERO,
+            },
+        ];
+        simulation.add_particles(particles.clone());
+        for particle in particles {
+            store.add_particle(particle);
+        }
+        simulation.tick().unwrap();
+
+        let expected = store.create_packed_data().unwrap();
+        assert_eq!(simulation.state.packed_data.indices, expected.indices);
+        assert_eq!(simulation.state.packed_data.positions, expected.positions);
+    }
+
+    #[test]
+    fn moving_the_view_swaps_particles_with_the_store() {
+        let mut simulation = simulation_with(WrachConfig::default());
+        simulation.add_particles(vec![
+            Particle {
+                position: Vec2::new(5.0, 5.0),
+                velocity: Vec2::ZERO,
+            },
+            Particle {
+                position: Vec2::new(25.0, 5.0),
+                velocity: Vec2::ZERO,
+            },
+            Particle {
+                position: Vec2::new(45.0, 5.0),
+                velocity: Vec2::ZERO,
+            },
+        ]);
+        simulation.tick().unwrap();
+        assert_eq!(simulation.state.packed_data.positions.len(), 2);
+
+        let grid = simulation.state.shader_settings.grid_dimensions;
+        simulation.set_viewport_anchor(Vec2::new(20.0, 0.0));
+        simulation.tick().unwrap();
+        simulation.tick().unwrap();
+
+        let state = &simulation.state;
+        assert_eq!(state.shader_settings.grid_dimensions, grid);
+        let mut simulated: Vec<f32> = state
+            .packed_data
+            .positions
+            .iter()
+            .map(|position| position.x)
+            .collect();
+        simulated.sort_by(f32::total_cmp);
+        assert_eq!(simulated, vec![25.0, 45.0]);
+        let stored: Vec<Vec2> = state
+            .particle_store
+            .hashmap
+            .values()
+            .flat_map(|data| data.positions.clone())
+            .collect();
+        assert_eq!(stored.len(), 1);
+        assert!(
+            (stored[0].x - 5.0).abs() < f32::EPSILON,
+            "It's handed back rather than culled"
+        );
+    }
+
+    #[test]
+    fn keeps_particles_across_frames() {
+        let mut simulation = CpuSimulation::new(WrachConfig {
+            dimensions: (10, 10),
+            cell_size: 3,
+            ..Default::default()
+        })
+        .unwrap();
+
+        simulation.add_particles(vec![Particle {
+            position: Vec2::new(0.5, 0.5),
+            velocity: Vec2::ZERO,
+        }]);
+        for _ in 0..3 {
+            simulation.tick().unwrap();
+        }
+        simulation.add_particles(vec![Particle {
+            position: Vec2::new(8.5, 8.5),
+            velocity: Vec2::ZERO,
+        }]);
+        for _ in 0..3 {
+            simulation.tick().unwrap();
+        }
+
+        let data = &simulation.state.packed_data;
+        assert_eq!(
+            data.positions.len(),
+            2,
+            "Both particles should be simulated"
+        );
+        assert_eq!(
+            data.indices.last(),
+            Some(&2),
+            "Guard item should be the total"
+        );
+        assert_eq!(
+            simulation.state.packed_data_frame, 6,
+            "Every tick is a frame"
+        );
+        assert!(
+            data.positions[0].x < data.positions[1].x,
+            "Particles should be ordered by cell"
+        );
+    }
+
+    #[test]
+    fn same_seed_gives_identical_results() {
+        assert_eq!(checksum_of_seeded_run(42), checksum_of_seeded_run(42));
+        assert_ne!(
+            checksum_of_seeded_run(42),
+            checksum_of_seeded_run(43),
+            "Different particles should give different results"
+        );
+    }
+
+    #[test]
+    fn particles_in_a_cell_are_sorted_whatever_order_they_are_added_in() {
+        let first = Particle {
+            position: Vec2::new(0.1, 0.1),
+            velocity: Vec2::ZERO,
+        };
+        let second = Particle {
+            position: Vec2::new(2.5, 2.5),
+            velocity: Vec2::ZERO,
+        };
+
+        let mut checksums = Vec::new();
+        for particles in [vec![first, second], vec![second, first]] {
+            let mut simulation = CpuSimulation::new(WrachConfig {
+                dimensions: (10, 10),
+                cell_size: 3,
+                deterministic: true,
+                ..Default::default()
+            })
+            .unwrap();
+            simulation.add_particles(particles);
+            simulation.tick().unwrap();
+            simulation.tick().unwrap();
+            checksums.push(simulation.state.packed_data.checksum());
+        }
+
+        assert_eq!(checksums[0], checksums[1]);
+    }
+}
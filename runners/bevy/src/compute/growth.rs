@@ -0,0 +1,104 @@
+//! Grow the GPU buffers when the simulation no longer fits in them.
+//!
+//! `bevy_easy_compute` fixes the size of its buffers when a worker is built, so to grow we rebuild
+//! the worker with bigger buffers. See [`PhysicsComputeWorker::rebuild`].
+
+use bevy::prelude::*;
+
+use crate::{compute::PhysicsComputeWorker, error::WrachError, WrachState};
+
+impl PhysicsComputeWorker {
+    /// Calculate a new capacity that fits `required`. Capacities double so that a steady trickle
+    /// of new particles doesn't cause a rebuild every frame.
+    pub const fn grown_capacity(current: u32, required: u32) -> u32 {
+        let mut capacity = if current == 0 { 1 } else { current };
+        while capacity < required {
+            capacity = capacity.saturating_mul(2);
+        }
+        capacity
+    }
+}
+
+/// Replace the compute worker with one that has bigger buffers, but only if the simulation has
+/// outgrown the current ones.
+pub fn maybe_grow_gpu_buffers(world: &mut World) -> Result<(), WrachError> {
+    let (required_particles, required_cells) = {
+        let state = world.resource::<WrachState>();
+        let required_particles = state.required_particles_capacity();
+        let required_cells = state.required_cells_capacity();
+        if required_particles <= state.particles_capacity && required_cells <= state.cells_capacity
+        {
+            return Ok(());
+        }
+        (required_particles, required_cells)
+    };
+
+    let mut state = world.resource_mut::<WrachState>();
+    state.particles_capacity =
+        PhysicsComputeWorker::grown_capacity(state.particles_capacity, required_particles);
+    state.cells_capacity =
+        PhysicsComputeWorker::grown_capacity(state.cells_capacity, required_cells);
+    info!(
+        "Growing GPU buffers to {} particles and {} cells",
+        state.particles_capacity, state.cells_capacity
+    );
+
+    PhysicsComputeWorker::rebuild(world)
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use crate::compute::PhysicsComputeWorker;
+    use crate::tests::utils::WrachTestAPI;
+    use crate::Particle;
+    use crate::WrachConfig;
+
+    #[test]
+    fn growing_capacity_doubles_until_it_fits() {
+        assert_eq!(PhysicsComputeWorker::grown_capacity(100, 101), 200);
+        assert_eq!(PhysicsComputeWorker::grown_capacity(100, 401), 800);
+        assert_eq!(PhysicsComputeWorker::grown_capacity(0, 3), 4);
+    }
+
+    #[test]
+    fn growing_capacity_keeps_capacity_that_already_fits() {
+        assert_eq!(PhysicsComputeWorker::grown_capacity(100, 100), 100);
+    }
+
+    #[test]
+    fn adding_more_particles_than_fit_grows_the_buffers() {
+        let mut wrach = WrachTestAPI::new(WrachConfig {
+            dimensions: (10, 10),
+            cell_size: 3,
+            ..Default::default()
+        });
+        let initial_capacity = wrach.get_simulation_state().particles_capacity;
+
+        let mut particles = Vec::new();
+        for y in 0_u8..15 {
+            for x in 0_u8..15 {
+                particles.push(Particle {
+                    position: Vec2::new(f32::from(x) * 0.6, f32::from(y) * 0.6),
+                    velocity: Vec2::new(0.0, 0.0),
+                });
+            }
+        }
+        wrach.add_particles(particles);
+
+        for _ in 0..10 {
+            wrach.tick();
+        }
+
+        let state = wrach.get_simulation_state();
+        assert_eq!(initial_capacity, 144);
+        assert!(state.particles_capacity >= 225);
+        assert_eq!(state.shader_settings.particles_in_frame_count, 225);
+        assert_eq!(state.packed_data.indices.last(), Some(&225));
+    }
+}
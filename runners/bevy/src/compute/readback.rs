@@ -0,0 +1,399 @@
+//! Copy the simulation's data back from the GPU without stalling the CPU.
+//!
+//! After every frame of the simulation, the buffers opted into with `WrachConfig::readback` are
+//! copied into one slot of a ring of mappable buffers, which are then mapped asynchronously. The
+//! CPU reads whichever slot is `WrachConfig::readback_latency` frames old. So with a latency of 1
+//! the CPU is consuming frame N-1 whilst the GPU computes frame N.
+//!
+//! If a slot isn't mapped by the time it's needed, its frame is read later instead, which only
+//! happens on the web, where the CPU can't wait for the GPU. If any of a slot's buffers fail to map
+//! then its whole frame is skipped, so that the CPU never mixes data from different frames.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Maintain, MapMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use bevy_easy_compute::prelude::*;
+
+use crate::{
+    compute::{buffers::Buffers, PhysicsComputeWorker},
+    config_shader::ShaderPackedParticle,
+    spatial_bin::PackedData,
+    ReadbackBuffers, WrachState,
+};
+
+/// The buffers that can be read back, in the order they're kept in each slot. All of a particle's
+/// data is in the one buffer, so it's copied if any of it is wanted, but only the wanted parts are
+/// unpacked into `PackedData`.
+const READABLE_BUFFERS: [&str; 2] = [Buffers::INDICES_MAIN, Buffers::PARTICLES_IN];
+
+/// The copies of a single frame's data.
+#[derive(Default)]
+struct ReadbackSlot {
+    /// A mappable copy of each of the [`READABLE_BUFFERS`] that has been opted into
+    buffers: [Option<Buffer>; 2],
+    /// The frame whose data is in this slot, if it hasn't been consumed yet
+    frame: Option<u64>,
+    /// The number of buffers that are still waiting to be mapped
+    pending_maps: Arc<AtomicUsize>,
+    /// Whether each of the buffers was mapped successfully, in the same order as `buffers`
+    successful_maps: Arc<[AtomicBool; 2]>,
+}
+
+impl ReadbackSlot {
+    /// Whether all the buffers in the slot have finished mapping, successfully or not.
+    fn is_mapped(&self) -> bool {
+        self.pending_maps.load(Ordering::Acquire) == 0
+    }
+
+    /// Whether every buffer in the slot was mapped successfully.
+    fn is_readable(&self) -> bool {
+        self.buffers
+            .iter()
+            .zip(self.successful_maps.iter())
+            .all(|(buffer, is_successful)| {
+                buffer.is_none() || is_successful.load(Ordering::Acquire)
+            })
+    }
+
+    /// Unmap the buffers that were mapped, without reading them.
+    fn unmap(&self) {
+        for (buffer, is_successful) in self.buffers.iter().zip(self.successful_maps.iter()) {
+            if let Some(mapped_buffer) = buffer.as_ref() {
+                if is_successful.swap(false, Ordering::AcqRel) {
+                    mapped_buffer.unmap();
+                }
+            }
+        }
+    }
+
+    /// Copy the latest data from the GPU into this slot and start mapping it.
+    fn fill(
+        &mut self,
+        frame: u64,
+        wanted: ReadbackBuffers,
+        compute_worker: &AppComputeWorker<PhysicsComputeWorker>,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) {
+        let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("wrach_readback"),
+        });
+
+        let is_any_particle_data_wanted = wanted.positions
+            || wanted.velocities
+            || wanted.ages
+            || wanted.temperatures
+            || wanted.materials;
+        let wanted_per_buffer = [wanted.indices, is_any_particle_data_wanted];
+        for ((name, destination), is_wanted) in READABLE_BUFFERS
+            .iter()
+            .zip(&mut self.buffers)
+            .zip(wanted_per_buffer)
+        {
+            let Some(source) = compute_worker.get_buffer(name).filter(|_| is_wanted) else {
+                *destination = None;
+                continue;
+            };
+
+            // The worker's buffers are replaced when they grow, so the copies need to keep up.
+            let is_right_size = destination
+                .as_ref()
+                .is_some_and(|buffer| buffer.size() == source.size());
+            if !is_right_size {
+                *destination = Some(render_device.create_buffer(&BufferDescriptor {
+                    label: Some(name),
+                    size: source.size(),
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }));
+            }
+
+            if let Some(buffer) = destination.as_ref() {
+                encoder.copy_buffer_to_buffer(source, 0, buffer, 0, source.size());
+            }
+        }
+
+        render_queue.submit([encoder.finish()]);
+
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            let Some(buffer_to_map) = buffer.as_ref() else {
+                continue;
+            };
+            self.pending_maps.fetch_add(1, Ordering::AcqRel);
+            let pending_maps = Arc::clone(&self.pending_maps);
+            let successful_maps = Arc::clone(&self.successful_maps);
+            render_device.map_buffer(&buffer_to_map.slice(..), MapMode::Read, move |result| {
+                match result {
+                    Ok(()) => {
+                        if let Some(is_successful) = successful_maps.get(index) {
+                            is_successful.store(true, Ordering::Release);
+                        }
+                    }
+                    Err(error) => error!("Couldn't map readback buffer: {error}"),
+                }
+                pending_maps.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+
+        self.frame = Some(frame);
+    }
+
+    /// Copy the mapped data into the simulation's state and free the slot up for reuse. The slot
+    /// keeps its frame if it isn't mapped yet, so that it can be consumed later.
+    fn consume(&mut self, render_device: &RenderDevice, state: &mut WrachState) {
+        let Some(frame) = self.frame else {
+            return;
+        };
+
+        // Normally the data is already waiting for us, unless there's no latency.
+        render_device.poll(Maintain::Poll);
+        if !self.is_mapped() {
+            // Waiting doesn't do anything on the web, where the browser maps buffers in its own
+            // time.
+            render_device.poll(Maintain::Wait);
+            if !self.is_mapped() {
+                return;
+            }
+        }
+        self.frame = None;
+
+        if !self.is_readable() {
+            warn!("Skipping the readback of frame {frame}, some of its buffers couldn't be mapped");
+            self.unmap();
+            return;
+        }
+
+        let [indices, particles] = self.buffers.each_ref();
+        if let Some(buffer) = indices.as_ref() {
+            read_mapped(buffer, &mut state.packed_data.indices);
+            let grid = state.particle_store.spatial_bin.grid_dimensions;
+            if let Some(count) = state.packed_data.particles_count(grid) {
+                state.culling.observe(frame, count);
+            }
+        }
+        if let Some(buffer) = particles.as_ref() {
+            {
+                let view = buffer.slice(..).get_mapped_range();
+                unpack_particles(
+                    bytemuck::cast_slice(&view),
+                    state.config.readback,
+                    &mut state.packed_data,
+                );
+            };
+            buffer.unmap();
+        }
+        for is_successful in self.successful_maps.iter() {
+            is_successful.store(false, Ordering::Release);
+        }
+
+        state.packed_data_frame = frame;
+    }
+}
+
+/// Copy the contents of a mapped buffer, reusing the destination's allocation, then unmap it.
+fn read_mapped<T: bytemuck::Pod>(buffer: &Buffer, destination: &mut Vec<T>) {
+    {
+        let view = buffer.slice(..).get_mapped_range();
+        destination.clear();
+        destination.extend_from_slice(bytemuck::cast_slice(&view));
+    };
+    buffer.unmap();
+}
+
+/// Copy the wanted parts of the particles read back from the GPU into their own lists, reusing
+/// their allocations.
+fn unpack_particles(
+    particles: &[ShaderPackedParticle],
+    wanted: ReadbackBuffers,
+    packed_data: &mut PackedData,
+) {
+    /// Replace the contents of a list with a field of every particle.
+    fn unpack<T>(
+        destination: &mut Vec<T>,
+        particles: &[ShaderPackedParticle],
+        field: impl Fn(&ShaderPackedParticle) -> T,
+    ) {
+        destination.clear();
+        destination.extend(particles.iter().map(field));
+    }
+
+    if wanted.positions {
+        unpack(&mut packed_data.positions, particles, |particle| {
+            particle.position
+        });
+    }
+    if wanted.velocities {
+        unpack(&mut packed_data.velocities, particles, |particle| {
+            particle.velocity
+        });
+    }
+    if wanted.ages {
+        unpack(&mut packed_data.ages, particles, |particle| particle.age);
+    }
+    if wanted.temperatures {
+        unpack(&mut packed_data.temperatures, particles, |particle| {
+            particle.temperature
+        });
+    }
+    if wanted.materials {
+        unpack(&mut packed_data.materials, particles, |particle| {
+            particle.material
+        });
+    }
+}
+
+/// The ring of buffers that the GPU's data is copied back into.
+#[derive(Resource, Default)]
+pub struct ReadbackRing {
+    /// One slot for every frame that can be in flight
+    slots: Vec<ReadbackSlot>,
+}
+
+impl ReadbackRing {
+    /// The slot that a frame's data lives in.
+    fn slot_index(frame: u64, slots: usize) -> usize {
+        let slots_u64 = u64::try_from(slots).unwrap_or(u64::MAX);
+        frame
+            .checked_rem(slots_u64)
+            .and_then(|index| usize::try_from(index).ok())
+            .unwrap_or(0)
+    }
+
+    /// Make sure there's a slot for every frame that can be in flight.
+    fn resize(&mut self, latency: u32, render_device: &RenderDevice) {
+        let slots = usize::try_from(latency)
+            .unwrap_or(usize::MAX)
+            .saturating_add(1);
+        if self.slots.len() == slots {
+            return;
+        }
+
+        // Any data that's still in flight has to be finished with before its buffers are dropped.
+        render_device.poll(Maintain::Wait);
+        for slot in &mut self.slots {
+            if slot.frame.take().is_some() {
+                slot.unmap();
+            }
+        }
+        self.slots.clear();
+        self.slots.resize_with(slots, ReadbackSlot::default);
+    }
+}
+
+/// Copy the most recent frame back from the GPU, and hand the CPU whichever frame is as old as
+/// `WrachConfig::readback_latency` allows.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "We have no choice because of the magic Bevy systems function signature"
+)]
+pub fn readback_from_gpu(
+    compute_worker: Res<AppComputeWorker<PhysicsComputeWorker>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut ring: ResMut<ReadbackRing>,
+    mut state: ResMut<WrachState>,
+) {
+    if !compute_worker.ready() {
+        return;
+    }
+
+    state.gpu_frame = state.gpu_frame.saturating_add(1);
+    let frame = state.gpu_frame;
+    let wanted = state.config.readback;
+    let latency = state.config.readback_latency;
+    if wanted.is_none() {
+        return;
+    }
+
+    ring.resize(latency, &render_device);
+    let slots = ring.slots.len();
+
+    if let Some(slot) = ring.slots.get_mut(ReadbackRing::slot_index(frame, slots)) {
+        // Only happens if this slot's data was never consumed, eg: it wasn't mapped in time.
+        if slot.frame.is_some() {
+            slot.consume(&render_device, &mut state);
+        }
+        // Its buffers can't be copied into until they're unmapped, so this frame isn't read back.
+        if slot.frame.is_none() {
+            slot.fill(
+                frame,
+                wanted,
+                &compute_worker,
+                &render_device,
+                &render_queue,
+            );
+        }
+    }
+
+    let Some(consumable_frame) = frame.checked_sub(latency.into()) else {
+        return;
+    };
+    if let Some(slot) = ring
+        .slots
+        .get_mut(ReadbackRing::slot_index(consumable_frame, slots))
+    {
+        if slot.frame == Some(consumable_frame) {
+            slot.consume(&render_device, &mut state);
+        }
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use crate::tests::utils::WrachTestAPI;
+    use crate::{Particle, ReadbackBuffers, WrachConfig};
+
+    use super::ReadbackRing;
+
+    #[test]
+    fn frames_take_turns_using_the_slots() {
+        assert_eq!(ReadbackRing::slot_index(1, 2), 1);
+        assert_eq!(ReadbackRing::slot_index(2, 2), 0);
+        assert_eq!(ReadbackRing::slot_index(3, 2), 1);
+        assert_eq!(ReadbackRing::slot_index(7, 1), 0);
+    }
+
+    #[test]
+    fn read_back_data_trails_the_gpu_by_the_latency() {
+        let mut wrach = WrachTestAPI::new(WrachConfig {
+            dimensions: (10, 10),
+            cell_size: 3,
+            readback: ReadbackBuffers::POSITIONS,
+            readback_latency: 2,
+            ..Default::default()
+        });
+        wrach.add_particles(vec![Particle {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(0.0, 0.0),
+        }]);
+
+        for _ in 0..10 {
+            wrach.tick();
+        }
+
+        let state = wrach.get_simulation_state();
+        assert!(state.gpu_frame > 2);
+        assert_eq!(state.packed_data_frame, state.gpu_frame - 2);
+        assert!(state.packed_data.positions.contains(&Vec2::new(5.0, 5.0)));
+        assert!(state.packed_data.velocities.is_empty());
+        assert!(state.packed_data.indices.is_empty());
+        assert!(state.packed_data.ages.is_empty());
+        assert!(state.packed_data.temperatures.is_empty());
+        assert!(state.packed_data.materials.is_empty());
+    }
+}
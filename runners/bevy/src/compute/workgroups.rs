@@ -0,0 +1,183 @@
+//! Choosing the number of threads per workgroup for the compute shaders.
+//!
+//! Workgroup sizes have to be known when a shader is compiled, so every particle-related shader has
+//! an entrypoint for each of the sizes in [`WORKGROUP_SIZES`], eg: `main_64`. The Rust type for
+//! each shader takes the size as a const generic so that it can name the right entrypoint. The size
+//! is chosen once at startup, either directly from config or by benchmarking each of the sizes for
+//! a few frames on the current GPU.
+//!
+//! Benchmarking uses the GPU's own timestamps when `WrachDiagnosticsPlugin` can measure them, see
+//! `WrachState::gpu_compute_time`. Otherwise it falls back to the time between Bevy frames, which
+//! is only a rough heuristic: it includes the CPU's work and is capped by vsync, so it can't tell
+//! sizes apart once the GPU is faster than the display.
+
+use core::time::Duration;
+
+use bevy::prelude::*;
+use bevy_easy_compute::prelude::*;
+use wrach_cpu_gpu_shared::WORKGROUP_SIZES;
+
+use crate::{compute::PhysicsComputeWorker, error::WrachError, WrachState};
+
+/// Add a pass whose shader type is generic over its workgroup size, using the workgroup size known
+/// at runtime. Unsupported sizes are caught when the worker is built, so they fall through to the
+/// biggest size here. Only available to the modules declared after this one.
+macro_rules! add_sized_pass {
+    ($builder:ident, $shader:ident, $workgroup_size:expr, $items:expr, $buffers:expr) => {
+        match $workgroup_size {
+            32 => $builder.add_pass::<$shader<32>>($shader::<32>::workgroups($items), $buffers),
+            64 => $builder.add_pass::<$shader<64>>($shader::<64>::workgroups($items), $buffers),
+            128 => $builder.add_pass::<$shader<128>>($shader::<128>::workgroups($items), $buffers),
+            _ => $builder.add_pass::<$shader<256>>($shader::<256>::workgroups($items), $buffers),
+        }
+    };
+}
+
+/// The name of a shader's entrypoint for a particular workgroup size.
+pub const fn entry_point(workgroup_size: u32) -> &'static str {
+    match workgroup_size {
+        32 => "main_32",
+        64 => "main_64",
+        128 => "main_128",
+        _ => "main_256",
+    }
+}
+
+/// Whether the shaders have an entrypoint for the workgroup size.
+pub fn is_supported(workgroup_size: u32) -> bool {
+    WORKGROUP_SIZES.contains(&workgroup_size)
+}
+
+/// Progress of benchmarking each of the workgroup sizes. Only exists whilst tuning.
+#[derive(Resource, Default)]
+pub struct WorkgroupSizeTuner {
+    /// Index into `WORKGROUP_SIZES` of the size currently being measured
+    candidate: usize,
+    /// Whether the worker has been rebuilt to use the current candidate yet
+    is_candidate_applied: bool,
+    /// Number of simulation frames run with the current candidate
+    frames: u32,
+    /// Total time of the measured frames for the current candidate, see `frame_time()`
+    elapsed: Duration,
+    /// The average frame time of each of the sizes measured so far
+    results: Vec<(u32, Duration)>,
+}
+
+impl WorkgroupSizeTuner {
+    /// Frames to ignore after switching sizes, because the first frames include the cost of
+    /// compiling pipelines.
+    const WARMUP_FRAMES: u32 = 10;
+
+    /// Frames to average over for each size.
+    const MEASURED_FRAMES: u32 = 30;
+
+    /// Advance the benchmark by a frame. Returns `true` once tuning has finished.
+    fn step(&mut self, world: &mut World) -> Result<bool, WrachError> {
+        // Only measure the real workload, so wait until all the initial particles are merged in.
+        let state = world.resource::<WrachState>();
+        if !state.new_particles.is_empty() || state.shader_settings.new_particles_count > 0 {
+            return Ok(false);
+        }
+
+        let Some(&workgroup_size) = WORKGROUP_SIZES.get(self.candidate) else {
+            self.finish(world)?;
+            return Ok(true);
+        };
+
+        if !self.is_candidate_applied {
+            world.resource_mut::<WrachState>().workgroup_size = workgroup_size;
+            PhysicsComputeWorker::rebuild(world)?;
+            self.is_candidate_applied = true;
+            self.frames = 0;
+            self.elapsed = Duration::ZERO;
+            return Ok(false);
+        }
+
+        let has_gpu_run = world
+            .get_resource::<AppComputeWorker<PhysicsComputeWorker>>()
+            .is_some_and(AppComputeWorker::ready);
+        if !has_gpu_run {
+            return Ok(false);
+        }
+
+        self.frames = self.frames.saturating_add(1);
+        if self.frames > Self::WARMUP_FRAMES {
+            self.elapsed = self.elapsed.saturating_add(Self::frame_time(world));
+        }
+
+        if self.frames == Self::WARMUP_FRAMES + Self::MEASURED_FRAMES {
+            let average = self
+                .elapsed
+                .checked_div(Self::MEASURED_FRAMES)
+                .unwrap_or_default();
+            self.results.push((workgroup_size, average));
+            self.candidate = self.candidate.saturating_add(1);
+            self.is_candidate_applied = false;
+        }
+
+        Ok(false)
+    }
+
+    /// How long the latest frame took. The GPU's timestamps only measure the compute passes, so
+    /// they're preferred. They're a frame or so behind, but that's covered by the warmup.
+    fn frame_time(world: &World) -> Duration {
+        world
+            .resource::<WrachState>()
+            .gpu_compute_time
+            .unwrap_or_else(|| world.resource::<Time<Real>>().delta())
+    }
+
+    /// Switch to the fastest of the measured sizes.
+    fn finish(&self, world: &mut World) -> Result<(), WrachError> {
+        let Some(&(fastest, frame_time)) = self.results.iter().min_by_key(|result| result.1) else {
+            return Ok(());
+        };
+
+        info!("Auto-tuned workgroup size to {fastest} ({frame_time:?} per frame)");
+        debug!("Workgroup size benchmarks: {:?}", self.results);
+
+        let mut state = world.resource_mut::<WrachState>();
+        if state.workgroup_size != fastest {
+            state.workgroup_size = fastest;
+            PhysicsComputeWorker::rebuild(world)?;
+        }
+        Ok(())
+    }
+}
+
+/// Benchmark each of the workgroup sizes in turn whilst the simulation runs, and then settle on
+/// the fastest one. Tuning is given up if anything goes wrong.
+pub fn auto_tune_workgroup_size(world: &mut World) -> Result<(), WrachError> {
+    let Some(mut tuner) = world.remove_resource::<WorkgroupSizeTuner>() else {
+        return Ok(());
+    };
+
+    let is_finished = tuner.step(world)?;
+    if !is_finished {
+        world.insert_resource(tuner);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use wrach_cpu_gpu_shared::WORKGROUP_SIZES;
+
+    use super::{entry_point, is_supported};
+
+    #[test]
+    fn every_supported_size_has_its_own_entry_point() {
+        for workgroup_size in WORKGROUP_SIZES {
+            assert_eq!(
+                entry_point(workgroup_size),
+                format!("main_{workgroup_size}")
+            );
+        }
+    }
+
+    #[test]
+    fn checking_whether_workgroup_sizes_are_supported() {
+        assert!(is_supported(64));
+        assert!(!is_supported(48));
+    }
+}
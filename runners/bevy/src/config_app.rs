@@ -1,12 +1,18 @@
 //! User-defineable config for Wrach
 
-/// All the config for the Wrach Bevy plugin
-#[derive(Clone, Copy)]
-#[expect(
-    clippy::exhaustive_structs,
-    reason = "TODO: Use `#[non_exhaustive]` and https://github.com/elastio/bon"
-)]
-// #[non_exhaustive]
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{workgroups, PhysicsComputeWorker};
+
+/// All the config for the Wrach Bevy plugin.
+///
+/// Make one with `WrachConfig::builder()`, which checks that the values make sense, or load one
+/// from a RON file, see `WrachConfigFile`. Fields missing from a file keep their default values.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
 pub struct WrachConfig {
     /// Dimensions of the realtime view onto the simulation. Doesn't necessarily imply the size of
     /// any window, that should be handled outside this plugin
@@ -15,9 +21,168 @@ pub struct WrachConfig {
     /// the viewport must move to interact with the entire simulation.
     pub boundaries_as_dimensions: bool,
     /// The size of a single cell in the spatial binning grid used to accelerate particle search.
-    ///   - The unit is multiples of the size of a particle (therefore 1).
+    ///   - The unit is multiples of the size of a particle, see `particle_radius`. So cells grow
+    ///     with the particles, and bigger particles still find their neighbours.
     ///   - Playing with this value may improve perforance on certain hardware.
     pub cell_size: u16,
+    /// The number of threads that each workgroup of the compute shaders runs. The best value
+    /// depends on the GPU.
+    pub workgroup_size: WorkgroupSize,
+    /// Which of the simulation's buffers to copy back from the GPU into `WrachState::packed_data`
+    /// after every frame. Apps that only render the simulation don't need any, and for a lot of
+    /// particles it's most of the work done on the CPU.
+    pub readback: ReadbackBuffers,
+    /// How many frames the data read back from the GPU can trail behind the GPU. At 0 the CPU
+    /// waits for each frame to be copied back. At 1 the CPU reads frame N-1 whilst the GPU is
+    /// still working on frame N, and so on. Each extra frame costs another copy of the buffers.
+    pub readback_latency: u32,
+    /// Give bit-identical results across runs on the same hardware, eg: for lockstep multiplayer
+    /// or replays. The GPU packs particles into their cells in whatever order its threads happen to
+    /// run, so this adds a pass that sorts the particles within each cell. Costs a little
+    /// performance.
+    pub deterministic: bool,
+    /// How heat moves between particles and their surroundings
+    pub thermal: ThermalConfig,
+    /// How much faster particles fall every frame, in particle sizes per frame per frame. 0 by
+    /// default, so that particles only move how they're told to.
+    pub gravity: f32,
+    /// Half the distance that particles are kept apart from each other. Particles closer than one
+    /// and a half times their size are neighbours, see `Materials`.
+    pub particle_radius: f32,
+    /// The fastest that a particle can move in a single frame. It's the particle's speed that's
+    /// limited, whichever direction it's going in.
+    pub max_speed: f32,
+}
+
+/// Which of the simulation's buffers to read back from the GPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[expect(
+    clippy::exhaustive_structs,
+    reason = "It's only ever going to be flags for each of the readable buffers"
+)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "They're independent flags, not a state machine"
+)]
+pub struct ReadbackBuffers {
+    /// The index of the first particle of every spatial bin cell
+    pub indices: bool,
+    /// Particle positions
+    pub positions: bool,
+    /// Particle velocities
+    pub velocities: bool,
+    /// Particle ages and lifetimes, see `ParticleProperties::lifetime`
+    #[serde(default)]
+    pub ages: bool,
+    /// Particle temperatures, see `ThermalConfig`
+    #[serde(default)]
+    pub temperatures: bool,
+    /// Particle materials, see `Materials`
+    #[serde(default)]
+    pub materials: bool,
+}
+
+impl ReadbackBuffers {
+    /// Read back everything.
+    pub const ALL: Self = Self {
+        indices: true,
+        positions: true,
+        velocities: true,
+        ages: true,
+        temperatures: true,
+        materials: true,
+    };
+
+    /// Don't read back anything, for when the simulation is only rendered.
+    pub const NONE: Self = Self {
+        indices: false,
+        positions: false,
+        velocities: false,
+        ages: false,
+        temperatures: false,
+        materials: false,
+    };
+
+    /// Only read back particle positions.
+    pub const POSITIONS: Self = Self {
+        indices: false,
+        positions: true,
+        velocities: false,
+        ages: false,
+        temperatures: false,
+        materials: false,
+    };
+
+    /// Whether nothing at all is read back.
+    #[inline]
+    #[must_use]
+    pub const fn is_none(self) -> bool {
+        !self.indices
+            && !self.positions
+            && !self.velocities
+            && !self.ages
+            && !self.temperatures
+            && !self.materials
+    }
+}
+
+/// How heat moves between particles and their surroundings. Temperatures don't have a unit, they
+/// only mean something relative to each other.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[expect(
+    clippy::exhaustive_structs,
+    reason = "It's only ever going to be the constants of the heat equation"
+)]
+pub struct ThermalConfig {
+    /// The temperature of the surroundings, and of new particles that aren't given one
+    pub ambient_temperature: f32,
+    /// The fraction of the difference in temperature that flows between two neighbouring
+    /// particles every frame, from 0 to 1
+    pub conductivity: f32,
+    /// The fraction of the difference from the ambient temperature that particles lose to their
+    /// surroundings every frame, from 0 to 1
+    pub cooling_rate: f32,
+}
+
+impl Default for ThermalConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            ambient_temperature: 20.0,
+            conductivity: 0.1,
+            cooling_rate: 0.001,
+        }
+    }
+}
+
+/// How to choose the number of threads per workgroup for the compute shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum WorkgroupSize {
+    /// A specific number of threads per workgroup. Must be one of
+    /// [`wrach_cpu_gpu_shared::WORKGROUP_SIZES`]. Apparently Nvidia has 32-width workgroups and
+    /// AMD has 64, so I think a multiple of 64 is best to get full occupancy?
+    Fixed(u32),
+    /// Run the simulation with each of the supported sizes for a few frames at startup, then keep
+    /// the fastest one. Add `WrachDiagnosticsPlugin` so that the GPU's timestamps are used,
+    /// otherwise frame times are only a rough guide.
+    AutoTune,
+}
+
+impl WorkgroupSize {
+    /// The size used when nothing else is known about the GPU.
+    pub const DEFAULT_THREADS: u32 = 64;
+
+    /// The number of threads to start the simulation with.
+    #[inline]
+    #[must_use]
+    pub const fn initial_threads(self) -> u32 {
+        match self {
+            Self::Fixed(threads) => threads,
+            Self::AutoTune => Self::DEFAULT_THREADS,
+        }
+    }
 }
 
 impl Default for WrachConfig {
@@ -31,6 +196,376 @@ impl Default for WrachConfig {
             boundaries_as_dimensions: false,
             // Good performance on my Asahi, Apple M1, OpenGL machine
             cell_size: wrach_cpu_gpu_shared::SPATIAL_BIN_CELL_SIZE,
+            workgroup_size: WorkgroupSize::Fixed(WorkgroupSize::DEFAULT_THREADS),
+            readback: ReadbackBuffers::ALL,
+            readback_latency: 0,
+            deterministic: false,
+            thermal: ThermalConfig::default(),
+            gravity: 0.0,
+            particle_radius: 0.5,
+            max_speed: 1.0,
+        }
+    }
+}
+
+impl WrachConfig {
+    /// Start building a config from the defaults.
+    #[inline]
+    pub fn builder() -> WrachConfigBuilder {
+        WrachConfigBuilder {
+            config: Self::default(),
+        }
+    }
+
+    /// Check that the config can actually be simulated. All the fields are public, so this is
+    /// checked again whenever a simulation is created, not just by the builder.
+    ///
+    /// # Errors
+    /// The first thing found that's wrong with the config.
+    #[inline]
+    pub fn validate(&self) -> Result<(), WrachConfigError> {
+        let (width, height) = self.dimensions;
+        if width == 0 || height == 0 {
+            return Err(WrachConfigError::ZeroDimensions);
+        }
+        if width.checked_rem(2) != Some(0) || height.checked_rem(2) != Some(0) {
+            return Err(WrachConfigError::OddDimensions { width, height });
+        }
+        if self.cell_size == 0 {
+            return Err(WrachConfigError::ZeroCellSize);
+        }
+        if !self.particle_radius.is_finite() || self.particle_radius <= 0.0 {
+            return Err(WrachConfigError::NotPositive("particle_radius"));
+        }
+
+        // The same as the spatial bin's grid, plus another row and column of cells for when the
+        // viewport is moved off the cell boundaries.
+        let cell_size = f64::from(self.cell_size) * f64::from(self.particle_radius) * 2.0_f64;
+        #[expect(
+            clippy::as_conversions,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            reason = "Float to int casts saturate, and the cells are only compared to a limit"
+        )]
+        let cells_across = |length: u16| ((f64::from(length) / cell_size) as u64).saturating_add(2);
+        let cells = cells_across(width)
+            .saturating_mul(cells_across(height))
+            .saturating_add(PhysicsComputeWorker::PREFIX_SUM_GUARD_ITEM.into());
+        let max_cells = PhysicsComputeWorker::PREFIX_SUM_MAX_TOTAL.into();
+        if cells >= max_cells {
+            return Err(WrachConfigError::TooManyCells { cells, max_cells });
+        }
+
+        if let WorkgroupSize::Fixed(threads) = self.workgroup_size {
+            if !workgroups::is_supported(threads) {
+                return Err(WrachConfigError::UnsupportedWorkgroupSize(threads));
+            }
+        }
+
+        if !self.thermal.ambient_temperature.is_finite() {
+            return Err(WrachConfigError::NotFinite("thermal.ambient_temperature"));
+        }
+        let is_fraction = |value: f32| (0.0..=1.0).contains(&value);
+        if !is_fraction(self.thermal.conductivity) {
+            return Err(WrachConfigError::NotAFraction("thermal.conductivity"));
+        }
+        if !is_fraction(self.thermal.cooling_rate) {
+            return Err(WrachConfigError::NotAFraction("thermal.cooling_rate"));
+        }
+        if !self.gravity.is_finite() {
+            return Err(WrachConfigError::NotFinite("gravity"));
+        }
+        if !self.max_speed.is_finite() || self.max_speed <= 0.0 {
+            return Err(WrachConfigError::NotPositive("max_speed"));
         }
+
+        Ok(())
+    }
+}
+
+/// Builds a `WrachConfig`, anything that isn't set keeps its value from `WrachConfig::default()`.
+#[derive(Clone, Copy, Debug)]
+#[must_use]
+pub struct WrachConfigBuilder {
+    /// The config being built
+    config: WrachConfig,
+}
+
+impl WrachConfigBuilder {
+    /// See `WrachConfig::dimensions`, they must be even and non-zero.
+    #[inline]
+    pub const fn dimensions(mut self, width: u16, height: u16) -> Self {
+        self.config.dimensions = (width, height);
+        self
+    }
+
+    /// See `WrachConfig::boundaries_as_dimensions`
+    #[inline]
+    pub const fn boundaries_as_dimensions(mut self, boundaries_as_dimensions: bool) -> Self {
+        self.config.boundaries_as_dimensions = boundaries_as_dimensions;
+        self
+    }
+
+    /// See `WrachConfig::cell_size`, it must be non-zero.
+    #[inline]
+    pub const fn cell_size(mut self, cell_size: u16) -> Self {
+        self.config.cell_size = cell_size;
+        self
+    }
+
+    /// See `WrachConfig::workgroup_size`
+    #[inline]
+    pub const fn workgroup_size(mut self, workgroup_size: WorkgroupSize) -> Self {
+        self.config.workgroup_size = workgroup_size;
+        self
+    }
+
+    /// See `WrachConfig::readback`
+    #[inline]
+    pub const fn readback(mut self, readback: ReadbackBuffers) -> Self {
+        self.config.readback = readback;
+        self
+    }
+
+    /// See `WrachConfig::readback_latency`
+    #[inline]
+    pub const fn readback_latency(mut self, readback_latency: u32) -> Self {
+        self.config.readback_latency = readback_latency;
+        self
+    }
+
+    /// See `WrachConfig::deterministic`
+    #[inline]
+    pub const fn deterministic(mut self, deterministic: bool) -> Self {
+        self.config.deterministic = deterministic;
+        self
+    }
+
+    /// See `WrachConfig::thermal`
+    #[inline]
+    pub const fn thermal(mut self, thermal: ThermalConfig) -> Self {
+        self.config.thermal = thermal;
+        self
+    }
+
+    /// See `WrachConfig::gravity`
+    #[inline]
+    pub const fn gravity(mut self, gravity: f32) -> Self {
+        self.config.gravity = gravity;
+        self
+    }
+
+    /// See `WrachConfig::particle_radius`, it must be bigger than zero.
+    #[inline]
+    pub const fn particle_radius(mut self, particle_radius: f32) -> Self {
+        self.config.particle_radius = particle_radius;
+        self
+    }
+
+    /// See `WrachConfig::max_speed`, it must be bigger than zero.
+    #[inline]
+    pub const fn max_speed(mut self, max_speed: f32) -> Self {
+        self.config.max_speed = max_speed;
+        self
+    }
+
+    /// Check the config and return it.
+    ///
+    /// # Errors
+    /// The first thing found that's wrong with the config, see `WrachConfig::validate()`.
+    #[inline]
+    pub fn build(self) -> Result<WrachConfig, WrachConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// The ways in which a `WrachConfig` can be wrong
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WrachConfigError {
+    /// The view onto the simulation has no area
+    ZeroDimensions,
+    /// The dimensions of the view have to be even numbers
+    OddDimensions {
+        /// The requested width
+        width: u16,
+        /// The requested height
+        height: u16,
+    },
+    /// Spatial bin cells can't have no size
+    ZeroCellSize,
+    /// The view is split into more cells than the prefix sum can count
+    TooManyCells {
+        /// The number of cells the view needs, including the guard item
+        cells: u64,
+        /// The prefix sum's limit
+        max_cells: u64,
+    },
+    /// The shaders don't have an entrypoint for this workgroup size
+    UnsupportedWorkgroupSize(u32),
+    /// The named field has to be a finite number
+    NotFinite(&'static str),
+    /// The named field has to be between 0 and 1
+    NotAFraction(&'static str),
+    /// The named field has to be a finite number bigger than 0
+    NotPositive(&'static str),
+    /// There are more reactions than fit into the GPU's reactions buffer, see `Materials`
+    TooManyReactions(usize),
+    /// Only materials below `MAX_MATERIALS` can have properties, see `Materials`
+    MaterialOutOfRange(u32),
+}
+
+impl fmt::Display for WrachConfigError {
+    #[inline]
+    #[expect(
+        clippy::use_debug,
+        reason = "The list of workgroup sizes is just numbers"
+    )]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::ZeroDimensions => write!(f, "Dimensions must be bigger than zero"),
+            Self::OddDimensions { width, height } => {
+                write!(f, "Dimensions must be even, got {width}x{height}")
+            }
+            Self::ZeroCellSize => write!(f, "Cell size must be bigger than zero"),
+            Self::TooManyCells { cells, max_cells } => write!(
+                f,
+                "The dimensions need {cells} spatial bin cells, but only fewer than {max_cells} \
+                 are supported. Try a bigger cell size."
+            ),
+            Self::UnsupportedWorkgroupSize(threads) => write!(
+                f,
+                "Workgroup size {threads} isn't one of the supported sizes: {:?}",
+                wrach_cpu_gpu_shared::WORKGROUP_SIZES
+            ),
+            Self::NotFinite(field) => write!(f, "`{field}` must be a finite number"),
+            Self::NotAFraction(field) => write!(f, "`{field}` must be between 0 and 1"),
+            Self::NotPositive(field) => write!(f, "`{field}` must be bigger than zero"),
+            Self::TooManyReactions(reactions) => write!(
+                f,
+                "There are {reactions} reactions, but only {} are supported",
+                wrach_cpu_gpu_shared::MAX_REACTIONS
+            ),
+            Self::MaterialOutOfRange(material) => write!(
+                f,
+                "Material {material} can't have properties, only materials below {} can",
+                wrach_cpu_gpu_shared::MAX_MATERIALS
+            ),
+        }
+    }
+}
+
+impl core::error::Error for WrachConfigError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builder_keeps_the_defaults() {
+        let config = WrachConfig::builder().build();
+        let default = WrachConfig::default();
+        assert!(config.is_ok(), "Defaults should be valid");
+        assert_eq!(config.map(|built| built.dimensions), Ok(default.dimensions));
+    }
+
+    #[test]
+    fn rejects_bad_dimensions() {
+        assert_eq!(
+            WrachConfig::builder().dimensions(0, 10).build().err(),
+            Some(WrachConfigError::ZeroDimensions)
+        );
+        assert_eq!(
+            WrachConfig::builder().dimensions(11, 10).build().err(),
+            Some(WrachConfigError::OddDimensions {
+                width: 11,
+                height: 10
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_cells() {
+        assert_eq!(
+            WrachConfig::builder().cell_size(0).build().err(),
+            Some(WrachConfigError::ZeroCellSize)
+        );
+        assert!(
+            matches!(
+                WrachConfig::builder()
+                    .dimensions(u16::MAX - 1, u16::MAX - 1)
+                    .cell_size(1)
+                    .build(),
+                Err(WrachConfigError::TooManyCells { .. })
+            ),
+            "A cell per pixel of a huge view is too many cells"
+        );
+        assert!(
+            matches!(
+                WrachConfig::builder()
+                    .dimensions(4000, 4000)
+                    .cell_size(1)
+                    .particle_radius(0.01)
+                    .build(),
+                Err(WrachConfigError::TooManyCells { .. })
+            ),
+            "Cells shrink with the particles"
+        );
+        assert!(
+            WrachConfig::builder()
+                .dimensions(4000, 4000)
+                .cell_size(1)
+                .particle_radius(5.0)
+                .build()
+                .is_ok(),
+            "Cells grow with the particles"
+        );
+    }
+
+    #[test]
+    fn rejects_particles_without_size_or_speed() {
+        assert_eq!(
+            WrachConfig::builder().particle_radius(0.0).build().err(),
+            Some(WrachConfigError::NotPositive("particle_radius"))
+        );
+        assert_eq!(
+            WrachConfig::builder()
+                .max_speed(f32::INFINITY)
+                .build()
+                .err(),
+            Some(WrachConfigError::NotPositive("max_speed"))
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_workgroup_sizes() {
+        assert_eq!(
+            WrachConfig::builder()
+                .workgroup_size(WorkgroupSize::Fixed(48))
+                .build()
+                .err(),
+            Some(WrachConfigError::UnsupportedWorkgroupSize(48))
+        );
+    }
+
+    #[test]
+    fn rejects_unstable_thermal_constants() {
+        let too_conductive = ThermalConfig {
+            conductivity: 1.5,
+            ..ThermalConfig::default()
+        };
+        assert_eq!(
+            WrachConfig::builder().thermal(too_conductive).build().err(),
+            Some(WrachConfigError::NotAFraction("thermal.conductivity"))
+        );
+        let no_ambient = ThermalConfig {
+            ambient_temperature: f32::NAN,
+            ..ThermalConfig::default()
+        };
+        assert_eq!(
+            WrachConfig::builder().thermal(no_ambient).build().err(),
+            Some(WrachConfigError::NotFinite("thermal.ambient_temperature"))
+        );
     }
 }
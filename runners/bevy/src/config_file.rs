@@ -0,0 +1,303 @@
+//! Load `WrachConfig` from a RON file through Bevy's asset server. Whenever the file changes, and
+//! Bevy's `file_watcher` feature is enabled, the new config is applied to the running simulation
+//! with `WrachState::apply_config()`.
+//!
+//! A file only needs the fields that differ from the defaults, eg: `assets/config.wrach.ron`:
+//!
+//! ```ron
+//! (
+//!     dimensions: (640, 480),
+//!     readback: (indices: false, positions: true, velocities: false),
+//! )
+//! ```
+
+use core::fmt;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+
+use crate::{error::WrachError, WrachConfig, WrachConfigError, WrachState};
+
+/// A `WrachConfig` loaded from a `.wrach.ron` file
+#[derive(Asset, TypePath, Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct WrachConfigFile {
+    /// The config in the file, already validated
+    pub config: WrachConfig,
+}
+
+impl WrachConfig {
+    /// Parse a config from RON, see [`WrachConfigFile`] for the format.
+    ///
+    /// # Errors
+    /// If the RON can't be parsed, or the config in it is invalid.
+    #[inline]
+    pub fn from_ron(ron: &[u8]) -> Result<Self, WrachConfigFileError> {
+        let config: Self = ron::de::from_bytes(ron)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// The ways in which loading a `WrachConfigFile` can fail
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WrachConfigFileError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    /// The file isn't valid RON, or doesn't match the shape of `WrachConfig`
+    Ron(ron::error::SpannedError),
+    /// The file is valid, but the config in it isn't
+    Invalid(WrachConfigError),
+}
+
+impl fmt::Display for WrachConfigFileError {
+    #[inline]
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Io(ref error) => write!(f, "Couldn't read config file: {error}"),
+            Self::Ron(ref error) => write!(f, "Couldn't parse config file: {error}"),
+            Self::Invalid(error) => write!(f, "Invalid config file: {error}"),
+        }
+    }
+}
+
+impl core::error::Error for WrachConfigFileError {
+    #[inline]
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref error) => Some(error),
+            Self::Ron(ref error) => Some(error),
+            Self::Invalid(ref error) => Some(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for WrachConfigFileError {
+    #[inline]
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for WrachConfigFileError {
+    #[inline]
+    fn from(error: ron::error::SpannedError) -> Self {
+        Self::Ron(error)
+    }
+}
+
+impl From<WrachConfigError> for WrachConfigFileError {
+    #[inline]
+    fn from(error: WrachConfigError) -> Self {
+        Self::Invalid(error)
+    }
+}
+
+/// Loads `.wrach.ron` files as [`WrachConfigFile`]s
+#[derive(Default)]
+#[non_exhaustive]
+pub struct WrachConfigFileLoader;
+
+impl AssetLoader for WrachConfigFileLoader {
+    type Asset = WrachConfigFile;
+    type Settings = ();
+    type Error = WrachConfigFileError;
+
+    #[inline]
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let config = WrachConfig::from_ron(&bytes)?;
+        Ok(WrachConfigFile { config })
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["wrach.ron"]
+    }
+}
+
+/// The config file that the simulation is kept in sync with, see `WrachPlugin::with_config_file()`
+#[derive(Resource)]
+pub struct WrachConfigFileHandle(pub Handle<WrachConfigFile>);
+
+/// Apply the config file to the simulation whenever it's loaded or changed. Files that fail to
+/// load are reported by the asset server itself.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "Bevy's system function signature can't be changed"
+)]
+pub fn apply_config_file(
+    mut events: EventReader<AssetEvent<WrachConfigFile>>,
+    files: Res<Assets<WrachConfigFile>>,
+    handle: Option<Res<WrachConfigFileHandle>>,
+    mut state: ResMut<WrachState>,
+) -> Result<(), WrachError> {
+    let Some(file_handle) = handle else {
+        return Ok(());
+    };
+
+    let mut result = Ok(());
+    for event in events.read() {
+        if !event.is_added(&file_handle.0) && !event.is_modified(&file_handle.0) {
+            continue;
+        }
+        if let Some(file) = files.get(&file_handle.0) {
+            info!("Applying config file: {:?}", file.config);
+            result = state.apply_config(file.config);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{state::GPUUpload, ReadbackBuffers, ThermalConfig, WorkgroupSize};
+
+    #[test]
+    fn missing_fields_are_defaults() {
+        let config = WrachConfig::from_ron(b"(dimensions: (640, 480))");
+        assert_eq!(
+            config.ok(),
+            Some(WrachConfig {
+                dimensions: (640, 480),
+                ..WrachConfig::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let ron = b"(
+            dimensions: (100, 50),
+            boundaries_as_dimensions: true,
+            cell_size: 5,
+            workgroup_size: AutoTune,
+            readback: (indices: false, positions: true, velocities: false),
+            readback_latency: 2,
+            deterministic: true,
+            thermal: (ambient_temperature: -10.0, conductivity: 0.5, cooling_rate: 0.0),
+            gravity: 0.1,
+            particle_radius: 1.5,
+            max_speed: 2.0,
+        )";
+        let config = WrachConfig::from_ron(ron);
+        assert_eq!(
+            config.ok(),
+            Some(WrachConfig {
+                dimensions: (100, 50),
+                boundaries_as_dimensions: true,
+                cell_size: 5,
+                workgroup_size: WorkgroupSize::AutoTune,
+                readback: ReadbackBuffers::POSITIONS,
+                readback_latency: 2,
+                deterministic: true,
+                thermal: ThermalConfig {
+                    ambient_temperature: -10.0,
+                    conductivity: 0.5,
+                    cooling_rate: 0.0,
+                },
+                gravity: 0.1,
+                particle_radius: 1.5,
+                max_speed: 2.0,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(
+            matches!(
+                WrachConfig::from_ron(b"(dimensions: 640)"),
+                Err(WrachConfigFileError::Ron(_))
+            ),
+            "Dimensions need a width and a height"
+        );
+        assert!(
+            matches!(
+                WrachConfig::from_ron(b"(cell_size: 0)"),
+                Err(WrachConfigFileError::Invalid(
+                    WrachConfigError::ZeroCellSize
+                ))
+            ),
+            "Files are validated like any other config"
+        );
+    }
+
+    #[test]
+    fn applies_changes_that_fit() {
+        let mut state = WrachState::new(WrachConfig::default());
+        state.cells_capacity = state.required_cells_capacity();
+
+        let changed = WrachConfig {
+            readback: ReadbackBuffers::NONE,
+            ..WrachConfig::default()
+        };
+        assert_eq!(state.apply_config(changed), Ok(()));
+        assert_eq!(state.config, changed);
+        assert!(
+            matches!(state.gpu_uploads.last(), Some(&GPUUpload::Settings(_))),
+            "New settings should be uploaded"
+        );
+
+        let bigger = WrachConfig {
+            dimensions: (1000, 1000),
+            ..WrachConfig::default()
+        };
+        assert_eq!(
+            state.apply_config(bigger),
+            Err(WrachError::NeedsRestart("dimensions"))
+        );
+        assert_eq!(
+            state.config, changed,
+            "Failed changes shouldn't change anything"
+        );
+    }
+
+    #[test]
+    fn rejects_smaller_dimensions() {
+        let mut state = WrachState::new(WrachConfig::default());
+        state.cells_capacity = state.required_cells_capacity();
+
+        let narrower = WrachConfig {
+            dimensions: (100, WrachConfig::default().dimensions.1),
+            ..WrachConfig::default()
+        };
+        assert_eq!(
+            state.apply_config(narrower),
+            Err(WrachError::NeedsRestart("dimensions")),
+            "Particles outside of the smaller view would be outside of the grid"
+        );
+        assert_eq!(state.config, WrachConfig::default());
+    }
+
+    #[test]
+    fn rejects_changes_that_need_a_restart() {
+        let mut state = WrachState::new(WrachConfig::default());
+        let cell_size = WrachConfig {
+            cell_size: 7,
+            ..WrachConfig::default()
+        };
+        assert_eq!(
+            state.apply_config(cell_size),
+            Err(WrachError::NeedsRestart("cell_size"))
+        );
+    }
+}
@@ -12,7 +12,7 @@ use bytemuck::{Pod, Zeroable};
 
 // TODO: Document why we can't share with `WorldSettings` in `shaders/shared/lib.rs`.
 /// Config for the shader about the simulation world
-#[derive(ShaderType, Resource, Pod, Zeroable, Clone, Copy, Default, Debug)]
+#[derive(ShaderType, Resource, Pod, Zeroable, Clone, Copy, Default, Debug, PartialEq)]
 #[repr(C)]
 pub struct ShaderWorldSettings {
     /// Dimensions of the view onto the simulation
@@ -21,9 +21,176 @@ pub struct ShaderWorldSettings {
     pub view_anchor: Vec2,
     /// The dimensions of the spatial bin grid, the unit is a cell
     pub grid_dimensions: UVec2,
-    /// The size of a spatial bin cell
-    pub cell_size: u32,
-    /// Total number of particles simulated in this frame. This will normally be much smaller than
-    /// the total number of particles that we have a record of.
+    /// The size of a spatial bin cell, in the same units as the positions of particles. That's
+    /// `WrachConfig::cell_size` particles across.
+    pub cell_size: f32,
+    /// Total number of particles simulated in this frame, or more if the GPU has culled some that
+    /// the CPU hasn't seen yet, see `Culling`. This will normally be much smaller than the total
+    /// number of particles that we have a record of.
     pub particles_in_frame_count: u32,
+    /// Number of freshly added particles waiting in `Buffers::PARTICLES_NEW` to be merged into
+    /// the frame's particle data by the GPU.
+    pub new_particles_count: u32,
+    /// The temperature that particles cool, or warm, towards when nothing else is heating them
+    pub ambient_temperature: f32,
+    /// The fraction of the difference in temperature that flows between two neighbouring particles
+    /// every frame, from 0 to 1
+    pub thermal_conductivity: f32,
+    /// The fraction of the difference from `ambient_temperature` that particles lose every frame,
+    /// from 0 to 1
+    pub cooling_rate: f32,
+    /// The number of heat sources in the heat sources buffer
+    pub heat_sources_count: u32,
+    /// The number of reactions in the reactions buffer
+    pub reactions_count: u32,
+    /// The number of sinks in the sinks buffer
+    pub sinks_count: u32,
+    /// A different number every frame, so that reactions which only happen with some probability
+    /// don't keep happening to the same particles
+    pub random_seed: u32,
+    /// How much faster particles fall every frame
+    pub gravity: f32,
+    /// Half the distance that particles are kept apart from each other
+    pub particle_radius: f32,
+    /// The fastest that a particle can move in a single frame
+    pub max_speed: f32,
+}
+
+/// A single rule of the reactions table in the format used by the GPU, see `Reaction` for the
+/// friendly version and `wrach_cpu_gpu_shared::Reaction` for what the fields mean.
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct ShaderReaction {
+    /// One of the `wrach_cpu_gpu_shared::REACTION_*` constants
+    pub kind: u32,
+    /// The material that reacts
+    pub reactant: u32,
+    /// The material that the reactant has to be touching, only for contact reactions
+    pub other_reactant: u32,
+    /// What the reactant becomes
+    pub product: u32,
+    /// What the other reactant becomes, only for contact reactions
+    pub other_product: u32,
+    /// The temperature that the reactant has to be above, or below
+    pub temperature: f32,
+    /// The chance of the reaction happening every frame that it can, from 0 to 1
+    pub probability: f32,
+}
+
+/// The properties of a single material in the format used by the GPU, see `MaterialProperties` for
+/// the friendly version.
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct ShaderMaterialProperties {
+    /// How strongly neighbouring particles are pulled together, from 0 to 1
+    pub cohesion: f32,
+    /// How much sliding a contact can resist completely, from 0 to 1
+    pub static_friction: f32,
+    /// How much sliding is slowed once a contact is sliding, from 0 to 1
+    pub dynamic_friction: f32,
+}
+
+/// A single particle in the format used by the GPU buffers, see
+/// `wrach_cpu_gpu_shared::PackedParticle` for why a particle's data is kept together. On the CPU
+/// it's kept in separate lists, see `PackedData`.
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct ShaderPackedParticle {
+    /// Where the particle is
+    pub position: Vec2,
+    /// How far the particle moves every frame
+    pub velocity: Vec2,
+    /// The particle's age and lifetime, see `ParticleProperties::lifetime`
+    pub age: UVec2,
+    /// The particle's temperature, see `ThermalConfig`
+    pub temperature: f32,
+    /// The particle's material, see `Materials`
+    pub material: u32,
+}
+
+impl ShaderPackedParticle {
+    /// Interleave separate lists of particle data into the format used by the GPU. There's a
+    /// particle for every position, anything that's missing for a particle is zeroed.
+    pub fn interleave(
+        positions: &[Vec2],
+        velocities: &[Vec2],
+        ages: &[UVec2],
+        temperatures: &[f32],
+        materials: &[u32],
+    ) -> Vec<Self> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(index, position)| Self {
+                position: *position,
+                velocity: velocities.get(index).copied().unwrap_or_default(),
+                age: ages.get(index).copied().unwrap_or_default(),
+                temperature: temperatures.get(index).copied().unwrap_or_default(),
+                material: materials.get(index).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+impl From<ShaderWorldSettings> for wrach_cpu_gpu_shared::WorldSettings {
+    #[inline]
+    fn from(settings: ShaderWorldSettings) -> Self {
+        Self {
+            view_dimensions: settings.view_dimensions,
+            view_anchor: settings.view_anchor,
+            grid_dimensions: settings.grid_dimensions,
+            cell_size: settings.cell_size,
+            particles_in_frame_count: settings.particles_in_frame_count,
+            new_particles_count: settings.new_particles_count,
+            ambient_temperature: settings.ambient_temperature,
+            thermal_conductivity: settings.thermal_conductivity,
+            cooling_rate: settings.cooling_rate,
+            heat_sources_count: settings.heat_sources_count,
+            reactions_count: settings.reactions_count,
+            sinks_count: settings.sinks_count,
+            random_seed: settings.random_seed,
+            gravity: settings.gravity,
+            particle_radius: settings.particle_radius,
+            max_speed: settings.max_speed,
+        }
+    }
+}
+
+impl From<ShaderReaction> for wrach_cpu_gpu_shared::Reaction {
+    #[inline]
+    fn from(reaction: ShaderReaction) -> Self {
+        Self {
+            kind: reaction.kind,
+            reactant: reaction.reactant,
+            other_reactant: reaction.other_reactant,
+            product: reaction.product,
+            other_product: reaction.other_product,
+            temperature: reaction.temperature,
+            probability: reaction.probability,
+        }
+    }
+}
+
+impl From<ShaderMaterialProperties> for wrach_cpu_gpu_shared::MaterialProperties {
+    #[inline]
+    fn from(properties: ShaderMaterialProperties) -> Self {
+        Self {
+            cohesion: properties.cohesion,
+            static_friction: properties.static_friction,
+            dynamic_friction: properties.dynamic_friction,
+        }
+    }
+}
+
+impl From<ShaderPackedParticle> for wrach_cpu_gpu_shared::PackedParticle {
+    #[inline]
+    fn from(particle: ShaderPackedParticle) -> Self {
+        Self {
+            position: particle.position,
+            velocity: particle.velocity,
+            age: particle.age,
+            temperature: particle.temperature,
+            material: particle.material,
+        }
+    }
 }
@@ -0,0 +1,134 @@
+//! Particles that the GPU culls without the CPU knowing exactly how many, like those that drain into
+//! a sink, see `WrachState::add_sink()`. The physics ends their lifetimes and the packing pass
+//! leaves them out just like expired particles, so nothing needs to be read back for them to leave
+//! the simulation.
+//!
+//! The CPU still needs to know roughly how many particles there are, so that it doesn't stage more
+//! new particles than the GPU buffers can hold. The count from `Expiries` can only ever be too big,
+//! because it doesn't know about culled particles. So whenever the indices are read back, the guard
+//! item gives the exact count for that frame, and adding every batch of new particles merged since
+//! then gives another count that can only be too big. The smaller of the two is used.
+//!
+//! They're kept apart, rather than correcting the count from `Expiries`, because a mortal particle
+//! that's been culled is still due to expire, and would otherwise be counted out twice. Without
+//! the indices being read back, culled particles are counted until the CPU next replaces the GPU's
+//! data, eg: when editing particles.
+
+use alloc::collections::BTreeMap;
+
+/// Keeps `ShaderWorldSettings::particles_in_frame_count` from counting culled particles forever.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Culling {
+    /// The number of particles if none had been culled, kept up to date with `Expiries`
+    unculled_count: u32,
+    /// The value of `WrachState::gpu_frame` for the latest frame whose particles were counted on the
+    /// GPU, and how many there were
+    observed: Option<(u64, u32)>,
+    /// The sizes of the batches of new particles merged into the simulation since the observed
+    /// frame, keyed by the value of `WrachState::gpu_frame` when they were counted
+    merged: BTreeMap<u64, u32>,
+}
+
+impl Culling {
+    /// The most particles that there can be in the current frame.
+    #[inline]
+    #[must_use]
+    pub fn particles_count(&self) -> u32 {
+        let Some((_, observed_count)) = self.observed else {
+            return self.unculled_count;
+        };
+        let bound = self
+            .merged
+            .values()
+            .fold(observed_count, |total, count| total.saturating_add(*count));
+        self.unculled_count.min(bound)
+    }
+
+    /// A batch of new particles has been merged into the simulation by `gpu_frame`.
+    pub(crate) fn merge_batch(&mut self, gpu_frame: u64, size: u32) {
+        self.unculled_count = self.unculled_count.saturating_add(size);
+        // The frame that merged it is never later than `gpu_frame`, so it's already in the observed
+        // count if that frame has been read back.
+        if self
+            .observed
+            .is_none_or(|(observed_frame, _)| observed_frame < gpu_frame)
+        {
+            let merged = self.merged.entry(gpu_frame).or_default();
+            *merged = merged.saturating_add(size);
+        }
+    }
+
+    /// Particles have reached the end of their lifetimes, see `Expiries::take_expired()`.
+    pub(crate) const fn expire(&mut self, count: u32) {
+        self.unculled_count = self.unculled_count.saturating_sub(count);
+    }
+
+    /// The GPU counted `count` particles at the end of the frame that took `WrachState::gpu_frame`
+    /// to `frame`, from the guard item of the indices read back for it.
+    pub(crate) fn observe(&mut self, frame: u64, count: u32) {
+        // Read back data can be older than what's already known, eg: from before the CPU replaced
+        // the GPU's data.
+        if self
+            .observed
+            .is_some_and(|(observed_frame, _)| observed_frame >= frame)
+        {
+            return;
+        }
+        self.observed = Some((frame, count));
+        // A batch is only ever counted in the same or a later frame than the one that merged it, so
+        // these are all already in the observed count.
+        self.merged = self.merged.split_off(&frame.saturating_add(1));
+    }
+
+    /// The CPU has replaced the GPU's data in `gpu_frame` with `count` particles.
+    pub(crate) fn reset(&mut self, gpu_frame: u64, count: u32) {
+        self.unculled_count = count;
+        self.observed = Some((gpu_frame, count));
+        self.merged.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Culling;
+
+    #[test]
+    fn culled_particles_stop_being_counted_once_they_are_observed() {
+        let mut culling = Culling::default();
+        culling.merge_batch(1, 10);
+        assert_eq!(culling.particles_count(), 10);
+
+        culling.observe(2, 7);
+        assert_eq!(culling.particles_count(), 7);
+        culling.merge_batch(2, 1);
+        culling.merge_batch(3, 4);
+        assert_eq!(
+            culling.particles_count(),
+            11,
+            "Only batches merged after the observed frame are added on"
+        );
+
+        culling.expire(10);
+        assert_eq!(
+            culling.particles_count(),
+            5,
+            "Expiring particles that may have been culled can't count them out twice"
+        );
+    }
+
+    #[test]
+    fn old_observations_are_ignored() {
+        let mut culling = Culling::default();
+        culling.reset(5, 20);
+        culling.observe(4, 3);
+        assert_eq!(culling.particles_count(), 20);
+        culling.observe(5, 3);
+        assert_eq!(
+            culling.particles_count(),
+            20,
+            "The frame that the data was replaced in was simulated with the old data"
+        );
+        culling.observe(6, 3);
+        assert_eq!(culling.particles_count(), 3);
+    }
+}
@@ -0,0 +1,479 @@
+//! Statistics about the simulation, both as a plain struct for headless users and as Bevy
+//! diagnostics.
+//!
+//! Most of the statistics come from the data read back from the GPU, so they're only as recent as
+//! `WrachConfig::readback_latency` allows. They also need the right `WrachConfig::readback`
+//! buffers: the cell statistics need the indices and the kinetic energy needs the velocities.
+
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic as _},
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, MapMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use bevy_easy_compute::prelude::*;
+use wrach_physics_shaders::MAX_PARTICLES_IN_CELL;
+
+use crate::{compute::PhysicsComputeWorker, WrachState};
+
+/// Statistics about the simulation, see `WrachState::stats()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct WrachStats {
+    /// Particles being simulated in the current frame, or more if the GPU has culled some that the
+    /// CPU hasn't seen yet, see `Culling`
+    pub particles_in_frame: u32,
+    /// Particles kept on the CPU in the particle store, outside of the simulated cells
+    pub stored_particles: usize,
+    /// Particles waiting to be merged into the simulation
+    pub queued_particles: usize,
+    /// Cells with at least one particle in them
+    pub active_cells: u32,
+    /// The most particles in any one cell
+    pub max_particles_per_cell: u32,
+    /// The average number of particles in the cells that have any
+    pub mean_particles_per_cell: f64,
+    /// Cells with more particles than get the full physics. Their extra particles are only
+    /// integrated, so lots of these means particles are bunching up.
+    pub overflowed_cells: u32,
+    /// The total kinetic energy of the simulated particles, as if every particle has a mass of 1
+    pub kinetic_energy: f64,
+    /// How long the GPU took to run the physics, only measured by `WrachDiagnosticsPlugin`
+    pub gpu_compute_time: Option<Duration>,
+}
+
+impl WrachState {
+    /// Statistics about the simulation, mostly from the frame in `packed_data`.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> WrachStats {
+        let mut stats = WrachStats {
+            particles_in_frame: self.shader_settings.particles_in_frame_count,
+            stored_particles: self
+                .particle_store
+                .hashmap
+                .values()
+                .map(|particles| particles.positions.len())
+                .sum(),
+            queued_particles: self.new_particles.len(),
+            gpu_compute_time: self.gpu_compute_time,
+            ..Default::default()
+        };
+
+        // The indices buffer is usually bigger than the grid, see `WrachState::cells_capacity`.
+        let grid = self.particle_store.spatial_bin.grid_dimensions;
+        let total_cells = usize::try_from(grid.x.saturating_mul(grid.y)).unwrap_or(usize::MAX);
+        let indices = &self.packed_data.indices;
+        let grid_indices = indices.get(..=total_cells).unwrap_or(indices);
+
+        let mut particles_in_active_cells = 0_u32;
+        for pair in grid_indices.windows(2) {
+            let [start, end] = *pair else {
+                continue;
+            };
+            let count = end.saturating_sub(start);
+            if count == 0 {
+                continue;
+            }
+
+            stats.active_cells = stats.active_cells.saturating_add(1);
+            stats.max_particles_per_cell = stats.max_particles_per_cell.max(count);
+            if usize::try_from(count).is_ok_and(|cell_count| cell_count > MAX_PARTICLES_IN_CELL) {
+                stats.overflowed_cells = stats.overflowed_cells.saturating_add(1);
+            }
+            particles_in_active_cells = particles_in_active_cells.saturating_add(count);
+        }
+        if stats.active_cells > 0 {
+            stats.mean_particles_per_cell =
+                f64::from(particles_in_active_cells) / f64::from(stats.active_cells);
+        }
+
+        // The velocities buffer also has unused space at the end, and the settings only have an
+        // upper bound for the number of particles, see `Culling`.
+        let particles = self
+            .packed_data
+            .particles_count(grid)
+            .and_then(|count| usize::try_from(count).ok())
+            .unwrap_or_else(|| usize::try_from(stats.particles_in_frame).unwrap_or(usize::MAX));
+        stats.kinetic_energy = self
+            .packed_data
+            .velocities
+            .iter()
+            .take(particles)
+            .map(|velocity| 0.5_f64 * f64::from(velocity.length_squared()))
+            .sum();
+
+        stats
+    }
+}
+
+/// An optional plugin that registers Bevy diagnostics for everything in `WrachStats`. Add Bevy's
+/// `LogDiagnosticsPlugin` to see them.
+///
+/// The GPU time is measured with timestamp queries, so it's only available on GPUs that support
+/// writing timestamps from command encoders. It's the total for all of the compute passes, they
+/// aren't timed separately.
+#[derive(Default)]
+#[non_exhaustive]
+pub struct WrachDiagnosticsPlugin;
+
+impl WrachDiagnosticsPlugin {
+    /// Particles being simulated in the current frame
+    pub const PARTICLES_IN_FRAME: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/particles_in_frame");
+    /// Particles kept on the CPU, outside of the simulated cells
+    pub const STORED_PARTICLES: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/stored_particles");
+    /// Cells with at least one particle in them
+    pub const ACTIVE_CELLS: DiagnosticPath = DiagnosticPath::const_new("wrach/active_cells");
+    /// The most particles in any one cell
+    pub const MAX_PARTICLES_PER_CELL: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/max_particles_per_cell");
+    /// The average number of particles in the cells that have any
+    pub const MEAN_PARTICLES_PER_CELL: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/mean_particles_per_cell");
+    /// Cells with more particles than get the full physics
+    pub const OVERFLOWED_CELLS: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/overflowed_cells");
+    /// The total kinetic energy of the simulated particles
+    pub const KINETIC_ENERGY: DiagnosticPath = DiagnosticPath::const_new("wrach/kinetic_energy");
+    /// How long the GPU took to run the physics, in milliseconds
+    pub const GPU_COMPUTE_TIME: DiagnosticPath =
+        DiagnosticPath::const_new("wrach/gpu_compute_time");
+}
+
+#[expect(clippy::missing_trait_methods, reason = "We just don't need 'em all")]
+impl Plugin for WrachDiagnosticsPlugin {
+    #[inline]
+    fn build(&self, app: &mut App) {
+        for path in [
+            Self::PARTICLES_IN_FRAME,
+            Self::STORED_PARTICLES,
+            Self::ACTIVE_CELLS,
+            Self::MAX_PARTICLES_PER_CELL,
+            Self::MEAN_PARTICLES_PER_CELL,
+            Self::OVERFLOWED_CELLS,
+            Self::KINETIC_ENERGY,
+        ] {
+            app.register_diagnostic(Diagnostic::new(path));
+        }
+        app.register_diagnostic(Diagnostic::new(Self::GPU_COMPUTE_TIME).with_suffix("ms"));
+
+        app.add_systems(Update, update_diagnostics).add_systems(
+            PostUpdate,
+            (
+                start_gpu_timer.before(AppComputeWorker::<PhysicsComputeWorker>::unmap_all),
+                finish_gpu_timer.after(AppComputeWorker::<PhysicsComputeWorker>::run),
+            ),
+        );
+    }
+
+    #[inline]
+    fn finish(&self, app: &mut App) {
+        let render_device = app.world().resource::<RenderDevice>();
+        let Some(timer) = GpuTimer::new(render_device, app.world().resource::<RenderQueue>())
+        else {
+            info!("GPU doesn't support timestamps in command encoders, so it won't be timed");
+            return;
+        };
+        app.insert_resource(timer);
+    }
+}
+
+/// Record the latest statistics as Bevy diagnostics.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "We have no choice because of the magic Bevy systems function signature"
+)]
+#[expect(
+    clippy::as_conversions,
+    clippy::cast_precision_loss,
+    reason = "Diagnostics are only `f64`s, and they don't need to be exact"
+)]
+fn update_diagnostics(mut diagnostics: Diagnostics, wrach_state: Res<WrachState>) {
+    let stats = wrach_state.stats();
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::PARTICLES_IN_FRAME, || {
+        f64::from(stats.particles_in_frame)
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::STORED_PARTICLES, || {
+        stats.stored_particles as f64
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::ACTIVE_CELLS, || {
+        f64::from(stats.active_cells)
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::MAX_PARTICLES_PER_CELL, || {
+        f64::from(stats.max_particles_per_cell)
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::MEAN_PARTICLES_PER_CELL, || {
+        stats.mean_particles_per_cell
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::OVERFLOWED_CELLS, || {
+        f64::from(stats.overflowed_cells)
+    });
+    diagnostics.add_measurement(&WrachDiagnosticsPlugin::KINETIC_ENERGY, || {
+        stats.kinetic_energy
+    });
+    if let Some(time) = stats.gpu_compute_time {
+        diagnostics.add_measurement(&WrachDiagnosticsPlugin::GPU_COMPUTE_TIME, || {
+            time.as_secs_f64() * 1000.0_f64
+        });
+    }
+}
+
+/// Times the physics on the GPU with a timestamp either side of the compute worker's submission.
+//
+// TODO: Time each of the compute passes separately. `bevy_easy_compute` records all of its passes
+// into a single command encoder without any `timestamp_writes`, so for now the timestamps can
+// only go around the whole submission.
+#[derive(Resource)]
+struct GpuTimer {
+    /// The two timestamps, from before and after the compute passes
+    query_set: wgpu::QuerySet,
+    /// Where the timestamps are resolved to
+    resolve_buffer: Buffer,
+    /// A mappable copy of the resolved timestamps
+    readback_buffer: Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Whether the compute worker runs this frame, otherwise there's nothing to time
+    is_timing: bool,
+    /// Whether the readback buffer is in use, from when the timestamps are copied into it until
+    /// they're read
+    is_in_flight: bool,
+    /// Whether the readback buffer has finished being mapped
+    is_mapped: Arc<AtomicBool>,
+    /// Whether the readback buffer couldn't be mapped, so it's free to be used again
+    has_map_failed: Arc<AtomicBool>,
+}
+
+impl GpuTimer {
+    /// The size of the two resolved timestamps, see `wgpu::QUERY_SIZE`
+    const SIZE: u64 = 16;
+
+    /// Instantiate, if the GPU supports it.
+    fn new(render_device: &RenderDevice, render_queue: &RenderQueue) -> Option<Self> {
+        let needed =
+            wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS;
+        if !render_device.features().contains(needed) {
+            return None;
+        }
+
+        let query_set = render_device
+            .wgpu_device()
+            .create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("wrach_gpu_timer"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            });
+        let resolve_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("wrach_gpu_timer_resolve"),
+            size: Self::SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("wrach_gpu_timer_readback"),
+            size: Self::SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: render_queue.get_timestamp_period(),
+            is_timing: false,
+            is_in_flight: false,
+            is_mapped: Arc::new(AtomicBool::new(false)),
+            has_map_failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Submit a single timestamp write on its own.
+    fn write_timestamp(
+        &self,
+        index: u32,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) {
+        let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("wrach_gpu_timer"),
+        });
+        encoder.write_timestamp(&self.query_set, index);
+        render_queue.submit([encoder.finish()]);
+    }
+
+    /// Read the timestamps, if they've been mapped, and free the readback buffer up again.
+    #[expect(
+        clippy::as_conversions,
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        reason = "It's only a measurement, it doesn't need to be exact"
+    )]
+    fn read(&mut self) -> Option<Duration> {
+        if self.has_map_failed.swap(false, Ordering::AcqRel) {
+            self.is_in_flight = false;
+            return None;
+        }
+        if !self.is_in_flight || !self.is_mapped.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let ticks = {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            match *bytemuck::cast_slice::<u8, u64>(&view) {
+                [start, end] => end.saturating_sub(start),
+                _ => 0,
+            }
+        };
+        self.readback_buffer.unmap();
+        self.is_mapped.store(false, Ordering::Release);
+        self.is_in_flight = false;
+
+        let nanoseconds = ticks as f64 * f64::from(self.period);
+        Some(Duration::from_nanos(nanoseconds as u64))
+    }
+}
+
+/// Write the first timestamp, if the compute worker is about to run.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "We have no choice because of the magic Bevy systems function signature"
+)]
+fn start_gpu_timer(
+    gpu_timer: Option<ResMut<GpuTimer>>,
+    compute_worker: Res<AppComputeWorker<PhysicsComputeWorker>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut state: ResMut<WrachState>,
+) {
+    let Some(mut timer) = gpu_timer else {
+        return;
+    };
+
+    if let Some(time) = timer.read() {
+        state.gpu_compute_time = Some(time);
+    }
+
+    // The worker only submits once its previous results are ready, see `readback_from_gpu()`.
+    timer.is_timing = compute_worker.ready() && !timer.is_in_flight;
+    if timer.is_timing {
+        timer.write_timestamp(0, &render_device, &render_queue);
+    }
+}
+
+/// Write the second timestamp after the compute worker's submission, and start reading them both
+/// back.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "We have no choice because of the magic Bevy systems function signature"
+)]
+fn finish_gpu_timer(
+    gpu_timer: Option<ResMut<GpuTimer>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(mut timer) = gpu_timer else {
+        return;
+    };
+    if !timer.is_timing {
+        return;
+    }
+
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("wrach_gpu_timer"),
+    });
+    encoder.write_timestamp(&timer.query_set, 1);
+    encoder.resolve_query_set(&timer.query_set, 0..2, &timer.resolve_buffer, 0);
+    encoder.copy_buffer_to_buffer(
+        &timer.resolve_buffer,
+        0,
+        &timer.readback_buffer,
+        0,
+        GpuTimer::SIZE,
+    );
+    render_queue.submit([encoder.finish()]);
+
+    let is_mapped = Arc::clone(&timer.is_mapped);
+    let has_map_failed = Arc::clone(&timer.has_map_failed);
+    render_device.map_buffer(
+        &timer.readback_buffer.slice(..),
+        MapMode::Read,
+        move |result| {
+            if let Err(error) = result {
+                error!("Couldn't map GPU timer buffer: {error}");
+                has_map_failed.store(true, Ordering::Release);
+                return;
+            }
+            is_mapped.store(true, Ordering::Release);
+        },
+    );
+    timer.is_timing = false;
+    timer.is_in_flight = true;
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::float_cmp,
+    clippy::similar_names,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use crate::{Particle, WrachConfig, WrachState};
+
+    #[test]
+    fn stats_for_an_empty_simulation() {
+        let state = WrachState::new(WrachConfig::default());
+        let stats = state.stats();
+        assert_eq!(stats.particles_in_frame, 0);
+        assert_eq!(stats.active_cells, 0);
+        assert_eq!(stats.mean_particles_per_cell, 0.0);
+        assert_eq!(stats.kinetic_energy, 0.0);
+        assert_eq!(stats.gpu_compute_time, None);
+    }
+
+    #[test]
+    fn stats_from_packed_data() {
+        let mut state = WrachState::new(WrachConfig {
+            dimensions: (6, 3),
+            cell_size: 3,
+            ..Default::default()
+        });
+        state.shader_settings.particles_in_frame_count = 3;
+        // Cells of 0, 2 and 1 particles then empty cells, with unused space in the velocities.
+        state.packed_data.indices = vec![0, 0, 2, 3, 3, 0];
+        state.packed_data.velocities = vec![
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 2.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(9.0, 9.0),
+        ];
+        state.add_particles(vec![Particle {
+            position: Vec2::new(-10.0, -10.0),
+            velocity: Vec2::ZERO,
+        }]);
+
+        let stats = state.stats();
+        assert_eq!(stats.stored_particles, 1);
+        assert_eq!(stats.active_cells, 2);
+        assert_eq!(stats.max_particles_per_cell, 2);
+        assert_eq!(stats.mean_particles_per_cell, 1.5);
+        assert_eq!(stats.overflowed_cells, 0);
+        assert_eq!(stats.kinetic_energy, 0.5 + 2.0 + 1.0);
+    }
+}
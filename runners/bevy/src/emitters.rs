@@ -0,0 +1,451 @@
+//! Emitters and sinks: fountains, faucets and drains that add and remove particles every frame.
+//!
+//! Emitted particles go through the same queue as `WrachState::add_particles()`, so they're merged
+//! into the simulation on the GPU without rebuilding the packed data on the CPU. Emitters are
+//! evaluated once for every frame that the GPU runs, and recordings only see the particles that
+//! they add.
+//!
+//! Sinks are uploaded to the GPU whenever they change, like heat sources. The physics ends the
+//! lifetime of every particle inside one, so the packing pass leaves them out just like expired
+//! particles, without anything being read back. See `Culling` for how the CPU keeps count.
+
+use core::iter;
+
+use bevy::math::{Vec2, Vec4};
+use rand::{rngs::StdRng, SeedableRng as _};
+
+use crate::{error::WrachError, state::GPUUpload, Particle, ParticleProperties, WrachState};
+
+/// The size of the GPU's sinks buffer, see `MAX_SINKS`.
+#[expect(
+    clippy::as_conversions,
+    reason = "`u32`s always fit into `usize` on the platforms that we support, and `try_from()` isn't const"
+)]
+const SINKS_CAPACITY: usize = wrach_cpu_gpu_shared::MAX_SINKS as usize;
+
+/// Adds particles to the simulation every frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Emitter {
+    /// Where the particles appear
+    pub position: Vec2,
+    /// Particles appear at random places within this distance of `position`. It's best not to
+    /// spawn particles exactly on top of each other.
+    pub radius: f32,
+    /// How many particles to add every frame, fractions accumulate over frames
+    pub rate: f32,
+    /// The average velocity of new particles
+    pub velocity: Vec2,
+    /// New particles get an extra random velocity of up to this magnitude
+    pub velocity_spread: f32,
+    /// How many frames the emitter runs for before it's removed, `None` runs forever
+    pub lifetime: Option<u64>,
+    /// The properties of every particle the emitter adds, eg: how long they live for
+    pub particle_properties: ParticleProperties,
+}
+
+impl Emitter {
+    /// An emitter that adds `rate` still particles every frame, forever.
+    #[inline]
+    #[must_use]
+    pub const fn new(position: Vec2, rate: f32) -> Self {
+        Self {
+            position,
+            radius: 1.0,
+            rate,
+            velocity: Vec2::ZERO,
+            velocity_spread: 0.0,
+            lifetime: None,
+            particle_properties: ParticleProperties {
+                lifetime: None,
+                temperature: None,
+                material: 0,
+            },
+        }
+    }
+
+    /// See `Emitter::radius`
+    #[inline]
+    #[must_use]
+    pub const fn radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// See `Emitter::velocity` and `Emitter::velocity_spread`
+    #[inline]
+    #[must_use]
+    pub const fn velocity(mut self, velocity: Vec2, spread: f32) -> Self {
+        self.velocity = velocity;
+        self.velocity_spread = spread;
+        self
+    }
+
+    /// See `Emitter::lifetime`
+    #[inline]
+    #[must_use]
+    pub const fn lifetime(mut self, frames: u64) -> Self {
+        self.lifetime = Some(frames);
+        self
+    }
+
+    /// See `Emitter::particle_properties`
+    #[inline]
+    #[must_use]
+    pub const fn particle_properties(mut self, properties: ParticleProperties) -> Self {
+        self.particle_properties = properties;
+        self
+    }
+}
+
+/// Removes all the particles that enter a circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Sink {
+    /// The centre of the circle
+    pub center: Vec2,
+    /// The radius of the circle
+    pub radius: f32,
+}
+
+impl Sink {
+    /// Instantiate
+    #[inline]
+    #[must_use]
+    pub const fn new(center: Vec2, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// The sink in the format used by the GPU, `(x, y, radius, 0)`.
+    const fn to_gpu(self) -> Vec4 {
+        Vec4::new(self.center.x, self.center.y, self.radius, 0.0)
+    }
+}
+
+/// Fill the GPU's sinks buffer. It's a uniform buffer, so it always has room for `MAX_SINKS`, and
+/// the settings say how many of them to use.
+#[inline]
+#[must_use]
+pub fn sinks_buffer(sinks: &[Vec4]) -> [Vec4; SINKS_CAPACITY] {
+    let mut buffer = [Vec4::ZERO; SINKS_CAPACITY];
+    for (slot, sink) in buffer.iter_mut().zip(sinks) {
+        *slot = *sink;
+    }
+    buffer
+}
+
+/// Identifies an emitter, for removing it, see `WrachState::add_emitter()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EmitterId(u64);
+
+/// Identifies a sink, for removing it, see `WrachState::add_sink()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SinkId(u64);
+
+/// An emitter and what it's done so far
+struct RunningEmitter {
+    /// For removing the emitter
+    id: EmitterId,
+    /// The emitter itself
+    emitter: Emitter,
+    /// The fraction of a particle that's built up from a fractional `Emitter::rate`
+    owed: f32,
+    /// How many frames the emitter has run for
+    age: u64,
+}
+
+/// All the emitters and sinks in a simulation
+pub struct Emitters {
+    /// The emitters, in the order they were added
+    running: Vec<RunningEmitter>,
+    /// The sinks, in the order they were added
+    sinks: Vec<(SinkId, Sink)>,
+    /// The next ID to give out to an emitter or sink
+    next_id: u64,
+    /// The GPU frame that the emitters were last run for
+    last_frame: Option<u64>,
+    /// Seeded, so that emitters are as deterministic as the rest of the simulation
+    rng: StdRng,
+}
+
+impl Default for Emitters {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            running: Vec::new(),
+            sinks: Vec::new(),
+            next_id: 0,
+            last_frame: None,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl Emitters {
+    /// The number of emitters still running
+    #[inline]
+    #[must_use]
+    pub const fn emitters_count(&self) -> usize {
+        self.running.len()
+    }
+
+    /// The number of sinks
+    #[inline]
+    #[must_use]
+    pub const fn sinks_count(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// All the sinks in the format used by the GPU.
+    pub(crate) fn sinks_to_gpu(&self) -> Vec<Vec4> {
+        self.sinks.iter().map(|&(_, sink)| sink.to_gpu()).collect()
+    }
+
+    /// Get a new ID.
+    const fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.saturating_add(1);
+        id
+    }
+
+    /// The particles that each emitter adds in a single frame, along with their properties.
+    /// Emitters that have reached the end of their lifetime are removed.
+    fn emit(&mut self) -> Vec<(Vec<Particle>, ParticleProperties)> {
+        let mut batches = Vec::new();
+        for running in &mut self.running {
+            let emitter = running.emitter;
+            running.age = running.age.saturating_add(1);
+            running.owed += emitter.rate;
+            let whole = running.owed.floor();
+            running.owed -= whole;
+            #[expect(
+                clippy::as_conversions,
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss,
+                reason = "Float to int casts saturate, and a negative rate just emits nothing"
+            )]
+            let count = whole as usize;
+            let particles: Vec<Particle> = iter::repeat_with(|| Particle {
+                position: random_in_circle(&mut self.rng, emitter.position, emitter.radius),
+                velocity: random_in_circle(
+                    &mut self.rng,
+                    emitter.velocity,
+                    emitter.velocity_spread,
+                ),
+            })
+            .take(count)
+            .collect();
+            if !particles.is_empty() {
+                batches.push((particles, emitter.particle_properties));
+            }
+        }
+        self.running.retain(|running| {
+            running
+                .emitter
+                .lifetime
+                .is_none_or(|lifetime| running.age < lifetime)
+        });
+        batches
+    }
+}
+
+/// A random position in a circle, evenly spread over its area.
+#[expect(
+    clippy::arithmetic_side_effects,
+    reason = "Float vectors don't overflow, they just become infinite"
+)]
+pub fn random_in_circle<R: rand::Rng>(rng: &mut R, center: Vec2, radius: f32) -> Vec2 {
+    let angle = rng.gen_range(0.0..core::f32::consts::TAU);
+    let distance = radius * rng.gen::<f32>().sqrt();
+    center + Vec2::from_angle(angle) * distance
+}
+
+impl WrachState {
+    /// Start adding particles every frame, see `Emitter`.
+    #[inline]
+    pub fn add_emitter(&mut self, emitter: Emitter) -> EmitterId {
+        let id = EmitterId(self.emitters.next_id());
+        self.emitters.running.push(RunningEmitter {
+            id,
+            emitter,
+            owed: 0.0,
+            age: 0,
+        });
+        id
+    }
+
+    /// Stop an emitter, returns it if it was still running.
+    #[inline]
+    pub fn remove_emitter(&mut self, id: EmitterId) -> Option<Emitter> {
+        let index = self
+            .emitters
+            .running
+            .iter()
+            .position(|running| running.id == id)?;
+        Some(self.emitters.running.remove(index).emitter)
+    }
+
+    /// Start removing all the particles that enter a circle, see `Sink`.
+    ///
+    /// The CPU only finds out how many particles have drained away from the indices read back from
+    /// the GPU, at any latency, see `ReadbackBuffers::indices`. Without them, drained particles keep
+    /// taking up room in the GPU buffers as far as the CPU is concerned, see `Culling`.
+    ///
+    /// # Errors
+    /// If there are already `MAX_SINKS` sinks.
+    #[inline]
+    pub fn add_sink(&mut self, sink: Sink) -> Result<SinkId, WrachError> {
+        let count = self.emitters.sinks_count().saturating_add(1);
+        if count > SINKS_CAPACITY {
+            return Err(WrachError::too_many("sinks", count));
+        }
+
+        let id = SinkId(self.emitters.next_id());
+        self.emitters.sinks.push((id, sink));
+        self.gpu_upload(GPUUpload::Sinks(self.emitters.sinks_to_gpu()));
+        Ok(id)
+    }
+
+    /// Stop a sink, returns it if it existed.
+    #[inline]
+    pub fn remove_sink(&mut self, id: SinkId) -> Option<Sink> {
+        let index = self
+            .emitters
+            .sinks
+            .iter()
+            .position(|&(sink_id, _)| sink_id == id)?;
+        let (_, sink) = self.emitters.sinks.remove(index);
+        self.gpu_upload(GPUUpload::Sinks(self.emitters.sinks_to_gpu()));
+        Some(sink)
+    }
+
+    /// Run the emitters for every frame that the GPU has run since they last ran.
+    pub(crate) fn run_emitters(&mut self) {
+        let frames = self
+            .emitters
+            .last_frame
+            .map_or(1, |last_frame| self.gpu_frame.saturating_sub(last_frame));
+        self.emitters.last_frame = Some(self.gpu_frame);
+
+        for _ in 0..frames {
+            for (particles, properties) in self.emitters.emit() {
+                self.add_particles_with(particles, properties);
+            }
+        }
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+    use rand::{rngs::StdRng, SeedableRng as _};
+
+    use super::{random_in_circle, Emitter, Sink};
+    use crate::{tests::utils::simulation, ParticleProperties};
+
+    #[test]
+    fn random_positions_are_inside_the_circle() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let center = Vec2::new(10.0, 20.0);
+        for _ in 0..100 {
+            let position = random_in_circle(&mut rng, center, 3.0);
+            assert!(position.distance(center) <= f32::EPSILON.mul_add(10.0, 3.0));
+        }
+    }
+
+    #[test]
+    fn emitters_accumulate_fractional_rates_until_their_lifetime_ends() {
+        let mut simulation = simulation();
+        simulation
+            .state
+            .add_emitter(Emitter::new(Vec2::new(15.0, 15.0), 0.5).lifetime(6));
+
+        for _ in 0..10 {
+            simulation.tick().unwrap();
+        }
+
+        assert_eq!(simulation.state.emitters.emitters_count(), 0);
+        assert_eq!(simulation.state.stats().particles_in_frame, 3);
+    }
+
+    #[test]
+    fn emitted_particles_can_have_lifetimes() {
+        let mut simulation = simulation();
+        simulation.state.add_emitter(
+            Emitter::new(Vec2::new(15.0, 15.0), 1.0)
+                .particle_properties(ParticleProperties::default().lifetime(4)),
+        );
+
+        for _ in 0..20 {
+            simulation.tick().unwrap();
+        }
+
+        assert_eq!(
+            simulation.state.packed_data.indices.last().copied(),
+            Some(4),
+            "Only the particles from the last 4 frames should still be alive"
+        );
+    }
+
+    #[test]
+    fn sinks_remove_particles_that_enter_them() {
+        let mut simulation = simulation();
+        simulation.state.add_emitter(
+            Emitter::new(Vec2::new(5.0, 15.0), 1.0)
+                .radius(0.5)
+                .velocity(Vec2::new(0.5, 0.0), 0.0),
+        );
+        let sink = simulation
+            .state
+            .add_sink(Sink::new(Vec2::new(5.0, 15.0), 3.0))
+            .unwrap();
+        assert_eq!(simulation.state.shader_settings.sinks_count, 1);
+
+        for _ in 0..10 {
+            simulation.tick().unwrap();
+        }
+        assert_eq!(
+            simulation.state.packed_data.indices.last().copied(),
+            Some(1),
+            "Only the particle merged in the last frame hasn't been simulated inside the sink yet"
+        );
+        assert_eq!(
+            simulation.state.stats().particles_in_frame,
+            1,
+            "The drained particles shouldn't still be counted"
+        );
+        assert!(simulation.state.particle_edits.is_empty());
+
+        assert!(simulation.state.remove_sink(sink).is_some());
+        assert!(simulation.state.remove_sink(sink).is_none());
+        assert_eq!(simulation.state.shader_settings.sinks_count, 0);
+        for _ in 0..10 {
+            simulation.tick().unwrap();
+        }
+        assert_eq!(
+            simulation.state.packed_data.indices.last().copied(),
+            Some(11),
+            "The last particle to be merged whilst there was a sink should have survived"
+        );
+    }
+
+    #[test]
+    fn there_can_only_be_so_many_sinks() {
+        let mut simulation = simulation();
+        for _ in 0..wrach_cpu_gpu_shared::MAX_SINKS {
+            simulation
+                .state
+                .add_sink(Sink::new(Vec2::new(5.0, 5.0), 1.0))
+                .unwrap();
+        }
+        simulation
+            .state
+            .add_sink(Sink::new(Vec2::new(5.0, 5.0), 1.0))
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,154 @@
+//! Errors from running the simulation. Bevy's systems can't return errors to anyone, so they're
+//! piped into [`report_errors`], which logs them, keeps the latest one in
+//! `WrachState::last_error` and sends them as [`WrachErrorEvent`]s for games to handle.
+
+use core::fmt;
+
+use bevy::prelude::*;
+
+use crate::{WrachConfigError, WrachState};
+
+/// Everything that can go wrong whilst simulating
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WrachError {
+    /// The config can't be simulated
+    Config(WrachConfigError),
+    /// There are more of something than fit into the `u32`s that the GPU uses
+    TooMany {
+        /// What there are too many of
+        what: &'static str,
+        /// How many there are
+        count: usize,
+    },
+    /// There are more particles in a frame than the prefix sum can count
+    PrefixSumLimit {
+        /// The number of particles
+        particles: u32,
+        /// The prefix sum's limit
+        max: u32,
+    },
+    /// A buffer that the compute worker should have doesn't exist
+    MissingBuffer(&'static str),
+    /// A Bevy resource that should have been setup by now doesn't exist
+    MissingResource(&'static str),
+    /// A config change can't be applied whilst the simulation is running
+    NeedsRestart(&'static str),
+    /// Particles can't be edited without reading back the simulation straight away
+    NeedsReadback,
+}
+
+impl fmt::Display for WrachError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Config(error) => write!(f, "Invalid config: {error}"),
+            Self::TooMany { what, count } => {
+                write!(f, "Too many {what} for the GPU to count: {count}")
+            }
+            Self::PrefixSumLimit { particles, max } => write!(
+                f,
+                "{particles} particles in a frame, but the prefix sum can only count fewer than \
+                 {max}"
+            ),
+            Self::MissingBuffer(name) => write!(f, "Couldn't find the `{name}` compute buffer"),
+            Self::MissingResource(name) => write!(f, "Couldn't find the `{name}` resource"),
+            Self::NeedsRestart(field) => write!(
+                f,
+                "Changing `{field}` whilst the simulation is running isn't supported, restart it \
+                 instead"
+            ),
+            Self::NeedsReadback => write!(
+                f,
+                "Editing particles needs all the buffers to be read back with no latency, see \
+                 `WrachConfig::readback`"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for WrachError {
+    #[inline]
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match *self {
+            Self::Config(ref error) => Some(error),
+            Self::TooMany { .. }
+            | Self::PrefixSumLimit { .. }
+            | Self::MissingBuffer(_)
+            | Self::MissingResource(_)
+            | Self::NeedsRestart(_)
+            | Self::NeedsReadback => None,
+        }
+    }
+}
+
+impl From<WrachConfigError> for WrachError {
+    #[inline]
+    fn from(error: WrachConfigError) -> Self {
+        Self::Config(error)
+    }
+}
+
+impl WrachError {
+    /// For when converting a count into a `u32` fails.
+    pub(crate) const fn too_many(what: &'static str, count: usize) -> Self {
+        Self::TooMany { what, count }
+    }
+}
+
+/// Convert a count into a `u32`, which is what the GPU counts in.
+#[expect(
+    clippy::map_err_ignore,
+    reason = "`TryFromIntError` doesn't say anything that the count doesn't"
+)]
+pub fn count_to_u32(what: &'static str, count: usize) -> Result<u32, WrachError> {
+    u32::try_from(count).map_err(|_| WrachError::too_many(what, count))
+}
+
+/// Sent whenever one of Wrach's systems fails
+#[derive(Event, Clone, Debug)]
+#[non_exhaustive]
+pub struct WrachErrorEvent {
+    /// What went wrong
+    pub error: WrachError,
+}
+
+/// Handle the result of a fallible system, use with `.pipe()`.
+pub fn report_errors(
+    In(result): In<Result<(), WrachError>>,
+    mut state: ResMut<WrachState>,
+    mut events: EventWriter<WrachErrorEvent>,
+) {
+    if let Err(error) = result {
+        error!("{error}");
+        state.last_error = Some(error.clone());
+        events.send(WrachErrorEvent { error });
+    }
+}
+
+/// Report an error from code that has the whole world, rather than being a system.
+pub fn report_error_in_world(world: &mut World, error: WrachError) {
+    error!("{error}");
+    if let Some(mut state) = world.get_resource_mut::<WrachState>() {
+        state.last_error = Some(error.clone());
+    }
+    world.send_event(WrachErrorEvent { error });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_errors_are_the_source() {
+        let error = WrachError::from(WrachConfigError::ZeroCellSize);
+        assert_eq!(
+            core::error::Error::source(&error).map(ToString::to_string),
+            Some(WrachConfigError::ZeroCellSize.to_string())
+        );
+    }
+}
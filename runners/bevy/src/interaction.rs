@@ -0,0 +1,196 @@
+//! Tools for playing with the simulation using the mouse, or any other pointer that Bevy treats as
+//! the mouse, like touch screens on the web. Hold the left button to use the current tool.
+
+use core::iter;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{emitters::random_in_circle, Particle, WrachState};
+
+/// An optional plugin to spawn, erase, push, pull and drag particles with the mouse. Choose the
+/// tool by changing the `WrachInteraction` resource.
+///
+/// Everything but spawning edits the simulated particles, so it needs all the buffers to be read
+/// back with no latency, see `WrachState::edit_particles()`.
+#[derive(Default)]
+#[non_exhaustive]
+pub struct WrachInteractionPlugin;
+
+#[expect(clippy::missing_trait_methods, reason = "We just don't need to others")]
+impl Plugin for WrachInteractionPlugin {
+    #[inline]
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WrachInteraction>()
+            .add_systems(Update, interact);
+    }
+}
+
+/// What happens whilst the left mouse button is held down
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InteractionTool {
+    /// Add new particles at random places in the circle
+    #[default]
+    Spawn,
+    /// Remove the particles in the circle
+    Erase,
+    /// Push the particles in the circle away from the pointer
+    Push,
+    /// Pull the particles in the circle towards the pointer
+    Pull,
+    /// Move the particles in the circle along with the pointer
+    Drag,
+}
+
+/// The current tool and its settings, see `WrachInteractionPlugin`
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct WrachInteraction {
+    /// What the left mouse button does
+    pub tool: InteractionTool,
+    /// The radius of the circle around the pointer that the tool affects, in units of
+    /// `WrachConfig::dimensions`
+    pub radius: f32,
+    /// The change in velocity at the centre of the circle when pushing or pulling
+    pub strength: f32,
+    /// How many particles to add every frame when spawning
+    pub spawn_per_frame: u32,
+}
+
+impl Default for WrachInteraction {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            tool: InteractionTool::default(),
+            radius: 10.0,
+            strength: 0.5,
+            spawn_per_frame: 20,
+        }
+    }
+}
+
+/// Convert a position in the window, like the cursor's, to a position in the simulation. It's the
+/// opposite of how `DrawPlugin` draws particles: the view, starting at its anchor, is stretched
+/// over the whole window and the window's Y axis points down.
+#[expect(
+    clippy::arithmetic_side_effects,
+    reason = "Float vectors don't overflow, they just become infinite"
+)]
+fn window_to_simulation(
+    cursor: Vec2,
+    window_size: Vec2,
+    view_anchor: Vec2,
+    view_dimensions: Vec2,
+) -> Option<Vec2> {
+    if window_size.x <= 0.0 || window_size.y <= 0.0 {
+        return None;
+    }
+    let uv = cursor / window_size;
+    Some(view_anchor + Vec2::new(uv.x, 1.0 - uv.y) * view_dimensions)
+}
+
+/// Use the current tool whilst the left mouse button is held down.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "Bevy's system function signature can't be changed"
+)]
+#[expect(
+    clippy::arithmetic_side_effects,
+    reason = "Float vectors don't overflow, they just become infinite"
+)]
+fn interact(
+    mouse: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    interaction: Res<WrachInteraction>,
+    mut state: ResMut<WrachState>,
+    mut last_position: Local<Option<Vec2>>,
+) {
+    let cursor_position = windows
+        .get_single()
+        .ok()
+        .filter(|_| mouse.pressed(MouseButton::Left))
+        .and_then(|window| {
+            window_to_simulation(
+                window.cursor_position()?,
+                window.size(),
+                state.shader_settings.view_anchor,
+                state.shader_settings.view_dimensions,
+            )
+        });
+    let Some(position) = cursor_position else {
+        *last_position = None;
+        return;
+    };
+
+    let radius = interaction.radius;
+    match interaction.tool {
+        InteractionTool::Spawn => {
+            let mut rng = rand::thread_rng();
+            let count = usize::try_from(interaction.spawn_per_frame).unwrap_or(usize::MAX);
+            let particles = iter::repeat_with(|| Particle {
+                position: random_in_circle(&mut rng, position, radius),
+                velocity: Vec2::ZERO,
+            })
+            .take(count)
+            .collect();
+            state.add_particles(particles);
+        }
+        InteractionTool::Erase => state.remove_particles(position, radius),
+        InteractionTool::Push => state.push_particles(position, radius, interaction.strength),
+        InteractionTool::Pull => state.push_particles(position, radius, -interaction.strength),
+        InteractionTool::Drag => {
+            if let Some(last) = *last_position {
+                if last != position {
+                    state.drag_particles(last, radius, position - last);
+                }
+            }
+        }
+    }
+
+    *last_position = Some(position);
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use super::window_to_simulation;
+
+    #[test]
+    fn the_cursor_maps_onto_the_view() {
+        // A 100x50 simulation in a window scaled up 4 times
+        let window = Vec2::new(400.0, 200.0);
+        let view = Vec2::new(100.0, 50.0);
+
+        assert_eq!(
+            window_to_simulation(Vec2::new(0.0, 200.0), window, Vec2::ZERO, view),
+            Some(Vec2::ZERO),
+            "The bottom left of the window is the origin"
+        );
+        assert_eq!(
+            window_to_simulation(Vec2::new(200.0, 50.0), window, Vec2::ZERO, view),
+            Some(Vec2::new(50.0, 37.5))
+        );
+        assert_eq!(
+            window_to_simulation(Vec2::ZERO, Vec2::ZERO, Vec2::ZERO, view),
+            None
+        );
+    }
+
+    #[test]
+    fn the_cursor_follows_the_view_anchor() {
+        let window = Vec2::new(400.0, 200.0);
+        let view = Vec2::new(100.0, 50.0);
+        let anchor = Vec2::new(30.0, -20.0);
+
+        assert_eq!(
+            window_to_simulation(Vec2::new(0.0, 200.0), window, anchor, view),
+            Some(anchor),
+            "The bottom left of the window is the view's anchor"
+        );
+        assert_eq!(
+            window_to_simulation(Vec2::new(200.0, 50.0), window, anchor, view),
+            Some(Vec2::new(80.0, 17.5))
+        );
+    }
+}
@@ -8,30 +8,47 @@
     "
 )]
 
+extern crate alloc;
+
 /// Tests
 #[cfg(test)]
 mod tests {
     pub mod utils;
 }
 
+mod ageing;
 /// All GPU-compute related code
 mod compute {
     pub use builder::PhysicsComputeWorker;
     pub mod buffers;
     mod builder;
+    pub mod cpu;
+    pub mod growth;
+    pub mod readback;
+    #[macro_use]
+    pub mod workgroups;
 
     #[path = "01_integration.rs"]
     mod integration;
     #[path = "04_pack_particle_data.rs"]
     mod pack_particle_data;
-    #[path = "02_particles_cell_count.rs"]
-    mod particles_cell_count;
     #[path = "03_prefix_sum.rs"]
-    mod prefix_sum;
+    pub mod prefix_sum;
+    #[path = "05_sort_cells.rs"]
+    mod sort_cells;
 }
 mod config_app;
+mod config_file;
 mod config_shader;
+mod culling;
+mod diagnostics;
+mod emitters;
+mod error;
+mod interaction;
+mod materials;
+mod particle_edits;
 mod particle_store;
+mod recording;
 /// The Bevy Wrach plugin
 mod plugin {
     pub mod bind_groups;
@@ -39,15 +56,55 @@ mod plugin {
 }
 /// Rendering code
 mod render {
+    pub mod ageing_curve;
+    pub mod debug_overlay;
     pub mod draw_plugin;
     mod graph_node;
     mod pipeline;
 }
 mod spatial_bin;
 mod state;
+mod thermal;
 
+pub use crate::ageing::Expiries;
+pub use crate::compute::cpu::CpuSimulation;
+pub use crate::config_app::ReadbackBuffers;
+pub use crate::config_app::ThermalConfig;
+pub use crate::config_app::WorkgroupSize;
 pub use crate::config_app::WrachConfig;
+pub use crate::config_app::WrachConfigBuilder;
+pub use crate::config_app::WrachConfigError;
+pub use crate::config_file::WrachConfigFile;
+pub use crate::config_file::WrachConfigFileError;
+pub use crate::culling::Culling;
+pub use crate::diagnostics::WrachDiagnosticsPlugin;
+pub use crate::diagnostics::WrachStats;
+pub use crate::emitters::Emitter;
+pub use crate::emitters::EmitterId;
+pub use crate::emitters::Emitters;
+pub use crate::emitters::Sink;
+pub use crate::emitters::SinkId;
+pub use crate::error::WrachError;
+pub use crate::error::WrachErrorEvent;
+pub use crate::interaction::InteractionTool;
+pub use crate::interaction::WrachInteraction;
+pub use crate::interaction::WrachInteractionPlugin;
+pub use crate::materials::Material;
+pub use crate::materials::MaterialProperties;
+pub use crate::materials::Materials;
+pub use crate::materials::Reaction;
+pub use crate::particle_edits::ParticleEdit;
 pub use crate::plugin::build::WrachPlugin;
+pub use crate::recording::RecordedEvent;
+pub use crate::recording::RecordedInput;
+pub use crate::recording::Recording;
+pub use crate::recording::RecordingError;
+pub use crate::render::ageing_curve::WrachAgeingCurve;
+pub use crate::render::debug_overlay::WrachDebugOverlay;
 pub use crate::render::draw_plugin::DrawPlugin;
 pub use crate::state::Particle;
+pub use crate::state::ParticleProperties;
 pub use crate::state::WrachState;
+pub use crate::thermal::HeatSource;
+pub use crate::thermal::HeatSourceId;
+pub use crate::thermal::HeatSources;
@@ -0,0 +1,644 @@
+//! Materials and the reactions between them. A particle's material is just a number, it only means
+//! something to the reactions, eg: "water that's hotter than 100 becomes steam" or "water touching
+//! lava becomes steam and stone", and to the material's properties, eg: how cohesive it is.
+//! Reactions happen in the physics shader, with some probability every frame that they can.
+//!
+//! The materials can be built in Rust or loaded from RON, with `Materials::from_ron()`, eg:
+//!
+//! ```ron
+//! (
+//!     properties: {
+//!         1: (cohesion: 0.3),
+//!         5: (static_friction: 1.0, dynamic_friction: 0.8),
+//!     },
+//!     reactions: [
+//!         AboveTemperature(material: 1, temperature: 100.0, becomes: 3, probability: 0.05),
+//!         Contact(reactants: (1, 2), products: (3, 4), probability: 0.5),
+//!     ],
+//! )
+//! ```
+//!
+//! Like heat sources, the materials are uploaded to the GPU whenever they change, so recordings see
+//! them as uploads.
+
+use alloc::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wrach_cpu_gpu_shared::{
+    MAX_MATERIALS, MAX_REACTIONS, REACTION_ABOVE_TEMPERATURE, REACTION_BELOW_TEMPERATURE,
+    REACTION_CONTACT,
+};
+
+use crate::{
+    config_shader::{ShaderMaterialProperties, ShaderReaction},
+    error::WrachError,
+    state::GPUUpload,
+    WrachConfigError, WrachConfigFileError, WrachState,
+};
+
+/// What a particle is made of, see `ParticleProperties::material()`. Particles are material 0
+/// unless they're given another one.
+pub type Material = u32;
+
+/// How particles of a material behave. Materials without any properties get the defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct MaterialProperties {
+    /// How strongly neighbouring particles are pulled together, from 0 to 1. Cohesive particles
+    /// settle at the minimum distance from each other, so they form droplets and blobs. A pair of
+    /// different materials is pulled by the average of their cohesions.
+    pub cohesion: f32,
+    /// How well touching particles resist starting to slide along each other, from 0 to 1. The
+    /// higher it is the steeper the piles that the material forms, like sand. Particles also rub
+    /// along the boundaries with their material's friction. A pair of different materials uses the
+    /// average of their frictions.
+    pub static_friction: f32,
+    /// How quickly touching particles stop sliding along each other once they are, from 0 to 1
+    pub dynamic_friction: f32,
+}
+
+impl MaterialProperties {
+    /// See `MaterialProperties::cohesion`
+    #[inline]
+    #[must_use]
+    pub const fn cohesion(mut self, cohesion: f32) -> Self {
+        self.cohesion = cohesion;
+        self
+    }
+
+    /// See `MaterialProperties::static_friction`
+    #[inline]
+    #[must_use]
+    pub const fn static_friction(mut self, static_friction: f32) -> Self {
+        self.static_friction = static_friction;
+        self
+    }
+
+    /// See `MaterialProperties::dynamic_friction`
+    #[inline]
+    #[must_use]
+    pub const fn dynamic_friction(mut self, dynamic_friction: f32) -> Self {
+        self.dynamic_friction = dynamic_friction;
+        self
+    }
+
+    /// The properties in the format used by the GPU.
+    const fn to_gpu(self) -> ShaderMaterialProperties {
+        ShaderMaterialProperties {
+            cohesion: self.cohesion,
+            static_friction: self.static_friction,
+            dynamic_friction: self.dynamic_friction,
+        }
+    }
+}
+
+/// A single rule for how materials change
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Reaction {
+    /// A particle that's hotter than `temperature` becomes another material, eg: melting
+    AboveTemperature {
+        /// The material that changes
+        material: Material,
+        /// The temperature that the particle has to be hotter than
+        temperature: f32,
+        /// What the particle becomes
+        becomes: Material,
+        /// The chance of it happening every frame that the particle is hot enough, from 0 to 1
+        probability: f32,
+    },
+    /// A particle that's colder than `temperature` becomes another material, eg: freezing
+    BelowTemperature {
+        /// The material that changes
+        material: Material,
+        /// The temperature that the particle has to be colder than
+        temperature: f32,
+        /// What the particle becomes
+        becomes: Material,
+        /// The chance of it happening every frame that the particle is cold enough, from 0 to 1
+        probability: f32,
+    },
+    /// Two neighbouring particles both become other materials, eg: water and lava become steam
+    /// and stone. The reactants can be either way round.
+    Contact {
+        /// The materials of the two particles
+        reactants: (Material, Material),
+        /// What each of the reactants becomes, in the same order
+        products: (Material, Material),
+        /// The chance of it happening every frame that the particles are neighbours, from 0 to 1
+        probability: f32,
+    },
+}
+
+impl Reaction {
+    /// The chance of the reaction happening every frame that it can.
+    const fn probability(self) -> f32 {
+        match self {
+            Self::AboveTemperature { probability, .. }
+            | Self::BelowTemperature { probability, .. }
+            | Self::Contact { probability, .. } => probability,
+        }
+    }
+
+    /// The reaction in the format used by the GPU.
+    const fn to_gpu(self) -> ShaderReaction {
+        match self {
+            Self::AboveTemperature {
+                material,
+                temperature,
+                becomes,
+                probability,
+            } => ShaderReaction {
+                kind: REACTION_ABOVE_TEMPERATURE,
+                reactant: material,
+                other_reactant: 0,
+                product: becomes,
+                other_product: 0,
+                temperature,
+                probability,
+            },
+            Self::BelowTemperature {
+                material,
+                temperature,
+                becomes,
+                probability,
+            } => ShaderReaction {
+                kind: REACTION_BELOW_TEMPERATURE,
+                reactant: material,
+                other_reactant: 0,
+                product: becomes,
+                other_product: 0,
+                temperature,
+                probability,
+            },
+            Self::Contact {
+                reactants,
+                products,
+                probability,
+            } => ShaderReaction {
+                kind: REACTION_CONTACT,
+                reactant: reactants.0,
+                other_reactant: reactants.1,
+                product: products.0,
+                other_product: products.1,
+                temperature: 0.0,
+                probability,
+            },
+        }
+    }
+}
+
+/// Everything about how materials behave
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Materials {
+    /// The properties of each material, only materials below `MAX_MATERIALS` can have any.
+    pub properties: BTreeMap<Material, MaterialProperties>,
+    /// The reactions, in order of priority. A particle, or a pair of particles, only ever reacts
+    /// once a frame, so when more than one reaction could happen the first one that does wins.
+    pub reactions: Vec<Reaction>,
+}
+
+impl Materials {
+    /// Instantiate
+    #[inline]
+    #[must_use]
+    pub const fn new(reactions: Vec<Reaction>) -> Self {
+        Self {
+            properties: BTreeMap::new(),
+            reactions,
+        }
+    }
+
+    /// Give a material some properties, see `MaterialProperties`
+    #[inline]
+    #[must_use]
+    pub fn material(mut self, material: Material, properties: MaterialProperties) -> Self {
+        self.properties.insert(material, properties);
+        self
+    }
+
+    /// Parse materials from RON, see the module docs for the format.
+    ///
+    /// # Errors
+    /// If the RON can't be parsed, or the materials in it are invalid.
+    #[inline]
+    pub fn from_ron(ron: &[u8]) -> Result<Self, WrachConfigFileError> {
+        let materials: Self = ron::de::from_bytes(ron)?;
+        materials.validate()?;
+        Ok(materials)
+    }
+
+    /// Check that the materials can actually be simulated.
+    ///
+    /// # Errors
+    /// The first thing found that's wrong with the materials.
+    #[inline]
+    pub fn validate(&self) -> Result<(), WrachConfigError> {
+        let is_too_many =
+            u32::try_from(self.reactions.len()).map_or(true, |reactions| reactions > MAX_REACTIONS);
+        if is_too_many {
+            return Err(WrachConfigError::TooManyReactions(self.reactions.len()));
+        }
+
+        for (&material, properties) in &self.properties {
+            if material >= MAX_MATERIALS {
+                return Err(WrachConfigError::MaterialOutOfRange(material));
+            }
+            if !(0.0..=1.0).contains(&properties.cohesion) {
+                return Err(WrachConfigError::NotAFraction("properties.cohesion"));
+            }
+            if !(0.0..=1.0).contains(&properties.static_friction) {
+                return Err(WrachConfigError::NotAFraction("properties.static_friction"));
+            }
+            if !(0.0..=1.0).contains(&properties.dynamic_friction) {
+                return Err(WrachConfigError::NotAFraction(
+                    "properties.dynamic_friction",
+                ));
+            }
+        }
+
+        for reaction in &self.reactions {
+            if !(0.0..=1.0).contains(&reaction.probability()) {
+                return Err(WrachConfigError::NotAFraction("reactions.probability"));
+            }
+            if let Reaction::AboveTemperature { temperature, .. }
+            | Reaction::BelowTemperature { temperature, .. } = *reaction
+            {
+                if !temperature.is_finite() {
+                    return Err(WrachConfigError::NotFinite("reactions.temperature"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The properties of every material below `MAX_MATERIALS` in the format used by the GPU,
+    /// indexed by material.
+    pub(crate) fn properties_to_gpu(&self) -> Vec<ShaderMaterialProperties> {
+        (0..MAX_MATERIALS)
+            .map(|material| {
+                self.properties
+                    .get(&material)
+                    .copied()
+                    .unwrap_or_default()
+                    .to_gpu()
+            })
+            .collect()
+    }
+
+    /// All the reactions in the format used by the GPU.
+    fn reactions_to_gpu(&self) -> Vec<ShaderReaction> {
+        self.reactions
+            .iter()
+            .map(|reaction| reaction.to_gpu())
+            .collect()
+    }
+}
+
+impl WrachState {
+    /// Replace the materials of the simulation, see `Materials`.
+    ///
+    /// # Errors
+    /// If the materials are invalid, see `Materials::validate()`. Nothing is changed if there's an
+    /// error.
+    #[inline]
+    pub fn set_materials(&mut self, materials: Materials) -> Result<(), WrachError> {
+        materials.validate()?;
+        self.gpu_upload(GPUUpload::MaterialProperties(materials.properties_to_gpu()));
+        self.gpu_upload(GPUUpload::Reactions(materials.reactions_to_gpu()));
+        self.materials = materials;
+        Ok(())
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        tests::utils::{particle, simulation_with},
+        CpuSimulation, ParticleProperties, ThermalConfig, WrachConfig,
+    };
+
+    /// Made up materials
+    const WATER: Material = 1;
+    /// Made up materials
+    const LAVA: Material = 2;
+    /// Made up materials
+    const STEAM: Material = 3;
+    /// Made up materials
+    const STONE: Material = 4;
+    /// Made up materials
+    const SAND: Material = 5;
+
+    /// A CPU simulation where nothing conducts or cools
+    fn simulation() -> CpuSimulation {
+        simulation_with(WrachConfig {
+            thermal: ThermalConfig {
+                ambient_temperature: 20.0,
+                conductivity: 0.0,
+                cooling_rate: 0.0,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn parses_reactions_from_ron() {
+        let materials = Materials::from_ron(
+            b"(
+                properties: {1: (cohesion: 0.3)},
+                reactions: [
+                    AboveTemperature(material: 1, temperature: 100.0, becomes: 3, probability: 0.05),
+                    Contact(reactants: (1, 2), products: (3, 4), probability: 1.0),
+                ],
+            )",
+        );
+        assert_eq!(
+            materials.ok(),
+            Some(
+                Materials::new(vec![
+                    Reaction::AboveTemperature {
+                        material: WATER,
+                        temperature: 100.0,
+                        becomes: STEAM,
+                        probability: 0.05,
+                    },
+                    Reaction::Contact {
+                        reactants: (WATER, LAVA),
+                        products: (STEAM, STONE),
+                        probability: 1.0,
+                    },
+                ])
+                .material(WATER, MaterialProperties::default().cohesion(0.3))
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_reactions() {
+        let unlikely = Materials::new(vec![Reaction::Contact {
+            reactants: (WATER, LAVA),
+            products: (STEAM, STONE),
+            probability: 2.0,
+        }]);
+        assert_eq!(
+            unlikely.validate(),
+            Err(WrachConfigError::NotAFraction("reactions.probability"))
+        );
+
+        let out_of_range =
+            Materials::default().material(MAX_MATERIALS, MaterialProperties::default());
+        assert_eq!(
+            out_of_range.validate(),
+            Err(WrachConfigError::MaterialOutOfRange(MAX_MATERIALS))
+        );
+        let too_cohesive =
+            Materials::default().material(WATER, MaterialProperties::default().cohesion(-0.5));
+        assert_eq!(
+            too_cohesive.validate(),
+            Err(WrachConfigError::NotAFraction("properties.cohesion"))
+        );
+
+        let too_many = Materials::new(vec![
+            Reaction::Contact {
+                reactants: (WATER, LAVA),
+                products: (STEAM, STONE),
+                probability: 1.0,
+            };
+            65
+        ]);
+        let mut state = WrachState::new(WrachConfig::default());
+        assert_eq!(
+            state.set_materials(too_many),
+            Err(WrachError::Config(WrachConfigError::TooManyReactions(65)))
+        );
+        assert!(
+            state.gpu_uploads.is_empty(),
+            "Invalid materials aren't uploaded"
+        );
+    }
+
+    #[test]
+    fn water_boils_and_reacts_with_lava() {
+        let mut simulation = simulation();
+        simulation
+            .state
+            .set_materials(Materials::new(vec![
+                Reaction::AboveTemperature {
+                    material: WATER,
+                    temperature: 100.0,
+                    becomes: STEAM,
+                    probability: 1.0,
+                },
+                Reaction::Contact {
+                    reactants: (WATER, LAVA),
+                    products: (STEAM, STONE),
+                    probability: 1.0,
+                },
+            ]))
+            .unwrap();
+        assert_eq!(simulation.state.shader_settings.reactions_count, 2);
+
+        // Touching water and lava, in the same cell
+        simulation.state.add_particles_with(
+            vec![particle(9.5, 10.5)],
+            ParticleProperties::default().material(WATER),
+        );
+        simulation.state.add_particles_with(
+            vec![particle(10.7, 10.5)],
+            ParticleProperties::default().material(LAVA),
+        );
+        // Boiling water on its own
+        simulation.state.add_particles_with(
+            vec![particle(25.5, 25.5)],
+            ParticleProperties::default()
+                .material(WATER)
+                .temperature(150.0),
+        );
+        // Cold water on its own
+        simulation.state.add_particles_with(
+            vec![particle(4.5, 25.5)],
+            ParticleProperties::default().material(WATER),
+        );
+
+        simulation.tick().unwrap();
+        let mut materials = simulation.state.packed_data.materials.clone();
+        materials.sort_unstable();
+        assert_eq!(
+            materials,
+            vec![WATER, WATER, WATER, LAVA],
+            "New particles aren't simulated in the frame that they're added"
+        );
+
+        simulation.tick().unwrap();
+        let mut reacted = simulation.state.packed_data.materials.clone();
+        reacted.sort_unstable();
+        assert_eq!(reacted, vec![WATER, STEAM, STEAM, STONE]);
+    }
+
+    #[test]
+    fn cohesive_particles_pull_together() {
+        let mut simulation = simulation();
+        simulation
+            .state
+            .set_materials(
+                Materials::default().material(WATER, MaterialProperties::default().cohesion(1.0)),
+            )
+            .unwrap();
+        // Two pairs of neighbours, each in a cell of their own
+        simulation.state.add_particles_with(
+            vec![particle(9.5, 10.5), particle(10.9, 10.5)],
+            ParticleProperties::default().material(WATER),
+        );
+        simulation.state.add_particles_with(
+            vec![particle(18.5, 19.5), particle(19.9, 19.5)],
+            ParticleProperties::default().material(SAND),
+        );
+
+        simulation.tick().unwrap();
+        simulation.tick().unwrap();
+        let positions = &simulation.state.packed_data.positions;
+        let distance_between = |material: Material| {
+            let [left, right] = [0, 1].map(|nth| {
+                simulation
+                    .state
+                    .packed_data
+                    .materials
+                    .iter()
+                    .zip(positions)
+                    .filter(|&(particle_material, _)| *particle_material == material)
+                    .nth(nth)
+                    .unwrap()
+                    .1
+            });
+            left.distance(*right)
+        };
+        assert!((distance_between(WATER) - 1.0).abs() < 0.001);
+        assert!(
+            (distance_between(SAND) - 1.4).abs() < 0.001,
+            "Materials without cohesion aren't pulled together"
+        );
+    }
+
+    #[test]
+    fn friction_lets_sand_pile_up() {
+        // The slope of the pile, in degrees, from its peak to the edges of its base
+        let slope_of_pile = |properties: MaterialProperties| {
+            // A single cell, so that every particle can touch every other
+            let mut simulation = CpuSimulation::new(WrachConfig {
+                dimensions: (12, 12),
+                cell_size: 12,
+                gravity: 0.05,
+                ..Default::default()
+            })
+            .unwrap();
+            simulation
+                .state
+                .set_materials(Materials::default().material(SAND, properties))
+                .unwrap();
+
+            // Dump a tightly packed heap, in rows of 4, 3 and 2, from just above the floor
+            let row_height = 3.0_f32.sqrt() / 2.0;
+            let heap = [(4_u8, 0.0), (3, 1.0), (2, 2.0)]
+                .into_iter()
+                .flat_map(|(count, row): (u8, f32)| {
+                    (0..count).map(move |nth| {
+                        particle(
+                            (-0.5_f32).mul_add(f32::from(count - 1), 6.0) + f32::from(nth),
+                            row.mul_add(row_height, 0.5),
+                        )
+                    })
+                })
+                .collect();
+            simulation
+                .state
+                .add_particles_with(heap, ParticleProperties::default().material(SAND));
+            for _ in 0..200 {
+                simulation.tick().unwrap();
+            }
+
+            let (left, right, peak) = simulation.state.packed_data.positions.iter().fold(
+                (f32::MAX, f32::MIN, f32::MIN),
+                |(left, right, peak), position| {
+                    (
+                        left.min(position.x),
+                        right.max(position.x),
+                        peak.max(position.y),
+                    )
+                },
+            );
+            peak.atan2(0.5 * (right - left)).to_degrees()
+        };
+
+        let slippery = slope_of_pile(MaterialProperties::default());
+        assert!(
+            slippery < 5.0,
+            "Without friction the heap flattens out, not {slippery}"
+        );
+        let sand = slope_of_pile(
+            MaterialProperties::default()
+                .static_friction(1.0)
+                .dynamic_friction(1.0),
+        );
+        assert!(
+            sand > 30.0,
+            "With friction the heap keeps its slope, not {sand}"
+        );
+    }
+
+    #[test]
+    fn reactions_that_might_happen_happen_eventually() {
+        let mut simulation = simulation();
+        simulation
+            .state
+            .set_materials(Materials::new(vec![Reaction::BelowTemperature {
+                material: WATER,
+                temperature: 50.0,
+                becomes: STONE,
+                probability: 0.1,
+            }]))
+            .unwrap();
+        let particles = (0_u8..5)
+            .flat_map(|x| {
+                (0_u8..5).map(move |y| {
+                    particle(
+                        f32::from(x).mul_add(5.0, 2.5),
+                        f32::from(y).mul_add(5.0, 2.5),
+                    )
+                })
+            })
+            .collect();
+        simulation
+            .state
+            .add_particles_with(particles, ParticleProperties::default().material(WATER));
+
+        let stones = |reacting: &CpuSimulation| {
+            reacting
+                .state
+                .packed_data
+                .materials
+                .iter()
+                .filter(|material| **material == STONE)
+                .count()
+        };
+        simulation.tick().unwrap();
+        simulation.tick().unwrap();
+        assert!(
+            stones(&simulation) < 25,
+            "Particles shouldn't all react at once"
+        );
+        for _ in 0..100 {
+            simulation.tick().unwrap();
+        }
+        assert_eq!(stones(&simulation), 25);
+    }
+}
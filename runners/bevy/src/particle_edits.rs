@@ -0,0 +1,352 @@
+//! Changes to the particles that are already being simulated, like removing them or pushing them
+//! around. Simulated particles only exist on the GPU, so edits are queued and then applied on the
+//! CPU to the data read back from the frame that the GPU has just run. The edited data is uploaded
+//! in its place, before the GPU runs another frame. So editing particles needs all the buffers to
+//! be read back, with a `WrachConfig::readback_latency` of 0.
+//!
+//! Edits only affect the particles being simulated, not those kept in the particle store.
+
+use bevy::math::{UVec2, Vec2, Vec4Swizzles as _};
+
+use crate::{
+    error::{count_to_u32, WrachError},
+    recording::RecordedInput,
+    spatial_bin::PackedData,
+    state::GPUUpload,
+    Particle, WrachState,
+};
+
+/// A change to all the simulated particles in a circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ParticleEdit {
+    /// Remove the particles
+    Remove {
+        /// The centre of the circle
+        center: Vec2,
+        /// The radius of the circle
+        radius: f32,
+    },
+    /// Push the particles away from the centre, or pull them towards it with a negative strength.
+    /// The push is strongest at the centre and fades to nothing at the edge of the circle.
+    Push {
+        /// The centre of the circle
+        center: Vec2,
+        /// The radius of the circle
+        radius: f32,
+        /// The change in velocity at the centre
+        strength: f32,
+    },
+    /// Move the particles, eg: to follow the mouse. They keep their velocities, otherwise the
+    /// physics would move them all over again.
+    Drag {
+        /// The centre of the circle
+        center: Vec2,
+        /// The radius of the circle
+        radius: f32,
+        /// How far to move the particles
+        offset: Vec2,
+    },
+}
+
+impl ParticleEdit {
+    /// Change a single particle, returns `None` if it's removed.
+    #[expect(
+        clippy::arithmetic_side_effects,
+        reason = "Float vectors don't overflow, they just become infinite"
+    )]
+    fn apply(self, position: Vec2, velocity: Vec2) -> Option<(Vec2, Vec2)> {
+        let (center, radius) = match self {
+            Self::Remove { center, radius }
+            | Self::Push { center, radius, .. }
+            | Self::Drag { center, radius, .. } => (center, radius),
+        };
+        let distance = position.distance(center);
+        if distance > radius {
+            return Some((position, velocity));
+        }
+
+        match self {
+            Self::Remove { .. } => None,
+            Self::Push { strength, .. } => {
+                let falloff = 1.0 - distance / radius;
+                let direction = (position - center).normalize_or_zero();
+                Some((position, velocity + direction * strength * falloff))
+            }
+            Self::Drag { offset, .. } => Some((position + offset, velocity)),
+        }
+    }
+}
+
+impl WrachState {
+    /// Queue a change to the simulated particles, see `ParticleEdit`. It's applied once the data
+    /// for the frame that the GPU is currently running has been read back.
+    #[inline]
+    pub fn edit_particles(&mut self, edit: ParticleEdit) {
+        self.record(|| RecordedInput::EditParticles(edit));
+        self.particle_edits.push(edit);
+    }
+
+    /// Remove all the simulated particles within `radius` of `center`.
+    #[inline]
+    pub fn remove_particles(&mut self, center: Vec2, radius: f32) {
+        self.edit_particles(ParticleEdit::Remove { center, radius });
+    }
+
+    /// Push the simulated particles within `radius` of `center` away from it, or pull them towards
+    /// it with a negative `strength`.
+    #[inline]
+    pub fn push_particles(&mut self, center: Vec2, radius: f32, strength: f32) {
+        self.edit_particles(ParticleEdit::Push {
+            center,
+            radius,
+            strength,
+        });
+    }
+
+    /// Move the simulated particles within `radius` of `center` by `offset`.
+    #[inline]
+    pub fn drag_particles(&mut self, center: Vec2, radius: f32, offset: Vec2) {
+        self.edit_particles(ParticleEdit::Drag {
+            center,
+            radius,
+            offset,
+        });
+    }
+
+    /// Apply the queued edits, and hand the particles that the view has left behind back to the
+    /// particle store, if the GPU has just run a frame and its data has been read back.
+    ///
+    /// # Errors
+    /// If there are edits but not all of the data is read back, or not straight away. The queued
+    /// edits are dropped.
+    pub(crate) fn apply_particle_edits_on_gpu(
+        &mut self,
+        has_gpu_run: bool,
+    ) -> Result<(), WrachError> {
+        if self.particle_edits.is_empty() && !self.has_view_moved {
+            return Ok(());
+        }
+
+        let readback = self.config.readback;
+        let is_read_back = readback.indices
+            && readback.positions
+            && readback.velocities
+            && readback.ages
+            && readback.temperatures
+            && readback.materials;
+        if !is_read_back || self.config.readback_latency != 0 {
+            // The GPU culls the particles outside the view instead, see `set_viewport_anchor()`.
+            self.has_view_moved = false;
+            if self.particle_edits.is_empty() {
+                return Ok(());
+            }
+            self.particle_edits.clear();
+            return Err(WrachError::NeedsReadback);
+        }
+
+        self.apply_particle_edits(has_gpu_run && self.packed_data_frame == self.gpu_frame)
+    }
+
+    /// Apply the queued edits to `packed_data` and upload it in place of the GPU's data. The
+    /// particles are re-packed, because dragged particles may have moved into another cell. They
+    /// keep their ages, temperatures and materials, and when they expire is worked out again from
+    /// the edited data. If the view has moved, the particles that it's left behind are handed back
+    /// to the particle store first, so they aren't edited.
+    ///
+    /// # Errors
+    /// If there are somehow more particles than fit into a `u32`.
+    pub(crate) fn apply_particle_edits(
+        &mut self,
+        is_packed_data_current: bool,
+    ) -> Result<(), WrachError> {
+        if (self.particle_edits.is_empty() && !self.has_view_moved) || !is_packed_data_current {
+            return Ok(());
+        }
+
+        let edits = core::mem::take(&mut self.particle_edits);
+        let is_handing_back = core::mem::take(&mut self.has_view_moved);
+        let mut left_behind = Vec::new();
+        let settings: wrach_cpu_gpu_shared::WorldSettings = self.shader_settings.into();
+        let viewport = self.particle_store.spatial_bin.viewport;
+        let grid = self.particle_store.spatial_bin.grid_dimensions;
+        // The settings only have an upper bound for the number of particles, see `Culling`.
+        let particles_count = self
+            .packed_data
+            .particles_count(grid)
+            .and_then(|count| usize::try_from(count).ok())
+            .unwrap_or(self.packed_data.positions.len());
+
+        let mut particles: Vec<(Vec2, Vec2, UVec2, f32, u32)> = self
+            .packed_data
+            .positions
+            .iter()
+            .copied()
+            .zip(self.packed_data.velocities.iter().copied())
+            .zip(self.packed_data.ages.iter().copied())
+            .zip(self.packed_data.temperatures.iter().copied())
+            .zip(self.packed_data.materials.iter().copied())
+            .take(particles_count)
+            .filter_map(|(((particle, age), temperature), material)| {
+                if is_handing_back && !self.particle_store.spatial_bin.is_in_view(particle.0) {
+                    left_behind.push((particle, age, temperature, material));
+                    return None;
+                }
+                let (position, velocity) = edits
+                    .iter()
+                    .try_fold(particle, |(position, velocity), edit| {
+                        edit.apply(position, velocity)
+                    })?;
+                // The physics only keeps particles inside the viewport, and packing relies on it.
+                Some((
+                    position.clamp(viewport.xy(), viewport.zw()),
+                    velocity,
+                    age,
+                    temperature,
+                    material,
+                ))
+            })
+            .collect();
+        for ((position, velocity), age, temperature, material) in left_behind {
+            self.particle_store.add_particle_with(
+                Particle { position, velocity },
+                age,
+                temperature,
+                material,
+            );
+        }
+        // Sorting is stable, so particles keep their order within their cells.
+        particles.sort_by_key(|&(position, _, _, _, _)| {
+            wrach_physics_shaders::cell_index(position, &settings)
+        });
+
+        let total_cells = usize::try_from(grid.x.saturating_mul(grid.y)).unwrap_or(usize::MAX);
+        // Count each cell's particles into the next item, so that the running total leaves every
+        // cell pointing to its first particle and the extra item at the end holding the total.
+        let mut indices = vec![0_u32; total_cells.saturating_add(1)];
+        for &(position, _, _, _, _) in &particles {
+            let cell = wrach_physics_shaders::cell_index(position, &settings);
+            if let Some(count) = indices.get_mut(cell.saturating_add(1)) {
+                *count = count.saturating_add(1);
+            }
+        }
+        let mut total = 0_u32;
+        for index in &mut indices {
+            total = total.saturating_add(*index);
+            *index = total;
+        }
+
+        self.shader_settings.particles_in_frame_count = count_to_u32("particles", particles.len())?;
+        let mut positions = Vec::with_capacity(particles.len());
+        let mut velocities = Vec::with_capacity(particles.len());
+        let mut ages = Vec::with_capacity(particles.len());
+        let mut temperatures = Vec::with_capacity(particles.len());
+        let mut materials = Vec::with_capacity(particles.len());
+        for (position, velocity, age, temperature, material) in particles {
+            positions.push(position);
+            velocities.push(velocity);
+            ages.push(age);
+            temperatures.push(temperature);
+            materials.push(material);
+        }
+        self.expiries.reset(self.gpu_frame, &ages);
+        self.culling.reset(
+            self.gpu_frame,
+            self.shader_settings.particles_in_frame_count,
+        );
+        self.packed_data = PackedData {
+            indices,
+            positions,
+            velocities,
+            ages,
+            temperatures,
+            materials,
+        };
+        self.gpu_uploads
+            .push(GPUUpload::PackedData(self.packed_data.clone()));
+        self.gpu_uploads
+            .push(GPUUpload::Settings(self.shader_settings));
+        Ok(())
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use super::ParticleEdit;
+    use crate::{
+        tests::utils::{particle, simulation},
+        CpuSimulation,
+    };
+
+    /// A CPU simulation with a still particle at each of the positions.
+    fn simulation_with(positions: &[Vec2]) -> CpuSimulation {
+        let mut simulation = simulation();
+        simulation.add_particles(
+            positions
+                .iter()
+                .map(|position| particle(position.x, position.y))
+                .collect(),
+        );
+        for _ in 0..2 {
+            simulation.tick().unwrap();
+        }
+        simulation
+    }
+
+    #[test]
+    fn pushing_fades_towards_the_edge() {
+        let push = ParticleEdit::Push {
+            center: Vec2::ZERO,
+            radius: 4.0,
+            strength: 1.0,
+        };
+        assert_eq!(
+            push.apply(Vec2::new(1.0, 0.0), Vec2::ZERO),
+            Some((Vec2::new(1.0, 0.0), Vec2::new(0.75, 0.0)))
+        );
+        assert_eq!(
+            push.apply(Vec2::new(0.0, -5.0), Vec2::ZERO),
+            Some((Vec2::new(0.0, -5.0), Vec2::ZERO)),
+            "Particles outside the circle aren't touched"
+        );
+    }
+
+    #[test]
+    fn removing_particles_in_a_circle() {
+        let mut simulation = simulation_with(&[
+            Vec2::new(5.0, 5.0),
+            Vec2::new(6.0, 5.0),
+            Vec2::new(25.0, 25.0),
+        ]);
+
+        simulation.state.remove_particles(Vec2::new(5.5, 5.0), 2.0);
+        simulation.tick().unwrap();
+
+        let state = &simulation.state;
+        assert_eq!(state.shader_settings.particles_in_frame_count, 1);
+        assert_eq!(state.packed_data.positions, vec![Vec2::new(25.0, 25.0)]);
+        assert_eq!(state.packed_data.indices.last(), Some(&1));
+    }
+
+    #[test]
+    fn dragged_particles_move_into_their_new_cells() {
+        let mut simulation = simulation_with(&[Vec2::new(5.0, 5.0), Vec2::new(25.0, 25.0)]);
+
+        simulation
+            .state
+            .drag_particles(Vec2::new(5.0, 5.0), 1.0, Vec2::new(10.0, 10.0));
+        simulation.tick().unwrap();
+
+        let state = &simulation.state;
+        assert_eq!(state.shader_settings.particles_in_frame_count, 2);
+        assert!(state.packed_data.positions.contains(&Vec2::new(25.0, 25.0)));
+        assert!(state.packed_data.positions.contains(&Vec2::new(15.0, 15.0)));
+    }
+}
@@ -1,13 +1,14 @@
 //! A hash store for particles
 
 use bevy::{
-    math::{Vec2, Vec4},
+    math::{UVec2, Vec2, Vec4},
     utils::hashbrown::HashMap,
 };
 
 use crate::{
+    error::{count_to_u32, WrachError},
     spatial_bin::{PackedData, SpatialBin, SpatialBinCoord},
-    Particle,
+    Material, Particle, ThermalConfig,
 };
 
 /// Store of all active particle data. Keyed by Spatial Binning coordinates
@@ -29,18 +30,25 @@ pub struct ParticleStore {
 /// Format of particle data to be stored in the store. This is the same format as it is used on the
 /// GPU. Separating the fields into vectors allows compute and render stages to only read the data
 /// they need. IO is expensive on GPUs.
-#[derive(Default)]
+#[derive(Default, Clone, Debug, PartialEq)]
 pub struct ParticleData {
     /// Vector of particle positions
     pub positions: Vec<Vec2>,
     /// Vector of particle velocities
     pub velocities: Vec<Vec2>,
+    /// Vector of particle ages and lifetimes, see `ParticleProperties::lifetime`
+    pub ages: Vec<UVec2>,
+    /// Vector of particle temperatures, see `ThermalConfig`
+    pub temperatures: Vec<f32>,
+    /// Vector of particle materials, see `Materials`
+    pub materials: Vec<u32>,
 }
 
 impl ParticleStore {
-    /// Instantiate. `cell_size` is the size of a cell in the spatial bin
-    pub fn new(cell_size: u16, viewport: Vec4) -> Self {
-        let spatial_bin = SpatialBin::new(cell_size, viewport);
+    /// Instantiate. `cell_size` is the size of a cell in the spatial bin, in particles of
+    /// `particle_size`.
+    pub fn new(cell_size: u16, particle_size: f32, viewport: Vec4) -> Self {
+        let spatial_bin = SpatialBin::new(cell_size, particle_size, viewport);
         Self {
             spatial_bin,
             hashmap: HashMap::new(),
@@ -50,12 +58,34 @@ impl ParticleStore {
     }
 
     /// Add a particle into the store. It will be placed into the spatial bin cell calculated from
-    /// its position.
+    /// its position. It lives forever, starts at the default ambient temperature and is made of
+    /// the default material.
     pub fn add_particle(&mut self, particle: Particle) {
+        self.add_particle_with(
+            particle,
+            UVec2::ZERO,
+            ThermalConfig::default().ambient_temperature,
+            0,
+        );
+    }
+
+    /// Add a particle that has an age, lifetime, temperature and material into the store.
+    /// Particles don't age, change temperature or react whilst they're in the store, only whilst
+    /// they're simulated.
+    pub fn add_particle_with(
+        &mut self,
+        particle: Particle,
+        age: UVec2,
+        temperature: f32,
+        material: Material,
+    ) {
         let cell_coord = self.spatial_bin.get_cell_coord(particle.position);
         let entry = self.hashmap.entry(cell_coord).or_default();
         entry.positions.push(particle.position);
         entry.velocities.push(particle.velocity);
+        entry.ages.push(age);
+        entry.temperatures.push(temperature);
+        entry.materials.push(material);
     }
 
     /// Add particles to the store. Overwrites previous cell.
@@ -83,58 +113,39 @@ impl ParticleStore {
     //     let particles_count = particles
     //     let particles
     // }
-    #[expect(
-        clippy::expect_used,
-        reason = "`expect`s until there's a way to use `?` in systems"
-    )]
-    pub fn create_packed_data(&mut self) -> PackedData {
-        let data = self.spatial_bin.create_packed_data(self);
+    pub fn create_packed_data(&mut self) -> Result<PackedData, WrachError> {
+        let data = self.spatial_bin.create_packed_data(self)?;
 
         // TODO: move this to `update_from_gpu` once GPU prefix sum is fully working.
-        {
-            self.particles_in_frame_count = data
-                .positions
-                .len()
-                .try_into()
-                .expect("More particles than fit into u32");
-        };
+        self.particles_in_frame_count = count_to_u32("particles", data.positions.len())?;
 
-        data
+        Ok(data)
     }
 
-    /// Calculate the maximum number of particles involved in a single frame. Equal to
-    /// those that can be seen from the viewport and those that make up a border of spatial bin
-    /// cells around the viewport
-    #[expect(
-        clippy::expect_used,
-        reason = "`expect`s until there's a way to use `?` in systems"
-    )]
-    #[expect(
-        clippy::arithmetic_side_effects,
-        reason = "I'm assuming that because of the numbers involved, there won't be any overflow"
-    )]
-    pub fn max_particles_per_frame(&self) -> u32 {
-        // Extra particles to add.
-        // TODO:
-        //   We probably won't need this when we start using Bevy's dynamically-sized buffers.
-        //   See: https://github.com/AnthonyTornetta/bevy_easy_compute/issues/14
-        let extra_percent = 10;
-
+    /// Calculate the number of particles normally involved in a single frame. Equal to those that
+    /// can be seen from the viewport and those that make up a border of spatial bin cells around the
+    /// viewport. It's only the starting size of the GPU buffers, they grow if more particles are
+    /// needed.
+    pub fn max_particles_per_frame(&self) -> Result<u32, WrachError> {
         let (cells, _grid) = self.spatial_bin.get_active_cells();
-        let total_cells: u32 = cells
-            .len()
-            .try_into()
-            .expect("Couldn't convert cell count into u32");
-        let particles_per_cell: u32 = self.spatial_bin.cell_size.pow(2).into();
-        let total_particles_normally = total_cells * particles_per_cell;
-        let one_percent = total_particles_normally.div_ceil(100);
-        let extra_particles = extra_percent * one_percent;
-        total_particles_normally + extra_particles
+        let total_cells = count_to_u32("cells", cells.len())?;
+        let particles_per_cell = u32::from(self.spatial_bin.cell_size).pow(2);
+        total_cells.checked_mul(particles_per_cell).ok_or_else(|| {
+            WrachError::too_many(
+                "particles per frame",
+                usize::try_from(u64::from(total_cells).saturating_mul(particles_per_cell.into()))
+                    .unwrap_or(usize::MAX),
+            )
+        })
     }
 }
 
 #[cfg(test)]
-#[expect(clippy::indexing_slicing, reason = "Tests aren't so strict")]
+#[expect(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    reason = "Tests aren't so strict"
+)]
 mod tests {
     use bevy::math::{Vec2, Vec4};
 
@@ -142,21 +153,21 @@ mod tests {
 
     #[test]
     fn creating_packed_data_for_one_particle_in_middle() {
-        let mut store = ParticleStore::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let mut store = ParticleStore::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         let particle = Particle {
             position: Vec2::new(4.5, 4.5),
             velocity: Vec2::new(1.1, 2.3),
         };
         store.add_particle(particle);
-        let data = store.create_packed_data();
+        let data = store.create_packed_data().unwrap();
 
         #[rustfmt::skip]
         assert_eq!(
             data.indices,
             vec![
             0,  0, 0, 0,
-                0, 0, 1,
-                1, 1, 1,  1
+                0, 1, 1,
+                1, 1, 1
             ]
         );
 
@@ -166,7 +177,7 @@ mod tests {
 
     #[test]
     fn creating_packed_data_for_three_particles_in_middle() {
-        let mut store = ParticleStore::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let mut store = ParticleStore::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         let particle = Particle {
             position: Vec2::new(3.0, 3.0),
             velocity: Vec2::new(1.1, 2.3),
@@ -174,8 +185,8 @@ mod tests {
         store.add_particle(particle);
         store.add_particle(particle);
         store.add_particle(particle);
-        let data = store.create_packed_data();
-        assert_eq!(data.indices, vec![0, 0, 0, 0, 0, 0, 3, 3, 3, 3, 3]);
+        let data = store.create_packed_data().unwrap();
+        assert_eq!(data.indices, vec![0, 0, 0, 0, 0, 3, 3, 3, 3, 3]);
         assert_eq!(data.positions.len(), 3);
         assert_eq!(data.positions[1], particle.position);
         assert_eq!(data.velocities[1], particle.velocity);
@@ -183,7 +194,7 @@ mod tests {
 
     #[test]
     fn creating_packed_data_for_many_particles() {
-        let mut store = ParticleStore::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let mut store = ParticleStore::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
 
         let particle1 = Particle {
             position: Vec2::new(0.0, 1.0),
@@ -203,8 +214,8 @@ mod tests {
         };
         store.add_particle(particle3);
 
-        let data = store.create_packed_data();
-        assert_eq!(data.indices, vec![0, 0, 1, 1, 1, 1, 3, 3, 3, 3, 3]);
+        let data = store.create_packed_data().unwrap();
+        assert_eq!(data.indices, vec![0, 1, 1, 1, 1, 3, 3, 3, 3, 3]);
         assert_eq!(data.positions.len(), 3);
         assert_eq!(data.positions[2], particle3.position);
         assert_eq!(data.velocities[1], particle2.velocity);
@@ -212,7 +223,7 @@ mod tests {
 
     #[test]
     fn creating_packed_data_for_a_particle_offscreen() {
-        let mut store = ParticleStore::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let mut store = ParticleStore::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         store.add_particle(Particle {
             position: Vec2::new(6.1, 6.1),
             velocity: Vec2::default(),
@@ -221,15 +232,15 @@ mod tests {
             position: Vec2::new(9.1, 9.1),
             velocity: Vec2::default(),
         });
-        let data = store.create_packed_data();
-        assert_eq!(data.indices, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let data = store.create_packed_data().unwrap();
+        assert_eq!(data.indices, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
         assert_eq!(data.positions, vec![Vec2::new(6.1, 6.1)]);
         assert_eq!(data.velocities, vec![Vec2::default()]);
     }
 
     #[test]
     fn max_particles_per_frame() {
-        let store = ParticleStore::new(2, Vec4::new(0.0, 0.0, 6.0, 6.0));
-        assert_eq!(store.max_particles_per_frame(), 74);
+        let store = ParticleStore::new(2, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        assert_eq!(store.max_particles_per_frame(), Ok(64));
     }
 }
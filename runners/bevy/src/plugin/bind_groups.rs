@@ -9,7 +9,7 @@ use bevy::{
     render::{
         extract_resource::ExtractResource,
         render_resource::{
-            binding_types::{storage_buffer, uniform_buffer},
+            binding_types::{storage_buffer_read_only, uniform_buffer},
             BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, ShaderStages,
         },
         renderer::RenderDevice,
@@ -19,10 +19,12 @@ use bevy_easy_compute::prelude::*;
 
 use crate::{
     compute::{buffers::Buffers, PhysicsComputeWorker},
-    config_shader::ShaderWorldSettings,
+    config_shader::{ShaderPackedParticle, ShaderWorldSettings},
+    error::WrachError,
 };
 
-/// The bind group layout for the minimal data needed to render particle
+/// The bind group layout for the minimal data needed to render particles, plus the spatial bin
+/// indices for the debug overlay
 #[derive(Resource, ExtractResource, Clone)]
 pub struct ParticleBindGroupLayout {
     /// The bind group layout itself
@@ -38,7 +40,8 @@ impl FromWorld for ParticleBindGroupLayout {
                 ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                 (
                     uniform_buffer::<ShaderWorldSettings>(false),
-                    storage_buffer::<Vec<Vec2>>(false),
+                    storage_buffer_read_only::<Vec<ShaderPackedParticle>>(false),
+                    storage_buffer_read_only::<Vec<u32>>(false),
                 ),
             ),
         );
@@ -54,30 +57,32 @@ pub struct ParticleBindGroup {
     pub bind_group: BindGroup,
 }
 
-#[expect(
-    clippy::expect_used,
-    reason = "`expect`s until there's a way to use `?` in systems"
-)]
-pub fn get_buffers_for_renderer(world: &mut World) {
+/// Make the bind group that gives the renderer the compute worker's buffers.
+pub fn get_buffers_for_renderer(world: &mut World) -> Result<(), WrachError> {
     let render_device = world.resource::<RenderDevice>();
-    let bind_group_layout = world.resource::<ParticleBindGroupLayout>();
-    let compute_worker = world.resource::<AppComputeWorker<PhysicsComputeWorker>>();
+    let bind_group_layout = world
+        .get_resource::<ParticleBindGroupLayout>()
+        .ok_or(WrachError::MissingResource("ParticleBindGroupLayout"))?;
+    let compute_worker = world
+        .get_resource::<AppComputeWorker<PhysicsComputeWorker>>()
+        .ok_or(WrachError::MissingResource("AppComputeWorker"))?;
+    let buffer = |name| {
+        compute_worker
+            .get_buffer(name)
+            .ok_or(WrachError::MissingBuffer(name))
+    };
 
     let bind_group = render_device.create_bind_group(
         None,
         &bind_group_layout.bind_group_layout,
         &BindGroupEntries::sequential((
-            compute_worker
-                .get_buffer(Buffers::WORLD_SETTINGS_UNIFORM)
-                .expect("Couldn't get world settings buffer")
-                .as_entire_binding(),
-            compute_worker
-                .get_buffer(Buffers::POSITIONS_IN)
-                .expect("Couldn't get particle positions buffer")
-                .as_entire_binding(),
+            buffer(Buffers::WORLD_SETTINGS_UNIFORM)?.as_entire_binding(),
+            buffer(Buffers::PARTICLES_IN)?.as_entire_binding(),
+            buffer(Buffers::INDICES_MAIN)?.as_entire_binding(),
         )),
     );
 
     let bindings = ParticleBindGroup { bind_group };
     world.insert_resource(bindings);
+    Ok(())
 }
@@ -4,11 +4,23 @@ use bevy::{asset::embedded_asset, prelude::*};
 use bevy_easy_compute::prelude::*;
 
 use crate::{
-    compute::{buffers::Buffers, PhysicsComputeWorker},
+    compute::{
+        buffers::Buffers,
+        growth::maybe_grow_gpu_buffers,
+        prefix_sum::clear_cell_counts,
+        readback::{readback_from_gpu, ReadbackRing},
+        workgroups::{auto_tune_workgroup_size, WorkgroupSizeTuner},
+        PhysicsComputeWorker,
+    },
+    config_file::{
+        apply_config_file, WrachConfigFile, WrachConfigFileHandle, WrachConfigFileLoader,
+    },
+    config_shader::ShaderPackedParticle,
+    emitters::sinks_buffer,
+    error::{report_errors, WrachError, WrachErrorEvent},
     plugin::bind_groups::get_buffers_for_renderer,
-    spatial_bin::PackedData,
     state::GPUUpload,
-    WrachConfig, WrachState,
+    WorkgroupSize, WrachConfig, WrachState,
 };
 
 use super::bind_groups::ParticleBindGroupLayout;
@@ -18,6 +30,9 @@ use super::bind_groups::ParticleBindGroupLayout;
 pub struct WrachPlugin {
     /// All the user-defineable config for Wrach
     pub config: WrachConfig,
+    /// A `.wrach.ron` file in the `assets/` folder to keep the config in sync with, see
+    /// `WrachConfigFile`
+    pub config_file: Option<String>,
 }
 
 impl Default for WrachPlugin {
@@ -25,6 +40,7 @@ impl Default for WrachPlugin {
     fn default() -> Self {
         Self {
             config: WrachConfig::default(),
+            config_file: None,
         }
     }
 }
@@ -34,7 +50,20 @@ impl WrachPlugin {
     #[must_use]
     #[inline]
     pub const fn new(config: WrachConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            config_file: None,
+        }
+    }
+
+    /// Apply the config in a `.wrach.ron` file once it's loaded, and again whenever it changes.
+    /// Changes that need a restart, like `cell_size`, are reported as errors, so the file should
+    /// start out agreeing with `config` for those.
+    #[must_use]
+    #[inline]
+    pub fn with_config_file<P: Into<String>>(mut self, path: P) -> Self {
+        self.config_file = Some(path.into());
+        self
     }
 }
 
@@ -53,12 +82,43 @@ impl Plugin for WrachPlugin {
         );
         state.types_shader_handle = types_shader_handle;
 
+        app.init_asset::<WrachConfigFile>()
+            .init_asset_loader::<WrachConfigFileLoader>();
+        if let Some(path) = self.config_file.as_ref() {
+            let handle = app.world().resource::<AssetServer>().load(path.clone());
+            app.insert_resource(WrachConfigFileHandle(handle));
+        }
+
         app.insert_resource(state)
+            .add_event::<WrachErrorEvent>()
             .add_plugins(AppComputePlugin)
             .add_plugins(AppComputeWorkerPlugin::<PhysicsComputeWorker>::default())
-            .add_systems(Startup, get_buffers_for_renderer)
-            .add_systems(PreUpdate, maybe_upload_to_gpu)
-            .add_systems(Update, tick);
+            .add_systems(Startup, get_buffers_for_renderer.pipe(report_errors))
+            // Growing happens after uploading so that the new buffers get a copy of everything that
+            // was just uploaded.
+            .init_resource::<ReadbackRing>()
+            // Reading back has to come first, so that the last frame's results are copied before
+            // any uploads overwrite them.
+            .add_systems(
+                PreUpdate,
+                (
+                    readback_from_gpu,
+                    apply_config_file.pipe(report_errors),
+                    run_emitters,
+                    maybe_upload_to_gpu.pipe(report_errors),
+                    maybe_grow_gpu_buffers.pipe(report_errors),
+                    auto_tune_workgroup_size.pipe(report_errors),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                clear_cell_counts.after(AppComputeWorker::<PhysicsComputeWorker>::run),
+            );
+
+        if self.config.workgroup_size == WorkgroupSize::AutoTune {
+            app.init_resource::<WorkgroupSizeTuner>();
+        }
     }
 
     #[inline]
@@ -71,13 +131,19 @@ impl Plugin for WrachPlugin {
 fn embed_shaders(app: &mut App) {
     embedded_asset!(app, "../../../../assets/shaders/wrach_physics_shaders.spv");
     embedded_asset!(app, "../../../../assets/shaders/types.wgsl");
-    embedded_asset!(app, "../../../../assets/shaders/particles_per_cell.wgsl");
     embedded_asset!(app, "../../../../assets/shaders/prefix_sum.wgsl");
     embedded_asset!(
         app,
         "../../../../assets/shaders/pack_new_particle_data.wgsl"
     );
+    embedded_asset!(app, "../../../../assets/shaders/sort_cells.wgsl");
     embedded_asset!(app, "../../../../assets/shaders/draw.wgsl");
+    embedded_asset!(app, "../../../../assets/shaders/debug_overlay.wgsl");
+}
+
+/// Add particles with the emitters, see `WrachState::add_emitter()`.
+fn run_emitters(mut wrach_state: ResMut<WrachState>) {
+    wrach_state.run_emitters();
 }
 
 /// Upload data to the GPU.
@@ -88,9 +154,14 @@ fn embed_shaders(app: &mut App) {
 fn maybe_upload_to_gpu(
     mut compute_worker: ResMut<AppComputeWorker<PhysicsComputeWorker>>,
     mut wrach_state: ResMut<WrachState>,
-) {
+) -> Result<(), WrachError> {
+    // Anything already queued still gets uploaded, even if staging the new particles or editing
+    // the simulated particles failed.
+    let staged = wrach_state.stage_new_particles(compute_worker.ready());
+    let edited = wrach_state.apply_particle_edits_on_gpu(compute_worker.ready());
+
     if wrach_state.gpu_uploads.is_empty() {
-        return;
+        return staged.and(edited);
     }
 
     for upload in &wrach_state.gpu_uploads {
@@ -107,14 +178,76 @@ fn maybe_upload_to_gpu(
                 }
 
                 if !data.positions.is_empty() {
-                    compute_worker.write_slice(Buffers::POSITIONS_IN, &data.positions);
+                    let particles = ShaderPackedParticle::interleave(
+                        &data.positions,
+                        &data.velocities,
+                        &data.ages,
+                        &data.temperatures,
+                        &data.materials,
+                    );
+                    compute_worker.write_slice(Buffers::PARTICLES_IN, &particles);
                 }
+            }
+
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::NewParticles(ref data) => {
+                debug!("Uploading {} new particles", data.positions.len());
+                let particles = ShaderPackedParticle::interleave(
+                    &data.positions,
+                    &data.velocities,
+                    &data.ages,
+                    &data.temperatures,
+                    &data.materials,
+                );
+                compute_worker.write_slice(Buffers::PARTICLES_NEW, &particles);
+            }
 
-                if !data.velocities.is_empty() {
-                    compute_worker.write_slice(Buffers::VELOCITIES_IN, &data.velocities);
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::HeatSources(ref sources) => {
+                debug!("Uploading {} heat sources", sources.len());
+                // The settings say how many of them to use, so old ones don't need clearing.
+                if !sources.is_empty() {
+                    compute_worker.write_slice(Buffers::HEAT_SOURCES, sources);
                 }
             }
 
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::Sinks(ref sinks) => {
+                debug!("Uploading {} sinks", sinks.len());
+                // It's a uniform buffer, so it's always written whole.
+                compute_worker.write(Buffers::SINKS_UNIFORM, &sinks_buffer(sinks));
+            }
+
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::Reactions(ref reactions) => {
+                debug!("Uploading {} reactions", reactions.len());
+                // Like heat sources, the settings say how many of them to use.
+                if !reactions.is_empty() {
+                    compute_worker.write_slice(Buffers::REACTIONS, reactions);
+                }
+            }
+
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::MaterialProperties(ref properties) => {
+                debug!("Uploading the properties of {} materials", properties.len());
+                compute_worker.write_slice(Buffers::MATERIAL_PROPERTIES, properties);
+            }
+
             GPUUpload::Settings(settings) => {
                 debug!("Uploading settings: {:?}", settings);
                 compute_worker.write(Buffers::WORLD_SETTINGS_UNIFORM, &settings);
@@ -123,36 +256,5 @@ fn maybe_upload_to_gpu(
     }
 
     wrach_state.gpu_uploads = Vec::new();
-}
-
-/// What to do for every frame/tick of the simulation
-//
-// Is there a way for a bevy system to receive a reference to a Resource
-#[expect(
-    clippy::needless_pass_by_value,
-    reason = "We have no choice because of the magic Bevy systems function signature"
-)]
-fn tick(
-    compute_worker: Res<AppComputeWorker<PhysicsComputeWorker>>,
-    mut wrach_state: ResMut<WrachState>,
-) {
-    if !compute_worker.ready() {
-        return;
-    };
-
-    let update = PackedData {
-        indices: compute_worker.read_vec(Buffers::INDICES_MAIN),
-        positions: compute_worker.read_vec(Buffers::POSITIONS_IN),
-        velocities: compute_worker.read_vec(Buffers::VELOCITIES_IN),
-    };
-
-    wrach_state.packed_data.indices.clone_from(&update.indices);
-    wrach_state
-        .packed_data
-        .positions
-        .clone_from(&update.positions);
-    wrach_state
-        .packed_data
-        .velocities
-        .clone_from(&update.velocities);
+    staged.and(edited)
 }
@@ -0,0 +1,860 @@
+//! Record everything that goes into a simulation, so that a session can be replayed headlessly,
+//! eg: for bug reports. Together with the config that the simulation started with, the inputs are
+//! all that's needed to reproduce it, especially with `WrachConfig::deterministic`.
+//!
+//! Recordings are saved in a compact binary format, all numbers are little-endian:
+//!   * The magic bytes `WRACHREC` and a version byte.
+//!   * The config as a length-prefixed RON string, see `WrachConfigFile`.
+//!   * The frame that recording started and stopped on.
+//!   * Every input: the frame that it happened on, a byte for its kind, then its data. Edits to
+//!     the simulated particles have another byte for the kind of edit.
+
+use core::{fmt, iter};
+use std::path::Path;
+
+use bevy::math::{UVec2, Vec2, Vec4};
+
+use crate::{
+    config_shader::{ShaderMaterialProperties, ShaderReaction, ShaderWorldSettings},
+    particle_store::ParticleData,
+    spatial_bin::PackedData,
+    state::GPUUpload,
+    Particle, ParticleEdit, ParticleProperties, WrachConfig, WrachConfigFileError, WrachError,
+};
+
+/// Everything that went into a simulation between `WrachState::start_recording()` and
+/// `WrachState::stop_recording()`
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Recording {
+    /// The config that the simulation had when recording started
+    pub config: WrachConfig,
+    /// The GPU frame that recording started on, see `WrachState::gpu_frame`
+    pub first_frame: u64,
+    /// The GPU frame that recording stopped on
+    pub last_frame: u64,
+    /// Every input, in the order that they happened
+    pub events: Vec<RecordedEvent>,
+}
+
+/// A single input into a simulation
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct RecordedEvent {
+    /// The GPU frame that the input happened before
+    pub frame: u64,
+    /// What went into the simulation
+    pub input: RecordedInput,
+}
+
+/// The kinds of things that can go into a simulation
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum RecordedInput {
+    /// `WrachState::add_particles_with()`
+    AddParticles(Vec<Particle>, ParticleProperties),
+    /// `WrachState::set_viewport_anchor()`
+    SetViewportAnchor(Vec2),
+    /// `WrachState::apply_config()`
+    ApplyConfig(WrachConfig),
+    /// `WrachState::gpu_upload()`
+    GpuUpload(GPUUpload),
+    /// `WrachState::edit_particles()`
+    EditParticles(ParticleEdit),
+}
+
+/// The ways in which saving or loading a recording can fail
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RecordingError {
+    /// The file couldn't be read or written
+    Io(std::io::Error),
+    /// The file isn't a Wrach recording
+    NotARecording,
+    /// The recording was made by a version of Wrach that can't be read by this one
+    UnsupportedVersion(u8),
+    /// The recording ends part way through something
+    Truncated,
+    /// The recording contains a kind of input that isn't known
+    UnknownInput(u8),
+    /// The config in the recording couldn't be written as RON
+    Ron(ron::Error),
+    /// The config in the recording couldn't be read
+    Config(WrachConfigFileError),
+    /// There's more of something than fits into the recording's `u32` lengths
+    TooLong(usize),
+    /// An input couldn't be replayed, so the replay has diverged from the recording
+    Replay(WrachError),
+    /// The simulation stopped running frames whilst being replayed
+    Stalled {
+        /// The frame that the simulation got stuck on
+        frame: u64,
+    },
+}
+
+impl fmt::Display for RecordingError {
+    #[inline]
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Io(ref error) => write!(f, "Couldn't access recording: {error}"),
+            Self::NotARecording => write!(f, "Not a Wrach recording"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported recording version: {version}")
+            }
+            Self::Truncated => write!(f, "The recording ends unexpectedly"),
+            Self::UnknownInput(kind) => write!(f, "Unknown kind of recorded input: {kind}"),
+            Self::Ron(ref error) => write!(f, "Couldn't write recorded config: {error}"),
+            Self::Config(ref error) => write!(f, "Couldn't read recorded config: {error}"),
+            Self::TooLong(length) => write!(f, "Too much data to record: {length}"),
+            Self::Replay(ref error) => write!(f, "Couldn't replay recorded input: {error}"),
+            Self::Stalled { frame } => write!(f, "The replay got stuck on frame {frame}"),
+        }
+    }
+}
+
+impl core::error::Error for RecordingError {
+    #[inline]
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref error) => Some(error),
+            Self::Ron(ref error) => Some(error),
+            Self::Config(ref error) => Some(error),
+            Self::Replay(ref error) => Some(error),
+            Self::NotARecording
+            | Self::UnsupportedVersion(_)
+            | Self::Truncated
+            | Self::UnknownInput(_)
+            | Self::TooLong(_)
+            | Self::Stalled { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordingError {
+    #[inline]
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl Recording {
+    /// The first bytes of every recording
+    const MAGIC: &'static [u8; 8] = b"WRACHREC";
+
+    /// The version of the format, for when it changes
+    const VERSION: u8 = 8;
+
+    /// Start a recording of a simulation with `config`, that is currently on `frame`.
+    pub(crate) const fn new(config: WrachConfig, frame: u64) -> Self {
+        Self {
+            config,
+            first_frame: frame,
+            last_frame: frame,
+            events: Vec::new(),
+        }
+    }
+
+    /// Add an input that happened before `frame`.
+    pub(crate) fn record(&mut self, frame: u64, input: RecordedInput) {
+        self.events.push(RecordedEvent { frame, input });
+        self.last_frame = frame;
+    }
+
+    /// Save to a file.
+    ///
+    /// # Errors
+    /// If the file can't be written, or there's too much to fit in the format.
+    #[inline]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RecordingError> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Load from a file made with `save()`.
+    ///
+    /// # Errors
+    /// If the file can't be read, or isn't a valid recording.
+    #[inline]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RecordingError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Encode in the recording format, see the module docs.
+    ///
+    /// # Errors
+    /// If there's too much of something to fit in the format.
+    #[inline]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RecordingError> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(Self::MAGIC);
+        writer.u8(Self::VERSION);
+        writer.config(&self.config)?;
+        writer.u64(self.first_frame);
+        writer.u64(self.last_frame);
+
+        for event in &self.events {
+            writer.u64(event.frame);
+            match event.input {
+                #[expect(
+                    clippy::ref_patterns,
+                    reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+                )]
+                RecordedInput::AddParticles(ref particles, properties) => {
+                    writer.u8(0);
+                    // A lifetime of 0 means forever, the same as on the GPU.
+                    writer.u32(properties.lifetime.unwrap_or(0));
+                    writer.optional_f32(properties.temperature);
+                    writer.u32(properties.material);
+                    writer.length(particles.len())?;
+                    for particle in particles {
+                        writer.vec2(particle.position);
+                        writer.vec2(particle.velocity);
+                    }
+                }
+                RecordedInput::SetViewportAnchor(anchor) => {
+                    writer.u8(1);
+                    writer.vec2(anchor);
+                }
+                RecordedInput::ApplyConfig(config) => {
+                    writer.u8(2);
+                    writer.config(&config)?;
+                }
+                #[expect(
+                    clippy::ref_patterns,
+                    reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+                )]
+                RecordedInput::GpuUpload(ref upload) => writer.upload(upload)?,
+                RecordedInput::EditParticles(edit) => writer.edit(edit),
+            }
+        }
+
+        Ok(writer.bytes)
+    }
+
+    /// Decode from the recording format, see the module docs.
+    ///
+    /// # Errors
+    /// If the bytes aren't a valid recording.
+    #[inline]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RecordingError> {
+        let mut reader = Reader { bytes };
+        if reader.take(Self::MAGIC.len()).ok() != Some(Self::MAGIC.as_slice()) {
+            return Err(RecordingError::NotARecording);
+        }
+        let version = reader.u8()?;
+        if version != Self::VERSION {
+            return Err(RecordingError::UnsupportedVersion(version));
+        }
+
+        let mut recording = Self::new(reader.config()?, reader.u64()?);
+        recording.last_frame = reader.u64()?;
+
+        while !reader.bytes.is_empty() {
+            let frame = reader.u64()?;
+            let input = match reader.u8()? {
+                0 => {
+                    let lifetime = reader.u32()?;
+                    let temperature = reader.optional_f32()?;
+                    let material = reader.u32()?;
+                    let count = reader.length()?;
+                    let mut particles = Vec::with_capacity(count.min(reader.bytes.len()));
+                    for _ in 0..count {
+                        particles.push(Particle {
+                            position: reader.vec2()?,
+                            velocity: reader.vec2()?,
+                        });
+                    }
+                    let mut properties = match lifetime {
+                        0 => ParticleProperties::default(),
+                        frames => ParticleProperties::default().lifetime(frames),
+                    };
+                    properties.temperature = temperature;
+                    properties.material = material;
+                    RecordedInput::AddParticles(particles, properties)
+                }
+                1 => RecordedInput::SetViewportAnchor(reader.vec2()?),
+                2 => RecordedInput::ApplyConfig(reader.config()?),
+                3 => RecordedInput::GpuUpload(GPUUpload::Settings(reader.settings()?)),
+                4 => RecordedInput::GpuUpload(GPUUpload::PackedData(PackedData {
+                    indices: reader.u32s()?,
+                    positions: reader.vec2s()?,
+                    velocities: reader.vec2s()?,
+                    ages: reader.uvec2s()?,
+                    temperatures: reader.f32s()?,
+                    materials: reader.u32s()?,
+                })),
+                5 => RecordedInput::GpuUpload(GPUUpload::NewParticles(ParticleData {
+                    positions: reader.vec2s()?,
+                    velocities: reader.vec2s()?,
+                    ages: reader.uvec2s()?,
+                    temperatures: reader.f32s()?,
+                    materials: reader.u32s()?,
+                })),
+                6 => RecordedInput::EditParticles(reader.edit()?),
+                7 => RecordedInput::GpuUpload(GPUUpload::HeatSources(reader.vec4s()?)),
+                8 => RecordedInput::GpuUpload(GPUUpload::Reactions(reader.reactions()?)),
+                9 => RecordedInput::GpuUpload(GPUUpload::MaterialProperties(
+                    reader.material_properties()?,
+                )),
+                10 => RecordedInput::GpuUpload(GPUUpload::Sinks(reader.vec4s()?)),
+                kind => return Err(RecordingError::UnknownInput(kind)),
+            };
+            recording.events.push(RecordedEvent { frame, input });
+        }
+
+        Ok(recording)
+    }
+}
+
+/// Builds up the bytes of a recording
+#[derive(Default)]
+struct Writer {
+    /// Everything written so far
+    bytes: Vec<u8>,
+}
+
+#[expect(
+    clippy::little_endian_bytes,
+    reason = "The format is little-endian, whatever the platform"
+)]
+impl Writer {
+    /// Write a single byte
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    /// Write a `u32`
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a `u64`
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write an `f32`
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write an `f32` that might not be there, preceded by a byte for whether it is
+    fn optional_f32(&mut self, value: Option<f32>) {
+        self.u8(value.is_some().into());
+        self.f32(value.unwrap_or(0.0));
+    }
+
+    /// Write the length of a list
+    #[expect(
+        clippy::map_err_ignore,
+        reason = "`TryFromIntError` doesn't say anything that the length doesn't"
+    )]
+    fn length(&mut self, length: usize) -> Result<(), RecordingError> {
+        self.u32(u32::try_from(length).map_err(|_| RecordingError::TooLong(length))?);
+        Ok(())
+    }
+
+    /// Write a 2D vector
+    fn vec2(&mut self, value: Vec2) {
+        self.f32(value.x);
+        self.f32(value.y);
+    }
+
+    /// Write a list of 2D vectors
+    fn vec2s(&mut self, values: &[Vec2]) -> Result<(), RecordingError> {
+        self.length(values.len())?;
+        for value in values {
+            self.vec2(*value);
+        }
+        Ok(())
+    }
+
+    /// Write a list of `f32`s
+    fn f32s(&mut self, values: &[f32]) -> Result<(), RecordingError> {
+        self.length(values.len())?;
+        for value in values {
+            self.f32(*value);
+        }
+        Ok(())
+    }
+
+    /// Write a list of `u32`s
+    fn u32s(&mut self, values: &[u32]) -> Result<(), RecordingError> {
+        self.length(values.len())?;
+        for value in values {
+            self.u32(*value);
+        }
+        Ok(())
+    }
+
+    /// Write a list of 4D vectors
+    fn vec4s(&mut self, values: &[Vec4]) -> Result<(), RecordingError> {
+        self.length(values.len())?;
+        for value in values {
+            self.f32(value.x);
+            self.f32(value.y);
+            self.f32(value.z);
+            self.f32(value.w);
+        }
+        Ok(())
+    }
+
+    /// Write a list of 2D integer vectors
+    fn uvec2s(&mut self, values: &[UVec2]) -> Result<(), RecordingError> {
+        self.length(values.len())?;
+        for value in values {
+            self.u32(value.x);
+            self.u32(value.y);
+        }
+        Ok(())
+    }
+
+    /// Write a config as RON
+    fn config(&mut self, config: &WrachConfig) -> Result<(), RecordingError> {
+        let ron = ron::to_string(config).map_err(RecordingError::Ron)?;
+        self.length(ron.len())?;
+        self.bytes.extend_from_slice(ron.as_bytes());
+        Ok(())
+    }
+
+    /// Write an upload to the GPU, including the byte for its kind
+    fn upload(&mut self, upload: &GPUUpload) -> Result<(), RecordingError> {
+        match *upload {
+            GPUUpload::Settings(settings) => {
+                self.u8(3);
+                self.vec2(settings.view_dimensions);
+                self.vec2(settings.view_anchor);
+                self.u32(settings.grid_dimensions.x);
+                self.u32(settings.grid_dimensions.y);
+                self.f32(settings.cell_size);
+                self.u32(settings.particles_in_frame_count);
+                self.u32(settings.new_particles_count);
+                self.f32(settings.ambient_temperature);
+                self.f32(settings.thermal_conductivity);
+                self.f32(settings.cooling_rate);
+                self.u32(settings.heat_sources_count);
+                self.u32(settings.reactions_count);
+                self.u32(settings.sinks_count);
+                self.u32(settings.random_seed);
+                self.f32(settings.gravity);
+                self.f32(settings.particle_radius);
+                self.f32(settings.max_speed);
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::PackedData(ref data) => {
+                self.u8(4);
+                self.u32s(&data.indices)?;
+                self.vec2s(&data.positions)?;
+                self.vec2s(&data.velocities)?;
+                self.uvec2s(&data.ages)?;
+                self.f32s(&data.temperatures)?;
+                self.u32s(&data.materials)?;
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::NewParticles(ref data) => {
+                self.u8(5);
+                self.vec2s(&data.positions)?;
+                self.vec2s(&data.velocities)?;
+                self.uvec2s(&data.ages)?;
+                self.f32s(&data.temperatures)?;
+                self.u32s(&data.materials)?;
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::HeatSources(ref sources) => {
+                self.u8(7);
+                self.vec4s(sources)?;
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::Sinks(ref sinks) => {
+                self.u8(10);
+                self.vec4s(sinks)?;
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::Reactions(ref reactions) => {
+                self.u8(8);
+                self.length(reactions.len())?;
+                for reaction in reactions {
+                    self.u32(reaction.kind);
+                    self.u32(reaction.reactant);
+                    self.u32(reaction.other_reactant);
+                    self.u32(reaction.product);
+                    self.u32(reaction.other_product);
+                    self.f32(reaction.temperature);
+                    self.f32(reaction.probability);
+                }
+            }
+            #[expect(
+                clippy::ref_patterns,
+                reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+            )]
+            GPUUpload::MaterialProperties(ref properties) => {
+                self.u8(9);
+                self.length(properties.len())?;
+                for material in properties {
+                    self.f32(material.cohesion);
+                    self.f32(material.static_friction);
+                    self.f32(material.dynamic_friction);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an edit to the simulated particles, including the byte for its kind and another for
+    /// the kind of edit
+    fn edit(&mut self, edit: ParticleEdit) {
+        self.u8(6);
+        match edit {
+            ParticleEdit::Remove { center, radius } => {
+                self.u8(0);
+                self.vec2(center);
+                self.f32(radius);
+            }
+            ParticleEdit::Push {
+                center,
+                radius,
+                strength,
+            } => {
+                self.u8(1);
+                self.vec2(center);
+                self.f32(radius);
+                self.f32(strength);
+            }
+            ParticleEdit::Drag {
+                center,
+                radius,
+                offset,
+            } => {
+                self.u8(2);
+                self.vec2(center);
+                self.f32(radius);
+                self.vec2(offset);
+            }
+        }
+    }
+}
+
+/// Reads the bytes of a recording from the front
+struct Reader<'bytes> {
+    /// Everything that hasn't been read yet
+    bytes: &'bytes [u8],
+}
+
+#[expect(
+    clippy::little_endian_bytes,
+    reason = "The format is little-endian, whatever the platform"
+)]
+impl<'bytes> Reader<'bytes> {
+    /// Read a number of bytes
+    fn take(&mut self, count: usize) -> Result<&'bytes [u8], RecordingError> {
+        let (taken, rest) = self
+            .bytes
+            .split_at_checked(count)
+            .ok_or(RecordingError::Truncated)?;
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// Read a fixed number of bytes
+    #[expect(
+        clippy::map_err_ignore,
+        reason = "`take()` already checked the length, so this can't actually fail"
+    )]
+    fn array<const COUNT: usize>(&mut self) -> Result<[u8; COUNT], RecordingError> {
+        self.take(COUNT)?
+            .try_into()
+            .map_err(|_| RecordingError::Truncated)
+    }
+
+    /// Read a single byte
+    fn u8(&mut self) -> Result<u8, RecordingError> {
+        Ok(u8::from_le_bytes(self.array()?))
+    }
+
+    /// Read a `u32`
+    fn u32(&mut self) -> Result<u32, RecordingError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    /// Read a `u64`
+    fn u64(&mut self) -> Result<u64, RecordingError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// Read an `f32`
+    fn f32(&mut self) -> Result<f32, RecordingError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    /// Read an `f32` that might not be there, see `Writer::optional_f32()`
+    fn optional_f32(&mut self) -> Result<Option<f32>, RecordingError> {
+        let is_some = self.u8()? != 0;
+        let value = self.f32()?;
+        Ok(is_some.then_some(value))
+    }
+
+    /// Read the length of a list
+    #[expect(
+        clippy::map_err_ignore,
+        reason = "A length that doesn't fit in memory can't have been written on this platform"
+    )]
+    fn length(&mut self) -> Result<usize, RecordingError> {
+        let length = self.u32()?;
+        usize::try_from(length).map_err(|_| RecordingError::Truncated)
+    }
+
+    /// Read a 2D vector
+    fn vec2(&mut self) -> Result<Vec2, RecordingError> {
+        Ok(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    /// Read a list, preceded by its length, reading each item with `read`
+    fn list<T, F>(&mut self, mut read: F) -> Result<Vec<T>, RecordingError>
+    where
+        F: FnMut(&mut Self) -> Result<T, RecordingError>,
+    {
+        let count = self.length()?;
+        iter::repeat_with(|| read(self)).take(count).collect()
+    }
+
+    /// Read a list of 2D vectors
+    fn vec2s(&mut self) -> Result<Vec<Vec2>, RecordingError> {
+        self.list(Self::vec2)
+    }
+
+    /// Read a list of `f32`s
+    fn f32s(&mut self) -> Result<Vec<f32>, RecordingError> {
+        self.list(Self::f32)
+    }
+
+    /// Read a list of 4D vectors
+    fn vec4s(&mut self) -> Result<Vec<Vec4>, RecordingError> {
+        self.list(|reader| {
+            Ok(Vec4::new(
+                reader.f32()?,
+                reader.f32()?,
+                reader.f32()?,
+                reader.f32()?,
+            ))
+        })
+    }
+
+    /// Read a list of 2D integer vectors
+    fn uvec2s(&mut self) -> Result<Vec<UVec2>, RecordingError> {
+        self.list(|reader| Ok(UVec2::new(reader.u32()?, reader.u32()?)))
+    }
+
+    /// Read a list of `u32`s
+    fn u32s(&mut self) -> Result<Vec<u32>, RecordingError> {
+        self.list(Self::u32)
+    }
+
+    /// Read a list of reactions
+    fn reactions(&mut self) -> Result<Vec<ShaderReaction>, RecordingError> {
+        self.list(|reader| {
+            Ok(ShaderReaction {
+                kind: reader.u32()?,
+                reactant: reader.u32()?,
+                other_reactant: reader.u32()?,
+                product: reader.u32()?,
+                other_product: reader.u32()?,
+                temperature: reader.f32()?,
+                probability: reader.f32()?,
+            })
+        })
+    }
+
+    /// Read the properties of a list of materials
+    fn material_properties(&mut self) -> Result<Vec<ShaderMaterialProperties>, RecordingError> {
+        self.list(|reader| {
+            Ok(ShaderMaterialProperties {
+                cohesion: reader.f32()?,
+                static_friction: reader.f32()?,
+                dynamic_friction: reader.f32()?,
+            })
+        })
+    }
+
+    /// Read a config written as RON
+    fn config(&mut self) -> Result<WrachConfig, RecordingError> {
+        let length = self.length()?;
+        WrachConfig::from_ron(self.take(length)?).map_err(RecordingError::Config)
+    }
+
+    /// Read the settings of a settings upload
+    fn settings(&mut self) -> Result<ShaderWorldSettings, RecordingError> {
+        Ok(ShaderWorldSettings {
+            view_dimensions: self.vec2()?,
+            view_anchor: self.vec2()?,
+            grid_dimensions: UVec2::new(self.u32()?, self.u32()?),
+            cell_size: self.f32()?,
+            particles_in_frame_count: self.u32()?,
+            new_particles_count: self.u32()?,
+            ambient_temperature: self.f32()?,
+            thermal_conductivity: self.f32()?,
+            cooling_rate: self.f32()?,
+            heat_sources_count: self.u32()?,
+            reactions_count: self.u32()?,
+            sinks_count: self.u32()?,
+            random_seed: self.u32()?,
+            gravity: self.f32()?,
+            particle_radius: self.f32()?,
+            max_speed: self.f32()?,
+        })
+    }
+
+    /// Read an edit to the simulated particles
+    fn edit(&mut self) -> Result<ParticleEdit, RecordingError> {
+        let kind = self.u8()?;
+        let center = self.vec2()?;
+        let radius = self.f32()?;
+        Ok(match kind {
+            0 => ParticleEdit::Remove { center, radius },
+            1 => ParticleEdit::Push {
+                center,
+                radius,
+                strength: self.f32()?,
+            },
+            2 => ParticleEdit::Drag {
+                center,
+                radius,
+                offset: self.vec2()?,
+            },
+            _ => return Err(RecordingError::UnknownInput(6)),
+        })
+    }
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, reason = "Tests aren't so strict")]
+mod test {
+    use super::*;
+    use crate::{HeatSource, MaterialProperties, Materials, Reaction, Sink, WrachState};
+
+    /// A state that's recorded one of each kind of input.
+    fn recorded_state() -> WrachState {
+        let mut state = WrachState::new(WrachConfig::default());
+        state.start_recording();
+        state.add_particles_with(
+            vec![Particle {
+                position: Vec2::new(1.5, 2.5),
+                velocity: Vec2::new(-0.5, 0.25),
+            }],
+            ParticleProperties::default()
+                .lifetime(60)
+                .temperature(80.0)
+                .material(2),
+        );
+        state.gpu_frame = 3;
+        state.set_viewport_anchor(Vec2::new(10.0, 20.0));
+        state
+            .apply_config(WrachConfig {
+                readback_latency: 1,
+                ..WrachConfig::default()
+            })
+            .unwrap();
+        state.gpu_upload(GPUUpload::Settings(state.shader_settings));
+        state.gpu_upload(GPUUpload::PackedData(PackedData {
+            indices: vec![0, 1],
+            positions: vec![Vec2::ONE],
+            velocities: vec![Vec2::NEG_ONE],
+            ages: vec![UVec2::new(2, 10)],
+            temperatures: vec![35.0],
+            materials: vec![1],
+        }));
+        state.gpu_upload(GPUUpload::NewParticles(ParticleData {
+            positions: vec![Vec2::X],
+            velocities: vec![Vec2::Y],
+            ages: vec![UVec2::ZERO],
+            temperatures: vec![-5.0],
+            materials: vec![3],
+        }));
+        state
+            .add_heat_source(HeatSource::new(Vec2::new(6.0, 7.0), 3.0, 0.5))
+            .unwrap();
+        state.add_sink(Sink::new(Vec2::new(8.0, 9.0), 1.5)).unwrap();
+        state
+            .set_materials(
+                Materials::new(vec![Reaction::Contact {
+                    reactants: (1, 2),
+                    products: (3, 4),
+                    probability: 0.5,
+                }])
+                .material(
+                    1,
+                    MaterialProperties::default()
+                        .cohesion(0.25)
+                        .static_friction(0.5)
+                        .dynamic_friction(0.25),
+                ),
+            )
+            .unwrap();
+        state.push_particles(Vec2::new(3.0, 4.0), 2.0, -0.5);
+        state.gpu_frame = 5;
+        state
+    }
+
+    #[test]
+    fn records_inputs_with_their_frame() {
+        let recording = recorded_state().stop_recording().unwrap();
+        assert_eq!(recording.events.len(), 11);
+        assert_eq!(recording.events.first().map(|event| event.frame), Some(0));
+        assert_eq!(recording.events.get(1).map(|event| event.frame), Some(3));
+        assert_eq!(recording.last_frame, 5);
+    }
+
+    #[test]
+    fn survives_the_round_trip_through_bytes() {
+        let recording = recorded_state().stop_recording().unwrap();
+        let bytes = recording.to_bytes().unwrap();
+        assert_eq!(Recording::from_bytes(&bytes).unwrap(), recording);
+    }
+
+    #[test]
+    fn rejects_bad_bytes() {
+        assert!(
+            matches!(
+                Recording::from_bytes(b"not a recording"),
+                Err(RecordingError::NotARecording)
+            ),
+            "Magic bytes should be checked"
+        );
+
+        let bytes = recorded_state()
+            .stop_recording()
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let (_, truncated) = bytes.split_last().unwrap();
+        assert!(
+            matches!(
+                Recording::from_bytes(truncated),
+                Err(RecordingError::Truncated)
+            ),
+            "Half an input shouldn't be read"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! How particles with a lifetime change colour as they age, see `ParticleProperties::lifetime`.
+
+#![expect(
+    clippy::shadow_reuse,
+    reason = "The `ShaderType` derive shadows the uniform's fields in the code it generates"
+)]
+
+use bevy::{
+    color::ColorToComponents as _,
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{
+            binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayout,
+            BindGroupLayoutEntries, ShaderStages, ShaderType, UniformBuffer,
+        },
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+
+/// The colour of particles over their lifetime.
+///
+/// Particles are drawn in `young` when they're added and fade into `old` at the end of their
+/// lifetime. Particles that live forever are always drawn in `young`. Change it at runtime like any
+/// other resource. Needs the `DrawPlugin`.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct WrachAgeingCurve {
+    /// The colour of new particles
+    pub young: LinearRgba,
+    /// The colour of particles at the end of their lifetime
+    pub old: LinearRgba,
+    /// The shape of the fade. At 1 it's linear, higher values stay young for longer and lower
+    /// values age quickly.
+    pub exponent: f32,
+}
+
+impl Default for WrachAgeingCurve {
+    /// White particles that fade away.
+    #[inline]
+    fn default() -> Self {
+        Self {
+            young: LinearRgba::WHITE,
+            old: LinearRgba::new(1.0, 1.0, 1.0, 0.0),
+            exponent: 1.0,
+        }
+    }
+}
+
+/// The ageing curve in the layout that `draw.wgsl` expects
+#[derive(ShaderType, Clone, Copy, Default)]
+struct AgeingCurveUniform {
+    /// See `WrachAgeingCurve::young`
+    young: Vec4,
+    /// See `WrachAgeingCurve::old`
+    old: Vec4,
+    /// See `WrachAgeingCurve::exponent`
+    exponent: f32,
+}
+
+impl From<WrachAgeingCurve> for AgeingCurveUniform {
+    fn from(curve: WrachAgeingCurve) -> Self {
+        Self {
+            young: curve.young.to_vec4(),
+            old: curve.old.to_vec4(),
+            exponent: curve.exponent,
+        }
+    }
+}
+
+/// The layout of the ageing curve's bind group, the second bind group of `draw.wgsl`
+#[derive(Resource)]
+pub struct AgeingCurveBindGroupLayout {
+    /// The bind group layout itself
+    pub bind_group_layout: BindGroupLayout,
+}
+
+impl FromWorld for AgeingCurveBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "AgeingCurveLayout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                uniform_buffer::<AgeingCurveUniform>(false),
+            ),
+        );
+
+        Self { bind_group_layout }
+    }
+}
+
+/// The ageing curve on the GPU
+#[derive(Resource, Default)]
+pub struct AgeingCurveBindGroup {
+    /// The uniform buffer holding the curve
+    buffer: UniformBuffer<AgeingCurveUniform>,
+    /// The curve that's currently in the buffer
+    uploaded: Option<WrachAgeingCurve>,
+    /// The bind group itself, once the curve has been uploaded
+    bind_group: Option<BindGroup>,
+}
+
+impl AgeingCurveBindGroup {
+    /// The bind group itself, once the curve has been uploaded
+    #[inline]
+    #[must_use]
+    pub const fn bind_group(&self) -> Option<&BindGroup> {
+        self.bind_group.as_ref()
+    }
+}
+
+/// Upload the ageing curve whenever it changes.
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "Bevy's magic system function signature can't be changed"
+)]
+pub fn prepare_ageing_curve(
+    curve: Res<WrachAgeingCurve>,
+    curve_layout: Option<Res<AgeingCurveBindGroupLayout>>,
+    mut ageing_curve: ResMut<AgeingCurveBindGroup>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(layout) = curve_layout else {
+        return;
+    };
+    if ageing_curve.uploaded == Some(*curve) {
+        return;
+    }
+
+    ageing_curve.buffer.set((*curve).into());
+    ageing_curve
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+    // The buffer is only created by its first write.
+    let bind_group = ageing_curve.buffer.binding().map(|binding| {
+        render_device.create_bind_group(
+            "AgeingCurve",
+            &layout.bind_group_layout,
+            &BindGroupEntries::single(binding),
+        )
+    });
+    ageing_curve.bind_group = bind_group;
+    ageing_curve.uploaded = Some(*curve);
+}
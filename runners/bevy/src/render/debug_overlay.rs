@@ -0,0 +1,250 @@
+//! A debug overlay for tuning `WrachConfig::cell_size`. It draws the spatial bin grid over the
+//! particles, along with how full each cell is.
+
+use bevy::{
+    asset::DirectAssetAccessExt as _,
+    ecs::query::QueryItem,
+    image::BevyDefault as _,
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_graph::{self, RenderGraphContext, RenderLabel},
+        render_resource::{
+            BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState,
+            MultisampleState, PipelineCache, PrimitiveState, RenderPassDescriptor,
+            RenderPipelineDescriptor, ShaderDefVal, TextureFormat, VertexState,
+        },
+        renderer::RenderContext,
+        view::ViewTarget,
+    },
+};
+use wrach_physics_shaders::MAX_PARTICLES_IN_CELL;
+
+use crate::plugin::bind_groups::{ParticleBindGroup, ParticleBindGroupLayout};
+
+/// Which layers of the debug overlay to draw over the particles. Change it at runtime like any
+/// other resource. Needs the `DrawPlugin`.
+///
+/// The overlay reads the spatial bin indices straight from the GPU, so it doesn't need
+/// `WrachConfig::readback`.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "They're independent toggles, not a state machine"
+)]
+pub struct WrachDebugOverlay {
+    /// The boundaries of the spatial bin cells
+    pub grid: bool,
+    /// A heat map of how many particles are in each cell, from blue for a single particle to red
+    /// for a full cell
+    pub occupancy: bool,
+    /// Cells with more particles than get the full physics, see `WrachStats::overflowed_cells`
+    pub overflow: bool,
+    /// An outline of the viewport, which is all that's simulated
+    pub viewport: bool,
+}
+
+impl WrachDebugOverlay {
+    /// Draw every layer.
+    pub const ALL: Self = Self {
+        grid: true,
+        occupancy: true,
+        overflow: true,
+        viewport: true,
+    };
+
+    /// Don't draw anything, the default.
+    pub const NONE: Self = Self {
+        grid: false,
+        occupancy: false,
+        overflow: false,
+        viewport: false,
+    };
+
+    /// Whether any of the layers are drawn.
+    #[inline]
+    #[must_use]
+    pub const fn is_enabled(self) -> bool {
+        self.grid || self.occupancy || self.overflow || self.viewport
+    }
+
+    /// Switch between drawing everything and drawing nothing, eg: from a key press.
+    #[inline]
+    pub const fn toggle(&mut self) {
+        *self = if self.is_enabled() {
+            Self::NONE
+        } else {
+            Self::ALL
+        };
+    }
+
+    /// Each of the layers with whether it's drawn, in the order they're drawn.
+    const fn layers(
+        self,
+        pipelines: &DebugOverlayPipelines,
+    ) -> [(bool, CachedRenderPipelineId); 4] {
+        [
+            (self.occupancy, pipelines.occupancy),
+            (self.overflow, pipelines.overflow),
+            (self.grid, pipelines.grid),
+            (self.viewport, pipelines.viewport),
+        ]
+    }
+}
+
+/// A render pipeline for each layer of the overlay. The layers only differ in their fragment
+/// shader.
+#[derive(Resource)]
+pub struct DebugOverlayPipelines {
+    /// See `WrachDebugOverlay::grid`
+    grid: CachedRenderPipelineId,
+    /// See `WrachDebugOverlay::occupancy`
+    occupancy: CachedRenderPipelineId,
+    /// See `WrachDebugOverlay::overflow`
+    overflow: CachedRenderPipelineId,
+    /// See `WrachDebugOverlay::viewport`
+    viewport: CachedRenderPipelineId,
+}
+
+impl FromWorld for DebugOverlayPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let layout = world
+            .resource::<ParticleBindGroupLayout>()
+            .bind_group_layout
+            .clone();
+        let shader = world.load_asset(
+            "embedded://wrach_bevy/plugin/../../../../assets/shaders/debug_overlay.wgsl",
+        );
+        let shader_defs = vec![ShaderDefVal::UInt(
+            "MAX_PARTICLES_IN_CELL".into(),
+            u32::try_from(MAX_PARTICLES_IN_CELL).unwrap_or(u32::MAX),
+        )];
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let queue_layer = |entry_point: &'static str| {
+            pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some(format!("wrach_debug_overlay_{entry_point}").into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: Vec::new(),
+                vertex: VertexState {
+                    shader: shader.clone(),
+                    entry_point: "vertex".into(),
+                    shader_defs: shader_defs.clone(),
+                    buffers: vec![],
+                },
+                fragment: Some(FragmentState {
+                    shader: shader.clone(),
+                    shader_defs: shader_defs.clone(),
+                    entry_point: entry_point.into(),
+                    targets: vec![Some(ColorTargetState {
+                        format: TextureFormat::bevy_default(),
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                // The same as the particles, see `DrawParticlePipeline`
+                multisample: MultisampleState {
+                    count: 4,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                zero_initialize_workgroup_memory: true,
+            })
+        };
+
+        Self {
+            grid: queue_layer("grid"),
+            occupancy: queue_layer("occupancy"),
+            overflow: queue_layer("overflow"),
+            viewport: queue_layer("viewport"),
+        }
+    }
+}
+
+/// The label for the overlay's node in the render graph
+#[derive(RenderLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugOverlayLabel;
+
+/// Draws the overlay after the particles
+#[derive(Default)]
+pub struct DebugOverlayNode;
+
+#[expect(clippy::missing_trait_methods, reason = "We just don't use 'em")]
+impl render_graph::ViewNode for DebugOverlayNode {
+    type ViewQuery = &'static ViewTarget;
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        view_query: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(overlay) = world.get_resource::<WrachDebugOverlay>() else {
+            return Ok(());
+        };
+        if !overlay.is_enabled() {
+            return Ok(());
+        }
+        let (Some(pipelines), Some(bindings)) = (
+            world.get_resource::<DebugOverlayPipelines>(),
+            world.get_resource::<ParticleBindGroup>(),
+        ) else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_render_pass(&RenderPassDescriptor {
+                label: Some("wrach_debug_overlay"),
+                color_attachments: &[Some(view_query.get_color_attachment())],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+        pass.set_bind_group(0, &bindings.bind_group, &[]);
+
+        for (is_drawn, id) in overlay.layers(pipelines) {
+            if !is_drawn {
+                continue;
+            }
+            // Pipelines that are still compiling are just skipped.
+            if let Some(pipeline) = pipeline_cache.get_render_pipeline(id) {
+                pass.set_pipeline(pipeline);
+                pass.draw(0..3, 0..1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WrachDebugOverlay;
+
+    #[test]
+    fn toggling_switches_everything_on_and_off() {
+        let mut overlay = WrachDebugOverlay::default();
+        assert!(!overlay.is_enabled());
+
+        overlay.toggle();
+        assert_eq!(overlay, WrachDebugOverlay::ALL);
+
+        overlay.toggle();
+        assert_eq!(overlay, WrachDebugOverlay::NONE);
+    }
+
+    #[test]
+    fn any_single_layer_enables_the_overlay() {
+        let overlay = WrachDebugOverlay {
+            overflow: true,
+            ..WrachDebugOverlay::NONE
+        };
+        assert!(overlay.is_enabled());
+    }
+}
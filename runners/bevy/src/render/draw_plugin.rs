@@ -4,21 +4,33 @@ use bevy::{
     core_pipeline::core_2d::graph::{Core2d, Node2d},
     prelude::*,
     render::{
+        extract_resource::ExtractResourcePlugin,
         render_graph::{RenderGraphApp as _, ViewNodeRunner},
-        MainWorld, RenderApp,
+        MainWorld, Render, RenderApp, RenderSet,
     },
 };
 
 use crate::{
     config_shader::ShaderWorldSettings,
+    error::{report_error_in_world, WrachError},
     plugin::bind_groups::{ParticleBindGroup, ParticleBindGroupLayout},
-    render::graph_node::{DrawParticleLabel, DrawParticleNode},
+    render::{
+        ageing_curve::{
+            prepare_ageing_curve, AgeingCurveBindGroup, AgeingCurveBindGroupLayout,
+            WrachAgeingCurve,
+        },
+        debug_overlay::{
+            DebugOverlayLabel, DebugOverlayNode, DebugOverlayPipelines, WrachDebugOverlay,
+        },
+        graph_node::{DrawParticleLabel, DrawParticleNode},
+    },
     WrachState,
 };
 
 use super::pipeline::DrawParticlePipeline;
 
-/// An optional plugin to draw particles as simple pixels
+/// An optional plugin to draw particles as simple pixels, coloured by their age, see
+/// `WrachAgeingCurve`. It also draws the debug overlay, see `WrachDebugOverlay`.
 #[derive(Default)]
 #[non_exhaustive]
 pub struct DrawPlugin;
@@ -27,16 +39,31 @@ pub struct DrawPlugin;
 impl Plugin for DrawPlugin {
     #[inline]
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup);
+        app.add_systems(Startup, startup)
+            .init_resource::<WrachDebugOverlay>()
+            .add_plugins(ExtractResourcePlugin::<WrachDebugOverlay>::default())
+            .init_resource::<WrachAgeingCurve>()
+            .add_plugins(ExtractResourcePlugin::<WrachAgeingCurve>::default());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .init_resource::<AgeingCurveBindGroup>()
+            .add_systems(
+                Render,
+                prepare_ageing_curve.in_set(RenderSet::PrepareBindGroups),
+            )
             .add_systems(
                 ExtractSchedule,
-                (setup.run_if(check_is_setup), sync_world_settings),
+                (
+                    setup.pipe(report_render_errors).run_if(check_is_setup),
+                    sync_particle_bind_group,
+                    sync_world_settings,
+                ),
             )
             .add_render_graph_node::<ViewNodeRunner<DrawParticleNode>>(Core2d, DrawParticleLabel)
-            .add_render_graph_edge(Core2d, Node2d::Tonemapping, DrawParticleLabel);
+            .add_render_graph_edge(Core2d, Node2d::Tonemapping, DrawParticleLabel)
+            .add_render_graph_node::<ViewNodeRunner<DebugOverlayNode>>(Core2d, DebugOverlayLabel)
+            .add_render_graph_edge(Core2d, DrawParticleLabel, DebugOverlayLabel);
     }
 }
 
@@ -58,20 +85,37 @@ const fn check_is_setup(maybe: Option<Res<ParticleBindGroupLayout>>) -> bool {
 /// compute plugin and the `PipelineCache` isn't ready for [`DrawParticlePipeline`].
 /// I'd like to know if there's a better way of doing this?
 #[expect(
-    clippy::expect_used,
-    reason = "`expect`s until there's a way to use `?` in systems"
+    clippy::needless_pass_by_value,
+    reason = "Bevy's magic system function signature can't be changed"
 )]
-fn setup(mut commands: Commands, mut world: ResMut<MainWorld>) {
+fn setup(mut commands: Commands, world: Res<MainWorld>) -> Result<(), WrachError> {
+    // The layout stays in the main world because it's needed again whenever the compute buffers
+    // are replaced.
     let particle_bind_group_layout = world
-        .remove_resource::<ParticleBindGroupLayout>()
-        .expect("Couldn't remove `ParticleBindGroupLayout` from main world");
-    let particle_bind_group = world
-        .remove_resource::<ParticleBindGroup>()
-        .expect("Couldn't remove `ParticleBindGroup` from main world");
+        .get_resource::<ParticleBindGroupLayout>()
+        .ok_or(WrachError::MissingResource("ParticleBindGroupLayout"))?
+        .clone();
     commands.insert_resource(particle_bind_group_layout);
-    commands.insert_resource(particle_bind_group);
 
+    commands.init_resource::<AgeingCurveBindGroupLayout>();
     commands.init_resource::<DrawParticlePipeline>();
+    commands.init_resource::<DebugOverlayPipelines>();
+    Ok(())
+}
+
+/// The render world has no `WrachState` of its own, so errors are reported to the main world.
+fn report_render_errors(In(result): In<Result<(), WrachError>>, mut world: ResMut<MainWorld>) {
+    if let Err(error) = result {
+        report_error_in_world(&mut world, error);
+    }
+}
+
+/// Move the [`ParticleBindGroup`] over from the main world whenever there's a new one. That's at
+/// startup and whenever the compute worker's buffers have grown.
+fn sync_particle_bind_group(mut commands: Commands, mut world: ResMut<MainWorld>) {
+    if let Some(particle_bind_group) = world.remove_resource::<ParticleBindGroup>() {
+        commands.insert_resource(particle_bind_group);
+    }
 }
 
 /// Synchronise the [`ShaderWorldSettings`] from the main world so they can be used in the shader.
@@ -11,7 +11,10 @@ use bevy::{
     },
 };
 
-use crate::{config_shader::ShaderWorldSettings, plugin::bind_groups::ParticleBindGroup};
+use crate::{
+    config_shader::ShaderWorldSettings, plugin::bind_groups::ParticleBindGroup,
+    render::ageing_curve::AgeingCurveBindGroup,
+};
 
 use super::pipeline::DrawParticlePipeline;
 
@@ -38,6 +41,12 @@ impl render_graph::ViewNode for DrawParticleNode {
         let pipeline = world.resource::<DrawParticlePipeline>();
         let settings = world.resource::<ShaderWorldSettings>();
         let bindings = world.resource::<ParticleBindGroup>();
+        let Some(ageing_curve) = world
+            .get_resource::<AgeingCurveBindGroup>()
+            .and_then(AgeingCurveBindGroup::bind_group)
+        else {
+            return Ok(());
+        };
 
         let color_attachment = view_query.get_color_attachment();
 
@@ -68,6 +77,7 @@ impl render_graph::ViewNode for DrawParticleNode {
             };
 
             pass.set_bind_group(0, &bindings.bind_group, &[]);
+            pass.set_bind_group(1, ageing_curve, &[]);
             pass.set_pipeline(pipeline_ready);
             pass.draw(0..6, 0..settings.particles_in_frame_count);
         }
@@ -5,12 +5,14 @@ use bevy::{
     image::BevyDefault as _,
     prelude::{FromWorld, Resource, World},
     render::render_resource::{
-        CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState, MultisampleState,
-        PipelineCache, PrimitiveState, TextureFormat, VertexState,
+        BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState,
+        MultisampleState, PipelineCache, PrimitiveState, TextureFormat, VertexState,
     },
 };
 
-use crate::plugin::bind_groups::ParticleBindGroupLayout;
+use crate::{
+    plugin::bind_groups::ParticleBindGroupLayout, render::ageing_curve::AgeingCurveBindGroupLayout,
+};
 
 /// The render pipeline for drawing particles as simple pixels
 #[derive(Resource)]
@@ -22,6 +24,7 @@ pub struct DrawParticlePipeline {
 impl FromWorld for DrawParticlePipeline {
     fn from_world(world: &mut World) -> Self {
         let bindings = world.resource::<ParticleBindGroupLayout>();
+        let ageing_curve = world.resource::<AgeingCurveBindGroupLayout>();
         let shader =
             world.load_asset("embedded://wrach_bevy/plugin/../../../../assets/shaders/draw.wgsl");
 
@@ -29,7 +32,11 @@ impl FromWorld for DrawParticlePipeline {
         let pipeline = pipeline_cache.queue_render_pipeline(
             bevy::render::render_resource::RenderPipelineDescriptor {
                 label: None,
-                layout: [bindings.bind_group_layout.clone()].to_vec(),
+                layout: [
+                    bindings.bind_group_layout.clone(),
+                    ageing_curve.bind_group_layout.clone(),
+                ]
+                .to_vec(),
                 push_constant_ranges: Vec::new(),
                 vertex: VertexState {
                     shader: shader.clone(),
@@ -43,7 +50,8 @@ impl FromWorld for DrawParticlePipeline {
                     entry_point: "fragment".into(),
                     targets: vec![Some(ColorTargetState {
                         format: TextureFormat::bevy_default(),
-                        blend: None,
+                        // Particles can fade away as they age, see `WrachAgeingCurve`.
+                        blend: Some(BlendState::ALPHA_BLENDING),
                         write_mask: ColorWrites::ALL,
                     })],
                 }),
@@ -1,7 +1,10 @@
 //! An acceleration structure for faster particle lookups
 //! [See:](https://matthias-research.github.io/pages/tenMinutePhysics/11-hashing.pdf)
 
-use crate::particle_store::{ParticleData, ParticleStore};
+use crate::{
+    error::{count_to_u32, WrachError},
+    particle_store::{ParticleData, ParticleStore},
+};
 use bevy::math::{IVec2, UVec2, Vec2, Vec4, Vec4Swizzles as _};
 
 /// The coordinates of a cell in the Spatial Binning grid
@@ -9,8 +12,11 @@ pub type SpatialBinCoord = IVec2;
 
 /// State for constructing and mangging a spatial binning of particles
 pub struct SpatialBin {
-    /// The size of an individual bin/cell in the spatial bin grid. A cell is a square.
+    /// The size of an individual bin/cell in the spatial bin grid. A cell is a square. The unit is
+    /// a particle, so that bigger particles get bigger cells and still find their neighbours.
     pub cell_size: u16,
+    /// The size of a particle, twice `WrachConfig::particle_radius`.
+    pub particle_size: f32,
     /// The width and height of the grid of cells, where the unit is a single cell.
     pub grid_dimensions: UVec2,
     /// Coordinates of the active view onto the simulation.
@@ -18,7 +24,7 @@ pub struct SpatialBin {
 }
 
 /// An efficient data structure for searching particles.
-#[derive(Default)]
+#[derive(Default, Clone, Debug, PartialEq)]
 pub struct PackedData {
     /// A vector of spatial bin cells. Each item points to the corresponding array index of the first
     /// particle in the cell. The next item, whether the cell has particles or not, contains the
@@ -30,13 +36,53 @@ pub struct PackedData {
     pub positions: Vec<Vec2>,
     /// All the particle velocities ordered by cells
     pub velocities: Vec<Vec2>,
+    /// All the particle ages and lifetimes ordered by cells, see `ParticleProperties::lifetime`
+    pub ages: Vec<UVec2>,
+    /// All the particle temperatures ordered by cells, see `ThermalConfig`
+    pub temperatures: Vec<f32>,
+    /// All the particle materials ordered by cells, see `Materials`
+    pub materials: Vec<u32>,
+}
+
+impl PackedData {
+    /// The number of particles, from the guard item at the end of a grid's indices, if the indices
+    /// are there.
+    #[inline]
+    #[must_use]
+    pub fn particles_count(&self, grid_dimensions: UVec2) -> Option<u32> {
+        let total_cells =
+            usize::try_from(grid_dimensions.x.saturating_mul(grid_dimensions.y)).ok()?;
+        self.indices.get(total_cells).copied()
+    }
+
+    /// A hash of all the data, for checking that two simulations are identical, eg: that lockstep
+    /// peers haven't diverged. It's FNV-1a, so unlike Rust's `DefaultHasher` it won't change
+    /// between Rust versions.
+    #[inline]
+    #[must_use]
+    pub fn checksum(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let bytes = bytemuck::cast_slice::<u32, u8>(&self.indices)
+            .iter()
+            .chain(bytemuck::cast_slice::<Vec2, u8>(&self.positions))
+            .chain(bytemuck::cast_slice::<Vec2, u8>(&self.velocities))
+            .chain(bytemuck::cast_slice::<UVec2, u8>(&self.ages))
+            .chain(bytemuck::cast_slice::<f32, u8>(&self.temperatures))
+            .chain(bytemuck::cast_slice::<u32, u8>(&self.materials));
+        bytes.fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        })
+    }
 }
 
 impl SpatialBin {
-    /// Instantiate. `cell_size` is the size of a spatial bin.
-    pub fn new(cell_size: u16, viewport: Vec4) -> Self {
+    /// Instantiate. `cell_size` is the size of a spatial bin, in particles of `particle_size`.
+    pub fn new(cell_size: u16, particle_size: f32, viewport: Vec4) -> Self {
         let mut spatial_bin = Self {
             cell_size,
+            particle_size,
             viewport,
             grid_dimensions: UVec2::default(),
         };
@@ -44,9 +90,14 @@ impl SpatialBin {
         spatial_bin
     }
 
+    /// The size of a cell in the same units as the positions of particles.
+    pub fn world_cell_size(&self) -> f32 {
+        f32::from(self.cell_size) * self.particle_size
+    }
+
     /// Given floating point coordinates find the spatial bin cell in which those coordinates lie.
     pub fn get_cell_coord(&self, position: Vec2) -> SpatialBinCoord {
-        let cell_size_f32: f32 = self.cell_size.into();
+        let cell_size_f32 = self.world_cell_size();
 
         #[expect(
             clippy::as_conversions,
@@ -88,23 +139,35 @@ impl SpatialBin {
         (cells, grid_dimensions)
     }
 
+    /// Is the cell one of those currently visible, or required, for simulating a single frame?
+    pub fn is_active_cell(&self, cell: SpatialBinCoord) -> bool {
+        let bottom_left = self.get_cell_coord(self.viewport.xy());
+        let top_right = self.get_cell_coord(self.viewport.zw());
+        cell.cmpge(bottom_left).all() && cell.cmple(top_right).all()
+    }
+
+    /// Is the position inside the viewport, including its edges? Only particles inside it are
+    /// simulated, see `wrach_physics_shaders::is_in_view()`.
+    pub fn is_in_view(&self, position: Vec2) -> bool {
+        position.cmpge(self.viewport.xy()).all() && position.cmple(self.viewport.zw()).all()
+    }
+
     /// Update the dimensions of the spatial bin grid. The unit is a cell.
-    fn update_grid_size(&mut self) {
+    pub fn update_grid_size(&mut self) {
         let (_cell_list, dimensions) = self.get_active_cells();
         self.grid_dimensions = dimensions;
     }
 
     /// Create an efficient spatial representation of all the currently active particles in and
     /// around the viewport.
-    #[expect(
-        clippy::expect_used,
-        reason = "`expect`s until there's a way to use `?` in systems"
-    )]
-    pub fn create_packed_data(&self, store: &ParticleStore) -> PackedData {
+    pub fn create_packed_data(&self, store: &ParticleStore) -> Result<PackedData, WrachError> {
         let (cells, _grid) = self.get_active_cells();
         let mut indices: Vec<u32> = Vec::new();
         let mut positions: Vec<Vec2> = Vec::new();
         let mut velocities: Vec<Vec2> = Vec::new();
+        let mut ages: Vec<UVec2> = Vec::new();
+        let mut temperatures: Vec<f32> = Vec::new();
+        let mut materials: Vec<u32> = Vec::new();
         let mut current_index = 0;
         let empty_cell = ParticleData::default();
 
@@ -115,37 +178,31 @@ impl SpatialBin {
         //      particles.
         indices.push(current_index);
 
-        // And then add another zero at the beginning because our current GPU prefix sum
-        // implementation shifts all its items one to the right.
-        indices.push(current_index);
-
         for cell in cells {
             let particles = store.hashmap.get(&cell).unwrap_or(&empty_cell);
 
-            let particle_count: u32 = particles
-                .positions
-                .len()
-                .try_into()
-                .expect("Couldn't convert particle count to u32");
-
-            #[expect(
-                clippy::arithmetic_side_effects,
-                reason = "Assuming there can't be any overflow"
-            )]
-            {
-                current_index += particle_count;
-            };
+            let particle_count = count_to_u32("particles in a cell", particles.positions.len())?;
+
+            current_index = current_index
+                .checked_add(particle_count)
+                .ok_or_else(|| WrachError::too_many("particles", positions.len()))?;
             indices.push(current_index);
 
             positions.extend(particles.positions.clone());
             velocities.extend(particles.velocities.clone());
+            ages.extend(particles.ages.clone());
+            temperatures.extend(particles.temperatures.clone());
+            materials.extend(particles.materials.clone());
         }
 
-        PackedData {
+        Ok(PackedData {
             indices,
             positions,
             velocities,
-        }
+            ages,
+            temperatures,
+            materials,
+        })
     }
 }
 
@@ -155,35 +212,43 @@ mod test {
 
     #[test]
     fn calculating_a_cell_coord_in_the_middle() {
-        let spatial_bin = SpatialBin::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let spatial_bin = SpatialBin::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         let coord = spatial_bin.get_cell_coord(Vec2::new(3.0, 3.0));
         assert_eq!(coord, SpatialBinCoord::new(1, 1));
     }
 
     #[test]
     fn calculating_a_cell_coord_in_the_origin_cell() {
-        let spatial_bin = SpatialBin::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let spatial_bin = SpatialBin::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         let coord = spatial_bin.get_cell_coord(Vec2::new(0.1, 0.1));
         assert_eq!(coord, SpatialBinCoord::new(0, 0));
     }
 
     #[test]
     fn calculating_a_cell_coord_in_the_top_right() {
-        let spatial_bin = SpatialBin::new(3, Vec4::new(0.0, 0.0, 6.0, 6.0));
+        let spatial_bin = SpatialBin::new(3, 1.0, Vec4::new(0.0, 0.0, 6.0, 6.0));
         let coord = spatial_bin.get_cell_coord(Vec2::new(6.5, 6.5));
         assert_eq!(coord, SpatialBinCoord::new(2, 2));
     }
 
     #[test]
     fn calculating_a_negative_cell_coord() {
-        let spatial_bin = SpatialBin::new(3, Vec4::new(0.0, 0.0, -6.0, -6.0));
+        let spatial_bin = SpatialBin::new(3, 1.0, Vec4::new(0.0, 0.0, -6.0, -6.0));
         let coord = spatial_bin.get_cell_coord(Vec2::new(-3.0, -3.0));
         assert_eq!(coord, SpatialBinCoord::new(-1, -1));
     }
 
+    #[test]
+    fn cells_grow_with_the_particles() {
+        let spatial_bin = SpatialBin::new(3, 2.0, Vec4::new(0.0, 0.0, 12.0, 12.0));
+        let coord = spatial_bin.get_cell_coord(Vec2::new(5.0, 7.0));
+        assert_eq!(coord, SpatialBinCoord::new(0, 1));
+        assert_eq!(spatial_bin.grid_dimensions, UVec2::new(3, 3));
+    }
+
     #[test]
     fn calculating_active_cells_at_origin() {
-        let spatial_bin = SpatialBin::new(6, Vec4::new(0.0, 0.0, 10.0, 10.0));
+        let spatial_bin = SpatialBin::new(6, 1.0, Vec4::new(0.0, 0.0, 10.0, 10.0));
         let (cells, grid) = spatial_bin.get_active_cells();
         assert_eq!(
             cells,
@@ -200,7 +265,7 @@ mod test {
 
     #[test]
     fn calculating_grid_for_rectangle_viewport() {
-        let spatial_bin = SpatialBin::new(6, Vec4::new(0.0, 0.0, 15.0, 10.0));
+        let spatial_bin = SpatialBin::new(6, 1.0, Vec4::new(0.0, 0.0, 15.0, 10.0));
         let (cells, grid) = spatial_bin.get_active_cells();
         assert_eq!(
             cells,
@@ -219,7 +284,7 @@ mod test {
 
     #[test]
     fn calculating_active_cells_where_viewport_offsets_cells() {
-        let spatial_bin = SpatialBin::new(6, Vec4::new(3.3, 3.3, 9.0, 9.0));
+        let spatial_bin = SpatialBin::new(6, 1.0, Vec4::new(3.3, 3.3, 9.0, 9.0));
         let (cells, _grid) = spatial_bin.get_active_cells();
         assert_eq!(
             cells,
@@ -232,9 +297,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn checking_whether_cells_are_active() {
+        let spatial_bin = SpatialBin::new(6, 1.0, Vec4::new(0.0, 0.0, 10.0, 10.0));
+        assert!(spatial_bin.is_active_cell(SpatialBinCoord::new(0, 0)));
+        assert!(spatial_bin.is_active_cell(SpatialBinCoord::new(1, 1)));
+        assert!(!spatial_bin.is_active_cell(SpatialBinCoord::new(2, 1)));
+        assert!(!spatial_bin.is_active_cell(SpatialBinCoord::new(-1, 0)));
+    }
+
     #[test]
     fn calculating_active_cells_with_negative_viewport() {
-        let spatial_bin = SpatialBin::new(6, Vec4::new(-5.0, -5.0, 0.0, 0.0));
+        let spatial_bin = SpatialBin::new(6, 1.0, Vec4::new(-5.0, -5.0, 0.0, 0.0));
         let (cells, _grid) = spatial_bin.get_active_cells();
         assert_eq!(
             cells,
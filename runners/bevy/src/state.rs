@@ -1,13 +1,26 @@
 //! All the state for the simulation, both the physics itself and state for managing the simulation
 
+use core::time::Duration;
+
 use bevy::{
     asset::Handle,
-    math::{Vec2, Vec4},
+    math::{UVec2, Vec2, Vec4, Vec4Swizzles as _},
     prelude::{Resource, Shader},
 };
 
 use crate::{
-    config_shader::ShaderWorldSettings, particle_store::ParticleStore, spatial_bin::PackedData,
+    ageing::Expiries,
+    compute::PhysicsComputeWorker,
+    config_shader::{ShaderMaterialProperties, ShaderReaction, ShaderWorldSettings},
+    culling::Culling,
+    emitters::Emitters,
+    error::{count_to_u32, WrachError},
+    materials::{Material, Materials},
+    particle_edits::ParticleEdit,
+    particle_store::{ParticleData, ParticleStore},
+    recording::{RecordedInput, Recording},
+    spatial_bin::PackedData,
+    thermal::HeatSources,
     WrachConfig,
 };
 
@@ -21,10 +34,46 @@ pub struct WrachState {
     pub shader_settings: ShaderWorldSettings,
     /// Store for all particles
     pub particle_store: ParticleStore,
-    /// The particle positions
+    /// The particle data read back from the GPU, see `WrachConfig::readback`
     pub packed_data: PackedData,
+    /// The frame that `packed_data` was read back from
+    pub packed_data_frame: u64,
+    /// The number of frames that the GPU has simulated
+    pub gpu_frame: u64,
     /// Data to send to the GPU, typically for CPU-side influence over the simulation
     pub gpu_uploads: Vec<GPUUpload>,
+    /// Particles waiting to be merged into the simulation on the GPU
+    pub new_particles: Vec<(Particle, ParticleProperties)>,
+    /// When the simulated particles reach the end of their lifetimes, see `ParticleProperties`
+    pub expiries: Expiries,
+    /// How many particles there can be now that the GPU has culled some by itself, see `Culling`
+    pub culling: Culling,
+    /// Edits waiting to be applied to the simulated particles, see `edit_particles()`
+    pub particle_edits: Vec<ParticleEdit>,
+    /// Whether the view has moved since the simulated particles outside of it were last handed
+    /// back to the particle store, see `set_viewport_anchor()`
+    pub has_view_moved: bool,
+    /// Emitters and sinks, see `add_emitter()` and `add_sink()`
+    pub emitters: Emitters,
+    /// Heat sources, see `add_heat_source()`
+    pub heat_sources: HeatSources,
+    /// Materials and the reactions between them, see `set_materials()`
+    pub materials: Materials,
+    /// The maximum number of particles that the GPU buffers can hold in a single frame
+    pub particles_capacity: u32,
+    /// The maximum number of new particles that can be merged into the simulation per frame
+    pub new_particles_capacity: u32,
+    /// The number of items, mostly spatial bin cells, that the GPU indices buffers can hold
+    pub cells_capacity: u32,
+    /// The number of threads per workgroup that the compute shaders are currently using
+    pub workgroup_size: u32,
+    /// The most recent error from any of Wrach's systems, see also `WrachErrorEvent`
+    pub last_error: Option<WrachError>,
+    /// Everything that's gone into the simulation since `start_recording()`
+    pub recording: Option<Recording>,
+    /// How long the GPU took to run the physics for a recent frame, only measured by
+    /// `WrachDiagnosticsPlugin`
+    pub gpu_compute_time: Option<Duration>,
 
     /// This is a bit of hack. The types shader is shared by various WGSL shaders, but its asset
     /// handle is not actually consumed by any of them. So we consume it here so that the asset
@@ -33,7 +82,7 @@ pub struct WrachState {
 }
 
 /// Wrach's representation of a particle. Probably will only ever be used for inserting.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[expect(
     clippy::exhaustive_structs,
     reason = "TODO: Use `#[non_exhaustive]` and https://github.com/elastio/bon"
@@ -45,17 +94,80 @@ pub struct Particle {
     pub velocity: Velocity,
 }
 
+/// Optional properties of newly added particles, shared by everything in a single call to
+/// `WrachState::add_particles_with()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct ParticleProperties {
+    /// How many frames the particle is simulated for before it's removed, `None` lives forever.
+    /// Particles only age whilst they're simulated, not whilst they're in the particle store.
+    pub lifetime: Option<u32>,
+    /// The particle's starting temperature, `None` starts at `ThermalConfig::ambient_temperature`.
+    pub temperature: Option<f32>,
+    /// What the particle is made of, see `Materials`
+    pub material: Material,
+}
+
+impl ParticleProperties {
+    /// See `ParticleProperties::lifetime`. A particle lives for at least a frame.
+    #[inline]
+    #[must_use]
+    pub const fn lifetime(mut self, frames: u32) -> Self {
+        self.lifetime = Some(if frames == 0 { 1 } else { frames });
+        self
+    }
+
+    /// See `ParticleProperties::temperature`
+    #[inline]
+    #[must_use]
+    pub const fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// See `ParticleProperties::material`
+    #[inline]
+    #[must_use]
+    pub const fn material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// The particle's age and lifetime, in the format used by the GPU.
+    pub(crate) fn initial_age(self) -> UVec2 {
+        UVec2::new(0, self.lifetime.unwrap_or(0))
+    }
+
+    /// The particle's starting temperature, given the simulation's ambient temperature.
+    pub(crate) fn initial_temperature(self, ambient_temperature: f32) -> f32 {
+        self.temperature.unwrap_or(ambient_temperature)
+    }
+}
+
 /// Wrach's type for particle position
 pub type Position = Vec2;
 /// Wrach's type for particle velocity
 pub type Velocity = Vec2;
 
 /// The various kinds of data that get uplaoded to the GPU
+#[derive(Clone, Debug, PartialEq)]
 pub enum GPUUpload {
-    /// The main particle data
+    /// The main particle data. A particle's data is all uploaded together, so anything missing for
+    /// a particle with a position is zeroed.
     PackedData(PackedData),
     /// Various settings like viewport dimensions, particle count etc
     Settings(ShaderWorldSettings),
+    /// Newly added particles to be merged into the existing particle data by the GPU
+    NewParticles(ParticleData),
+    /// All the heat sources, `(x, y, radius, power)`, see `WrachState::add_heat_source()`
+    HeatSources(Vec<Vec4>),
+    /// All the sinks, `(x, y, radius, 0)`, see `WrachState::add_sink()`
+    Sinks(Vec<Vec4>),
+    /// The whole reactions table, see `WrachState::set_materials()`
+    Reactions(Vec<ShaderReaction>),
+    /// The properties of every material below `MAX_MATERIALS`, indexed by material, see
+    /// `WrachState::set_materials()`
+    MaterialProperties(Vec<ShaderMaterialProperties>),
 }
 
 impl WrachState {
@@ -72,31 +184,405 @@ impl WrachState {
         Self {
             config,
             shader_settings: ShaderWorldSettings::default(),
-            particle_store: ParticleStore::new(config.cell_size, viewport),
+            particle_store: ParticleStore::new(
+                config.cell_size,
+                config.particle_radius * 2.0,
+                viewport,
+            ),
             packed_data: PackedData::default(),
+            packed_data_frame: 0,
+            gpu_frame: 0,
             gpu_uploads: Vec::new(),
+            new_particles: Vec::new(),
+            expiries: Expiries::default(),
+            culling: Culling::default(),
+            particle_edits: Vec::new(),
+            has_view_moved: false,
+            emitters: Emitters::default(),
+            heat_sources: HeatSources::default(),
+            materials: Materials::default(),
+            particles_capacity: 0,
+            new_particles_capacity: 0,
+            cells_capacity: 0,
+            workgroup_size: config.workgroup_size.initial_threads(),
+            last_error: None,
+            recording: None,
+            gpu_compute_time: None,
             types_shader_handle: None,
         }
     }
 
-    /// Overwrites the simulation data from the first pixel to the size of the overwriting data
+    /// Overwrites the simulation data from the first pixel to the size of the overwriting data.
+    /// Uploads from here are recorded, see `start_recording()`, so uploads that Wrach works out
+    /// for itself go straight into `gpu_uploads` instead.
     #[inline]
     pub fn gpu_upload(&mut self, upload: GPUUpload) {
+        self.record(|| RecordedInput::GpuUpload(upload.clone()));
+        #[expect(
+            clippy::ref_patterns,
+            reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+        )]
+        let has_count_changed = match upload {
+            GPUUpload::PackedData(ref data) => {
+                self.expiries.reset(self.gpu_frame, &data.ages);
+                self.culling.reset(
+                    self.gpu_frame,
+                    u32::try_from(data.positions.len()).unwrap_or(u32::MAX),
+                );
+                false
+            }
+            GPUUpload::HeatSources(ref sources) => {
+                self.shader_settings.heat_sources_count =
+                    u32::try_from(sources.len()).unwrap_or(u32::MAX);
+                true
+            }
+            GPUUpload::Sinks(ref sinks) => {
+                self.shader_settings.sinks_count = u32::try_from(sinks.len()).unwrap_or(u32::MAX);
+                true
+            }
+            GPUUpload::Reactions(ref reactions) => {
+                self.shader_settings.reactions_count =
+                    u32::try_from(reactions.len()).unwrap_or(u32::MAX);
+                true
+            }
+            GPUUpload::Settings(_)
+            | GPUUpload::NewParticles(_)
+            | GPUUpload::MaterialProperties(_) => false,
+        };
         self.gpu_uploads.push(upload);
+
+        // The shader only looks at as many heat sources, sinks and reactions as the settings say
+        // there are.
+        if has_count_changed {
+            self.gpu_uploads
+                .push(GPUUpload::Settings(self.shader_settings));
+        }
+    }
+
+    /// Start recording everything that goes into the simulation, see `Recording`. For a recording
+    /// that can be replayed, start before adding any particles.
+    #[inline]
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::new(self.config, self.gpu_frame));
+    }
+
+    /// Stop recording and get everything that was recorded, if anything was being recorded.
+    #[inline]
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let mut recording = self.recording.take()?;
+        recording.last_frame = self.gpu_frame;
+        Some(recording)
+    }
+
+    /// Add something to the recording, if there is one.
+    pub(crate) fn record(&mut self, input: impl FnOnce() -> RecordedInput) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record(self.gpu_frame, input());
+        }
+    }
+
+    /// Do what an input from a recording did when it was recorded.
+    ///
+    /// # Errors
+    /// If it was a config change that can't be applied, see `apply_config()`.
+    #[inline]
+    pub fn replay_input(&mut self, input: RecordedInput) -> Result<(), WrachError> {
+        match input {
+            RecordedInput::AddParticles(particles, properties) => {
+                self.add_particles_with(particles, properties);
+            }
+            RecordedInput::SetViewportAnchor(anchor) => self.set_viewport_anchor(anchor),
+            RecordedInput::ApplyConfig(config) => self.apply_config(config)?,
+            RecordedInput::GpuUpload(upload) => self.gpu_upload(upload),
+            RecordedInput::EditParticles(edit) => self.edit_particles(edit),
+        }
+        Ok(())
     }
 
-    /// Overwrites the simulation data from the first pixel to the size of the overwriting data
+    /// Add particles to the simulation. Particles in the cells currently being simulated are queued
+    /// to be merged into the simulation by the GPU, all others are kept in the particle store.
     #[inline]
     pub fn add_particles(&mut self, particles: Vec<Particle>) {
+        self.add_particles_with(particles, ParticleProperties::default());
+    }
+
+    /// Add particles that all share the same properties, like a lifetime. See `add_particles()`.
+    #[inline]
+    pub fn add_particles_with(&mut self, particles: Vec<Particle>, properties: ParticleProperties) {
+        self.record(|| RecordedInput::AddParticles(particles.clone(), properties));
         for particle in particles {
-            self.particle_store.add_particle(particle);
+            if self
+                .particle_store
+                .spatial_bin
+                .is_in_view(particle.position)
+            {
+                self.new_particles.push((particle, properties));
+            } else {
+                self.particle_store.add_particle_with(
+                    particle,
+                    properties.initial_age(),
+                    properties.initial_temperature(self.config.thermal.ambient_temperature),
+                    properties.material,
+                );
+            }
         }
+    }
 
-        let upload = GPUUpload::PackedData(self.particle_store.create_packed_data());
-        self.gpu_upload(upload);
+    /// Settings for the shaders that match the current config and viewport. The counts of particles,
+    /// heat sources, sinks and reactions are carried over, because the compute worker may be rebuilt
+    /// mid-simulation when its buffers need to grow.
+    pub(crate) fn current_shader_settings(&self) -> ShaderWorldSettings {
+        ShaderWorldSettings {
+            view_dimensions: Vec2::new(
+                self.config.dimensions.0.into(),
+                self.config.dimensions.1.into(),
+            ),
+            view_anchor: self.particle_store.spatial_bin.viewport.xy(),
+            grid_dimensions: self.particle_store.spatial_bin.grid_dimensions,
+            cell_size: self.particle_store.spatial_bin.world_cell_size(),
+            particles_in_frame_count: self.shader_settings.particles_in_frame_count,
+            new_particles_count: self.shader_settings.new_particles_count,
+            ambient_temperature: self.config.thermal.ambient_temperature,
+            thermal_conductivity: self.config.thermal.conductivity,
+            cooling_rate: self.config.thermal.cooling_rate,
+            heat_sources_count: self.shader_settings.heat_sources_count,
+            reactions_count: self.shader_settings.reactions_count,
+            sinks_count: self.shader_settings.sinks_count,
+            random_seed: self.shader_settings.random_seed,
+            gravity: self.config.gravity,
+            particle_radius: self.config.particle_radius,
+            max_speed: self.config.max_speed,
+        }
+    }
+
+    /// Move the view onto the simulation to a new bottom-left corner, keeping its size. The grid
+    /// keeps its dimensions too. Its cells are measured from the view's corner, so it fits any view
+    /// of the same size, and the indices that the GPU has already worked out still match it.
+    ///
+    /// Stored particles that come into view are queued to be merged into the simulation, with
+    /// whatever's left of their lifetimes. Simulated particles that are left outside the view are
+    /// handed back to the particle store once the GPU's data has been read back, which needs the
+    /// same readback as `edit_particles()`. Otherwise the GPU culls them.
+    #[inline]
+    #[expect(
+        clippy::arithmetic_side_effects,
+        reason = "Float vectors don't overflow, they just become infinite"
+    )]
+    pub fn set_viewport_anchor(&mut self, anchor: Vec2) {
+        self.record(|| RecordedInput::SetViewportAnchor(anchor));
+        let spatial_bin = &mut self.particle_store.spatial_bin;
+        let size = spatial_bin.viewport.zw() - spatial_bin.viewport.xy();
+        let top_right = anchor + size;
+        spatial_bin.viewport = Vec4::new(anchor.x, anchor.y, top_right.x, top_right.y);
+
+        self.hand_back_queued_particles();
+        self.pull_in_stored_particles();
+        self.has_view_moved = true;
+
+        self.shader_settings.view_anchor = anchor;
+        self.gpu_uploads
+            .push(GPUUpload::Settings(self.shader_settings));
+    }
+
+    /// Put the queued particles that aren't in view any more into the particle store.
+    fn hand_back_queued_particles(&mut self) {
+        let ambient_temperature = self.config.thermal.ambient_temperature;
+        for (particle, properties) in core::mem::take(&mut self.new_particles) {
+            if self
+                .particle_store
+                .spatial_bin
+                .is_in_view(particle.position)
+            {
+                self.new_particles.push((particle, properties));
+            } else {
+                self.particle_store.add_particle_with(
+                    particle,
+                    properties.initial_age(),
+                    properties.initial_temperature(ambient_temperature),
+                    properties.material,
+                );
+            }
+        }
+    }
+
+    /// Queue the stored particles that are in view to be merged into the simulation. They aren't
+    /// recorded, because replaying the move pulls them in all over again.
+    fn pull_in_stored_particles(&mut self) {
+        let (cells, _grid) = self.particle_store.spatial_bin.get_active_cells();
+        for cell in cells {
+            let Some(stored) = self.particle_store.hashmap.remove(&cell) else {
+                continue;
+            };
+            let particles = stored
+                .positions
+                .into_iter()
+                .zip(stored.velocities)
+                .zip(stored.ages)
+                .zip(stored.temperatures)
+                .zip(stored.materials);
+            for ((((position, velocity), age), temperature), material) in particles {
+                let particle = Particle { position, velocity };
+                if !self.particle_store.spatial_bin.is_in_view(position) {
+                    self.particle_store
+                        .add_particle_with(particle, age, temperature, material);
+                    continue;
+                }
+                let properties = ParticleProperties {
+                    lifetime: (age.y != 0).then(|| age.y.saturating_sub(age.x).max(1)),
+                    temperature: Some(temperature),
+                    material,
+                };
+                self.new_particles.push((particle, properties));
+            }
+        }
+    }
+
+    /// Change the config of a running simulation, eg: when a `WrachConfigFile` is edited. Only
+    /// changes that fit into the current GPU buffers are supported, they're uploaded with the next
+    /// frame. The view keeps its bottom-left corner when its dimensions change, and it can only
+    /// grow, because the simulated particles outside of a smaller view would be outside of the grid.
+    ///
+    /// # Errors
+    /// If the config is invalid or changes something that needs a restart. Nothing is changed if
+    /// there's an error.
+    #[inline]
+    #[expect(
+        clippy::arithmetic_side_effects,
+        reason = "Float vectors don't overflow, they just become infinite"
+    )]
+    pub fn apply_config(&mut self, config: WrachConfig) -> Result<(), WrachError> {
+        config.validate()?;
+        if config.cell_size != self.config.cell_size {
+            // Every particle in the store would need to be re-binned.
+            return Err(WrachError::NeedsRestart("cell_size"));
+        }
+        if config.particle_radius.to_bits() != self.config.particle_radius.to_bits() {
+            // The cells are measured in particles, so they'd change size too.
+            return Err(WrachError::NeedsRestart("particle_radius"));
+        }
+        if config.workgroup_size != self.config.workgroup_size {
+            return Err(WrachError::NeedsRestart("workgroup_size"));
+        }
+        if config.deterministic != self.config.deterministic {
+            return Err(WrachError::NeedsRestart("deterministic"));
+        }
+        if config.dimensions.0 < self.config.dimensions.0
+            || config.dimensions.1 < self.config.dimensions.1
+        {
+            return Err(WrachError::NeedsRestart("dimensions"));
+        }
+
+        let previous_viewport = self.particle_store.spatial_bin.viewport;
+        let anchor = previous_viewport.xy();
+        let top_right = anchor + Vec2::new(config.dimensions.0.into(), config.dimensions.1.into());
+        self.particle_store.spatial_bin.viewport =
+            Vec4::new(anchor.x, anchor.y, top_right.x, top_right.y);
+        self.particle_store.spatial_bin.update_grid_size();
+
+        // A capacity of 0 means that the GPU buffers haven't been built yet, so they'll be built
+        // for the new config anyway.
+        if self.cells_capacity > 0 && self.required_cells_capacity() > self.cells_capacity {
+            self.particle_store.spatial_bin.viewport = previous_viewport;
+            self.particle_store.spatial_bin.update_grid_size();
+            return Err(WrachError::NeedsRestart("dimensions"));
+        }
+
+        self.record(|| RecordedInput::ApplyConfig(config));
+        self.config = config;
+        self.shader_settings = self.current_shader_settings();
+        self.gpu_uploads
+            .push(GPUUpload::Settings(self.shader_settings));
+        Ok(())
+    }
+
+    /// The number of particles the GPU buffers need to hold for the current frame's particles, any
+    /// new particles currently being merged and the next batch of queued particles.
+    pub(crate) fn required_particles_capacity(&self) -> u32 {
+        let queued = u32::try_from(self.new_particles.len())
+            .unwrap_or(u32::MAX)
+            .min(self.new_particles_capacity);
+        self.shader_settings
+            .particles_in_frame_count
+            .saturating_add(self.shader_settings.new_particles_count)
+            .saturating_add(queued)
+    }
+
+    /// The number of items the GPU indices buffers need to hold for all the active spatial bin
+    /// cells.
+    pub(crate) const fn required_cells_capacity(&self) -> u32 {
+        let grid = self.particle_store.spatial_bin.grid_dimensions;
+        grid.x
+            .saturating_mul(grid.y)
+            .saturating_add(PhysicsComputeWorker::PREFIX_SUM_GUARD_ITEM)
+    }
+
+    /// Stage the next batch of queued particles to be merged into the simulation by the GPU.
+    ///
+    /// The previous batch only counts as part of the simulation once the GPU has actually run a
+    /// frame with it, otherwise it is left in place to be merged in the next frame. Particles that
+    /// the GPU has since left out at the end of their lifetimes stop being counted, and so do any
+    /// that it's been seen to cull, see `Culling`.
+    ///
+    /// It's also where the settings get a new random seed for every frame, see `Materials`.
+    pub(crate) fn stage_new_particles(
+        &mut self,
+        has_gpu_run_since_last_stage: bool,
+    ) -> Result<(), WrachError> {
+        let previous_batch_size = self.shader_settings.new_particles_count;
+        if previous_batch_size > 0 {
+            if !has_gpu_run_since_last_stage {
+                return Ok(());
+            }
+            self.culling
+                .merge_batch(self.gpu_frame, previous_batch_size);
+            self.shader_settings.new_particles_count = 0;
+            self.expiries.merge_batch(self.gpu_frame);
+        }
+
+        self.culling
+            .expire(self.expiries.take_expired(self.gpu_frame));
+        let previous_count = self.shader_settings.particles_in_frame_count;
+        self.shader_settings.particles_in_frame_count = self.culling.particles_count();
+
+        let queued = count_to_u32("new particles", self.new_particles.len())?;
+        let free_space = self
+            .particles_capacity
+            .saturating_sub(self.shader_settings.particles_in_frame_count);
+        let batch_size = queued.min(free_space).min(self.new_particles_capacity);
+
+        if batch_size > 0 {
+            // It's never more than the number of queued particles, which came from a `usize`.
+            let batch_size_usize = usize::try_from(batch_size).unwrap_or(usize::MAX);
+            let mut batch = ParticleData::default();
+            let mut lifetimes = Vec::with_capacity(batch_size_usize);
+            for (particle, properties) in self.new_particles.drain(..batch_size_usize) {
+                batch.positions.push(particle.position);
+                batch.velocities.push(particle.velocity);
+                batch.ages.push(properties.initial_age());
+                batch
+                    .temperatures
+                    .push(properties.initial_temperature(self.config.thermal.ambient_temperature));
+                batch.materials.push(properties.material);
+                lifetimes.push(properties.lifetime.unwrap_or(0));
+            }
+            self.expiries.stage_batch(lifetimes);
+            self.gpu_uploads.push(GPUUpload::NewParticles(batch));
+            self.shader_settings.new_particles_count = batch_size;
+        }
+
+        // Reactions with a probability would otherwise keep happening to the same particles.
+        let has_reactions = self.shader_settings.reactions_count > 0;
+        if has_reactions {
+            self.shader_settings.random_seed =
+                u32::try_from(self.gpu_frame & u64::from(u32::MAX)).unwrap_or(0);
+        }
+
+        let has_count_changed = self.shader_settings.particles_in_frame_count != previous_count;
+        if previous_batch_size > 0 || batch_size > 0 || has_count_changed || has_reactions {
+            self.gpu_uploads
+                .push(GPUUpload::Settings(self.shader_settings));
+        }
 
-        self.shader_settings.particles_in_frame_count =
-            self.particle_store.particles_in_frame_count;
-        self.gpu_upload(GPUUpload::Settings(self.shader_settings));
+        Ok(())
     }
 }
@@ -1,18 +1,46 @@
 //! Rust interface to Wrach simulations
 
 use bevy::prelude::PluginGroup as _;
-use bevy::{app::App, winit::WinitPlugin, DefaultPlugins};
+use bevy::{app::App, math::Vec2, winit::WinitPlugin, DefaultPlugins};
 
-use crate::{Particle, WrachConfig, WrachPlugin, WrachState};
+use crate::{CpuSimulation, Particle, WrachConfig, WrachPlugin, WrachState};
+
+/// A small, empty CPU simulation with the default config.
+#[must_use]
+#[inline]
+pub fn simulation() -> CpuSimulation {
+    simulation_with(WrachConfig::default())
+}
+
+/// A small, empty CPU simulation. Everything but the dimensions comes from `config`.
+///
+/// # Panics
+/// If the config is invalid
+#[must_use]
+#[inline]
+#[expect(clippy::unwrap_used, reason = "Tests don't need to be so strict")]
+pub fn simulation_with(config: WrachConfig) -> CpuSimulation {
+    CpuSimulation::new(WrachConfig {
+        dimensions: (30, 30),
+        ..config
+    })
+    .unwrap()
+}
+
+/// A still particle
+#[must_use]
+#[inline]
+pub const fn particle(x: f32, y: f32) -> Particle {
+    Particle {
+        position: Vec2::new(x, y),
+        velocity: Vec2::ZERO,
+    }
+}
 
 /// Main struct for Wrach physics simulations
 pub struct WrachTestAPI {
     /// An instance of a Bevy app, already setup for Wrach
     pub app: App,
-    /// All the positions of the particles
-    pub positions: Vec<(f32, f32)>,
-    /// All the velocities of the particles
-    pub velocities: Vec<(f32, f32)>,
 }
 
 impl WrachTestAPI {
@@ -20,13 +48,9 @@ impl WrachTestAPI {
     #[must_use]
     #[inline]
     pub fn new(config: WrachConfig) -> Self {
-        let mut wrach = Self {
-            app: App::new(),
-            positions: Vec::new(),
-            velocities: Vec::new(),
-        };
+        let mut wrach = Self { app: App::new() };
 
-        let plugin = WrachPlugin { config };
+        let plugin = WrachPlugin::new(config);
         wrach
             .app
             .add_plugins(DefaultPlugins.build().disable::<WinitPlugin>())
@@ -40,29 +64,6 @@ impl WrachTestAPI {
     #[inline]
     pub fn tick(&mut self) {
         self.app.update();
-        self.read_data();
-    }
-
-    /// Get data from the simulation
-    // TODO: Check performance of this. Are we using the data directly? There's no copying going
-    // on?
-    #[inline]
-    pub fn read_data(&mut self) {
-        let state = self.app.world().resource::<WrachState>();
-
-        self.positions = state
-            .packed_data
-            .positions
-            .iter()
-            .map(|particle| (particle.x, particle.y))
-            .collect();
-
-        self.velocities = state
-            .packed_data
-            .velocities
-            .iter()
-            .map(|particle| (particle.x, particle.y))
-            .collect();
     }
 
     /// Add particles to the simulation
@@ -0,0 +1,206 @@
+//! Heat sources: hotplates, fires and freezers that change the temperature of the particles inside
+//! them every frame. Everything else about heat, conduction between neighbouring particles and
+//! cooling towards the ambient temperature, happens in the physics shader, see `ThermalConfig`.
+//!
+//! Heat sources are uploaded to the GPU whenever they change, so recordings see them as uploads.
+//! Replays therefore heat the particles in exactly the same way, but don't know the sources' IDs.
+
+use bevy::math::{Vec2, Vec4};
+
+use crate::{error::WrachError, state::GPUUpload, WrachState};
+
+/// Heats, or cools, every particle inside a circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct HeatSource {
+    /// The centre of the circle
+    pub center: Vec2,
+    /// The radius of the circle
+    pub radius: f32,
+    /// How much the temperature of every particle inside the circle changes every frame. Negative
+    /// powers cool particles down.
+    pub power: f32,
+}
+
+impl HeatSource {
+    /// Instantiate
+    #[inline]
+    #[must_use]
+    pub const fn new(center: Vec2, radius: f32, power: f32) -> Self {
+        Self {
+            center,
+            radius,
+            power,
+        }
+    }
+
+    /// The heat source in the format used by the GPU, `(x, y, radius, power)`.
+    const fn to_gpu(self) -> Vec4 {
+        Vec4::new(self.center.x, self.center.y, self.radius, self.power)
+    }
+}
+
+/// Identifies a heat source, for removing it, see `WrachState::add_heat_source()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HeatSourceId(u64);
+
+/// All the heat sources in a simulation
+#[derive(Default)]
+pub struct HeatSources {
+    /// The heat sources, in the order they were added
+    sources: Vec<(HeatSourceId, HeatSource)>,
+    /// The next ID to give out
+    next_id: u64,
+}
+
+impl HeatSources {
+    /// The number of heat sources
+    #[inline]
+    #[must_use]
+    pub const fn count(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// All the heat sources in the format used by the GPU.
+    fn to_gpu(&self) -> Vec<Vec4> {
+        self.sources
+            .iter()
+            .map(|&(_, source)| source.to_gpu())
+            .collect()
+    }
+}
+
+impl WrachState {
+    /// Start heating, or cooling, the particles in a circle every frame, see `HeatSource`.
+    ///
+    /// # Errors
+    /// If there are already `MAX_HEAT_SOURCES` heat sources.
+    #[inline]
+    pub fn add_heat_source(&mut self, source: HeatSource) -> Result<HeatSourceId, WrachError> {
+        let count = self.heat_sources.count().saturating_add(1);
+        let is_full = u32::try_from(count)
+            .map_or(true, |total| total > wrach_cpu_gpu_shared::MAX_HEAT_SOURCES);
+        if is_full {
+            return Err(WrachError::too_many("heat sources", count));
+        }
+
+        let id = HeatSourceId(self.heat_sources.next_id);
+        self.heat_sources.next_id = self.heat_sources.next_id.saturating_add(1);
+        self.heat_sources.sources.push((id, source));
+        self.gpu_upload(GPUUpload::HeatSources(self.heat_sources.to_gpu()));
+        Ok(id)
+    }
+
+    /// Stop a heat source, returns it if it existed.
+    #[inline]
+    pub fn remove_heat_source(&mut self, id: HeatSourceId) -> Option<HeatSource> {
+        let index = self
+            .heat_sources
+            .sources
+            .iter()
+            .position(|&(source_id, _)| source_id == id)?;
+        let (_, source) = self.heat_sources.sources.remove(index);
+        self.gpu_upload(GPUUpload::HeatSources(self.heat_sources.to_gpu()));
+        Some(source)
+    }
+}
+
+#[expect(
+    clippy::default_numeric_fallback,
+    clippy::unwrap_used,
+    reason = "Tests don't need to be so strict"
+)]
+#[cfg(test)]
+mod test {
+    use bevy::math::Vec2;
+
+    use super::HeatSource;
+    use crate::{
+        tests::utils::{particle, simulation_with},
+        CpuSimulation, ParticleProperties, ThermalConfig, WrachConfig,
+    };
+
+    /// A CPU simulation with the given thermal constants
+    fn simulation(thermal: ThermalConfig) -> CpuSimulation {
+        simulation_with(WrachConfig {
+            thermal,
+            ..Default::default()
+        })
+    }
+
+    /// The total temperature of all the simulated particles
+    fn total_heat(simulation: &CpuSimulation) -> f32 {
+        simulation.state.packed_data.temperatures.iter().sum()
+    }
+
+    #[test]
+    fn hot_particles_cool_down_to_the_ambient_temperature() {
+        let mut simulation = simulation(ThermalConfig {
+            ambient_temperature: 20.0,
+            conductivity: 0.0,
+            cooling_rate: 0.5,
+        });
+        simulation.state.add_particles_with(
+            vec![particle(5.0, 5.0)],
+            ParticleProperties::default().temperature(100.0),
+        );
+        simulation.add_particles(vec![particle(20.0, 20.0)]);
+
+        simulation.tick().unwrap();
+        assert_eq!(
+            simulation.state.packed_data.temperatures,
+            vec![100.0, 20.0],
+            "New particles aren't simulated in the frame that they're added"
+        );
+        for _ in 0..30 {
+            simulation.tick().unwrap();
+        }
+        for temperature in &simulation.state.packed_data.temperatures {
+            assert!((temperature - 20.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn heat_spreads_out_from_a_heat_source() {
+        let mut simulation = simulation(ThermalConfig {
+            ambient_temperature: 0.0,
+            conductivity: 0.5,
+            cooling_rate: 0.0,
+        });
+        // A row of touching particles in the same cell, with only the first one inside the heat
+        // source
+        simulation.add_particles(
+            (0_u8..3)
+                .map(|x| particle(9.5 + f32::from(x), 10.5))
+                .collect(),
+        );
+        let source = simulation
+            .state
+            .add_heat_source(HeatSource::new(Vec2::new(9.5, 10.5), 0.5, 1.0))
+            .unwrap();
+        assert_eq!(simulation.state.shader_settings.heat_sources_count, 1);
+
+        for _ in 0..10 {
+            simulation.tick().unwrap();
+        }
+        assert!(
+            (total_heat(&simulation) - 9.0).abs() < 0.001,
+            "The source heats for every frame that the particles are simulated"
+        );
+        let hottest = simulation
+            .state
+            .packed_data
+            .temperatures
+            .iter()
+            .fold(0.0_f32, |hottest, temperature| hottest.max(*temperature));
+        assert!(hottest < 9.0, "Heat should have been conducted away");
+
+        assert!(simulation.state.remove_heat_source(source).is_some());
+        assert_eq!(simulation.state.shader_settings.heat_sources_count, 0);
+        simulation.tick().unwrap();
+        assert!(
+            (total_heat(&simulation) - 9.0).abs() < 0.001,
+            "Conduction doesn't create or destroy heat"
+        );
+    }
+}
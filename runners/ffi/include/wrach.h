@@ -0,0 +1,147 @@
+/* Wrach 2D pixel physics. Generated by cbindgen from `src/lib.rs`, don't edit by hand. */
+
+#ifndef WRACH_H
+#define WRACH_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The number of floats that make up a single particle in `wrach_add_particles()`: position x,
+// position y, velocity x, velocity y.
+#define WRACH_FLOATS_PER_PARTICLE 4
+
+// The outcome of calling a Wrach function
+typedef enum WrachStatus {
+  // Everything went fine
+  WRACH_STATUS_OK = 0,
+  // One of the pointer arguments was null
+  WRACH_STATUS_NULL_POINTER = 1,
+  // An argument didn't make sense, like a particle array that isn't a multiple of 4 floats
+  WRACH_STATUS_INVALID_ARGUMENT = 2,
+  // Wrach panicked. The details will have been printed to stderr.
+  WRACH_STATUS_PANIC = 3,
+} WrachStatus;
+
+// An instance of a Wrach simulation. Only ever handled through a pointer.
+typedef struct Wrach Wrach;
+
+// Config for creating a simulation, see `WrachConfig` in the Rust API for details.
+typedef struct WrachCConfig {
+  // Width of the realtime view onto the simulation
+  uint16_t width;
+  // Height of the realtime view onto the simulation
+  uint16_t height;
+  // Should particles be limited to within the viewport dimensions?
+  bool boundaries_as_dimensions;
+  // The size of a single cell in the spatial binning grid, in multiples of a particle's size
+  uint16_t cell_size;
+  // Threads per workgroup for the compute shaders, 0 means auto-tune at startup
+  uint32_t workgroup_size;
+  // Whether to read the spatial bin indices back from the GPU every frame
+  bool readback_indices;
+  // Whether to read the particle positions back from the GPU every frame
+  bool readback_positions;
+  // Whether to read the particle velocities back from the GPU every frame
+  bool readback_velocities;
+  // Whether to read the particle ages and lifetimes back from the GPU every frame
+  bool readback_ages;
+  // Whether to read the particle temperatures back from the GPU every frame
+  bool readback_temperatures;
+  // Whether to read the particle materials back from the GPU every frame
+  bool readback_materials;
+  // How many frames the data read back from the GPU can trail behind the GPU
+  uint32_t readback_latency;
+  // Give bit-identical results across runs on the same hardware
+  bool deterministic;
+  // The temperature of the surroundings, and of new particles
+  float ambient_temperature;
+  // The fraction of the difference in temperature that flows between neighbouring particles
+  // every frame
+  float thermal_conductivity;
+  // The fraction of the difference from the ambient temperature that particles lose every frame
+  float cooling_rate;
+  // How much faster particles fall every frame
+  float gravity;
+  // Half the distance that particles are kept apart from each other
+  float particle_radius;
+  // The fastest that a particle can move in a single frame
+  float max_speed;
+} WrachCConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The default config, for changing just the fields you care about.
+struct WrachCConfig wrach_config_default(void);
+
+// Create a new simulation. Returns null if `config` is null or invalid, or the simulation
+// couldn't be created. Must be freed with `wrach_destroy()`.
+//
+// # Safety
+// `config` must be null or point to a valid `WrachCConfig`.
+struct Wrach *wrach_create(const struct WrachCConfig *config);
+
+// Free a simulation created by `wrach_create()`. Does nothing if `wrach` is null.
+//
+// # Safety
+// `wrach` must be null or have come from `wrach_create()`, and it must not be used afterwards.
+void wrach_destroy(struct Wrach *wrach);
+
+// Add particles to the simulation. `data` is a flat array of `WRACH_FLOATS_PER_PARTICLE` floats
+// for every particle, `length` is the total number of floats.
+//
+// # Safety
+// `wrach` must be null or a live simulation. `data` must be null or point to `length` floats.
+enum WrachStatus wrach_add_particles(struct Wrach *wrach, const float *data, size_t length);
+
+// Run a single tick/frame of the simulation.
+//
+// # Safety
+// `wrach` must be null or a live simulation.
+enum WrachStatus wrach_tick(struct Wrach *wrach);
+
+// Move the view onto the simulation, `x` and `y` are its new bottom-left corner.
+//
+// # Safety
+// `wrach` must be null or a live simulation.
+enum WrachStatus wrach_set_viewport(struct Wrach *wrach, float x, float y);
+
+// The particle positions from the most recent frame read back from the GPU.
+//
+// They're a flat array of x and y floats, the number of floats is written to `length`. Particles
+// are packed by spatial bin cell, and the array is the size of the GPU buffers, so there may be
+// unused space at the end, see `wrach_indices()`.
+//
+// The pointer is only valid until the next call to `wrach_tick()` or `wrach_destroy()`. Returns
+// null if `wrach` or `length` is null.
+//
+// # Safety
+// `wrach` must be null or a live simulation, `length` must be null or writable.
+const float *wrach_positions(const struct Wrach *wrach, size_t *length);
+
+// The particle velocities from the most recent frame read back from the GPU, in the same layout
+// as `wrach_positions()`.
+//
+// # Safety
+// `wrach` must be null or a live simulation, `length` must be null or writable.
+const float *wrach_velocities(const struct Wrach *wrach, size_t *length);
+
+// For every spatial bin cell, the index of its first particle in `wrach_positions()`. The extra
+// item at the end is the total number of particles. The number of items is written to `length`.
+//
+// The pointer is only valid until the next call to `wrach_tick()` or `wrach_destroy()`. Returns
+// null if `wrach` or `length` is null.
+//
+// # Safety
+// `wrach` must be null or a live simulation, `length` must be null or writable.
+const uint32_t *wrach_indices(const struct Wrach *wrach, size_t *length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WRACH_H */
@@ -0,0 +1,424 @@
+//! C interface to Wrach simulations, for embedding Wrach in non-Rust hosts.
+//!
+//! The header is checked in at `include/wrach.h`, `tests/header.rs` makes sure that it matches what
+//! cbindgen generates. Every function is safe to call with null pointers, they just return
+//! `WRACH_STATUS_NULL_POINTER`. Panics never cross the FFI boundary, they're caught and returned as
+//! `WRACH_STATUS_PANIC`.
+
+#![expect(
+    unsafe_code,
+    reason = "Taking and returning raw pointers is the whole point of a C interface"
+)]
+
+use core::{
+    ffi::c_float,
+    panic::AssertUnwindSafe,
+    ptr::{self, NonNull},
+};
+use std::panic::catch_unwind;
+
+use wrach_api::{
+    Particle, ReadbackBuffers, ThermalConfig, Vec2, WorkgroupSize, WrachAPI, WrachConfig,
+    WrachConfigError,
+};
+
+/// An instance of a Wrach simulation. Only ever handled through a pointer.
+#[non_exhaustive]
+pub struct Wrach {
+    /// The Rust API that all the calls are forwarded to
+    api: WrachAPI,
+}
+
+/// The outcome of calling a Wrach function
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WrachStatus {
+    /// Everything went fine
+    Ok = 0,
+    /// One of the pointer arguments was null
+    NullPointer = 1,
+    /// An argument didn't make sense, like a particle array that isn't a multiple of 4 floats
+    InvalidArgument = 2,
+    /// Wrach panicked. The details will have been printed to stderr.
+    Panic = 3,
+}
+
+/// Config for creating a simulation, see `WrachConfig` in the Rust API for details.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+#[expect(clippy::exhaustive_structs, reason = "C needs to see every field")]
+pub struct WrachCConfig {
+    /// Width of the realtime view onto the simulation
+    pub width: u16,
+    /// Height of the realtime view onto the simulation
+    pub height: u16,
+    /// Should particles be limited to within the viewport dimensions?
+    pub boundaries_as_dimensions: bool,
+    /// The size of a single cell in the spatial binning grid, in multiples of a particle's size
+    pub cell_size: u16,
+    /// Threads per workgroup for the compute shaders, 0 means auto-tune at startup
+    pub workgroup_size: u32,
+    /// Whether to read the spatial bin indices back from the GPU every frame
+    pub readback_indices: bool,
+    /// Whether to read the particle positions back from the GPU every frame
+    pub readback_positions: bool,
+    /// Whether to read the particle velocities back from the GPU every frame
+    pub readback_velocities: bool,
+    /// Whether to read the particle ages and lifetimes back from the GPU every frame
+    pub readback_ages: bool,
+    /// Whether to read the particle temperatures back from the GPU every frame
+    pub readback_temperatures: bool,
+    /// Whether to read the particle materials back from the GPU every frame
+    pub readback_materials: bool,
+    /// How many frames the data read back from the GPU can trail behind the GPU
+    pub readback_latency: u32,
+    /// Give bit-identical results across runs on the same hardware
+    pub deterministic: bool,
+    /// The temperature of the surroundings, and of new particles
+    pub ambient_temperature: f32,
+    /// The fraction of the difference in temperature that flows between neighbouring particles
+    /// every frame
+    pub thermal_conductivity: f32,
+    /// The fraction of the difference from the ambient temperature that particles lose every frame
+    pub cooling_rate: f32,
+    /// How much faster particles fall every frame
+    pub gravity: f32,
+    /// Half the distance that particles are kept apart from each other
+    pub particle_radius: f32,
+    /// The fastest that a particle can move in a single frame
+    pub max_speed: f32,
+}
+
+impl From<WrachConfig> for WrachCConfig {
+    #[inline]
+    fn from(config: WrachConfig) -> Self {
+        Self {
+            width: config.dimensions.0,
+            height: config.dimensions.1,
+            boundaries_as_dimensions: config.boundaries_as_dimensions,
+            cell_size: config.cell_size,
+            workgroup_size: match config.workgroup_size {
+                WorkgroupSize::Fixed(threads) => threads,
+                WorkgroupSize::AutoTune | _ => 0,
+            },
+            readback_indices: config.readback.indices,
+            readback_positions: config.readback.positions,
+            readback_velocities: config.readback.velocities,
+            readback_ages: config.readback.ages,
+            readback_temperatures: config.readback.temperatures,
+            readback_materials: config.readback.materials,
+            readback_latency: config.readback_latency,
+            deterministic: config.deterministic,
+            ambient_temperature: config.thermal.ambient_temperature,
+            thermal_conductivity: config.thermal.conductivity,
+            cooling_rate: config.thermal.cooling_rate,
+            gravity: config.gravity,
+            particle_radius: config.particle_radius,
+            max_speed: config.max_speed,
+        }
+    }
+}
+
+impl TryFrom<WrachCConfig> for WrachConfig {
+    type Error = WrachConfigError;
+
+    #[inline]
+    fn try_from(config: WrachCConfig) -> Result<Self, Self::Error> {
+        Self::builder()
+            .dimensions(config.width, config.height)
+            .boundaries_as_dimensions(config.boundaries_as_dimensions)
+            .cell_size(config.cell_size)
+            .workgroup_size(match config.workgroup_size {
+                0 => WorkgroupSize::AutoTune,
+                threads => WorkgroupSize::Fixed(threads),
+            })
+            .readback(ReadbackBuffers {
+                indices: config.readback_indices,
+                positions: config.readback_positions,
+                velocities: config.readback_velocities,
+                ages: config.readback_ages,
+                temperatures: config.readback_temperatures,
+                materials: config.readback_materials,
+            })
+            .readback_latency(config.readback_latency)
+            .deterministic(config.deterministic)
+            .thermal(ThermalConfig {
+                ambient_temperature: config.ambient_temperature,
+                conductivity: config.thermal_conductivity,
+                cooling_rate: config.cooling_rate,
+            })
+            .gravity(config.gravity)
+            .particle_radius(config.particle_radius)
+            .max_speed(config.max_speed)
+            .build()
+    }
+}
+
+/// The number of floats that make up a single particle in `wrach_add_particles()`: position x,
+/// position y, velocity x, velocity y.
+pub const WRACH_FLOATS_PER_PARTICLE: usize = 4;
+
+/// Run a closure, turning any panic into `WrachStatus::Panic`.
+fn guard(callback: impl FnOnce() -> WrachStatus) -> WrachStatus {
+    catch_unwind(AssertUnwindSafe(callback)).unwrap_or(WrachStatus::Panic)
+}
+
+/// The default config, for changing just the fields you care about.
+#[no_mangle]
+pub extern "C" fn wrach_config_default() -> WrachCConfig {
+    WrachConfig::default().into()
+}
+
+/// Create a new simulation. Returns null if `config` is null or invalid, or the simulation
+/// couldn't be created. Must be freed with `wrach_destroy()`.
+///
+/// # Safety
+/// `config` must be null or point to a valid `WrachCConfig`.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_create(config: *const WrachCConfig) -> *mut Wrach {
+    // SAFETY: The caller promises that a non-null `config` is valid.
+    let Some(c_config) = (unsafe { config.as_ref() }) else {
+        return ptr::null_mut();
+    };
+    let Ok(rust_config) = WrachConfig::try_from(*c_config) else {
+        return ptr::null_mut();
+    };
+
+    catch_unwind(|| {
+        Box::new(Wrach {
+            api: WrachAPI::new(rust_config),
+        })
+    })
+    .map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Free a simulation created by `wrach_create()`. Does nothing if `wrach` is null.
+///
+/// # Safety
+/// `wrach` must be null or have come from `wrach_create()`, and it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_destroy(wrach: *mut Wrach) {
+    if wrach.is_null() {
+        return;
+    }
+    // SAFETY: The caller promises the pointer came from `Box::into_raw()` in `wrach_create()`.
+    let simulation = unsafe { Box::from_raw(wrach) };
+    // There's nothing that C could do about a panic whilst freeing, but it still mustn't cross the
+    // FFI boundary.
+    guard(|| {
+        drop(simulation);
+        WrachStatus::Ok
+    });
+}
+
+/// Add particles to the simulation. `data` is a flat array of `WRACH_FLOATS_PER_PARTICLE` floats
+/// for every particle, `length` is the total number of floats.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation. `data` must be null or point to `length` floats.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_add_particles(
+    wrach: *mut Wrach,
+    data: *const c_float,
+    length: usize,
+) -> WrachStatus {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_mut() }) else {
+        return WrachStatus::NullPointer;
+    };
+    if data.is_null() {
+        return WrachStatus::NullPointer;
+    }
+    if length.checked_rem(WRACH_FLOATS_PER_PARTICLE) != Some(0) {
+        return WrachStatus::InvalidArgument;
+    }
+
+    // SAFETY: The caller promises that `data` points to `length` floats.
+    let floats = unsafe { core::slice::from_raw_parts(data, length) };
+    let particles = floats
+        .chunks_exact(WRACH_FLOATS_PER_PARTICLE)
+        .filter_map(|chunk| match *chunk {
+            [x, y, velocity_x, velocity_y] => Some(Particle {
+                position: Vec2::new(x, y),
+                velocity: Vec2::new(velocity_x, velocity_y),
+            }),
+            _ => None,
+        })
+        .collect();
+
+    guard(|| {
+        simulation.api.add_particles(particles);
+        WrachStatus::Ok
+    })
+}
+
+/// Run a single tick/frame of the simulation.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_tick(wrach: *mut Wrach) -> WrachStatus {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_mut() }) else {
+        return WrachStatus::NullPointer;
+    };
+    guard(|| {
+        simulation.api.tick();
+        WrachStatus::Ok
+    })
+}
+
+/// Move the view onto the simulation, `x` and `y` are its new bottom-left corner.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_set_viewport(
+    wrach: *mut Wrach,
+    x: c_float,
+    y: c_float,
+) -> WrachStatus {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_mut() }) else {
+        return WrachStatus::NullPointer;
+    };
+    guard(|| {
+        simulation.api.set_viewport(Vec2::new(x, y));
+        WrachStatus::Ok
+    })
+}
+
+/// Point C at a slice of `Vec2`s as a flat array of floats, writing the number of floats to
+/// `length`.
+const fn vec2s_as_floats(vectors: &[Vec2], length: &mut usize) -> *const c_float {
+    *length = vectors.len().saturating_mul(2);
+    if vectors.is_empty() {
+        return NonNull::dangling().as_ptr();
+    }
+    vectors.as_ptr().cast()
+}
+
+/// The particle positions from the most recent frame read back from the GPU.
+///
+/// They're a flat array of x and y floats, the number of floats is written to `length`. Particles
+/// are packed by spatial bin cell, and the array is the size of the GPU buffers, so there may be
+/// unused space at the end, see `wrach_indices()`.
+///
+/// The pointer is only valid until the next call to `wrach_tick()` or `wrach_destroy()`. Returns
+/// null if `wrach` or `length` is null.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation, `length` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_positions(
+    wrach: *const Wrach,
+    length: *mut usize,
+) -> *const c_float {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_ref() }) else {
+        return ptr::null();
+    };
+    // SAFETY: The caller promises that a non-null `length` is writable.
+    let Some(length_out) = (unsafe { length.as_mut() }) else {
+        return ptr::null();
+    };
+    vec2s_as_floats(simulation.api.frame().positions, length_out)
+}
+
+/// The particle velocities from the most recent frame read back from the GPU, in the same layout
+/// as `wrach_positions()`.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation, `length` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_velocities(
+    wrach: *const Wrach,
+    length: *mut usize,
+) -> *const c_float {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_ref() }) else {
+        return ptr::null();
+    };
+    // SAFETY: The caller promises that a non-null `length` is writable.
+    let Some(length_out) = (unsafe { length.as_mut() }) else {
+        return ptr::null();
+    };
+    vec2s_as_floats(simulation.api.frame().velocities, length_out)
+}
+
+/// For every spatial bin cell, the index of its first particle in `wrach_positions()`. The extra
+/// item at the end is the total number of particles. The number of items is written to `length`.
+///
+/// The pointer is only valid until the next call to `wrach_tick()` or `wrach_destroy()`. Returns
+/// null if `wrach` or `length` is null.
+///
+/// # Safety
+/// `wrach` must be null or a live simulation, `length` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn wrach_indices(wrach: *const Wrach, length: *mut usize) -> *const u32 {
+    // SAFETY: The caller promises that a non-null `wrach` is a live simulation.
+    let Some(simulation) = (unsafe { wrach.as_ref() }) else {
+        return ptr::null();
+    };
+    // SAFETY: The caller promises that a non-null `length` is writable.
+    let Some(length_out) = (unsafe { length.as_mut() }) else {
+        return ptr::null();
+    };
+    let indices = simulation.api.frame().indices;
+    *length_out = indices.len();
+    if indices.is_empty() {
+        return NonNull::dangling().as_ptr();
+    }
+    indices.as_ptr()
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, reason = "Tests aren't so strict")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_survives_the_round_trip_through_c() {
+        let config = WrachConfig::builder()
+            .dimensions(20, 30)
+            .workgroup_size(WorkgroupSize::AutoTune)
+            .readback(ReadbackBuffers::POSITIONS)
+            .readback_latency(2)
+            .deterministic(true)
+            .build()
+            .unwrap();
+        let round_tripped = WrachConfig::try_from(WrachCConfig::from(config)).unwrap();
+        assert_eq!(round_tripped.dimensions, (20, 30));
+        assert_eq!(round_tripped.workgroup_size, WorkgroupSize::AutoTune);
+        assert_eq!(round_tripped.readback, ReadbackBuffers::POSITIONS);
+        assert_eq!(round_tripped.readback_latency, 2);
+        assert!(round_tripped.deterministic, "Should still be deterministic");
+    }
+
+    #[test]
+    fn null_pointers_are_rejected() {
+        let mut length = 0;
+        // SAFETY: Null is always allowed.
+        let created = unsafe { wrach_create(ptr::null()) };
+        assert!(created.is_null());
+        // SAFETY: Null is always allowed.
+        let ticked = unsafe { wrach_tick(ptr::null_mut()) };
+        assert_eq!(ticked, WrachStatus::NullPointer);
+        // SAFETY: Null is always allowed.
+        let added = unsafe { wrach_add_particles(ptr::null_mut(), ptr::null(), 0) };
+        assert_eq!(added, WrachStatus::NullPointer);
+        // SAFETY: Null is always allowed, and `length` is writable.
+        let positions = unsafe { wrach_positions(ptr::null(), &raw mut length) };
+        assert!(positions.is_null());
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let mut config = wrach_config_default();
+        config.cell_size = 0;
+        // SAFETY: `config` is a valid `WrachCConfig`.
+        let created = unsafe { wrach_create(&raw const config) };
+        assert!(created.is_null());
+    }
+}
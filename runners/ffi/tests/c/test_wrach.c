@@ -0,0 +1,67 @@
+/* Exercises Wrach's C interface from actual C. Run by `tests/c_api.rs`. */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "wrach.h"
+
+#define CHECK(condition)                                                       \
+  do {                                                                         \
+    if (!(condition)) {                                                        \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,         \
+              #condition);                                                     \
+      return EXIT_FAILURE;                                                     \
+    }                                                                          \
+  } while (0)
+
+int main(void) {
+  size_t length = 0;
+
+  CHECK(wrach_create(NULL) == NULL);
+  CHECK(wrach_tick(NULL) == WRACH_STATUS_NULL_POINTER);
+  CHECK(wrach_positions(NULL, &length) == NULL);
+  wrach_destroy(NULL);
+
+  WrachCConfig config = wrach_config_default();
+  config.width = 10;
+  config.height = 10;
+  config.cell_size = 3;
+
+  Wrach *wrach = wrach_create(&config);
+  CHECK(wrach != NULL);
+
+  float particles[3 * WRACH_FLOATS_PER_PARTICLE];
+  for (size_t i = 0; i < 3; i++) {
+    float *particle = &particles[i * WRACH_FLOATS_PER_PARTICLE];
+    particle[0] = 5.0f;
+    particle[1] = 5.0f;
+    particle[2] = 0.5f;
+    particle[3] = 0.5f;
+  }
+  CHECK(wrach_add_particles(wrach, particles, 3) == WRACH_STATUS_INVALID_ARGUMENT);
+  CHECK(wrach_add_particles(wrach, particles, 3 * WRACH_FLOATS_PER_PARTICLE) ==
+        WRACH_STATUS_OK);
+
+  for (int frame = 0; frame < 5; frame++) {
+    CHECK(wrach_tick(wrach) == WRACH_STATUS_OK);
+  }
+
+  const float *positions = wrach_positions(wrach, &length);
+  CHECK(positions != NULL);
+  CHECK(length == 144 * 2);
+  CHECK(positions[0] != 0.0f || positions[1] != 0.0f);
+
+  const float *velocities = wrach_velocities(wrach, &length);
+  CHECK(velocities != NULL);
+  CHECK(length == 144 * 2);
+
+  const uint32_t *indices = wrach_indices(wrach, &length);
+  CHECK(indices != NULL && length > 0);
+  CHECK(indices[length - 1] == 3);
+
+  CHECK(wrach_set_viewport(wrach, 1.0f, 1.0f) == WRACH_STATUS_OK);
+  CHECK(wrach_tick(wrach) == WRACH_STATUS_OK);
+
+  wrach_destroy(wrach);
+  return EXIT_SUCCESS;
+}
@@ -0,0 +1,51 @@
+//! Compile and run a C program against Wrach's C library and header
+
+#![expect(
+    clippy::expect_used,
+    clippy::tests_outside_test_module,
+    reason = "Tests aren't so strict"
+)]
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The directory that Cargo builds the library into when testing, which is the same one as the
+/// test binary itself, e.g. `target/debug/deps`.
+fn library_dir() -> PathBuf {
+    let test_binary = env::current_exe().expect("Couldn't find the test binary");
+    test_binary
+        .parent()
+        .expect("Test binary isn't in a directory")
+        .to_path_buf()
+}
+
+#[test]
+fn c_program_can_run_a_simulation() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let library_dir = library_dir();
+    let executable = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("test_wrach_c");
+    let c_compiler = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+
+    let compiled = Command::new(c_compiler)
+        .arg(crate_dir.join("tests").join("c").join("test_wrach.c"))
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .arg("-lwrach")
+        .arg("-o")
+        .arg(&executable)
+        .status()
+        .expect("Couldn't run the C compiler");
+    assert!(compiled.success(), "Couldn't compile the C test");
+
+    let ran = Command::new(&executable)
+        .status()
+        .expect("Couldn't run the compiled C test");
+    assert!(ran.success(), "C test failed");
+}
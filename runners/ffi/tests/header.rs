@@ -0,0 +1,41 @@
+//! Make sure that the checked in C header matches the C interface. To update it, run:
+//!
+//! `WRACH_UPDATE_HEADER=1 cargo test -p wrach-ffi --test header`
+
+#![expect(
+    clippy::expect_used,
+    clippy::tests_outside_test_module,
+    reason = "Tests aren't so strict"
+)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let header_path = crate_dir.join("include").join("wrach.h");
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Couldn't read `cbindgen.toml`");
+
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Couldn't generate the C header")
+        .write(&mut generated);
+
+    if env::var_os("WRACH_UPDATE_HEADER").is_some() {
+        fs::write(&header_path, &generated).expect("Couldn't write the C header");
+        return;
+    }
+
+    let checked_in = fs::read(&header_path).expect("Couldn't read the C header");
+    assert!(
+        checked_in == generated,
+        "`include/wrach.h` is out of date, update it with: \
+         `WRACH_UPDATE_HEADER=1 cargo test -p wrach-ffi --test header`"
+    );
+}
@@ -0,0 +1,524 @@
+//! Python bindings for Wrach simulations
+//!
+//! Build with `maturin develop` from this directory, then:
+//!
+//! ```python
+//! import numpy as np
+//! import wrach
+//!
+//! simulation = wrach.WrachAPI(wrach.WrachConfig(width=100, height=100))
+//! simulation.add_particles(np.array([[50.0, 50.0]], dtype=np.float32))
+//! simulation.tick(10)
+//! print(simulation.positions)
+//! ```
+
+use core::mem;
+
+use numpy::{
+    ndarray::{ArrayView, ArrayView1, ArrayView2, Dimension, ShapeError},
+    AllowTypeChange, Element, PyArray, PyArray1, PyArray2, PyArrayLike2, PyArrayMethods as _,
+    PyReadonlyArray2, PyUntypedArrayMethods as _,
+};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use wrach_api::{
+    Particle, ReadbackBuffers, Vec2, WorkgroupSize, WrachAPI, WrachConfig, WrachConfigError,
+};
+use wrach_bevy::{CpuSimulation, WrachState};
+
+/// Config for a simulation, see `WrachConfig` in the Rust API for details.
+#[pyclass(name = "WrachConfig", module = "wrach", get_all, set_all)]
+#[derive(Clone, Debug)]
+struct PyWrachConfig {
+    /// Width of the realtime view onto the simulation
+    width: u16,
+    /// Height of the realtime view onto the simulation
+    height: u16,
+    /// Should particles be limited to within the viewport dimensions?
+    boundaries_as_dimensions: bool,
+    /// The size of a single cell in the spatial binning grid, in multiples of a particle's size
+    cell_size: u16,
+    /// Threads per workgroup for the compute shaders, `None` means auto-tune at startup
+    workgroup_size: Option<u32>,
+    /// How many frames the data read back from the GPU can trail behind the GPU
+    readback_latency: u32,
+    /// Give bit-identical results across runs on the same hardware
+    deterministic: bool,
+}
+
+#[pymethods]
+impl PyWrachConfig {
+    /// Anything that isn't given keeps the value from `WrachConfig::default()`.
+    #[new]
+    #[pyo3(signature = (
+        *,
+        width = None,
+        height = None,
+        boundaries_as_dimensions = None,
+        cell_size = None,
+        workgroup_size = None,
+        readback_latency = None,
+        deterministic = None,
+    ))]
+    fn new(
+        width: Option<u16>,
+        height: Option<u16>,
+        boundaries_as_dimensions: Option<bool>,
+        cell_size: Option<u16>,
+        workgroup_size: Option<u32>,
+        readback_latency: Option<u32>,
+        deterministic: Option<bool>,
+    ) -> Self {
+        let defaults = Self::from(WrachConfig::default());
+        Self {
+            width: width.unwrap_or(defaults.width),
+            height: height.unwrap_or(defaults.height),
+            boundaries_as_dimensions: boundaries_as_dimensions
+                .unwrap_or(defaults.boundaries_as_dimensions),
+            cell_size: cell_size.unwrap_or(defaults.cell_size),
+            workgroup_size: workgroup_size.or(defaults.workgroup_size),
+            readback_latency: readback_latency.unwrap_or(defaults.readback_latency),
+            deterministic: deterministic.unwrap_or(defaults.deterministic),
+        }
+    }
+
+    /// How the object is shown in Python
+    fn __repr__(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl From<WrachConfig> for PyWrachConfig {
+    #[inline]
+    fn from(config: WrachConfig) -> Self {
+        Self {
+            width: config.dimensions.0,
+            height: config.dimensions.1,
+            boundaries_as_dimensions: config.boundaries_as_dimensions,
+            cell_size: config.cell_size,
+            workgroup_size: match config.workgroup_size {
+                WorkgroupSize::Fixed(threads) => Some(threads),
+                WorkgroupSize::AutoTune | _ => None,
+            },
+            readback_latency: config.readback_latency,
+            deterministic: config.deterministic,
+        }
+    }
+}
+
+impl TryFrom<PyWrachConfig> for WrachConfig {
+    type Error = WrachConfigError;
+
+    #[inline]
+    fn try_from(config: PyWrachConfig) -> Result<Self, Self::Error> {
+        Self::builder()
+            .dimensions(config.width, config.height)
+            .boundaries_as_dimensions(config.boundaries_as_dimensions)
+            .cell_size(config.cell_size)
+            .workgroup_size(
+                config
+                    .workgroup_size
+                    .map_or(WorkgroupSize::AutoTune, WorkgroupSize::Fixed),
+            )
+            // There's no way yet to read just some of the buffers into `NumPy`.
+            .readback(ReadbackBuffers::ALL)
+            .readback_latency(config.readback_latency)
+            .deterministic(config.deterministic)
+            .build()
+    }
+}
+
+/// A single particle, for when `NumPy` arrays are overkill
+#[pyclass(name = "Particle", module = "wrach", get_all, set_all)]
+#[derive(Clone, Debug)]
+struct PyParticle {
+    /// Position of the particle as `(x, y)`
+    position: (f32, f32),
+    /// Velocity of the particle as `(x, y)`
+    velocity: (f32, f32),
+}
+
+#[pymethods]
+impl PyParticle {
+    /// The velocity defaults to zero.
+    #[new]
+    #[pyo3(signature = (position, velocity = (0.0, 0.0)))]
+    const fn new(position: (f32, f32), velocity: (f32, f32)) -> Self {
+        Self { position, velocity }
+    }
+
+    /// How the object is shown in Python
+    fn __repr__(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl From<&PyParticle> for Particle {
+    #[inline]
+    fn from(particle: &PyParticle) -> Self {
+        Self {
+            position: Vec2::new(particle.position.0, particle.position.1),
+            velocity: Vec2::new(particle.velocity.0, particle.velocity.1),
+        }
+    }
+}
+
+/// The `base` of the `NumPy` arrays for a single frame. The arrays are views straight onto the
+/// simulation's memory, and once the simulation moves on to another frame whilst Python still has
+/// them, this takes over that memory, see `PyWrachAPI::release_frame()`.
+#[pyclass(name = "Frame", module = "wrach", unsendable)]
+#[derive(Default)]
+struct PyFrame {
+    /// Particle positions, once the simulation has moved on
+    positions: Vec<Vec2>,
+    /// Particle velocities, once the simulation has moved on
+    velocities: Vec<Vec2>,
+    /// Spatial bin indices, once the simulation has moved on
+    indices: Vec<u32>,
+}
+
+/// The `NumPy` arrays for the most recent frame
+struct FrameArrays {
+    /// The `base` of all the arrays, which keeps the memory that they point to alive
+    owner: Py<PyFrame>,
+    /// Particle positions, an `(N, 2)` array of `float32`
+    positions: Py<PyArray2<f32>>,
+    /// Particle velocities, an `(N, 2)` array of `float32`
+    velocities: Py<PyArray2<f32>>,
+    /// The index of the first particle of every spatial bin cell, plus the total at the end
+    indices: Py<PyArray1<u32>>,
+}
+
+/// Where the simulation is actually run
+enum Engine {
+    /// On the GPU
+    Gpu(Box<WrachAPI>),
+    /// On the CPU, for machines without a GPU
+    Cpu(Box<CpuSimulation>),
+}
+
+/// A Wrach simulation.
+///
+/// The `positions`, `velocities` and `indices` arrays aren't copies, they're read-only views
+/// straight onto the simulation's memory. Ticking doesn't change arrays that Python still has, the
+/// memory is handed over to the arrays' `base` and the simulation carries on with a copy. So they
+/// stay valid for as long as Python holds on to them, and only cost a copy if they're kept past a
+/// tick.
+#[pyclass(name = "WrachAPI", module = "wrach", unsendable)]
+struct PyWrachAPI {
+    /// Where the simulation is actually run
+    engine: Engine,
+    /// The arrays for the most recent frame, made the first time any of them are asked for
+    arrays: Option<FrameArrays>,
+}
+
+#[pymethods]
+impl PyWrachAPI {
+    /// Pass `cpu=True` to run the physics on the CPU, for machines without a GPU. Raises
+    /// `ValueError` if the config is invalid.
+    #[new]
+    #[pyo3(signature = (config = None, cpu = false))]
+    fn new(config: Option<PyWrachConfig>, cpu: bool) -> PyResult<Self> {
+        let engine = config
+            .map_or_else(|| Ok(WrachConfig::default()), WrachConfig::try_from)
+            .and_then(|rust_config| {
+                if cpu {
+                    Ok(Engine::Cpu(Box::new(CpuSimulation::new(rust_config)?)))
+                } else {
+                    Ok(Engine::Gpu(Box::new(WrachAPI::try_new(rust_config)?)))
+                }
+            })
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+        Ok(Self {
+            engine,
+            arrays: None,
+        })
+    }
+
+    /// Run the simulation for a number of ticks/frames. Raises `RuntimeError` if the CPU
+    /// simulation fails to stage new particles.
+    #[pyo3(signature = (frames = 1))]
+    fn tick(&mut self, py: Python<'_>, frames: u32) -> PyResult<()> {
+        self.release_frame(py);
+        for _ in 0..frames {
+            match self.engine {
+                Engine::Gpu(ref mut api) => api.tick(),
+                Engine::Cpu(ref mut simulation) => simulation
+                    .tick()
+                    .map_err(|error| PyRuntimeError::new_err(error.to_string()))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Add particles from an `(N, 2)` array of positions, and optionally an `(N, 2)` array of
+    /// velocities. Anything that `NumPy` can convert to `float32` arrays is fine.
+    #[pyo3(signature = (positions, velocities = None))]
+    #[expect(
+        clippy::needless_pass_by_value,
+        reason = "PyO3 needs to own the arguments it converts from Python"
+    )]
+    fn add_particles(
+        &mut self,
+        positions: PyArrayLike2<'_, f32, AllowTypeChange>,
+        velocities: Option<PyArrayLike2<'_, f32, AllowTypeChange>>,
+    ) -> PyResult<()> {
+        if positions.shape().get(1) != Some(&2) {
+            return Err(PyValueError::new_err(
+                "`positions` must have the shape (N, 2)",
+            ));
+        }
+        let velocity_vectors = match velocities.as_ref() {
+            Some(array) if array.shape() != positions.shape() => {
+                return Err(PyValueError::new_err(
+                    "`velocities` must have the same shape as `positions`",
+                ));
+            }
+            Some(array) => array_to_vec2s(array),
+            None => Vec::new(),
+        };
+
+        let particles = array_to_vec2s(&positions)
+            .into_iter()
+            .enumerate()
+            .map(|(index, position)| Particle {
+                position,
+                velocity: velocity_vectors.get(index).copied().unwrap_or(Vec2::ZERO),
+            })
+            .collect();
+        self.add(particles);
+        Ok(())
+    }
+
+    /// Add particles from a list of `Particle`s.
+    #[expect(
+        clippy::needless_pass_by_value,
+        reason = "PyO3 needs to own the arguments it converts from Python"
+    )]
+    fn add_particle_list(&mut self, particles: Vec<PyRef<'_, PyParticle>>) {
+        self.add(
+            particles
+                .iter()
+                .map(|particle| Particle::from(&**particle))
+                .collect(),
+        );
+    }
+
+    /// Move the view onto the simulation, `x` and `y` are its new bottom-left corner.
+    fn set_viewport(&mut self, x: f32, y: f32) {
+        let anchor = Vec2::new(x, y);
+        match self.engine {
+            Engine::Gpu(ref mut api) => api.set_viewport(anchor),
+            Engine::Cpu(ref mut simulation) => simulation.set_viewport_anchor(anchor),
+        }
+    }
+
+    /// Particle positions from the most recent frame read back from the GPU, an `(N, 2)` array of
+    /// `float32`. Particles are packed by spatial bin cell, see `indices`.
+    #[getter]
+    fn positions(&mut self, py: Python<'_>) -> PyResult<Py<PyArray2<f32>>> {
+        Ok(self.frame_arrays(py)?.positions.clone_ref(py))
+    }
+
+    /// Particle velocities from the most recent frame read back from the GPU, in the same layout
+    /// as `positions`.
+    #[getter]
+    fn velocities(&mut self, py: Python<'_>) -> PyResult<Py<PyArray2<f32>>> {
+        Ok(self.frame_arrays(py)?.velocities.clone_ref(py))
+    }
+
+    /// For every spatial bin cell, the index of its first particle in `positions`. The extra item
+    /// at the end is the total number of particles.
+    #[getter]
+    fn indices(&mut self, py: Python<'_>) -> PyResult<Py<PyArray1<u32>>> {
+        Ok(self.frame_arrays(py)?.indices.clone_ref(py))
+    }
+
+    /// The frame that `positions`, `velocities` and `indices` were read back from.
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.state().packed_data_frame
+    }
+
+    /// The number of spatial bin cells across and up the simulation, as `(x, y)`. `indices` has an
+    /// item for each of them, plus the total at the end.
+    #[getter]
+    fn grid_dimensions(&self) -> (u32, u32) {
+        let grid = self.state().particle_store.spatial_bin.grid_dimensions;
+        (grid.x, grid.y)
+    }
+}
+
+impl PyWrachAPI {
+    /// The state of the simulation, wherever it's running
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn state(&self) -> &WrachState {
+        match self.engine {
+            Engine::Gpu(ref api) => api.get_simulation_state(),
+            Engine::Cpu(ref simulation) => &simulation.state,
+        }
+    }
+
+    /// The state of the simulation, for handing its memory over to a `Frame`
+    fn state_mut(&mut self) -> &mut WrachState {
+        match self.engine {
+            Engine::Gpu(ref mut api) => api.get_simulation_state_mut().into_inner(),
+            Engine::Cpu(ref mut simulation) => &mut simulation.state,
+        }
+    }
+
+    /// Add particles to the simulation, wherever it's running
+    fn add(&mut self, particles: Vec<Particle>) {
+        match self.engine {
+            Engine::Gpu(ref mut api) => api.add_particles(particles),
+            Engine::Cpu(ref mut simulation) => simulation.add_particles(particles),
+        }
+    }
+
+    /// The arrays for the most recent frame, made the first time that any of them are needed.
+    fn frame_arrays(&mut self, py: Python<'_>) -> PyResult<&FrameArrays> {
+        if self.arrays.is_none() {
+            self.arrays = Some(self.borrow_frame(py)?);
+        }
+        self.arrays
+            .as_ref()
+            .ok_or_else(|| PyValueError::new_err("No frame data"))
+    }
+
+    /// Make read-only arrays that point straight at the simulation's memory. Only the particles and
+    /// cells that are actually in the frame are included, the buffers can be bigger.
+    fn borrow_frame(&self, py: Python<'_>) -> PyResult<FrameArrays> {
+        let state = self.state();
+        let data = &state.packed_data;
+        let grid = state.particle_store.spatial_bin.grid_dimensions;
+        let cells = usize::try_from(grid.x.saturating_mul(grid.y)).unwrap_or(usize::MAX);
+        let particles = data
+            .particles_count(grid)
+            .and_then(|count| usize::try_from(count).ok())
+            .unwrap_or(0);
+        let indices = data.indices.get(..=cells).unwrap_or(&data.indices);
+        let positions = data.positions.get(..particles).unwrap_or(&data.positions);
+        let velocities = data.velocities.get(..particles).unwrap_or(&data.velocities);
+
+        let owner = Bound::new(py, PyFrame::default())?;
+        let view_error = |error: ShapeError| PyValueError::new_err(error.to_string());
+        let positions_view =
+            ArrayView2::from_shape((positions.len(), 2), bytemuck::cast_slice(positions))
+                .map_err(view_error)?;
+        let velocities_view =
+            ArrayView2::from_shape((velocities.len(), 2), bytemuck::cast_slice(velocities))
+                .map_err(view_error)?;
+        let positions_array = borrow_array(&positions_view, &owner);
+        let velocities_array = borrow_array(&velocities_view, &owner);
+        let indices_array = borrow_array(&ArrayView1::from(indices), &owner);
+
+        Ok(FrameArrays {
+            owner: owner.unbind(),
+            positions: read_only(positions_array)?,
+            velocities: read_only(velocities_array)?,
+            indices: read_only(indices_array)?,
+        })
+    }
+
+    /// Let go of the arrays before the simulation changes its memory. If Python still has any of
+    /// them, their memory is moved into their `Frame` and the simulation carries on with a copy.
+    fn release_frame(&mut self, py: Python<'_>) {
+        let Some(arrays) = self.arrays.take() else {
+            return;
+        };
+        // Arrays that Python doesn't have are freed here, along with their references to the owner.
+        drop(arrays.positions);
+        drop(arrays.velocities);
+        drop(arrays.indices);
+        if arrays.owner.get_refcnt(py) <= 1 {
+            return;
+        }
+
+        let data = &mut self.state_mut().packed_data;
+        let mut frame = arrays.owner.borrow_mut(py);
+        frame.positions = replace_with_copy(&mut data.positions);
+        frame.velocities = replace_with_copy(&mut data.velocities);
+        frame.indices = replace_with_copy(&mut data.indices);
+    }
+}
+
+/// Read an `(N, 2)` array as `Vec2`s, whatever its memory layout.
+fn array_to_vec2s(array: &PyReadonlyArray2<'_, f32>) -> Vec<Vec2> {
+    let floats: Vec<f32> = array.as_array().iter().copied().collect();
+    floats
+        .chunks_exact(2)
+        .filter_map(|chunk| match *chunk {
+            [x, y] => Some(Vec2::new(x, y)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Make an array that points at the simulation's memory and keeps `owner` alive.
+#[expect(
+    unsafe_code,
+    reason = "Arrays that borrow memory from Rust can't be made without `unsafe`"
+)]
+fn borrow_array<'py, T: Element, D: Dimension>(
+    view: &ArrayView<'_, T, D>,
+    owner: &Bound<'py, PyFrame>,
+) -> Bound<'py, PyArray<T, D>> {
+    // SAFETY: The array keeps `owner` alive, and the simulation's memory isn't touched until
+    // `release_frame()` either leaves it be, when nothing but `PyWrachAPI` has the arrays, or
+    // moves it into `owner`. Moving a `Vec` doesn't move its heap allocation.
+    unsafe { PyArray::borrow_from_array(view, owner.clone().into_any()) }
+}
+
+/// Stop Python from writing to an array that borrows the simulation's memory.
+fn read_only<T: Element, D: Dimension>(
+    array: Bound<'_, PyArray<T, D>>,
+) -> PyResult<Py<PyArray<T, D>>> {
+    let readwrite = array
+        .try_readwrite()
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+    drop(readwrite.make_nonwriteable());
+    Ok(array.unbind())
+}
+
+/// Put a copy in place of a `Vec`, and return the original along with its allocation.
+fn replace_with_copy<T: Clone>(original: &mut Vec<T>) -> Vec<T> {
+    let copy = original.clone();
+    mem::replace(original, copy)
+}
+
+/// The `wrach` Python module
+#[pymodule]
+fn wrach(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyWrachConfig>()?;
+    module.add_class::<PyParticle>()?;
+    module.add_class::<PyFrame>()?;
+    module.add_class::<PyWrachAPI>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, reason = "Tests aren't so strict")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_survives_the_round_trip_through_python() {
+        let config = WrachConfig::builder()
+            .dimensions(20, 30)
+            .workgroup_size(WorkgroupSize::AutoTune)
+            .readback_latency(2)
+            .deterministic(true)
+            .build()
+            .unwrap();
+        let round_tripped = WrachConfig::try_from(PyWrachConfig::from(config)).unwrap();
+        assert_eq!(round_tripped.dimensions, (20, 30));
+        assert_eq!(round_tripped.workgroup_size, WorkgroupSize::AutoTune);
+        assert_eq!(round_tripped.readback_latency, 2);
+        assert!(round_tripped.deterministic, "Should still be deterministic");
+    }
+}
@@ -0,0 +1,75 @@
+"""Tests for the Python bindings. Run with `maturin develop && pytest` from `runners/python`."""
+
+import numpy as np
+import pytest
+
+import wrach
+
+
+def small_simulation():
+    config = wrach.WrachConfig(width=10, height=10, cell_size=3)
+    return wrach.WrachAPI(config, cpu=True)
+
+
+def test_config_keeps_defaults_for_anything_not_given():
+    config = wrach.WrachConfig(width=10)
+    assert config.width == 10
+    assert config.height == wrach.WrachConfig().height
+
+
+def test_invalid_config_raises_value_error():
+    with pytest.raises(ValueError, match="Cell size"):
+        wrach.WrachAPI(wrach.WrachConfig(cell_size=0), cpu=True)
+
+
+def test_simulation_returns_numpy_arrays():
+    simulation = small_simulation()
+    positions = np.full((3, 2), 5.0)
+    velocities = np.full((3, 2), 0.5)
+    simulation.add_particles(positions, velocities)
+    simulation.tick(5)
+
+    cells_across, cells_up = simulation.grid_dimensions
+    assert simulation.indices.shape == (cells_across * cells_up + 1,)
+    assert simulation.indices[-1] == 3
+    assert simulation.positions.shape == (3, 2)
+    assert simulation.positions.dtype == np.float32
+    assert simulation.velocities.shape == (3, 2)
+    assert simulation.frame > 0
+
+
+def test_arrays_are_read_only_views_onto_the_simulation():
+    simulation = small_simulation()
+    simulation.add_particle_list([wrach.Particle((5.0, 5.0))])
+    simulation.tick(3)
+
+    positions = simulation.positions
+    assert isinstance(positions.base, wrach.Frame)
+    assert simulation.velocities.base is positions.base
+    assert simulation.indices.base is positions.base
+    assert not positions.flags.owndata, "The data shouldn't have been copied into the array"
+    assert not positions.flags.writeable
+    with pytest.raises(ValueError):
+        positions[0, 0] = 1.0
+
+
+def test_arrays_outlive_later_ticks():
+    simulation = small_simulation()
+    simulation.add_particle_list([wrach.Particle((5.0, 5.0), (0.5, 0.5))])
+    simulation.tick(3)
+
+    positions = simulation.positions
+    assert simulation.positions is positions
+    before = positions.copy()
+    simulation.tick(3)
+
+    assert simulation.positions is not positions
+    np.testing.assert_array_equal(positions, before)
+
+
+def test_mismatched_shapes_are_rejected():
+    simulation = small_simulation()
+    with pytest.raises(ValueError):
+        simulation.add_particles(np.zeros((3, 3)))
+    with pytest.raises(ValueError):
+        simulation.add_particles(np.zeros((3, 2)), np.zeros((2, 2)))
@@ -0,0 +1,445 @@
+//! WebAssembly bindings for Wrach simulations, for running in browsers.
+//!
+//! The simulation runs on WebGPU when the browser supports it, otherwise it falls back to running
+//! the same physics on the CPU. Build with:
+//!
+//! ```sh
+//! cargo build --profile wasm-release --target wasm32-unknown-unknown -p wrach-wasm
+//! wasm-bindgen --target web --out-dir examples/wasm/pkg \
+//!   target/wasm32-unknown-unknown/wasm-release/wrach.wasm
+//! ```
+//!
+//! Then from JavaScript:
+//!
+//! ```js
+//! import init, { Config, Wrach } from "./pkg/wrach.js";
+//! await init();
+//! const wrach = await Wrach.create(new Config());
+//! wrach.addParticles(new Float32Array([50.0, 50.0, 0.0, 0.0]));
+//! wrach.tick();
+//! console.log(wrach.positions());
+//! ```
+
+#![expect(
+    unsafe_code,
+    reason = "Typed array views straight onto the WebAssembly memory need `unsafe`"
+)]
+
+use js_sys::{Float32Array, Function, Promise, Reflect, Uint32Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use wrach_api::{Particle, ReadbackBuffers, Vec2, WorkgroupSize, WrachAPI, WrachConfig};
+use wrach_bevy::{CpuSimulation, WrachState};
+
+/// The number of floats that describe a single particle in `addParticles()`: the x and y of its
+/// position, then the x and y of its velocity.
+const FLOATS_PER_PARTICLE: usize = 4;
+
+/// Config for a simulation, see `WrachConfig` in the Rust API for details.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct Config {
+    /// Width of the realtime view onto the simulation
+    pub width: u16,
+    /// Height of the realtime view onto the simulation
+    pub height: u16,
+    /// Should particles be limited to within the viewport dimensions?
+    #[wasm_bindgen(js_name = boundariesAsDimensions)]
+    pub boundaries_as_dimensions: bool,
+    /// The size of a single cell in the spatial binning grid, in multiples of a particle's size
+    #[wasm_bindgen(js_name = cellSize)]
+    pub cell_size: u16,
+    /// How many frames the data read back from the GPU can trail behind the GPU. Browsers can't
+    /// wait for the GPU, so it has to be at least 1, and is more reliable at 2.
+    #[wasm_bindgen(js_name = readbackLatency)]
+    pub readback_latency: u32,
+    /// Give bit-identical results across runs on the same hardware
+    pub deterministic: bool,
+}
+
+#[wasm_bindgen]
+impl Config {
+    /// The same defaults as `WrachConfig::default()`, except for the readback latency.
+    #[wasm_bindgen(constructor)]
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        let defaults = WrachConfig::default();
+        Self {
+            width: defaults.dimensions.0,
+            height: defaults.dimensions.1,
+            boundaries_as_dimensions: defaults.boundaries_as_dimensions,
+            cell_size: defaults.cell_size,
+            readback_latency: 2,
+            deterministic: defaults.deterministic,
+        }
+    }
+}
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Config> for WrachConfig {
+    type Error = JsError;
+
+    #[inline]
+    fn try_from(config: Config) -> Result<Self, Self::Error> {
+        Self::builder()
+            .dimensions(config.width, config.height)
+            .boundaries_as_dimensions(config.boundaries_as_dimensions)
+            .cell_size(config.cell_size)
+            // Without timestamp queries, which browsers don't reliably support, auto-tuning can only
+            // compare frame times, and those are capped by the display's refresh rate.
+            .workgroup_size(WorkgroupSize::Fixed(WorkgroupSize::DEFAULT_THREADS))
+            .readback(ReadbackBuffers::ALL)
+            .readback_latency(config.readback_latency.max(1))
+            .deterministic(config.deterministic)
+            .build()
+            .map_err(|error| JsError::new(&error.to_string()))
+    }
+}
+
+/// Where the simulation is actually run
+enum Engine {
+    /// On the GPU, through WebGPU
+    Gpu(Box<WrachAPI>),
+    /// On the CPU, for browsers without WebGPU
+    Cpu(Box<CpuSimulation>),
+}
+
+/// A simulation that's been started by `Wrach::start()`
+enum Started {
+    /// It's ready to go
+    Ready(Wrach),
+    /// It's on WebGPU, which still needs the browser to finish setting it up
+    SettingUp(Box<WrachAPI>),
+}
+
+/// A Wrach simulation.
+///
+/// The arrays returned by `positions()`, `velocities()` and `indices()` aren't copies, they're views
+/// straight onto the simulation's memory. So they're only valid until the next call to any of
+/// `Wrach`'s methods. Use `.slice()` on them to keep a copy.
+#[wasm_bindgen]
+#[non_exhaustive]
+pub struct Wrach {
+    /// Where the simulation is actually run
+    engine: Engine,
+}
+
+#[wasm_bindgen]
+impl Wrach {
+    /// Create a simulation on WebGPU if the browser supports it, otherwise on the CPU.
+    ///
+    /// # Errors
+    /// If the config is invalid, or there's no browser window to wait on whilst WebGPU is setup.
+    #[expect(
+        clippy::future_not_send,
+        reason = "JavaScript values can't leave the browser's thread anyway"
+    )]
+    #[inline]
+    pub async fn create(config: Option<Config>) -> Result<Self, JsValue> {
+        let window = web_sys::window().ok_or("Wrach needs a browser window")?;
+        let mut api = match Self::start(config, has_webgpu(&window).await)? {
+            Started::Ready(wrach) => return Ok(wrach),
+            Started::SettingUp(api) => api,
+        };
+        while !api.is_ready() {
+            yield_to_browser(&window).await?;
+        }
+        api.finish_setup();
+        Ok(Self {
+            engine: Engine::Gpu(api),
+        })
+    }
+
+    /// Create a simulation that runs on the CPU, even if the browser supports WebGPU.
+    ///
+    /// # Errors
+    /// If the config is invalid.
+    #[wasm_bindgen(js_name = onCpu)]
+    #[inline]
+    pub fn on_cpu(config: Option<Config>) -> Result<Self, JsError> {
+        let rust_config = WrachConfig::try_from(config.unwrap_or_default())?;
+        let simulation =
+            CpuSimulation::new(rust_config).map_err(|error| JsError::new(&error.to_string()))?;
+        Ok(Self {
+            engine: Engine::Cpu(Box::new(simulation)),
+        })
+    }
+
+    /// Whether the simulation is running on the GPU
+    #[wasm_bindgen(getter, js_name = usingGpu)]
+    #[expect(
+        clippy::missing_const_for_fn,
+        reason = "`wasm_bindgen` doesn't support `const` functions"
+    )]
+    #[inline]
+    #[must_use]
+    pub fn using_gpu(&self) -> bool {
+        matches!(self.engine, Engine::Gpu(_))
+    }
+
+    /// Run the simulation for a number of ticks/frames, 1 by default.
+    ///
+    /// # Errors
+    /// If the CPU simulation fails to stage new particles.
+    #[inline]
+    pub fn tick(&mut self, frames: Option<u32>) -> Result<(), JsError> {
+        for _ in 0..frames.unwrap_or(1) {
+            match self.engine {
+                Engine::Gpu(ref mut api) => api.tick(),
+                Engine::Cpu(ref mut simulation) => simulation
+                    .tick()
+                    .map_err(|error| JsError::new(&error.to_string()))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Add particles from a flat array of 4 floats per particle: the x and y of its position, then
+    /// the x and y of its velocity.
+    ///
+    /// # Errors
+    /// If the length of the array isn't a multiple of 4.
+    #[wasm_bindgen(js_name = addParticles)]
+    #[inline]
+    pub fn add_particles(&mut self, floats: &[f32]) -> Result<(), JsError> {
+        let particles = floats_to_particles(floats)?;
+        match self.engine {
+            Engine::Gpu(ref mut api) => api.add_particles(particles),
+            Engine::Cpu(ref mut simulation) => simulation.add_particles(particles),
+        }
+        Ok(())
+    }
+
+    /// Move the view onto the simulation, `x` and `y` are its new bottom-left corner.
+    #[wasm_bindgen(js_name = setViewport)]
+    #[inline]
+    pub fn set_viewport(&mut self, x: f32, y: f32) {
+        let anchor = Vec2::new(x, y);
+        match self.engine {
+            Engine::Gpu(ref mut api) => api.set_viewport(anchor),
+            Engine::Cpu(ref mut simulation) => simulation.set_viewport_anchor(anchor),
+        }
+    }
+
+    /// The number of particles in the most recent frame
+    #[wasm_bindgen(getter, js_name = particleCount)]
+    #[inline]
+    #[must_use]
+    pub fn particle_count(&self) -> usize {
+        self.live_particles(&self.state().packed_data.positions)
+            .len()
+    }
+
+    /// The particle positions from the most recent frame, as a flat array of x and y floats.
+    /// Particles are packed by spatial bin cell, see `indices()`.
+    #[inline]
+    #[must_use]
+    pub fn positions(&self) -> Float32Array {
+        let positions = self.live_particles(&self.state().packed_data.positions);
+        // SAFETY: The view is only valid until Rust next touches the simulation's memory, which is
+        // documented for JavaScript on `Wrach`.
+        unsafe { Float32Array::view(bytemuck::cast_slice(positions)) }
+    }
+
+    /// The particle velocities from the most recent frame, in the same layout as `positions()`.
+    #[inline]
+    #[must_use]
+    pub fn velocities(&self) -> Float32Array {
+        let velocities = self.live_particles(&self.state().packed_data.velocities);
+        // SAFETY: See `positions()`.
+        unsafe { Float32Array::view(bytemuck::cast_slice(velocities)) }
+    }
+
+    /// For every spatial bin cell, the index of its first particle in `positions()`. The extra
+    /// item at the end is the total number of particles.
+    #[inline]
+    #[must_use]
+    pub fn indices(&self) -> Uint32Array {
+        // SAFETY: See `positions()`.
+        unsafe { Uint32Array::view(&self.state().packed_data.indices) }
+    }
+}
+
+impl Wrach {
+    /// Start a simulation on WebGPU if the browser has it, otherwise on the CPU.
+    fn start(config: Option<Config>, has_webgpu: bool) -> Result<Started, JsError> {
+        if !has_webgpu {
+            return Ok(Started::Ready(Self::on_cpu(config)?));
+        }
+        let rust_config = WrachConfig::try_from(config.unwrap_or_default())?;
+        let api =
+            WrachAPI::new_pending(rust_config).map_err(|error| JsError::new(&error.to_string()))?;
+        Ok(Started::SettingUp(Box::new(api)))
+    }
+
+    /// The state of the simulation, wherever it's running
+    #[expect(
+        clippy::ref_patterns,
+        reason = "I don't understand the `ref` keyword. `&` gives a 'mismatched types' error."
+    )]
+    fn state(&self) -> &WrachState {
+        match self.engine {
+            Engine::Gpu(ref api) => api.get_simulation_state(),
+            Engine::Cpu(ref simulation) => &simulation.state,
+        }
+    }
+
+    /// The GPU buffers are bigger than the number of particles, so only return the particles
+    /// that are actually in the simulation.
+    fn live_particles<'data>(&self, data: &'data [Vec2]) -> &'data [Vec2] {
+        let total = self
+            .state()
+            .packed_data
+            .indices
+            .last()
+            .and_then(|count| usize::try_from(*count).ok())
+            .unwrap_or(data.len());
+        data.get(..total).unwrap_or(data)
+    }
+}
+
+/// Turn a flat array of floats into particles, see `Wrach::add_particles()`.
+fn floats_to_particles(floats: &[f32]) -> Result<Vec<Particle>, JsError> {
+    if floats.len().checked_rem(FLOATS_PER_PARTICLE) != Some(0) {
+        return Err(JsError::new(
+            "Particles must be 4 floats each: x, y, velocity x and velocity y",
+        ));
+    }
+
+    Ok(floats
+        .chunks_exact(FLOATS_PER_PARTICLE)
+        .filter_map(|chunk| match *chunk {
+            [x, y, velocity_x, velocity_y] => Some(Particle {
+                position: Vec2::new(x, y),
+                velocity: Vec2::new(velocity_x, velocity_y),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Whether the browser can actually give us a WebGPU adapter. `navigator.gpu` existing isn't
+/// enough, some browsers have it but no usable adapter.
+#[expect(
+    clippy::future_not_send,
+    reason = "JavaScript values can't leave the browser's thread anyway"
+)]
+async fn has_webgpu(window: &web_sys::Window) -> bool {
+    let Ok(gpu) = Reflect::get(&window.navigator(), &"gpu".into()) else {
+        return false;
+    };
+    if gpu.is_undefined() || gpu.is_null() {
+        return false;
+    }
+    let Ok(request_adapter) =
+        Reflect::get(&gpu, &"requestAdapter".into()).and_then(JsCast::dyn_into::<Function>)
+    else {
+        return false;
+    };
+    let Ok(adapter_promise) = request_adapter
+        .call0(&gpu)
+        .and_then(JsCast::dyn_into::<Promise>)
+    else {
+        return false;
+    };
+    JsFuture::from(adapter_promise)
+        .await
+        .is_ok_and(|adapter| !adapter.is_undefined() && !adapter.is_null())
+}
+
+/// Let the browser run its event loop, which is what actually sets up WebGPU.
+#[expect(
+    clippy::future_not_send,
+    reason = "JavaScript values can't leave the browser's thread anyway"
+)]
+async fn yield_to_browser(window: &web_sys::Window) -> Result<(), JsValue> {
+    let mut timeout_result = Ok(0_i32);
+    let promise = Promise::new(&mut |resolve, _reject| {
+        timeout_result = window.set_timeout_with_callback(&resolve);
+    });
+    timeout_result?;
+    JsFuture::from(promise).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[expect(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    reason = "Tests aren't so strict"
+)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runs_on_the_cpu() {
+        let mut wrach = Wrach::on_cpu(Some(Config {
+            width: 10,
+            height: 10,
+            cell_size: 3,
+            ..Config::new()
+        }))
+        .unwrap();
+        wrach
+            .add_particles(&[5.0, 5.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0])
+            .unwrap();
+        wrach.tick(Some(3)).unwrap();
+
+        assert!(!wrach.using_gpu(), "Should be on the CPU");
+        assert_eq!(wrach.particle_count(), 2);
+        let positions = wrach.live_particles(&wrach.state().packed_data.positions);
+        assert_eq!(positions[0], Vec2::new(1.0, 1.0));
+    }
+
+    #[test]
+    fn falls_back_to_the_cpu_without_webgpu() {
+        assert!(
+            matches!(
+                Wrach::start(None, false),
+                Ok(Started::Ready(Wrach {
+                    engine: Engine::Cpu(_)
+                }))
+            ),
+            "Should be ready straight away, on the CPU"
+        );
+    }
+
+    #[test]
+    fn particles_are_positions_then_velocities() {
+        let particles = floats_to_particles(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(particles.len(), 1);
+        assert_eq!(particles[0].position, Vec2::new(1.0, 2.0));
+        assert_eq!(particles[0].velocity, Vec2::new(3.0, 4.0));
+    }
+}
+
+/// Tests that need JavaScript, because they make errors for it. Run with `wasm-pack test --node`.
+#[cfg(all(test, target_arch = "wasm32"))]
+#[expect(clippy::unwrap_used, reason = "Tests aren't so strict")]
+mod wasm_test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn particles_must_be_four_floats() {
+        let mut wrach = Wrach::on_cpu(None).unwrap();
+        assert!(
+            wrach.add_particles(&[1.0, 2.0, 3.0]).is_err(),
+            "3 floats aren't a whole particle"
+        );
+        assert!(
+            wrach.add_particles(&[1.0, 2.0, 3.0, 4.0, 5.0]).is_err(),
+            "5 floats are one too many"
+        );
+        assert!(wrach.add_particles(&[1.0, 2.0, 3.0, 4.0]).is_ok());
+    }
+}
@@ -1,13 +1,22 @@
 //! A cell is the unit of work in our GPU compute workload. A single work item loads all the
 //! particles in a spatial bin cell (and its surroundings) and does physics on this particles.
 
-use spirv_std::{arch::IndexUnchecked as _, glam::Vec2};
-
-use wrach_cpu_gpu_shared::{self as shared, WorldSettings};
-
-use crate::{particle::Particle, particles::Particles, PREFIX_SUM_HACK};
-
-/// The amount of extra space for over-packed cells. If the `MIN_DISTANCE` is right then this
+#[cfg(target_arch = "spirv")]
+use spirv_std::arch::IndexUnchecked as _;
+use spirv_std::glam::{UVec2, Vec4};
+
+use wrach_cpu_gpu_shared::{
+    self as shared, MaterialProperties, PackedParticle, Reaction, WorldSettings, MAX_SINKS,
+};
+
+#[cfg(not(target_arch = "spirv"))]
+use crate::index::IndexUnchecked as _;
+use crate::{
+    particle::{count_particle_in_cell, is_in_view, Particle},
+    particles::{properties_of, Particles},
+};
+
+/// The amount of extra space for over-packed cells. If the `min_distance()` is right then this
 /// should not generally be needed. I think it's most useful for the very beginning of a simulation
 /// when the particles have been randomly placed and there's a chance that some cells are
 /// over-packed because their particles haven't been pushed apart yet.
@@ -35,23 +44,30 @@ pub struct World<'world> {
     pub current_cell: usize,
     /// Config, like viewport position etc.
     pub settings: &'world WorldSettings,
-    /// An array of spatial bin cells and how many particles each contains.
-    pub indices: &'world mut [u32],
-    /// Particle positions for reading.
-    pub positions_input: &'world [Vec2],
-    /// Particle positions for writing.
-    pub positions_output: &'world mut [Vec2],
-    /// Velocity positions for reading.
-    pub velocities_input: &'world [Vec2],
-    /// Velocity positions for writing.
-    pub velocities_output: &'world mut [Vec2],
+    /// An array of spatial bin cells and the index of each one's first particle.
+    pub indices: &'world [u32],
+    /// Particles for reading.
+    pub particles_input: &'world [PackedParticle],
+    /// Particles for writing.
+    pub particles_output: &'world mut [PackedParticle],
+    /// Heat sources from the CPU, see `Particle::exchange_heat()`.
+    pub heat_sources: &'world [Vec4],
+    /// The reactions table from the CPU, see `Particle::transition()`.
+    pub reactions: &'world [Reaction],
+    /// The properties of every material from the CPU, indexed by material.
+    pub material_properties: &'world [MaterialProperties],
+    /// The sinks from the CPU, see `Particle::drain()`.
+    pub sinks: &'world [Vec4; MAX_SINKS as usize],
+    /// Atomically counted particles per spatial bin cell, for the next frame's prefix sum.
+    pub cell_counts: &'world mut [u32],
+    /// Newly added particles that are being merged into the simulation this frame.
+    pub particles_new: &'world [PackedParticle],
 }
 
 impl World<'_> {
     /// Iterate over all the particles in a cell and do physics on them.
     pub fn physics_for_cell(&mut self) {
-        let total_cells =
-            self.settings.grid_dimensions.x * self.settings.grid_dimensions.y + PREFIX_SUM_HACK;
+        let total_cells = self.settings.grid_dimensions.x * self.settings.grid_dimensions.y;
         let last_cell = total_cells as usize - 1;
         if self.current_cell > last_cell {
             return;
@@ -63,15 +79,54 @@ impl World<'_> {
         let mut particles = Particles::new(
             particles_start_at,
             all_particles_count,
-            self.positions_input,
-            self.velocities_input,
+            self.particles_input,
+        );
+        particles.integrate(self.settings);
+        particles.pairs(self.settings, self.reactions, self.material_properties);
+        particles.exchange_heat(self.settings, self.heat_sources);
+        particles.transition(self.settings, self.reactions);
+        particles.finish(
+            self.settings,
+            self.material_properties,
+            self.sinks,
+            self.particles_output,
+            self.cell_counts,
         );
-        particles.pairs();
-        particles.finish(self.settings, self.positions_output, self.velocities_output);
 
         self.handle_overflown_particles(particles_start_at, particles.count, all_particles_count);
+    }
+
+    /// Newly added particles don't belong to a cell yet, so they're shared out between all the
+    /// invocations just to be counted. They're integrated, and aged, from the next frame onwards.
+    /// Those outside the view, because it moved after they were staged, are culled instead.
+    pub fn count_new_particles(&mut self, total_invocations: usize) {
+        let mut new_index = self.current_cell;
+        while new_index < self.settings.new_particles_count as usize {
+            // SAFETY: See same comment for `Particle::new()`
+            let position = unsafe { self.particles_new.index_unchecked(new_index).position };
+            if is_in_view(position, self.settings) {
+                count_particle_in_cell(position, self.settings, self.cell_counts);
+            }
+            new_index += total_invocations;
+        }
+    }
 
-        self.clear_cells_for_next_frame(last_cell);
+    /// The CPU only knows an upper bound for the number of particles, because the GPU culls some
+    /// by itself, so that's how many the packing pass reads back from the particles that were
+    /// written. Those between the ones that were actually integrated and the upper bound are left
+    /// over from earlier frames, so they're shared out between all the invocations to be expired.
+    pub fn expire_leftover_particles(&mut self, total_invocations: usize) {
+        let total_cells =
+            (self.settings.grid_dimensions.x * self.settings.grid_dimensions.y) as usize;
+        // SAFETY: See same comment for `get_start_end_indices_for_particles_in_cell()`
+        let integrated_count = unsafe { *self.indices.index_unchecked(total_cells) } as usize;
+        let mut leftover_index = integrated_count + self.current_cell;
+        while leftover_index < self.settings.particles_in_frame_count as usize {
+            // SAFETY: See same comment for `Particle::new()`
+            let leftover = unsafe { self.particles_output.index_unchecked_mut(leftover_index) };
+            leftover.age = UVec2::ONE;
+            leftover_index += total_invocations;
+        }
     }
 
     /// Handle particles that overflew the [`MAX_PARTICLES_IN_CELL`] limit. They should at least be
@@ -86,11 +141,17 @@ impl World<'_> {
         let all_particles_end_at = particles_start_at + all_particles_count;
 
         for particle_index in particles_end_at..all_particles_end_at {
-            let mut particle =
-                Particle::new(particle_index, self.positions_input, self.velocities_input);
-            particle.integrate();
-            particle.enforce_limits(self.settings);
-            particle.write(self.positions_output, self.velocities_output);
+            let mut particle = Particle::new(particle_index, self.particles_input);
+            particle.integrate(self.settings);
+            particle.exchange_heat(self.settings, self.heat_sources);
+            particle.transition(self.settings, self.reactions);
+            particle.enforce_limits(
+                self.settings,
+                properties_of(self.material_properties, particle.material),
+            );
+            particle.drain(self.settings, self.sinks);
+            particle.cull_outside_view(self.settings);
+            particle.write(self.settings, self.particles_output, self.cell_counts);
         }
     }
 
@@ -110,23 +171,4 @@ impl World<'_> {
 
         (*particles_start_at as usize, particles_count as usize)
     }
-
-    /// The spatial bin cells recieve atomically added particle counts, so they need to all be set
-    /// to zero at the beginning of each pass.
-    fn clear_cells_for_next_frame(&mut self, last_cell: usize) {
-        self.clear_indices_cell_for_next_frame(self.current_cell);
-        // This is normal, it's not because of our prefix off-by-one issue. It's needed because
-        // of the "guard item" at the end of the prefix sum.
-        if self.current_cell == last_cell {
-            self.clear_indices_cell_for_next_frame(self.current_cell + 1);
-        }
-    }
-
-    /// Clear the current value for this cell's index pointer in the indices buffer. Considering we are
-    /// already in the cell we save having a whole other shader and GPU pass.
-    fn clear_indices_cell_for_next_frame(&mut self, cell_index: usize) {
-        // SAFETY: We rely on the rest of the pipeline for correct index values.
-        let cell_reference = unsafe { self.indices.index_unchecked_mut(cell_index) };
-        *cell_reference = 0;
-    }
 }
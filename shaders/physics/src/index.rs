@@ -0,0 +1,72 @@
+//! A CPU stand-in for `spirv_std::arch::IndexUnchecked`.
+//!
+//! Bounds checks are too expensive on the GPU, so the shaders index their buffers without them.
+//! But the same code also runs on the CPU, see `physics_on_cpu()`, where an out of bounds index
+//! would be undefined behaviour. So on the CPU every index is checked, and panics if it's out of
+//! bounds.
+
+/// Index into a slice or array, with the same methods as `spirv_std::arch::IndexUnchecked`.
+pub trait IndexUnchecked<T> {
+    /// Get a reference to an item.
+    ///
+    /// # Safety
+    /// Nothing, it's only `unsafe` to match `spirv_std::arch::IndexUnchecked`
+    ///
+    /// # Panics
+    /// If `index` is out of bounds
+    unsafe fn index_unchecked(&self, index: usize) -> &T;
+
+    /// Get a mutable reference to an item.
+    ///
+    /// # Safety
+    /// Nothing, it's only `unsafe` to match `spirv_std::arch::IndexUnchecked`
+    ///
+    /// # Panics
+    /// If `index` is out of bounds
+    unsafe fn index_unchecked_mut(&mut self, index: usize) -> &mut T;
+}
+
+#[expect(
+    clippy::indexing_slicing,
+    reason = "Panicking is the point, it's much better than undefined behaviour"
+)]
+impl<T> IndexUnchecked<T> for [T] {
+    #[inline]
+    unsafe fn index_unchecked(&self, index: usize) -> &T {
+        &self[index]
+    }
+
+    #[inline]
+    unsafe fn index_unchecked_mut(&mut self, index: usize) -> &mut T {
+        &mut self[index]
+    }
+}
+
+#[expect(
+    clippy::indexing_slicing,
+    reason = "Panicking is the point, it's much better than undefined behaviour"
+)]
+impl<T, const N: usize> IndexUnchecked<T> for [T; N] {
+    #[inline]
+    unsafe fn index_unchecked(&self, index: usize) -> &T {
+        &self[index]
+    }
+
+    #[inline]
+    unsafe fn index_unchecked_mut(&mut self, index: usize) -> &mut T {
+        &mut self[index]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IndexUnchecked as _;
+
+    #[test]
+    #[should_panic(expected = "index out of bounds")]
+    fn out_of_bounds_indices_panic_on_the_cpu() {
+        let items = [1_u32, 2, 3];
+        // SAFETY: It's checked on the CPU.
+        let _item = unsafe { items.as_slice().index_unchecked(3) };
+    }
+}
@@ -16,49 +16,144 @@
 
 use cell::World;
 use spirv_std::{
-    glam::{UVec3, Vec2},
+    glam::{UVec3, Vec2, Vec4},
     spirv,
 };
-use wrach_cpu_gpu_shared::WorldSettings;
+use wrach_cpu_gpu_shared::{
+    MaterialProperties, PackedParticle, Reaction, WorldSettings, MAX_SINKS,
+};
 
 mod cell;
+#[cfg(not(target_arch = "spirv"))]
+mod index;
 mod particle;
 mod particles;
+mod random;
 
-/// NB: We add one to cell indexes because our current prefix sum implementation shifts all its items
-/// one to the right.
-pub const PREFIX_SUM_HACK: u32 = 1;
+/// The maximum number of particles in a cell that get the full physics, any more than this are
+/// only integrated. See `cell::MAX_PARTICLES_IN_CELL`.
+pub const MAX_PARTICLES_IN_CELL: usize = cell::MAX_PARTICLES_IN_CELL;
 
-/// Physics entrypoint
-#[allow(
-    clippy::allow_attributes,
-    reason = "For some reason `expect` doesn't detect the veracity of the 'inline' lint"
-)]
-#[allow(
-    clippy::missing_inline_in_public_items,
-    reason = "SPIR-V requires an entrypoint"
+/// Define a physics entrypoint for a particular workgroup size. The workgroup size has to be known
+/// at compile time, so we compile one entrypoint for each of `shared::WORKGROUP_SIZES` and let the
+/// CPU pick one at runtime.
+macro_rules! physics_entrypoint {
+    ($name:ident, $threads:literal) => {
+        /// Physics entrypoint
+        #[allow(
+            clippy::allow_attributes,
+            reason = "For some reason `expect` doesn't detect the veracity of the 'inline' lint"
+        )]
+        #[allow(
+            clippy::missing_inline_in_public_items,
+            reason = "SPIR-V requires an entrypoint"
+        )]
+        #[expect(
+            clippy::too_many_arguments,
+            reason = "SPIR-V entrypoints get all their buffers as arguments"
+        )]
+        #[spirv(compute(threads($threads)))]
+        pub fn $name(
+            #[spirv(global_invocation_id)] id: UVec3,
+            #[spirv(num_workgroups)] num_workgroups: UVec3,
+            #[spirv(uniform, descriptor_set = 0, binding = 0)] settings: &WorldSettings,
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] indices: &[u32],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+            particles_input: &[PackedParticle],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+            particles_output: &mut [PackedParticle],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] cell_counts: &mut [u32],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 5)]
+            particles_new: &[PackedParticle],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] heat_sources: &[Vec4],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] reactions: &[Reaction],
+            #[spirv(storage_buffer, descriptor_set = 0, binding = 8)]
+            material_properties: &[MaterialProperties],
+            #[spirv(uniform, descriptor_set = 0, binding = 9)] sinks: &[Vec4; MAX_SINKS as usize],
+        ) {
+            let world = World {
+                current_cell: id.x as usize,
+                settings,
+                indices,
+                particles_input,
+                particles_output,
+                heat_sources,
+                reactions,
+                material_properties,
+                sinks,
+                cell_counts,
+                particles_new,
+            };
+            physics(world, num_workgroups.x * $threads);
+        }
+    };
+}
+
+physics_entrypoint!(main_32, 32);
+physics_entrypoint!(main_64, 64);
+physics_entrypoint!(main_128, 128);
+physics_entrypoint!(main_256, 256);
+
+/// Run the physics for every cell on the CPU, one after the other, for when there's no GPU with
+/// compute shaders. The buffers are the same as the ones the GPU entrypoints get.
+#[cfg(not(target_arch = "spirv"))]
+#[expect(
+    clippy::too_many_arguments,
+    reason = "It mirrors the SPIR-V entrypoints, which get all their buffers as arguments"
 )]
-#[spirv(compute(threads(32)))]
-pub fn main(
-    #[spirv(global_invocation_id)] id: UVec3,
-    #[spirv(uniform, descriptor_set = 0, binding = 0)] settings: &WorldSettings,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] indices: &mut [u32],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] positions_input: &[Vec2],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] positions_output: &mut [Vec2],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] velocities_input: &[Vec2],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] velocities_output: &mut [Vec2],
+#[inline]
+pub fn physics_on_cpu(
+    settings: &WorldSettings,
+    indices: &[u32],
+    particles_input: &[PackedParticle],
+    particles_output: &mut [PackedParticle],
+    cell_counts: &mut [u32],
+    particles_new: &[PackedParticle],
+    heat_sources: &[Vec4],
+    reactions: &[Reaction],
+    material_properties: &[MaterialProperties],
+    sinks: &[Vec4; MAX_SINKS as usize],
 ) {
-    let current_cell = (id.x + PREFIX_SUM_HACK) as usize;
+    // There's always at least one "invocation", otherwise new particles would never be counted.
+    let total_cells = (settings.grid_dimensions.x * settings.grid_dimensions.y).max(1);
+    for current_cell in 0..total_cells {
+        let world = World {
+            current_cell: current_cell as usize,
+            settings,
+            indices,
+            particles_input,
+            particles_output: &mut *particles_output,
+            heat_sources,
+            reactions,
+            material_properties,
+            sinks,
+            cell_counts: &mut *cell_counts,
+            particles_new,
+        };
+        physics(world, total_cells);
+    }
+}
 
-    let mut world = World {
-        current_cell,
-        settings,
-        indices,
-        positions_input,
-        positions_output,
-        velocities_input,
-        velocities_output,
-    };
+/// The index of the spatial bin cell that contains `position`, for packing particles on the CPU.
+#[cfg(not(target_arch = "spirv"))]
+#[inline]
+#[must_use]
+pub fn cell_index(position: Vec2, settings: &WorldSettings) -> usize {
+    particle::cell_index(position, settings)
+}
+
+/// Whether `position` is inside the view, for leaving out the same particles as the GPU when
+/// packing on the CPU.
+#[cfg(not(target_arch = "spirv"))]
+#[inline]
+#[must_use]
+pub fn is_in_view(position: Vec2, settings: &WorldSettings) -> bool {
+    particle::is_in_view(position, settings)
+}
 
+/// Do the physics for the cell that corresponds to the current invocation.
+fn physics(mut world: World, total_invocations: u32) {
     world.physics_for_cell();
+    world.count_new_particles(total_invocations as usize);
+    world.expire_leftover_particles(total_invocations as usize);
 }
@@ -1,10 +1,16 @@
 //! A single particle and everything that can be done to it
 
-use spirv_std::{
-    arch::IndexUnchecked as _,
-    glam::{vec4, Vec2},
+#[cfg(target_arch = "spirv")]
+use spirv_std::arch::IndexUnchecked as _;
+use spirv_std::glam::{vec4, UVec2, Vec2, Vec4, Vec4Swizzles as _};
+use wrach_cpu_gpu_shared::{
+    MaterialProperties, PackedParticle, Reaction, WorldSettings, MAX_SINKS,
+    REACTION_ABOVE_TEMPERATURE, REACTION_BELOW_TEMPERATURE,
 };
-use wrach_cpu_gpu_shared::WorldSettings;
+
+#[cfg(not(target_arch = "spirv"))]
+use crate::index::IndexUnchecked as _;
+use crate::random::random;
 
 /// Convenient representation of a particle
 #[derive(Default, Copy, Clone)]
@@ -15,35 +21,52 @@ pub struct Particle {
     pub position: Vec2,
     /// Particle velocity
     pub velocity: Vec2,
+    /// Where the particle was at the start of the frame. The velocity is worked out from how far
+    /// the particle has moved since, see `update_velocity()`.
+    pub previous_position: Vec2,
+    /// The number of frames the particle has been simulated for, and how many it lives for. A
+    /// lifetime of 0 means that it lives forever.
+    pub age: UVec2,
+    /// Particle temperature
+    pub temperature: f32,
+    /// The material that the particle is made of, it only means something to the reactions
+    pub material: u32,
 }
 
-impl<'particle> Particle {
+impl Particle {
     /// Instantiate
-    pub fn new(
-        index: usize,
-        positions_input: &'particle [Vec2],
-        velocities_input: &'particle [Vec2],
-    ) -> Self {
+    pub fn new(index: usize, particles_input: &[PackedParticle]) -> Self {
         // SAFETY:
-        //   Getting data with bounds checks is obviously undefined behaviour. We rely on the
-        //   rest of the pipeline to ensure that indices are always within limits.
-        unsafe {
-            Self {
-                index,
-                position: *positions_input.index_unchecked(index),
-                velocity: *velocities_input.index_unchecked(index),
-            }
+        //   Getting data without bounds checks is obviously undefined behaviour. We rely on the
+        //   rest of the pipeline to ensure that indices are always within limits. On the CPU
+        //   they're checked anyway, see `index`.
+        let packed = unsafe { *particles_input.index_unchecked(index) };
+        Self {
+            index,
+            position: packed.position,
+            velocity: packed.velocity,
+            previous_position: packed.position,
+            age: packed.age,
+            temperature: packed.temperature,
+            material: packed.material,
         }
     }
 
-    /// Enforce particle limits like bouundaries and speed
-    pub fn enforce_limits(&mut self, world_config: &WorldSettings) {
-        self.enforce_boundaries(world_config);
-        self.enforce_velocity();
+    /// Enforce particle limits like bouundaries and speed. The boundaries are the last of the
+    /// constraints on the particle's position, so this is also where its velocity is worked out.
+    pub fn enforce_limits(&mut self, world_config: &WorldSettings, properties: MaterialProperties) {
+        self.enforce_boundaries(world_config, properties);
+        self.update_velocity();
+        self.enforce_velocity(world_config);
     }
 
-    /// Enforce particle boundaries
-    pub fn enforce_boundaries(&mut self, world_config: &WorldSettings) {
+    /// Enforce particle boundaries. Like collisions between particles, particles are moved back
+    /// inside and rub along the boundaries with friction, rather than bouncing off them.
+    pub fn enforce_boundaries(
+        &mut self,
+        world_config: &WorldSettings,
+        properties: MaterialProperties,
+    ) {
         let viewport = vec4(
             world_config.view_anchor.x,
             world_config.view_anchor.y,
@@ -51,57 +74,215 @@ impl<'particle> Particle {
             world_config.view_anchor.y + world_config.view_dimensions.y,
         );
 
-        if self.position.x > viewport.z {
-            self.position.x = viewport.z;
-            self.velocity.x *= -1.0;
+        let inside = self.position.clamp(viewport.xy(), viewport.zw());
+        let correction = (inside - self.position).abs();
+        self.position = inside;
+
+        let movement = self.position - self.previous_position;
+        if correction.x > 0.0 {
+            self.position.y -= friction(Vec2::new(0.0, movement.y), correction.x, properties).y;
         }
-        if self.position.x < viewport.x {
-            self.position.x = viewport.x;
-            self.velocity.x *= -1.0;
+        if correction.y > 0.0 {
+            self.position.x -= friction(Vec2::new(movement.x, 0.0), correction.y, properties).x;
         }
-        if self.position.y > viewport.w {
-            self.position.y = viewport.w;
-            self.velocity.y *= -1.0;
+    }
+
+    /// Position based dynamics: the velocity is however far the particle actually moved this
+    /// frame, once collisions and boundaries have corrected where it was heading. That's what stops
+    /// particles that are resting on each other from building up speed.
+    pub fn update_velocity(&mut self) {
+        self.velocity = self.position - self.previous_position;
+    }
+
+    /// Enforce maximum particle speed. It's the length of the velocity that's limited, so particles
+    /// moving diagonally aren't any faster than those moving along an axis.
+    pub fn enforce_velocity(&mut self, world_config: &WorldSettings) {
+        self.velocity = self.velocity.clamp_length_max(world_config.max_speed);
+    }
+
+    /// Integration, therefore accelerate the particle by gravity, move it by its velocity and age it
+    /// by a frame. The new position is only a prediction that collisions and boundaries go on to
+    /// correct, see `update_velocity()`. Particles that live forever don't age, so their age can't
+    /// overflow.
+    pub fn integrate(&mut self, settings: &WorldSettings) {
+        self.previous_position = self.position;
+        self.velocity.y -= settings.gravity;
+        self.position += self.velocity;
+        if self.age.y != 0 {
+            self.age.x += 1;
         }
-        if self.position.y < viewport.y {
-            self.position.y = viewport.y;
-            self.velocity.y *= -1.0;
+    }
+
+    /// Exchange heat with everything that isn't another particle. The particle cools, or warms,
+    /// towards the ambient temperature and is heated by every heat source that it's inside of. A
+    /// heat source is `(x, y, radius, power)`, where power is the change in temperature per frame.
+    pub fn exchange_heat(&mut self, settings: &WorldSettings, heat_sources: &[Vec4]) {
+        self.temperature +=
+            (settings.ambient_temperature - self.temperature) * settings.cooling_rate;
+
+        for source_index in 0..settings.heat_sources_count as usize {
+            // SAFETY: See same comment for `new()`
+            let source = unsafe { *heat_sources.index_unchecked(source_index) };
+            if self.position.distance(source.xy()) <= source.z {
+                self.temperature += source.w;
+            }
         }
     }
 
-    /// Enforce maximum particle velocity
-    pub fn enforce_velocity(&mut self) {
-        let max = 1.0;
-        self.velocity.x = self.velocity.x.clamp(-max, max);
-        self.velocity.y = self.velocity.y.clamp(-max, max);
+    /// Change material if the particle is hot, or cold, enough for one of the reactions. Only the
+    /// first reaction that happens counts, so a particle changes at most once a frame.
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "Particle and reaction indices started out as `u32`s"
+    )]
+    pub fn transition(&mut self, settings: &WorldSettings, reactions: &[Reaction]) {
+        for reaction_index in 0..settings.reactions_count as usize {
+            // SAFETY: See same comment for `new()`
+            let reaction = unsafe { *reactions.index_unchecked(reaction_index) };
+            if reaction.reactant != self.material {
+                continue;
+            }
+
+            let is_past_temperature = (reaction.kind == REACTION_ABOVE_TEMPERATURE
+                && self.temperature > reaction.temperature)
+                || (reaction.kind == REACTION_BELOW_TEMPERATURE
+                    && self.temperature < reaction.temperature);
+            if !is_past_temperature {
+                continue;
+            }
+
+            let index = self.index as u32;
+            let chance = random(settings.random_seed, index, index, reaction_index as u32);
+            if chance < reaction.probability {
+                self.material = reaction.product;
+                return;
+            }
+        }
     }
 
-    /// Integration, therefore move the particle by its velocity
-    pub fn integrate(&mut self) {
-        self.position += self.velocity;
+    /// End the particle's lifetime if it's inside a sink, so that it isn't counted and the packing
+    /// pass leaves it out like any other expired particle. A sink is `(x, y, radius, 0)`.
+    pub fn drain(&mut self, settings: &WorldSettings, sinks: &[Vec4; MAX_SINKS as usize]) {
+        for sink_index in 0..settings.sinks_count as usize {
+            // SAFETY: The CPU never adds more than `MAX_SINKS` sinks.
+            let sink = unsafe { *sinks.index_unchecked(sink_index) };
+            if self.position.distance(sink.xy()) <= sink.z {
+                self.expire();
+                return;
+            }
+        }
     }
 
-    /// Write particle data back to buffer
-    pub fn write(&self, positions_output: &mut [Vec2], velocities_output: &mut [Vec2]) {
+    /// End the particle's lifetime if it started the frame outside the view. The boundaries keep
+    /// particles inside it, so that only happens when the view has moved away from the particle,
+    /// see `WrachState::set_viewport_anchor()`. It wouldn't fit into the grid.
+    pub fn cull_outside_view(&mut self, settings: &WorldSettings) {
+        if !is_in_view(self.previous_position, settings) {
+            self.expire();
+        }
+    }
+
+    /// Bring the particle to the end of its lifetime, even if it was going to live forever.
+    const fn expire(&mut self) {
+        if self.age.y == 0 {
+            self.age.y = 1;
+        }
+        self.age.x = self.age.y;
+    }
+
+    /// Whether the particle has reached the end of its lifetime. Expired particles are still
+    /// written out, but aren't counted, so the packing pass leaves them out of the next frame.
+    pub const fn is_expired(&self) -> bool {
+        self.age.y != 0 && self.age.x >= self.age.y
+    }
+
+    /// Write particle data back to buffer, and count the particle in the spatial bin cell that it
+    /// has moved to. The counts are the input for the next frame's prefix sum.
+    pub fn write(
+        &self,
+        settings: &WorldSettings,
+        particles_output: &mut [PackedParticle],
+        cell_counts: &mut [u32],
+    ) {
         // SAFETY: See same comment for `new()`
-        unsafe {
-            let position_reference = positions_output.index_unchecked_mut(self.index);
-            *position_reference = self.position;
-
-            let velocity_reference = velocities_output.index_unchecked_mut(self.index);
-            *velocity_reference = self.velocity;
-
-            // TODO:
-            //   Now that we're on Bevy 0.15 we have support for Naga's atomics, see:
-            //   https://github.com/gfx-rs/wgpu/issues/4489
-            //   This will allow us to remove a whole GPU pass.
-            //
-            // let count_reference = indices_counter.index_unchecked_mut(self.index);
-            // spirv_std::arch::atomic_i_increment::<
-            //     _,
-            //     { spirv_std::memory::Scope::Device as u32 },
-            //     { spirv_std::memory::Semantics::NONE.bits() },
-            // >(count_reference);
+        let particle_reference = unsafe { particles_output.index_unchecked_mut(self.index) };
+        *particle_reference = PackedParticle {
+            position: self.position,
+            velocity: self.velocity,
+            age: self.age,
+            temperature: self.temperature,
+            material: self.material,
+        };
+
+        if self.is_expired() {
+            return;
         }
+        count_particle_in_cell(self.position, settings, cell_counts);
+    }
+}
+
+/// Position based friction between two surfaces that are pressed together, [see "Unified Particle
+/// Physics for Real-Time Applications"](https://mmacklin.com/uppfrta_preprint.pdf). `sliding` is
+/// how far the surfaces have slid along each other this frame and `penetration` how far they were
+/// pushed apart. Returns how much of the sliding to undo: all of it if static friction holds,
+/// otherwise dynamic friction slows it down.
+pub fn friction(sliding: Vec2, penetration: f32, properties: MaterialProperties) -> Vec2 {
+    let distance = sliding.length();
+    if distance == 0.0 {
+        return Vec2::ZERO;
+    }
+    if distance < properties.static_friction * penetration {
+        return sliding;
+    }
+
+    sliding * (properties.dynamic_friction * penetration / distance).min(1.0)
+}
+
+/// Whether `position` is inside the view, including its edges, which is where the boundaries keep
+/// the particles. Only positions inside the view have a cell in the grid.
+pub fn is_in_view(position: Vec2, settings: &WorldSettings) -> bool {
+    let top_right = settings.view_anchor + settings.view_dimensions;
+    position.x >= settings.view_anchor.x
+        && position.y >= settings.view_anchor.y
+        && position.x <= top_right.x
+        && position.y <= top_right.y
+}
+
+/// The index of the spatial bin cell that contains `position`.
+pub fn cell_index(position: Vec2, settings: &WorldSettings) -> usize {
+    let relative_to_viewport = position - settings.view_anchor;
+    #[expect(
+        clippy::cast_sign_loss,
+        clippy::cast_possible_truncation,
+        reason = "Particles are always kept inside the viewport"
+    )]
+    let (cell_x, cell_y) = (
+        (relative_to_viewport.x / settings.cell_size).floor() as u32,
+        (relative_to_viewport.y / settings.cell_size).floor() as u32,
+    );
+    (cell_y * settings.grid_dimensions.x + cell_x) as usize
+}
+
+/// Atomically add one to the count of particles in the spatial bin cell that contains `position`.
+pub fn count_particle_in_cell(position: Vec2, settings: &WorldSettings, cell_counts: &mut [u32]) {
+    // SAFETY: See same comment for `Particle::new()`
+    let count_reference =
+        unsafe { cell_counts.index_unchecked_mut(cell_index(position, settings)) };
+
+    // SAFETY: It's just an atomic add to an existing `u32`.
+    #[cfg(target_arch = "spirv")]
+    unsafe {
+        spirv_std::arch::atomic_i_increment::<
+            _,
+            { spirv_std::memory::Scope::Device as u32 },
+            { spirv_std::memory::Semantics::NONE.bits() },
+        >(count_reference);
+    }
+
+    // The CPU runs one cell at a time, so there's nothing to be atomic about. `spirv-std`'s atomics
+    // only exist on the GPU anyway.
+    #[cfg(not(target_arch = "spirv"))]
+    {
+        *count_reference += 1;
     }
 }
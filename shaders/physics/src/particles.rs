@@ -1,9 +1,20 @@
 //! Handle particles interacting with each other
 
-use spirv_std::{arch::IndexUnchecked as _, glam::Vec2};
-use wrach_cpu_gpu_shared::WorldSettings;
+#[cfg(target_arch = "spirv")]
+use spirv_std::arch::IndexUnchecked as _;
+use spirv_std::glam::{Vec2, Vec4};
+use wrach_cpu_gpu_shared::{
+    MaterialProperties, PackedParticle, Reaction, WorldSettings, MAX_MATERIALS, MAX_SINKS,
+    REACTION_CONTACT,
+};
 
-use crate::{cell::MAX_PARTICLES_IN_CELL, particle::Particle};
+#[cfg(not(target_arch = "spirv"))]
+use crate::index::IndexUnchecked as _;
+use crate::{
+    cell::MAX_PARTICLES_IN_CELL,
+    particle::{friction, Particle},
+    random::random,
+};
 
 /// A local array of particles to check for interactions. Because multiple particles will be
 /// checked multiple times, hopefully we save some global memory read latency by only reading them
@@ -14,8 +25,47 @@ use crate::{cell::MAX_PARTICLES_IN_CELL, particle::Particle};
 //   then global memory is used.
 type LocalParticles = [Particle; MAX_PARTICLES_IN_CELL];
 
-/// The minimum distance allowed between particles
-pub const MIN_DISTANCE: f32 = 1.0;
+/// The minimum distance allowed between particles, the diameter of a particle
+pub fn min_distance(settings: &WorldSettings) -> f32 {
+    settings.particle_radius * 2.0
+}
+
+/// Particles closer than this are neighbours and conduct heat between each other. It's a bit more
+/// than `min_distance()`, so that particles that are resting against each other stay in contact.
+/// Only neighbours react with each other, and cohesive neighbours are pulled together.
+pub fn neighbour_distance(settings: &WorldSettings) -> f32 {
+    min_distance(settings) * 1.5
+}
+
+/// The properties of a material. Materials that don't fit in the material properties buffer have
+/// the default properties.
+pub fn properties_of(
+    material_properties: &[MaterialProperties],
+    material: u32,
+) -> MaterialProperties {
+    if material >= MAX_MATERIALS {
+        return MaterialProperties::default();
+    }
+
+    // SAFETY: The material properties buffer is always `MAX_MATERIALS` long.
+    unsafe { *material_properties.index_unchecked(material as usize) }
+}
+
+/// The properties of a pair of touching materials, the average of each of their properties.
+fn properties_of_pair(
+    material_properties: &[MaterialProperties],
+    left: u32,
+    right: u32,
+) -> MaterialProperties {
+    let left_properties = properties_of(material_properties, left);
+    let right_properties = properties_of(material_properties, right);
+    MaterialProperties {
+        cohesion: 0.5 * (left_properties.cohesion + right_properties.cohesion),
+        static_friction: 0.5 * (left_properties.static_friction + right_properties.static_friction),
+        dynamic_friction: 0.5
+            * (left_properties.dynamic_friction + right_properties.dynamic_friction),
+    }
+}
 
 /// All the particles in a cell.
 pub struct Particles {
@@ -31,8 +81,7 @@ impl Particles {
     pub fn new(
         particles_start_at: usize,
         all_particles_count: usize,
-        positions: &[Vec2],
-        velocities: &[Vec2],
+        particles_input: &[PackedParticle],
     ) -> Self {
         let mut particles_count = all_particles_count;
         if particles_count > MAX_PARTICLES_IN_CELL {
@@ -48,18 +97,25 @@ impl Particles {
 
         let mut local_index = 0;
         for global_index in particles_start_at..particles_end_at {
-            particles.set(
-                local_index,
-                Particle::new(global_index, positions, velocities),
-            );
+            particles.set(local_index, Particle::new(global_index, particles_input));
             local_index += 1;
         }
 
         particles
     }
 
-    /// Iterate through unique pairs of particles and do physics on them.
-    pub fn pairs(&mut self) {
+    /// Iterate through unique pairs of particles and do physics on them. Each push moves particles
+    /// that later pairs then see, so the result depends on the order of the pairs. They're always
+    /// visited in the order of the particles' indices, so as long as the particles are packed in
+    /// the same order, as they are with `WrachConfig::deterministic`, so are the results.
+    pub fn pairs(
+        &mut self,
+        settings: &WorldSettings,
+        reactions: &[Reaction],
+        material_properties: &[MaterialProperties],
+    ) {
+        let min_distance = min_distance(settings);
+        let neighbour_distance = neighbour_distance(settings);
         for i_left in 0..self.count {
             for i_right in (i_left + 1)..self.count {
                 let mut distance = self
@@ -67,7 +123,21 @@ impl Particles {
                     .position
                     .distance(self.particle(i_right).position);
 
-                if distance > MIN_DISTANCE {
+                if distance > neighbour_distance {
+                    continue;
+                }
+
+                self.conduct_heat(settings, i_left, i_right);
+                self.react_on_contact(settings, reactions, i_left, i_right);
+
+                if distance > min_distance {
+                    self.pull_cohesive_particles_together(
+                        material_properties,
+                        min_distance,
+                        distance,
+                        i_left,
+                        i_right,
+                    );
                     continue;
                 }
 
@@ -75,34 +145,183 @@ impl Particles {
                     distance = 0.0001;
                 }
 
-                self.push_close_particles_apart(distance, i_left, i_right);
+                self.push_close_particles_apart(
+                    material_properties,
+                    min_distance,
+                    distance,
+                    i_left,
+                    i_right,
+                );
             }
         }
     }
 
     /// If 2 particles are closer than their size allows then just forcefully move them apart to a
-    /// safe distance.
-    fn push_close_particles_apart(&mut self, distance: f32, i_left: usize, i_right: usize) {
-        let force = 0.5 * (MIN_DISTANCE - distance) / distance;
+    /// safe distance. The harder they're pushed apart the more friction stops them sliding along
+    /// each other, which is what lets granular materials, like sand, pile up.
+    fn push_close_particles_apart(
+        &mut self,
+        material_properties: &[MaterialProperties],
+        min_distance: f32,
+        distance: f32,
+        i_left: usize,
+        i_right: usize,
+    ) {
+        let penetration = min_distance - distance;
+        let force = 0.5 * penetration / distance;
         let mut distance_vec: Vec2 =
             self.particle(i_right).position - self.particle(i_left).position;
+        let normal = distance_vec / distance;
         distance_vec *= force;
 
         self.particle(i_left).position -= distance_vec;
         self.particle(i_right).position += distance_vec;
+
+        let left = *self.particle(i_left);
+        let right = *self.particle(i_right);
+        let movement =
+            (left.position - left.previous_position) - (right.position - right.previous_position);
+        let sliding = movement - normal * movement.dot(normal);
+        let properties = properties_of_pair(material_properties, left.material, right.material);
+        let undo = 0.5 * friction(sliding, penetration, properties);
+
+        self.particle(i_left).position -= undo;
+        self.particle(i_right).position += undo;
     }
 
-    /// Integrate the final values and write them back to VRAM.
+    /// The opposite of `push_close_particles_apart()`, neighbours that are further apart than
+    /// `min_distance` are pulled towards it by the average cohesion of their materials. So
+    /// cohesive particles settle at exactly `min_distance` from each other, which is what makes
+    /// droplets and blobs.
+    fn pull_cohesive_particles_together(
+        &mut self,
+        material_properties: &[MaterialProperties],
+        min_distance: f32,
+        distance: f32,
+        i_left: usize,
+        i_right: usize,
+    ) {
+        let cohesion = properties_of_pair(
+            material_properties,
+            self.particle(i_left).material,
+            self.particle(i_right).material,
+        )
+        .cohesion;
+        if cohesion == 0.0 {
+            return;
+        }
+
+        let force = 0.5 * cohesion * (distance - min_distance) / distance;
+        let mut distance_vec: Vec2 =
+            self.particle(i_right).position - self.particle(i_left).position;
+        distance_vec *= force;
+
+        self.particle(i_left).position += distance_vec;
+        self.particle(i_right).position -= distance_vec;
+    }
+
+    /// Move heat from the hotter of 2 neighbouring particles to the colder one. Whatever one loses
+    /// the other gains, so conduction never creates or destroys heat. At a conductivity of 1 the
+    /// pair ends up at their average temperature.
+    fn conduct_heat(&mut self, settings: &WorldSettings, i_left: usize, i_right: usize) {
+        let flow = 0.5
+            * settings.thermal_conductivity
+            * (self.particle(i_right).temperature - self.particle(i_left).temperature);
+
+        self.particle(i_left).temperature += flow;
+        self.particle(i_right).temperature -= flow;
+    }
+
+    /// Change the materials of 2 neighbouring particles if they're the reactants of one of the
+    /// contact reactions. The reactants can be either way round. Only the first reaction that
+    /// happens counts, so a pair reacts at most once a frame.
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "Particle and reaction indices started out as `u32`s"
+    )]
+    fn react_on_contact(
+        &mut self,
+        settings: &WorldSettings,
+        reactions: &[Reaction],
+        i_left: usize,
+        i_right: usize,
+    ) {
+        let left = *self.particle(i_left);
+        let right = *self.particle(i_right);
+        for reaction_index in 0..settings.reactions_count as usize {
+            // SAFETY: We're relying on surrounding code to make sure this is never out of bounds.
+            let reaction = unsafe { *reactions.index_unchecked(reaction_index) };
+            if reaction.kind != REACTION_CONTACT {
+                continue;
+            }
+
+            let (left_product, right_product) = if reaction.reactant == left.material
+                && reaction.other_reactant == right.material
+            {
+                (reaction.product, reaction.other_product)
+            } else if reaction.reactant == right.material
+                && reaction.other_reactant == left.material
+            {
+                (reaction.other_product, reaction.product)
+            } else {
+                continue;
+            };
+
+            let chance = random(
+                settings.random_seed,
+                left.index as u32,
+                right.index as u32,
+                reaction_index as u32,
+            );
+            if chance < reaction.probability {
+                self.particle(i_left).material = left_product;
+                self.particle(i_right).material = right_product;
+                return;
+            }
+        }
+    }
+
+    /// Change the materials of particles that are hot, or cold, enough, see
+    /// `Particle::transition()`.
+    pub fn transition(&mut self, settings: &WorldSettings, reactions: &[Reaction]) {
+        for i in 0..self.count {
+            self.particle(i).transition(settings, reactions);
+        }
+    }
+
+    /// Exchange heat with the ambient temperature and any heat sources, see
+    /// `Particle::exchange_heat()`.
+    pub fn exchange_heat(&mut self, settings: &WorldSettings, heat_sources: &[Vec4]) {
+        for i in 0..self.count {
+            self.particle(i).exchange_heat(settings, heat_sources);
+        }
+    }
+
+    /// Predict where every particle is heading, see `Particle::integrate()`. The physics then
+    /// corrects the predictions.
+    pub fn integrate(&mut self, settings: &WorldSettings) {
+        for i in 0..self.count {
+            self.particle(i).integrate(settings);
+        }
+    }
+
+    /// Enforce the limits, which works out the final velocities, drain the particles that have
+    /// entered a sink, cull those that the view has left behind and write them back to VRAM.
     pub fn finish(
         &mut self,
         settings: &WorldSettings,
-        positions: &mut [Vec2],
-        velocities: &mut [Vec2],
+        material_properties: &[MaterialProperties],
+        sinks: &[Vec4; MAX_SINKS as usize],
+        particles_output: &mut [PackedParticle],
+        cell_counts: &mut [u32],
     ) {
         for i in 0..self.count {
-            self.particle(i).integrate();
-            self.particle(i).enforce_limits(settings);
-            self.particle(i).write(positions, velocities);
+            let properties = properties_of(material_properties, self.particle(i).material);
+            self.particle(i).enforce_limits(settings, properties);
+            self.particle(i).drain(settings, sinks);
+            self.particle(i).cull_outside_view(settings);
+            self.particle(i)
+                .write(settings, particles_output, cell_counts);
         }
     }
 
@@ -120,17 +339,198 @@ impl Particles {
     }
 }
 
-#[expect(clippy::unreadable_literal, reason = "Tests aren't so strict")]
+#[expect(
+    clippy::unreadable_literal,
+    clippy::float_cmp,
+    reason = "Tests aren't so strict"
+)]
 #[cfg(test)]
 mod test {
+    use spirv_std::glam::UVec2;
+    use wrach_cpu_gpu_shared::{REACTION_ABOVE_TEMPERATURE, REACTION_BELOW_TEMPERATURE};
+
     use super::*;
 
+    /// Settings for particles that conduct half of their difference in temperature
+    fn settings() -> WorldSettings {
+        WorldSettings {
+            view_dimensions: Vec2::new(10.0, 10.0),
+            view_anchor: Vec2::ZERO,
+            grid_dimensions: UVec2::new(4, 4),
+            cell_size: 3.0,
+            particles_in_frame_count: 0,
+            new_particles_count: 0,
+            ambient_temperature: 0.0,
+            thermal_conductivity: 0.5,
+            cooling_rate: 0.0,
+            heat_sources_count: 0,
+            reactions_count: 0,
+            sinks_count: 0,
+            random_seed: 0,
+            gravity: 0.0,
+            particle_radius: 0.5,
+            max_speed: 1.0,
+        }
+    }
+
+    /// Particles at `positions`, moving at `velocities`, with the given temperatures and materials
+    fn packed<const N: usize>(
+        positions: &[Vec2; N],
+        velocities: &[Vec2; N],
+        temperatures: &[f32; N],
+        materials: &[u32; N],
+    ) -> [PackedParticle; N] {
+        let mut particles = [PackedParticle::default(); N];
+        for ((((particle, position), velocity), temperature), material) in particles
+            .iter_mut()
+            .zip(positions)
+            .zip(velocities)
+            .zip(temperatures)
+            .zip(materials)
+        {
+            *particle = PackedParticle {
+                position: *position,
+                velocity: *velocity,
+                age: UVec2::ZERO,
+                temperature: *temperature,
+                material: *material,
+            };
+        }
+        particles
+    }
+
+    /// Material properties where every material has the same cohesion
+    fn properties(cohesion: f32) -> [MaterialProperties; MAX_MATERIALS as usize] {
+        [MaterialProperties {
+            cohesion,
+            ..MaterialProperties::default()
+        }; MAX_MATERIALS as usize]
+    }
+
+    /// A reaction that always happens
+    fn reaction(
+        kind: u32,
+        reactants: (u32, u32),
+        products: (u32, u32),
+        temperature: f32,
+    ) -> Reaction {
+        Reaction {
+            kind,
+            reactant: reactants.0,
+            other_reactant: reactants.1,
+            product: products.0,
+            other_product: products.1,
+            temperature,
+            probability: 1.0,
+        }
+    }
+
+    #[test]
+    fn pulls_cohesive_particles_together() {
+        let positions = &[
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.4, 1.0),
+            Vec2::new(6.0, 1.0),
+        ];
+        let velocities = &[Vec2::ZERO; 3];
+        let mut particles =
+            Particles::new(0, 3, &packed(positions, velocities, &[0.0; 3], &[0; 3]));
+        particles.pairs(&settings(), &[], &properties(0.5));
+
+        assert!(particles.data[0].position.distance(Vec2::new(1.1, 1.0)) < 0.001);
+        assert!(particles.data[1].position.distance(Vec2::new(2.3, 1.0)) < 0.001);
+        assert_eq!(
+            particles.data[2].position,
+            Vec2::new(6.0, 1.0),
+            "Particles that aren't neighbours aren't pulled together"
+        );
+
+        for _ in 0..50 {
+            particles.pairs(&settings(), &[], &properties(0.5));
+        }
+        let distance = particles.data[0]
+            .position
+            .distance(particles.data[1].position);
+        assert!(
+            (distance - min_distance(&settings())).abs() < 0.001,
+            "Cohesive particles settle at `min_distance()`, not {distance}"
+        );
+    }
+
+    #[test]
+    fn friction_stops_touching_particles_sliding() {
+        let positions = &[Vec2::new(1.0, 1.0), Vec2::new(1.9, 1.0)];
+        let velocities = &[Vec2::ZERO; 2];
+        let sliding = |static_friction: f32| {
+            let mut particles =
+                Particles::new(0, 2, &packed(positions, velocities, &[0.0; 2], &[0; 2]));
+            // The right particle has slid down this frame, whilst being pressed into the left one
+            particles.data[1].previous_position = Vec2::new(1.9, 1.05);
+            particles.pairs(
+                &settings(),
+                &[],
+                &[MaterialProperties {
+                    static_friction,
+                    ..MaterialProperties::default()
+                }; MAX_MATERIALS as usize],
+            );
+            let [left, right] = [particles.data[0], particles.data[1]];
+            ((right.position - right.previous_position) - (left.position - left.previous_position))
+                .y
+        };
+
+        assert!((sliding(0.0) + 0.05).abs() < 0.001);
+        assert!(
+            sliding(1.0).abs() < 0.001,
+            "Static friction undoes all the sliding"
+        );
+    }
+
+    #[test]
+    fn only_cohesive_materials_pull() {
+        let positions = &[Vec2::new(1.0, 1.0), Vec2::new(2.4, 1.0)];
+        let velocities = &[Vec2::ZERO; 2];
+        let mut material_properties = properties(0.0);
+        material_properties[1].cohesion = 1.0;
+
+        let mut sticky = Particles::new(0, 2, &packed(positions, velocities, &[0.0; 2], &[1, 1]));
+        sticky.pairs(&settings(), &[], &material_properties);
+        let distance = sticky.data[0].position.distance(sticky.data[1].position);
+        assert!((distance - min_distance(&settings())).abs() < 0.001);
+
+        let mut mixed = Particles::new(0, 2, &packed(positions, velocities, &[0.0; 2], &[1, 0]));
+        mixed.pairs(&settings(), &[], &material_properties);
+        let distance = mixed.data[0].position.distance(mixed.data[1].position);
+        assert!(
+            (distance - 1.2).abs() < 0.001,
+            "Pairs are pulled by their average cohesion"
+        );
+
+        let mut unknown = Particles::new(
+            0,
+            2,
+            &packed(
+                positions,
+                velocities,
+                &[0.0; 2],
+                &[MAX_MATERIALS, MAX_MATERIALS],
+            ),
+        );
+        unknown.pairs(&settings(), &[], &material_properties);
+        assert_eq!(
+            unknown.data[1].position,
+            Vec2::new(2.4, 1.0),
+            "Materials without properties aren't cohesive"
+        );
+    }
+
     #[test]
     fn pushes_particles_apart() {
         let positions = &[Vec2::new(1.0, 1.0), Vec2::new(1.1, 1.1)];
         let velocities = &[Vec2::new(0.1, 0.2), Vec2::new(0.3, 0.4)];
-        let mut particles = Particles::new(0, 2, positions, velocities);
-        particles.pairs();
+        let mut particles =
+            Particles::new(0, 2, &packed(positions, velocities, &[0.0; 2], &[0; 2]));
+        particles.pairs(&settings(), &[], &properties(0.0));
 
         assert_eq!(
             particles.data[0].position,
@@ -144,4 +544,279 @@ mod test {
         assert!(new_distance < 1.001);
         assert!(new_distance > 0.999);
     }
+
+    #[test]
+    fn bigger_particles_are_pushed_further_apart() {
+        let positions = &[Vec2::new(1.0, 1.0), Vec2::new(2.5, 1.0)];
+        let velocities = &[Vec2::ZERO; 2];
+        let mut particles =
+            Particles::new(0, 2, &packed(positions, velocities, &[0.0; 2], &[0; 2]));
+        let settings = WorldSettings {
+            particle_radius: 1.0,
+            ..settings()
+        };
+        particles.pairs(&settings, &[], &properties(0.0));
+
+        let distance = particles.data[0]
+            .position
+            .distance(particles.data[1].position);
+        assert!(
+            (distance - 2.0).abs() < 0.001,
+            "Particles with a radius of 1 are kept 2 apart, not {distance}"
+        );
+    }
+
+    #[test]
+    fn limits_speed_rather_than_each_axis() {
+        let mut particle = Particle {
+            position: Vec2::new(5.0, 5.0),
+            previous_position: Vec2::new(3.0, 3.0),
+            ..Particle::default()
+        };
+        particle.enforce_limits(&settings(), MaterialProperties::default());
+
+        assert!((particle.velocity.length() - 1.0).abs() < 0.001);
+        assert!(
+            (particle.velocity.x - particle.velocity.y).abs() < 0.001,
+            "The direction is kept"
+        );
+    }
+
+    #[test]
+    fn pairs_are_repeatable() {
+        let positions = &[
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.1, 1.2),
+            Vec2::new(1.3, 0.9),
+        ];
+        let velocities = &[Vec2::ZERO; 3];
+        let temperatures = &[10.0, 20.0, 30.0];
+        let materials = &[0; 3];
+        let mut first = Particles::new(
+            0,
+            3,
+            &packed(positions, velocities, temperatures, materials),
+        );
+        let mut second = Particles::new(
+            0,
+            3,
+            &packed(positions, velocities, temperatures, materials),
+        );
+        first.pairs(&settings(), &[], &properties(0.0));
+        second.pairs(&settings(), &[], &properties(0.0));
+
+        for (left, right) in first.data.iter().zip(&second.data) {
+            assert_eq!(
+                left.position.to_array().map(f32::to_bits),
+                right.position.to_array().map(f32::to_bits)
+            );
+            assert_eq!(left.temperature.to_bits(), right.temperature.to_bits());
+        }
+    }
+
+    #[test]
+    fn neighbours_conduct_heat_without_losing_any() {
+        let positions = &[
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.2, 1.0),
+            Vec2::new(5.0, 1.0),
+        ];
+        let velocities = &[Vec2::ZERO; 3];
+        let mut particles = Particles::new(
+            0,
+            3,
+            &packed(positions, velocities, &[100.0, 0.0, 0.0], &[0; 3]),
+        );
+        particles.pairs(&settings(), &[], &properties(0.0));
+
+        assert_eq!(particles.data[0].temperature, 75.0);
+        assert_eq!(particles.data[1].temperature, 25.0);
+        assert_eq!(
+            particles.data[2].temperature, 0.0,
+            "Particles that aren't neighbours don't conduct heat"
+        );
+        assert_eq!(
+            particles.data[0].position,
+            Vec2::new(1.0, 1.0),
+            "Neighbours that aren't too close aren't pushed apart"
+        );
+    }
+
+    #[test]
+    fn particles_cool_down_and_are_heated_by_heat_sources() {
+        let positions = &[Vec2::new(1.0, 1.0), Vec2::new(8.0, 8.0)];
+        let velocities = &[Vec2::ZERO; 2];
+        let mut particles = Particles::new(
+            0,
+            2,
+            &packed(positions, velocities, &[100.0, 100.0], &[0; 2]),
+        );
+        let cooling = WorldSettings {
+            ambient_temperature: 20.0,
+            cooling_rate: 0.25,
+            heat_sources_count: 1,
+            ..settings()
+        };
+        particles.exchange_heat(&cooling, &[Vec4::new(1.0, 1.5, 1.0, 10.0)]);
+
+        assert_eq!(particles.data[0].temperature, 90.0);
+        assert_eq!(particles.data[1].temperature, 80.0);
+    }
+
+    #[test]
+    fn particles_in_sinks_expire() {
+        let mut sinks = [Vec4::ZERO; MAX_SINKS as usize];
+        sinks[0] = Vec4::new(1.0, 1.5, 1.0, 0.0);
+        let draining = WorldSettings {
+            sinks_count: 1,
+            ..settings()
+        };
+
+        let mut immortal = Particle {
+            position: Vec2::new(1.0, 1.0),
+            ..Particle::default()
+        };
+        immortal.drain(&draining, &sinks);
+        assert!(immortal.is_expired());
+
+        let mut mortal = Particle {
+            position: Vec2::new(1.0, 1.0),
+            age: UVec2::new(2, 10),
+            ..Particle::default()
+        };
+        mortal.drain(&draining, &sinks);
+        assert!(mortal.is_expired());
+
+        let mut outside = Particle {
+            position: Vec2::new(8.0, 8.0),
+            ..Particle::default()
+        };
+        outside.drain(&draining, &sinks);
+        assert!(!outside.is_expired());
+    }
+
+    #[test]
+    fn particles_left_outside_the_view_are_culled() {
+        let positions = &[
+            Vec2::new(-5.0, 1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(10.0, 10.0),
+        ];
+        let velocities = &[Vec2::new(1.0, 0.0), Vec2::new(-1.0, 0.0), Vec2::ZERO];
+        let mut particles =
+            Particles::new(0, 3, &packed(positions, velocities, &[0.0; 3], &[0; 3]));
+        particles.integrate(&settings());
+        let mut output = [PackedParticle::default(); 3];
+        let mut cell_counts = [0; 16];
+        particles.finish(
+            &settings(),
+            &properties(0.0),
+            &[Vec4::ZERO; MAX_SINKS as usize],
+            &mut output,
+            &mut cell_counts,
+        );
+
+        assert!(
+            particles.data[0].is_expired(),
+            "It's culled even though the boundaries moved it inside"
+        );
+        assert!(
+            !particles.data[1].is_expired(),
+            "Particles that move through the boundaries are kept inside"
+        );
+        assert!(!particles.data[2].is_expired(), "The edges are inside");
+        assert_eq!(cell_counts.iter().sum::<u32>(), 2);
+    }
+
+    #[test]
+    fn neighbours_react_whichever_way_round_they_are() {
+        let water = 1;
+        let lava = 2;
+        let steam = 3;
+        let stone = 4;
+        let positions = &[
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.2, 1.0),
+            Vec2::new(5.0, 1.0),
+            Vec2::new(6.2, 1.0),
+            Vec2::new(8.0, 8.0),
+        ];
+        let velocities = &[Vec2::ZERO; 5];
+        let mut particles = Particles::new(
+            0,
+            5,
+            &packed(
+                positions,
+                velocities,
+                &[0.0; 5],
+                &[water, lava, lava, water, water],
+            ),
+        );
+        let reactions = &[reaction(
+            REACTION_CONTACT,
+            (water, lava),
+            (steam, stone),
+            0.0,
+        )];
+        let reacting = WorldSettings {
+            reactions_count: 1,
+            ..settings()
+        };
+        particles.pairs(&reacting, reactions, &properties(0.0));
+
+        let materials = particles.data.map(|particle| particle.material);
+        assert_eq!(materials[0..4], [steam, stone, stone, steam]);
+        assert_eq!(
+            materials[4], water,
+            "Particles that aren't touching anything don't react"
+        );
+    }
+
+    #[test]
+    fn particles_change_material_past_a_temperature() {
+        let ice = 1;
+        let water = 2;
+        let steam = 3;
+        let positions = &[
+            Vec2::new(1.0, 1.0),
+            Vec2::new(5.0, 5.0),
+            Vec2::new(8.0, 8.0),
+        ];
+        let velocities = &[Vec2::ZERO; 3];
+        let mut particles = Particles::new(
+            0,
+            3,
+            &packed(
+                positions,
+                velocities,
+                &[-10.0, 50.0, 150.0],
+                &[ice, water, water],
+            ),
+        );
+        let mut reactions = [
+            reaction(REACTION_ABOVE_TEMPERATURE, (ice, 0), (water, 0), 0.0),
+            reaction(REACTION_ABOVE_TEMPERATURE, (water, 0), (steam, 0), 100.0),
+            reaction(REACTION_BELOW_TEMPERATURE, (water, 0), (ice, 0), 0.0),
+        ];
+        let reacting = WorldSettings {
+            reactions_count: 3,
+            ..settings()
+        };
+        particles.transition(&reacting, &reactions);
+
+        let materials = particles.data.map(|particle| particle.material);
+        assert_eq!(
+            materials[0..3],
+            [ice, water, steam],
+            "Ice stays frozen below its melting point, and water only boils above 100"
+        );
+
+        reactions[0].temperature = -20.0;
+        reactions[0].probability = 0.0;
+        particles.transition(&reacting, &reactions);
+        assert_eq!(
+            particles.data[0].material, ice,
+            "Reactions with no chance of happening never happen"
+        );
+    }
 }
@@ -0,0 +1,43 @@
+//! Random numbers for the physics. GPUs don't have a random number generator, so numbers are
+//! hashed from the frame's seed and whatever identifies the thing that needs one. The same inputs
+//! always give the same number, so deterministic simulations stay deterministic.
+
+/// The PCG hash, [see](https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/)
+const fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+/// A random number from 0 up to, but not including, 1. Particles that only need one number pass
+/// their index as both `particle` and `other`.
+#[expect(
+    clippy::cast_precision_loss,
+    reason = "24 bits always fit into an `f32`"
+)]
+pub fn random(seed: u32, particle: u32, other: u32, rule: u32) -> f32 {
+    let bits = hash(hash(hash(seed ^ particle) ^ other) ^ rule);
+    // The top 24 bits, which is as many as an `f32` can hold exactly.
+    (bits >> 8) as f32 / 16_777_216.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn random_numbers_are_fractions_that_change_with_every_input() {
+        let first = random(1, 2, 3, 4);
+        assert!((0.0..1.0).contains(&first));
+        assert_eq!(first.to_bits(), random(1, 2, 3, 4).to_bits());
+        for different in [
+            random(5, 2, 3, 4),
+            random(1, 5, 3, 4),
+            random(1, 2, 5, 4),
+            random(1, 2, 3, 5),
+        ] {
+            assert!((0.0..1.0).contains(&different));
+            assert_ne!(first.to_bits(), different.to_bits());
+        }
+    }
+}
@@ -23,12 +23,134 @@ pub struct WorldSettings {
     pub view_anchor: Vec2,
     /// The dimensions of the spatial bin grid, the unit is a cell
     pub grid_dimensions: UVec2,
-    /// The size of a spatial bin cell
-    pub cell_size: u32,
-    /// Total number of particles simulated in this frame. This will normally be much smaller than
-    /// the total number of particles that we have a record of.
+    /// The size of a spatial bin cell, in the same units as the positions of particles. That's
+    /// `WrachConfig::cell_size` particles across.
+    pub cell_size: f32,
+    /// Total number of particles simulated in this frame, or more if the GPU has culled some that
+    /// the CPU hasn't seen yet, see `Culling`. This will normally be much smaller than the total
+    /// number of particles that we have a record of.
     pub particles_in_frame_count: u32,
+    /// Number of freshly added particles waiting in `Buffers::PARTICLES_NEW` to be merged into
+    /// the frame's particle data by the GPU.
+    pub new_particles_count: u32,
+    /// The temperature that particles cool, or warm, towards when nothing else is heating them
+    pub ambient_temperature: f32,
+    /// The fraction of the difference in temperature that flows between two neighbouring particles
+    /// every frame, from 0 to 1
+    pub thermal_conductivity: f32,
+    /// The fraction of the difference from `ambient_temperature` that particles lose every frame,
+    /// from 0 to 1
+    pub cooling_rate: f32,
+    /// The number of heat sources in the heat sources buffer
+    pub heat_sources_count: u32,
+    /// The number of reactions in the reactions buffer
+    pub reactions_count: u32,
+    /// The number of sinks in the sinks buffer
+    pub sinks_count: u32,
+    /// A different number every frame, so that reactions which only happen with some probability
+    /// don't keep happening to the same particles
+    pub random_seed: u32,
+    /// How much faster particles fall every frame
+    pub gravity: f32,
+    /// Half the distance that particles are kept apart from each other
+    pub particle_radius: f32,
+    /// The fastest that a particle can move in a single frame
+    pub max_speed: f32,
+}
+
+/// Everything about a single particle, an item of the particle buffers.
+///
+/// Keeping all of a particle's data in one buffer, rather than a buffer for each field, keeps the
+/// physics within the 8 storage buffers per shader stage that WebGPU guarantees. Every field is 4
+/// or 8 bytes, so that it's laid out the same on the CPU and the GPU. See `ShaderPackedParticle` in
+/// the Bevy crate for the version that gets uploaded.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+#[expect(clippy::exhaustive_structs, reason = "")]
+pub struct PackedParticle {
+    /// Where the particle is
+    pub position: Vec2,
+    /// How far the particle moves every frame
+    pub velocity: Vec2,
+    /// The number of frames the particle has been simulated for, and how many it lives for. A
+    /// lifetime of 0 means that it lives forever.
+    pub age: UVec2,
+    /// The particle's temperature
+    pub temperature: f32,
+    /// The material that the particle is made of
+    pub material: u32,
+}
+
+/// The most heat sources that can be in the simulation at once. It's the size of the heat sources
+/// buffer.
+pub const MAX_HEAT_SOURCES: u32 = 64;
+
+/// The most reactions that a simulation can have. It's the size of the reactions buffer.
+pub const MAX_REACTIONS: u32 = 64;
+
+/// The most sinks that can be in the simulation at once. It's the size of the sinks buffer, which
+/// is a uniform buffer so that the physics stays within WebGPU's limit on storage buffers.
+pub const MAX_SINKS: u32 = 16;
+
+/// A particle of the reactant material that's hotter than the reaction's temperature becomes the
+/// product material.
+pub const REACTION_ABOVE_TEMPERATURE: u32 = 0;
+
+/// A particle of the reactant material that's colder than the reaction's temperature becomes the
+/// product material.
+pub const REACTION_BELOW_TEMPERATURE: u32 = 1;
+
+/// Neighbouring particles of the reactant and other reactant materials become the product and
+/// other product materials respectively.
+pub const REACTION_CONTACT: u32 = 2;
+
+/// A single rule of the reactions table. Every field is 4 bytes, so that it's laid out the same on
+/// the CPU and the GPU. See `Reaction` in the Bevy crate for the friendly version.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+#[expect(clippy::exhaustive_structs, reason = "")]
+pub struct Reaction {
+    /// One of the `REACTION_*` constants
+    pub kind: u32,
+    /// The material that reacts
+    pub reactant: u32,
+    /// The material that the reactant has to be touching, only for contact reactions
+    pub other_reactant: u32,
+    /// What the reactant becomes
+    pub product: u32,
+    /// What the other reactant becomes, only for contact reactions
+    pub other_product: u32,
+    /// The temperature that the reactant has to be above, or below
+    pub temperature: f32,
+    /// The chance of the reaction happening every frame that it can, from 0 to 1
+    pub probability: f32,
+}
+
+/// The number of materials that can have properties, see `MaterialProperties`. It's the size of
+/// the material properties buffer, materials from this number upwards just get the defaults.
+pub const MAX_MATERIALS: u32 = 64;
+
+/// How particles of a material behave.
+///
+/// The material properties buffer has one of these for every material below `MAX_MATERIALS`,
+/// indexed by the material. See `MaterialProperties` in the Bevy crate for the friendly version.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+#[expect(clippy::exhaustive_structs, reason = "")]
+pub struct MaterialProperties {
+    /// How strongly neighbouring particles are pulled together, from 0 to 1
+    pub cohesion: f32,
+    /// How much sliding a contact can resist completely, relative to how hard the particles are
+    /// pressed together, from 0 to 1
+    pub static_friction: f32,
+    /// How much sliding is slowed once a contact is sliding, relative to how hard the particles are
+    /// pressed together, from 0 to 1
+    pub dynamic_friction: f32,
 }
 
 /// The size of a single spatial bin cell. The unit is one side of the square.
 pub const SPATIAL_BIN_CELL_SIZE: u16 = 3;
+
+/// All the workgroup sizes, ie threads per workgroup, that our compute shaders provide an
+/// entrypoint for. Shaders name their entrypoints after the size, eg: `main_64`.
+pub const WORKGROUP_SIZES: [u32; 4] = [32, 64, 128, 256];